@@ -0,0 +1,246 @@
+//! Object libraries (`.lib`), which bundle several object files so that a program only links the ones it uses.
+//!
+//! A library is a JSON object like:
+//! ```json
+//! { "format": "lc3-library", "members": [{ "name": "print.obj", "symbols": ["PRINT"], "object": "LC-3 OBJ FILE\n..." }] }
+//! ```
+//! where each member has its name, the labels it defines (which make up the library's symbol index),
+//! and its object file in ensemble's text format (along with its relocatable sections, see [`crate::reloc`]).
+//!
+//! When linking, a member is only linked if it defines an external label which nothing linked so far defines
+//! (so a member can pull in other members, from its own library or another).
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use lc3_ensemble::asm::encoding::{ObjFileFormat, TextFormat};
+use lc3_ensemble::asm::ObjectFile;
+use serde_json::{json, Value};
+
+use crate::obj;
+use crate::reloc::{self, Relocs};
+
+/// The `format` field of a library.
+const LIBRARY_FORMAT: &str = "lc3-library";
+
+/// An object file in a library.
+#[derive(Clone)]
+pub(crate) struct Member {
+    /// The file name of the object file.
+    pub(crate) name: String,
+    /// The labels the object file defines.
+    pub(crate) symbols: Vec<String>,
+    pub(crate) obj: ObjectFile,
+    pub(crate) relocs: Relocs,
+}
+impl Member {
+    /// Creates a member from an object file (indexing the labels it defines).
+    pub(crate) fn new(name: String, obj: ObjectFile, relocs: Relocs) -> Self {
+        let mut symbols: Vec<_> = defined(&obj).map(String::from).collect();
+        symbols.sort();
+        Member { name, symbols, obj, relocs }
+    }
+}
+
+/// The labels an object file defines (rather than its external labels).
+fn defined(obj: &ObjectFile) -> impl Iterator<Item=&str> {
+    obj.symbol_table().into_iter()
+        .flat_map(|sym| sym.label_iter())
+        .filter(|&(_, _, external)| !external)
+        .map(|(name, _, _)| name)
+}
+/// The external labels of an object file.
+fn externals(obj: &ObjectFile) -> impl Iterator<Item=&str> {
+    obj.symbol_table().into_iter()
+        .flat_map(|sym| sym.label_iter())
+        .filter(|&(_, _, external)| external)
+        .map(|(name, _, _)| name)
+}
+
+/// Creates a library of the given object files (with their paths and relocatable sections).
+pub(crate) fn write(objects: &[(PathBuf, ObjectFile, Relocs)]) -> String {
+    let members: Vec<_> = objects.iter()
+        .map(|(path, obj, relocs)| {
+            let name = path.file_name().unwrap_or(path.as_os_str()).to_string_lossy();
+            let mut symbols: Vec<_> = defined(obj).collect();
+            symbols.sort();
+
+            let mut object = TextFormat::serialize(obj).into_bytes();
+            reloc::append(&mut object, relocs, true);
+            json!({ "name": name, "symbols": symbols, "object": String::from_utf8_lossy(&object) })
+        })
+        .collect();
+
+    let mut out = serde_json::to_string_pretty(&json!({ "format": LIBRARY_FORMAT, "members": members }))
+        .unwrap_or_default();
+    out.push('\n');
+    out
+}
+
+/// Reads the library at the given path,
+/// returning `None` if the file isn't a library (or a member's object file is malformed).
+pub(crate) fn read(path: &Path) -> std::io::Result<Option<Vec<Member>>> {
+    let bytes = std::fs::read(path)?;
+    let Ok(Value::Object(fields)) = serde_json::from_slice::<Value>(&bytes) else { return Ok(None) };
+    if fields.get("format").and_then(Value::as_str) != Some(LIBRARY_FORMAT) {
+        return Ok(None);
+    }
+
+    let members = fields.get("members")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .map(|member| {
+            let name = member.get("name")?.as_str()?.to_string();
+            let symbols = member.get("symbols")?.as_array()?.iter()
+                .map(|s| s.as_str().map(String::from))
+                .collect::<Option<_>>()?;
+            let object = member.get("object")?.as_str()?;
+            let obj = obj::deserialize_text(object)?;
+            let (_, relocs) = reloc::split(object.as_bytes());
+            Some(Member { name, symbols, obj, relocs })
+        })
+        .collect();
+    Ok(members)
+}
+
+/// Selects the members of libraries (given with their paths) which the object files need,
+/// returning them in the order they were needed (named like `cs2110.lib(print.obj)`).
+///
+/// A member is needed if it defines an external label which isn't defined by the object files or another needed member.
+/// If several members define the label, the first one (in the order of the libraries) is used.
+pub(crate) fn select(inputs: &[(PathBuf, ObjectFile, Relocs)], libraries: Vec<(PathBuf, Vec<Member>)>) -> Vec<(PathBuf, ObjectFile, Relocs)> {
+    let mut defined_labels: HashSet<String> = inputs.iter()
+        .flat_map(|(_, obj, _)| defined(obj).map(String::from))
+        .collect();
+    let mut wanted: Vec<String> = inputs.iter()
+        .flat_map(|(_, obj, _)| {
+            let mut names: Vec<_> = externals(obj).map(String::from).collect();
+            names.sort();
+            names
+        })
+        .collect();
+
+    let mut libraries: Vec<(PathBuf, Vec<Option<Member>>)> = libraries.into_iter()
+        .map(|(path, members)| (path, members.into_iter().map(Some).collect()))
+        .collect();
+    let mut selected = vec![];
+    let mut i = 0;
+    while let Some(name) = wanted.get(i).cloned() {
+        i += 1;
+        if defined_labels.contains(&name) { continue; }
+
+        let found = libraries.iter_mut().find_map(|(path, members)| {
+            let slot = members.iter_mut().find(|m| m.as_ref().is_some_and(|m| m.symbols.contains(&name)))?;
+            Some((&*path, slot.take()?))
+        });
+        let Some((lib_path, member)) = found else { continue };
+
+        defined_labels.extend(defined(&member.obj).map(String::from));
+        let mut names: Vec<_> = externals(&member.obj).map(String::from).collect();
+        names.sort();
+        wanted.extend(names);
+
+        let path = PathBuf::from(format!("{}({})", lib_path.display(), member.name));
+        selected.push((path, member.obj, member.relocs));
+    }
+    selected
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::tests::{assemble, assemble_relocatable};
+    use crate::preproc::tests::temp_dir;
+
+    const MAIN: &str = ".ORIG x3000\n.EXTERNAL PRINT\nLDI R0, PTR\nJSRR R0\nHALT\nPTR .FILL PRINT\n.END\n";
+    const PRINT: &str = ".RELOC\n.EXTERNAL EMIT\nPRINT LDI R1, PTR\nJMP R1\nPTR .FILL EMIT\n.END\n";
+    const EMIT: &str = ".RELOC\nEMIT OUT\nRET\n.END\n";
+    const SCAN: &str = ".RELOC\nSCAN GETC\nRET\n.END\n";
+
+    fn member(name: &str, src: &str) -> Member {
+        let (obj, relocs) = assemble_relocatable(src);
+        Member::new(name.to_string(), obj, relocs)
+    }
+    fn main_input() -> Vec<(PathBuf, ObjectFile, Relocs)> {
+        vec![(PathBuf::from("main.obj"), assemble(MAIN), Relocs::default())]
+    }
+    /// Selects the members [`MAIN`] needs.
+    fn select_for_main(libraries: Vec<(PathBuf, Vec<Member>)>) -> Vec<(PathBuf, ObjectFile, Relocs)> {
+        select(&main_input(), libraries)
+    }
+    /// The names of the selected members.
+    fn names(selected: &[(PathBuf, ObjectFile, Relocs)]) -> Vec<String> {
+        selected.iter().map(|(path, _, _)| path.display().to_string()).collect()
+    }
+
+    #[test]
+    fn selects_only_needed_members() {
+        let libraries = vec![(PathBuf::from("io.lib"), vec![member("scan.obj", SCAN), member("print.obj", PRINT), member("emit.obj", EMIT)])];
+        assert_eq!(names(&select_for_main(libraries)), ["io.lib(print.obj)", "io.lib(emit.obj)"]);
+
+        // Nothing is selected if nothing is needed:
+        let libraries = vec![(PathBuf::from("io.lib"), vec![member("scan.obj", SCAN)])];
+        assert!(select(&main_input(), libraries).is_empty());
+
+        // ...or if the object files already define it:
+        let mut inputs = main_input();
+        inputs.push((PathBuf::from("print.obj"), assemble(".ORIG x4000\nPRINT RET\n.END\n"), Relocs::default()));
+        let libraries = vec![(PathBuf::from("io.lib"), vec![member("print.obj", PRINT)])];
+        assert!(select(&inputs, libraries).is_empty());
+    }
+
+    #[test]
+    fn pulls_members_transitively() {
+        // A member can pull in a member of an earlier library:
+        let libraries = vec![
+            (PathBuf::from("char.lib"), vec![member("emit.obj", EMIT)]),
+            (PathBuf::from("string.lib"), vec![member("print.obj", PRINT)]),
+        ];
+        assert_eq!(names(&select_for_main(libraries)), ["string.lib(print.obj)", "char.lib(emit.obj)"]);
+    }
+
+    #[test]
+    fn first_library_wins() {
+        let libraries = vec![
+            (PathBuf::from("a.lib"), vec![member("print.obj", PRINT), member("emit.obj", EMIT)]),
+            (PathBuf::from("b.lib"), vec![member("print.obj", PRINT), member("emit.obj", EMIT)]),
+        ];
+        assert_eq!(names(&select_for_main(libraries)), ["a.lib(print.obj)", "a.lib(emit.obj)"]);
+
+        // ...even for the labels a later library's member needs:
+        let libraries = vec![
+            (PathBuf::from("a.lib"), vec![member("emit.obj", EMIT)]),
+            (PathBuf::from("b.lib"), vec![member("emit.obj", EMIT), member("print.obj", PRINT)]),
+        ];
+        assert_eq!(names(&select_for_main(libraries)), ["b.lib(print.obj)", "a.lib(emit.obj)"]);
+    }
+
+    #[test]
+    fn libraries_round_trip() {
+        let dir = temp_dir("archive");
+        let objects: Vec<_> = [("print.obj", PRINT), ("emit.obj", EMIT)].into_iter()
+            .map(|(name, src)| {
+                let (obj, relocs) = assemble_relocatable(src);
+                (dir.join(name), obj, relocs)
+            })
+            .collect();
+        let path = dir.join("io.lib");
+        std::fs::write(&path, write(&objects)).expect("library should be written");
+
+        let members = read(&path).expect("library should be read").expect("library should be well-formed");
+        assert_eq!(members.iter().map(|m| &*m.name).collect::<Vec<_>>(), ["print.obj", "emit.obj"]);
+        assert_eq!(members.iter().map(|m| m.symbols.clone()).collect::<Vec<_>>(), [vec!["PRINT", "PTR"], vec!["EMIT"]]);
+        for (member, (_, obj, relocs)) in members.iter().zip(&objects) {
+            assert_eq!(member.obj.addr_iter().collect::<Vec<_>>(), obj.addr_iter().collect::<Vec<_>>(), "{}", member.name);
+            assert_eq!(&member.relocs, relocs, "{}", member.name);
+            assert!(!member.relocs.is_empty());
+        }
+
+        // Other JSON (like a project file) isn't a library:
+        std::fs::write(&path, r#"{ "sources": ["main.asm"], "output": "main.obj" }"#).expect("file should be written");
+        assert!(read(&path).expect("file should be read").is_none());
+        std::fs::write(&path, TextFormat::serialize(&objects[0].1)).expect("file should be written");
+        assert!(read(&path).expect("file should be read").is_none());
+    }
+}
@@ -0,0 +1,660 @@
+//! Assembling source code while collecting every error (rather than stopping at the first).
+//!
+//! Ensemble's parser and assembler both stop at the first error they encounter.
+//! To report every error in one go, this module repeatedly runs them,
+//! and after each error, patches the input so that the error does not occur again:
+//! - For parse errors, the offending line is blanked out (preserving the offsets of every other line).
+//! - For assembler errors, the offending statement or label is replaced or removed from the AST.
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ops::Range;
+
+use lc3_ensemble::asm::{assemble_debug, AsmErr, AsmErrKind, ObjectFile};
+use lc3_ensemble::ast::asm::{AsmInstr, Directive, Stmt, StmtKind};
+use lc3_ensemble::ast::{Label, Offset, PCOffset};
+use lc3_ensemble::err::{ErrSpan, ParseErr};
+use lc3_ensemble::parse::parse_ast;
+
+use crate::dialect::{Dialect, LabelCaseCollision};
+use crate::err::{asm_err_code, parse_err_code};
+use crate::expr::{DataWords, ExprError, Exprs};
+use crate::obj::{from_chunks, to_chunks, Chunk};
+use crate::preproc::Expanded;
+use crate::reloc::{RelocSection, Relocation, Relocs};
+
+/// An error from either parsing or assembling.
+#[derive(Debug)]
+pub(crate) enum AsmError {
+    /// A parse error, along with its span in the source
+    /// (which can differ from the error's own span if expressions were rewritten before parsing).
+    Parse(ParseErr, Range<usize>),
+    Expr(ExprError),
+    Asm(AsmErr),
+    /// Source the dialect accepts, but this assembler can't assemble.
+    LabelCase(LabelCaseCollision),
+}
+impl AsmError {
+    /// Gets the diagnostic code for this error.
+    pub(crate) fn code(&self) -> &'static str {
+        match self {
+            AsmError::Parse(e, _) => parse_err_code(e),
+            AsmError::Expr(e) => e.code(),
+            AsmError::Asm(e) => asm_err_code(e),
+            AsmError::LabelCase(e) => e.code(),
+        }
+    }
+
+    /// Whether this is an error in the syntax of the source (rather than in what it means).
+    pub(crate) fn is_syntax(&self) -> bool {
+        match self {
+            AsmError::Parse(..) => true,
+            AsmError::Expr(e) => e.is_syntax(),
+            AsmError::Asm(_) | AsmError::LabelCase(_) => false,
+        }
+    }
+
+    pub(crate) fn first_span(&self) -> Option<Range<usize>> {
+        use lc3_ensemble::err::Error;
+        self.span().map(|s| s.first())
+    }
+}
+impl std::fmt::Display for AsmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AsmError::Parse(e, _) => e.fmt(f),
+            AsmError::Expr(e) => e.fmt(f),
+            AsmError::Asm(e) => e.fmt(f),
+            AsmError::LabelCase(e) => e.fmt(f),
+        }
+    }
+}
+impl std::error::Error for AsmError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AsmError::Parse(e, _) => e.source(),
+            AsmError::Expr(e) => e.source(),
+            AsmError::Asm(e) => e.source(),
+            AsmError::LabelCase(e) => e.source(),
+        }
+    }
+}
+impl lc3_ensemble::err::Error for AsmError {
+    fn span(&self) -> Option<ErrSpan> {
+        match self {
+            AsmError::Parse(_, span) => Some(ErrSpan::from(span.clone())),
+            AsmError::Expr(e) => e.span(),
+            AsmError::Asm(e) => e.span(),
+            AsmError::LabelCase(e) => e.span(),
+        }
+    }
+
+    fn help(&self) -> Option<Cow<'_, str>> {
+        match self {
+            AsmError::Parse(e, _) => e.help(),
+            AsmError::Expr(e) => e.help(),
+            AsmError::Asm(e) => e.help(),
+            AsmError::LabelCase(e) => e.help(),
+        }
+    }
+}
+
+/// Replaces every character on the lines touched by `span` with spaces,
+/// keeping the byte offsets of all other characters the same.
+///
+/// This returns the blanked out text, or `None` if the lines were already blank.
+fn blank_lines(text: &mut String, span: Range<usize>) -> Option<String> {
+    let start = text[..span.start.min(text.len())].rfind('\n').map_or(0, |i| i + 1);
+    let end = text[span.end.min(text.len())..].find('\n').map_or(text.len(), |i| span.end + i);
+
+    let removed = text[start..end].to_string();
+    if removed.trim().is_empty() { return None; }
+
+    let blanked: String = removed.chars()
+        .map(|c| match c {
+            '\n' | '\r' => c.to_string(),
+            c => " ".repeat(c.len_utf8()),
+        })
+        .collect();
+    text.replace_range(start..end, &blanked);
+
+    Some(removed)
+}
+
+/// Parses the source, recovering from any parse errors.
+///
+/// This returns the AST of every line that could be parsed
+/// along with the errors of every line that could not be parsed.
+/// The spans of the AST still point into the original source.
+///
+/// Also returned is the set of labels defined on lines that were removed,
+/// so that errors that cascade from the removal can be ignored.
+pub(crate) fn parse_recovering(src: &str) -> (Vec<Stmt>, Vec<ParseErr>, HashSet<String>) {
+    let mut text = src.to_string();
+    let mut errors = vec![];
+    let mut removed_labels = HashSet::new();
+
+    loop {
+        match parse_ast(&text) {
+            Ok(ast) => return (ast, errors, removed_labels),
+            Err(e) => {
+                use lc3_ensemble::err::Error;
+                let span = e.span().map_or(0..0, |s| s.first());
+                errors.push(e);
+
+                let Some(removed) = blank_lines(&mut text, span) else {
+                    // Could not recover from this error.
+                    return (vec![], errors, removed_labels);
+                };
+                // A label is defined by the first token of a line (if it's an identifier).
+                removed_labels.extend({
+                    removed.lines()
+                        .filter_map(|line| line.trim_start().split(|c: char| !(c.is_alphanumeric() || c == '_')).next())
+                        .filter(|w| w.starts_with(|c: char| c.is_alphabetic() || c == '_'))
+                        .map(str::to_uppercase)
+                });
+            },
+        }
+    }
+}
+
+/// Gets the label that a statement references (if it references one).
+pub(crate) fn referenced_label(stmt: &Stmt) -> Option<&Label> {
+    match &stmt.nucleus {
+        | StmtKind::Instr(AsmInstr::BR(_, PCOffset::Label(l)))
+        | StmtKind::Instr(AsmInstr::JSR(PCOffset::Label(l)))
+        | StmtKind::Instr(AsmInstr::LD(_, PCOffset::Label(l)))
+        | StmtKind::Instr(AsmInstr::LDI(_, PCOffset::Label(l)))
+        | StmtKind::Instr(AsmInstr::LEA(_, PCOffset::Label(l)))
+        | StmtKind::Instr(AsmInstr::ST(_, PCOffset::Label(l)))
+        | StmtKind::Instr(AsmInstr::STI(_, PCOffset::Label(l)))
+        | StmtKind::Instr(AsmInstr::NOP(PCOffset::Label(l)))
+        | StmtKind::Directive(Directive::Fill(PCOffset::Label(l)))
+        | StmtKind::Directive(Directive::External(l)) => Some(l),
+        _ => None,
+    }
+}
+
+/// Gets the label that a statement references, mutably (if it references one).
+pub(crate) fn referenced_label_mut(stmt: &mut Stmt) -> Option<&mut Label> {
+    match &mut stmt.nucleus {
+        | StmtKind::Instr(AsmInstr::BR(_, PCOffset::Label(l)))
+        | StmtKind::Instr(AsmInstr::JSR(PCOffset::Label(l)))
+        | StmtKind::Instr(AsmInstr::LD(_, PCOffset::Label(l)))
+        | StmtKind::Instr(AsmInstr::LDI(_, PCOffset::Label(l)))
+        | StmtKind::Instr(AsmInstr::LEA(_, PCOffset::Label(l)))
+        | StmtKind::Instr(AsmInstr::ST(_, PCOffset::Label(l)))
+        | StmtKind::Instr(AsmInstr::STI(_, PCOffset::Label(l)))
+        | StmtKind::Instr(AsmInstr::NOP(PCOffset::Label(l)))
+        | StmtKind::Directive(Directive::Fill(PCOffset::Label(l)))
+        | StmtKind::Directive(Directive::External(l)) => Some(l),
+        _ => None,
+    }
+}
+
+/// The number of words a statement occupies in memory.
+pub(crate) fn stmt_len(stmt: &Stmt) -> u16 {
+    match &stmt.nucleus {
+        StmtKind::Directive(Directive::Orig(_) | Directive::End | Directive::External(_)) => 0,
+        StmtKind::Directive(Directive::Fill(_)) => 1,
+        StmtKind::Directive(Directive::Blkw(n)) => n.get(),
+        StmtKind::Directive(Directive::Stringz(s)) => s.len() as u16 + 1,
+        StmtKind::Instr(_) => 1,
+    }
+}
+
+/// Gets the span of a label in the source, given the span computed from its name.
+///
+/// Local labels are renamed to their qualified names (e.g., `.loop` to `MULTIPLY.loop`),
+/// so their names can be longer than the label as written.
+pub(crate) fn label_span(src: &str, span: Range<usize>) -> Range<usize> {
+    let Some(text) = src.get(span.start..) else { return span };
+    let len = text.find(|c: char| !(c.is_alphanumeric() || matches!(c, '_' | '.'))).unwrap_or(text.len());
+    span.start..span.start + len.min(span.len())
+}
+
+/// Whether execution never continues to the next statement after this instruction.
+pub(crate) fn is_unconditional(instr: &AsmInstr) -> bool {
+    match instr {
+        AsmInstr::BR(cc, _) => *cc == 0b111,
+        AsmInstr::TRAP(vect) => vect.get() == 0x25,
+        AsmInstr::JMP(_) | AsmInstr::RET | AsmInstr::RTI | AsmInstr::HALT => true,
+        _ => false,
+    }
+}
+
+/// Finds the index of the statement that contains the given span.
+fn find_stmt(ast: &[Stmt], span: &Range<usize>) -> Option<usize> {
+    ast.iter().position(|stmt| {
+        stmt.span.start <= span.start && span.end <= stmt.span.end
+        || stmt.labels.iter().any(|l| l.span() == *span)
+    })
+}
+
+/// Patches the AST so that the given assembler error does not occur again.
+///
+/// This returns false if the AST could not be patched.
+fn patch_ast(ast: &mut Vec<Stmt>, err: &AsmErr) -> bool {
+    let spans: Vec<_> = err.span.iter().cloned().collect();
+
+    match err.kind {
+        // Replace the statement with a placeholder of the same size,
+        // so that the addresses of every other statement are preserved.
+        AsmErrKind::OffsetNewErr(_) | AsmErrKind::OffsetExternal | AsmErrKind::CouldNotFindLabel => {
+            let Some(i) = spans.first().and_then(|s| find_stmt(ast, s)) else { return false };
+            ast[i].nucleus = StmtKind::Directive(Directive::Fill(PCOffset::Offset(Offset::new_trunc(0))));
+        },
+        // Remove the duplicate label.
+        AsmErrKind::OverlappingLabels => {
+            let Some(dup) = spans.last() else { return false };
+            let Some(stmt) = ast.iter_mut().find(|s| s.labels.iter().any(|l| l.span() == *dup)) else { return false };
+            stmt.labels.retain(|l| l.span() != *dup);
+        },
+        // Remove the labels which do not have an address.
+        AsmErrKind::UndetAddrLabel => {
+            let Some(stmt) = ast.iter_mut().find(|s| s.labels.iter().any(|l| spans.contains(&l.span()))) else { return false };
+            stmt.labels.retain(|l| !spans.contains(&l.span()));
+        },
+        // Remove the statement which does not have an address (or the unmatched .end).
+        AsmErrKind::UndetAddrStmt | AsmErrKind::UnopenedOrig => {
+            let Some(i) = spans.first().and_then(|s| ast.iter().position(|stmt| stmt.span == *s)) else { return false };
+            ast.remove(i);
+        },
+        // Close the block at the end of the file.
+        AsmErrKind::UnclosedOrig => {
+            let end = ast.last().map_or(0, |s| s.span.end);
+            ast.push(Stmt { labels: vec![], nucleus: StmtKind::Directive(Directive::End), span: end..end });
+        },
+        // Close the outer block right before the inner block.
+        AsmErrKind::OverlappingOrig => {
+            let Some(i) = spans.last().and_then(|s| ast.iter().position(|stmt| stmt.span == *s)) else { return false };
+            let start = ast[i].span.start;
+            ast.insert(i, Stmt { labels: vec![], nucleus: StmtKind::Directive(Directive::End), span: start..start });
+        },
+        // Drop the rest of the block.
+        AsmErrKind::WrappingBlock | AsmErrKind::BlockInIO => {
+            let Some(i) = spans.first().and_then(|s| ast.iter().position(|stmt| stmt.span == *s)) else { return false };
+            let end = ast[i..].iter()
+                .position(|s| matches!(s.nucleus, StmtKind::Directive(Directive::End)))
+                .map_or(ast.len(), |j| i + j);
+            ast.drain(i..end);
+        },
+        AsmErrKind::OverlappingBlocks => return false,
+    }
+
+    true
+}
+
+/// Adds an `.END` to every block which is missing one
+/// (for dialects which don't require them).
+fn close_blocks(ast: &mut Vec<Stmt>) {
+    let end = |at: usize| Stmt { labels: vec![], nucleus: StmtKind::Directive(Directive::End), span: at..at };
+
+    let mut open = false;
+    let mut i = 0;
+    while i < ast.len() {
+        match ast[i].nucleus {
+            StmtKind::Directive(Directive::Orig(_)) => {
+                if open {
+                    ast.insert(i, end(ast[i].span.start));
+                    i += 1;
+                }
+                open = true;
+            },
+            StmtKind::Directive(Directive::End) => open = false,
+            _ => {},
+        }
+        i += 1;
+    }
+    if open {
+        ast.push(end(ast.last().map_or(0, |s| s.span.end)));
+    }
+}
+
+/// Writes the words of data directives into an assembled object file.
+///
+/// Ensemble's object files can't be modified directly, so this goes through the blocks of memory
+/// of its binary format (see [`Chunk`]).
+///
+/// This fails (at the first directive which wasn't written) if a directive's words aren't all in a block,
+/// or if the modified object file can't be read back.
+fn write_words(obj: ObjectFile, data: &[DataWords]) -> Result<ObjectFile, ExprError> {
+    if data.is_empty() { return Ok(obj); }
+
+    let mut chunks = to_chunks(&obj);
+    let mut written = vec![0; data.len()];
+    for chunk in &mut chunks {
+        let Chunk::Memory { start, words: block } = chunk else { continue };
+
+        for (DataWords { addr, words, .. }, written) in data.iter().zip(&mut written) {
+            for (i, &value) in words.iter().enumerate() {
+                let offset = addr.wrapping_add(i as u16).wrapping_sub(*start);
+                let Some(word) = block.get_mut(usize::from(offset)) else { continue };
+
+                *word = Some(value);
+                *written += 1;
+            }
+        }
+    }
+
+    let unwritten = data.iter().zip(&written).find(|&(d, &n)| n != d.words.len());
+    match (unwritten, from_chunks(&chunks)) {
+        (None, Some(obj)) => Ok(obj),
+        (Some((d, _)), _) => Err(ExprError::unwritable_data(d.span.clone())),
+        (None, None) => Err(ExprError::unwritable_data(data[0].span.clone())),
+    }
+}
+
+/// Preprocessed source code which has been parsed.
+pub(crate) struct Parsed {
+    /// The statements (in program order), with spans into the preprocessed source.
+    pub(crate) ast: Vec<Stmt>,
+    /// The errors from parsing and evaluating expressions.
+    pub(crate) errors: Vec<AsmError>,
+    /// The (uppercase) labels defined on lines which failed to parse.
+    pub(crate) removed_labels: HashSet<String>,
+    /// The labels used in operand expressions (with their spans in the preprocessed source).
+    pub(crate) expr_labels: Vec<Label>,
+    /// The words of data directives which were replaced with placeholders (and their addresses).
+    pub(crate) data: Vec<DataWords>,
+    /// The spans of the `.RELOC` directives (which start relocatable sections).
+    pub(crate) reloc_spans: Vec<Range<usize>>,
+}
+
+/// Parses the preprocessed source code (in the given dialect), recovering from any parse errors
+/// and evaluating any constants and operand expressions.
+pub(crate) fn parse_expanded(expanded: &Expanded, dialect: Dialect) -> Parsed {
+    parse_shifted(expanded, dialect, None)
+}
+/// Parses the preprocessed source code, with one of its relocatable sections (if `shifted`) moved by the given number of words
+/// (see [`find_relocations`]).
+fn parse_shifted(expanded: &Expanded, dialect: Dialect, shifted: Option<(usize, i16)>) -> Parsed {
+    let src = expanded.src();
+    let mut exprs = Exprs::scan(expanded, dialect);
+
+    // Place the relocatable sections (which needs their lengths):
+    if !exprs.reloc_spans().is_empty() {
+        let rewritten = exprs.rewrite(src);
+        let (ast, _, _) = parse_recovering(rewritten.text());
+        let ast: Vec<_> = ast.into_iter().map(|s| rewritten.map_stmt(s)).collect();
+        exprs.place(&expanded.order(ast), shifted);
+    }
+
+    // Find the addresses of labels (with placeholders for expressions which use them),
+    // so that those expressions can be evaluated:
+    if exprs.has_unresolved() {
+        let rewritten = exprs.rewrite(src);
+        let (ast, _, _) = parse_recovering(rewritten.text());
+        let ast: Vec<_> = ast.into_iter().map(|s| rewritten.map_stmt(s)).collect();
+        exprs.resolve(&expanded.order(ast));
+    }
+
+    let rewritten = exprs.rewrite(src);
+    let (ast, parse_errors, removed_labels) = parse_recovering(rewritten.text());
+    let mut ast = expanded.order(ast.into_iter().map(|s| rewritten.map_stmt(s)).collect());
+    if !dialect.requires_end() {
+        close_blocks(&mut ast);
+    }
+
+    let errors = exprs.take_errors().into_iter()
+        .map(AsmError::Expr)
+        .chain(parse_errors.into_iter().map(|e| {
+            use lc3_ensemble::err::Error;
+            let span = rewritten.map_span(e.span().map_or(0..0, |s| s.first()));
+            AsmError::Parse(e, span)
+        }))
+        .collect();
+
+    Parsed { ast, errors, removed_labels, expr_labels: exprs.labels(), data: exprs.data_words(), reloc_spans: exprs.reloc_spans() }
+}
+
+/// Parses and assembles the (preprocessed) source code in the given dialect with debug symbols,
+/// collecting every error that occurs.
+///
+/// This also returns the relocatable sections of the object file and their relocations.
+/// The errors are sorted by where they occur in source.
+pub(crate) fn assemble_all(expanded: &Expanded, dialect: Dialect) -> Result<(ObjectFile, Relocs), Vec<AsmError>> {
+    let src = expanded.src();
+    let Parsed { mut ast, mut errors, removed_labels, data, reloc_spans, .. } = parse_expanded(expanded, dialect);
+
+    // Each patch removes a label or statement (or closes a block),
+    // so this should never be hit unless patching fails to fix an error.
+    let max_iters = 2 * ast.len() + 8;
+    for _ in 0..max_iters {
+        match assemble_debug(ast.clone(), src) {
+            Ok(obj) if errors.is_empty() => {
+                let obj = match write_words(obj, &data) {
+                    Ok(obj) => obj,
+                    Err(e) => {
+                        errors.push(AsmError::Expr(e));
+                        break;
+                    },
+                };
+                match find_relocations(expanded, dialect, &ast, &reloc_spans, &obj) {
+                    Ok(relocs) => return Ok((obj, relocs)),
+                    Err(e) => {
+                        errors = e;
+                        break;
+                    },
+                }
+            },
+            Ok(_) => break,
+            Err(mut e) => {
+                let patched = patch_ast(&mut ast, &e);
+                if matches!(e.kind, AsmErrKind::CouldNotFindLabel | AsmErrKind::OverlappingLabels | AsmErrKind::UndetAddrLabel) {
+                    e.span = match e.span {
+                        ErrSpan::One(s) => ErrSpan::One(label_span(src, s)),
+                        ErrSpan::Two(s) => ErrSpan::Two(s.map(|s| label_span(src, s))),
+                        ErrSpan::Many(s) => ErrSpan::Many(s.into_iter().map(|s| label_span(src, s)).collect()),
+                    };
+                }
+
+                // A label not being found might be due to its definition being on a line which failed to parse.
+                // If so, this error is just noise.
+                let cascaded = e.kind == AsmErrKind::CouldNotFindLabel
+                    && e.span.iter().all(|s| src.get(s.clone()).is_some_and(|l| removed_labels.contains(&l.to_uppercase())));
+                // Labels which only differ in case are different labels in case-sensitive dialects,
+                // which can't be assembled:
+                let collision = match &e.span {
+                    ErrSpan::Two(spans) if e.kind == AsmErrKind::OverlappingLabels => LabelCaseCollision::find(src, dialect, spans),
+                    _ => None,
+                };
+                match collision {
+                    Some(c) => errors.push(AsmError::LabelCase(c)),
+                    None if !cascaded => errors.push(AsmError::Asm(e)),
+                    None => {},
+                }
+
+                if !patched { break; }
+            }
+        }
+    }
+
+    errors.sort_by_key(|e| e.first_span().map_or(usize::MAX, |s| s.start));
+    Err(errors)
+}
+
+/// Finds the relocatable sections of an assembled program (given its statements) and their relocations.
+///
+/// Expressions have already been evaluated when the program is parsed, so which words depend on where a section is placed
+/// can't be found from its statements. Instead, the program is assembled again with each relocatable section placed a word later,
+/// and every word which changed (by one, in its data or PC offset) is a relocation.
+/// If that puts a PC offset out of range (because it's at the edge of its range), the section is placed a word earlier instead.
+fn find_relocations(expanded: &Expanded, dialect: Dialect, ast: &[Stmt], reloc_spans: &[Range<usize>], obj: &ObjectFile) -> Result<Relocs, Vec<AsmError>> {
+    if reloc_spans.is_empty() { return Ok(Relocs::default()); }
+
+    // Find the relocatable sections, and the size of the field which can be relocated in each word
+    // (along with the span of its statement):
+    let mut sections: Vec<RelocSection> = vec![];
+    let mut fields = BTreeMap::new();
+    let mut current = None;
+    let mut pc = None;
+    for stmt in ast {
+        match &stmt.nucleus {
+            StmtKind::Directive(Directive::Orig(addr)) => {
+                pc.replace(addr.get());
+                current = reloc_spans.iter()
+                    .any(|s| stmt.span.start <= s.start && s.end <= stmt.span.end)
+                    .then_some(sections.len());
+                if current.is_some() {
+                    sections.push(RelocSection { name: None, start: addr.get(), len: 0 });
+                }
+            },
+            StmtKind::Directive(Directive::End) => {
+                pc.take();
+                current.take();
+            },
+            _ => {},
+        }
+        let Some(addr) = pc else { continue };
+        if let Some(section) = current.map(|i| &mut sections[i]) {
+            if section.name.is_none() {
+                section.name = stmt.labels.first().map(|l| l.name.clone());
+            }
+            section.len += stmt_len(stmt);
+        }
+
+        let bits: u8 = match &stmt.nucleus {
+            StmtKind::Instr(AsmInstr::JSR(_)) => 11,
+            | StmtKind::Instr(AsmInstr::BR(..))
+            | StmtKind::Instr(AsmInstr::LD(..))
+            | StmtKind::Instr(AsmInstr::LDI(..))
+            | StmtKind::Instr(AsmInstr::LEA(..))
+            | StmtKind::Instr(AsmInstr::ST(..))
+            | StmtKind::Instr(AsmInstr::STI(..))
+            | StmtKind::Instr(AsmInstr::NOP(_)) => 9,
+            StmtKind::Instr(_) => 0,
+            StmtKind::Directive(_) => 16,
+        };
+        for i in 0..stmt_len(stmt) {
+            fields.insert(addr.wrapping_add(i), (bits, stmt.span.clone()));
+        }
+        pc = Some(addr.wrapping_add(stmt_len(stmt)));
+    }
+
+    let words = |obj: &ObjectFile| -> HashMap<u16, u16> {
+        obj.addr_iter().filter_map(|(addr, word)| Some((addr, word?))).collect()
+    };
+    let original = words(obj);
+    let mut terms: BTreeMap<u16, Vec<(usize, i8)>> = BTreeMap::new();
+    let mut errors = vec![];
+    let mut not_relocatable = HashSet::new();
+    for (i, section) in sections.iter().enumerate() {
+        let shift = |by: i16| {
+            let Parsed { ast, data, .. } = parse_shifted(expanded, dialect, Some((i, by)));
+            let obj = assemble_debug(ast, expanded.src()).map_err(AsmError::Asm)?;
+            write_words(obj, &data).map(|obj| words(&obj)).map_err(AsmError::Expr)
+        };
+        let (by, shifted) = match shift(1) {
+            Ok(shifted) => (1, shifted),
+            // A PC offset at the edge of its range:
+            Err(e) => match shift(-1) {
+                Ok(shifted) => (-1, shifted),
+                Err(_) => {
+                    errors.push(e);
+                    continue;
+                },
+            },
+        };
+
+        for (&addr, (bits, span)) in &fields {
+            let moved = match section.start <= addr && addr - section.start < section.len {
+                true  => addr.wrapping_add_signed(by),
+                false => addr,
+            };
+            let (Some(&old), Some(&new)) = (original.get(&addr), shifted.get(&moved)) else { continue };
+            if old == new { continue; }
+
+            let change = match *bits {
+                0 => None,
+                16 => Some(new.wrapping_sub(old) as i16),
+                bits => {
+                    let mask = (1 << bits) - 1;
+                    let shift = 16 - u32::from(bits);
+                    ((old ^ new) & !mask == 0).then(|| ((new.wrapping_sub(old) & mask) << shift) as i16 >> shift)
+                },
+            };
+            match change {
+                Some(c @ (1 | -1)) => terms.entry(addr).or_default().push((i, (c * by) as i8)),
+                _ => if not_relocatable.insert(span.clone()) {
+                    errors.push(AsmError::Expr(ExprError::not_relocatable(span.clone())));
+                },
+            }
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let relocations = terms.into_iter()
+        .map(|(addr, terms)| Relocation { addr, bits: fields[&addr].0, terms })
+        .collect();
+    Ok(Relocs { sections, relocations })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use lc3_ensemble::asm::encoding::{BinaryFormat, ObjFileFormat, TextFormat};
+
+    use super::*;
+    use crate::obj::deserialize_binary;
+    use crate::preproc::preprocess;
+
+    /// Assembles a source which should assemble (for the tests of other modules too).
+    pub(crate) fn assemble(src: &str) -> ObjectFile {
+        assemble_relocatable(src).0
+    }
+    /// Assembles a source which should assemble, along with its relocatable sections.
+    pub(crate) fn assemble_relocatable(src: &str) -> (ObjectFile, Relocs) {
+        let (expanded, errors) = preprocess(src, None);
+        assert!(errors.is_empty(), "source should preprocess: {errors:?}");
+        assemble_all(&expanded, Dialect::default()).unwrap_or_else(|e| panic!("source should assemble: {e:?}"))
+    }
+
+    #[test]
+    fn data_directives_round_trip() {
+        let obj = assemble(".ORIG x3000\n.FILL 1, 2, 3\n.BLKW 2, #-1\n.ASCII \"hi\"\n.STRINGZ \"a\\n\"\n.ALIGN 4\n.FILL x1234\n.END\n");
+        let expected = vec![
+            (0x3000, Some(1)), (0x3001, Some(2)), (0x3002, Some(3)),
+            (0x3003, Some(0xFFFF)), (0x3004, Some(0xFFFF)),
+            (0x3005, Some(u16::from(b'h'))), (0x3006, Some(u16::from(b'i'))),
+            (0x3007, Some(u16::from(b'a'))), (0x3008, Some(u16::from(b'\n'))), (0x3009, Some(0)),
+            (0x300A, None), (0x300B, None),
+            (0x300C, Some(0x1234)),
+        ];
+        assert_eq!(obj.addr_iter().collect::<Vec<_>>(), expected);
+
+        let text = TextFormat::deserialize(&TextFormat::serialize(&obj)).expect("text object file should be read back");
+        assert_eq!(text.addr_iter().collect::<Vec<_>>(), expected);
+        let binary = deserialize_binary(&BinaryFormat::serialize(&obj)).expect("binary object file should be read back");
+        assert_eq!(binary.addr_iter().collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn unwritable_data_is_an_error() {
+        let obj = assemble(".ORIG x3000\nHALT\n.END\n");
+        let data = [DataWords { span: 0..5, addr: 0x3001, words: vec![1] }];
+        assert!(write_words(obj, &data).is_err_and(|e| e.code() == "expr::unwritable-data"));
+    }
+
+    #[test]
+    fn offsets_at_the_limit_are_relocatable() {
+        for (src, addr, terms) in [
+            (".RELOC\nDATA .FILL 5\n.BLKW 253\n.END\n.RELOC\nSUB LD R0, DATA\nRET\n.END\n", 0x30FF, vec![(0, 1), (1, -1)]),
+            (".RELOC\nSUB LD R0, DATA\n.BLKW 254\n.END\n.RELOC\nDATA .FILL 5\n.END\n", 0x3000, vec![(0, -1), (1, 1)]),
+        ] {
+            let (_, relocs) = assemble_relocatable(src);
+            assert_eq!(relocs.relocations, [Relocation { addr, bits: 9, terms }]);
+        }
+    }
+
+    #[test]
+    fn align_is_not_relocatable() {
+        let (expanded, _) = preprocess(".RELOC\nA ADD R0,R0,#0\n.ALIGN 4\nB .FILL B\n.END\n", None);
+        let errors = assemble_all(&expanded, Dialect::default()).expect_err(".ALIGN should be rejected in a relocatable section");
+        assert_eq!(errors.iter().map(|e| e.code()).collect::<Vec<_>>(), ["expr::not-relocatable"]);
+
+        // ...but not after one:
+        assemble(".RELOC\nA ADD R0,R0,#0\n.END\n.ORIG x4000\n.ALIGN 4\nB .FILL B\n.END\n");
+    }
+}
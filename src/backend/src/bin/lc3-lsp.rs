@@ -0,0 +1,5 @@
+//! The LC-3 assembly language server, which communicates over stdio.
+
+fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    lc3_backend::lsp::run()
+}
@@ -0,0 +1,5 @@
+//! The LC-3 command-line tools.
+
+fn main() -> std::process::ExitCode {
+    lc3_backend::cli::run(std::env::args().skip(1))
+}
@@ -64,6 +64,13 @@ impl IntoJsValue for &'_ str {
         cx.string(self)
     }
 }
+impl IntoJsValue for String {
+    type Value = JsString;
+
+    fn into_js<'a>(self, cx: &mut impl Context<'a>) -> Handle<'a, Self::Value> {
+        cx.string(self)
+    }
+}
 impl IntoJsValue for bool {
     type Value = JsBoolean;
     
@@ -0,0 +1,215 @@
+//! The classic object files of Patt & Patel's `lc3as` (which many textbook resources and older tools use).
+//!
+//! A classic program is a single block of memory, which is written as:
+//! - `.obj`: big-endian binary words, where the first word is the origin
+//! - `.bin`: a line of 16 binary digits for each word (again starting with the origin)
+//! - `.hex`: a line of 4 hex digits for each word (again starting with the origin)
+//!
+//! Its labels are written separately to a `.sym` file, which has a line for each label
+//! (`//`, a tab, the label padded to 16 characters, and its hex address) in a table under a few header lines.
+//!
+//! Ensemble's object files can't be built directly, so classic files are read by building them in its binary format
+//! (see [`crate::obj::Chunk`]).
+
+use std::path::{Path, PathBuf};
+
+use lc3_ensemble::asm::ObjectFile;
+
+use crate::expr::is_symbol;
+use crate::obj::{from_chunks, Chunk, BINARY_MAGIC};
+
+/// How ensemble's binary and text formats start (so files which start like these are never classic programs).
+const ENSEMBLE_MAGIC: [&[u8]; 2] = [BINARY_MAGIC, b"LC-3 OBJ FILE"];
+
+/// Whether a file could be a classic program.
+///
+/// A `.obj` file could be anything (including one of ensemble's object files which is corrupted),
+/// so it's only read as a classic program if it has a `.sym` file next to it.
+fn is_classic(bytes: &[u8], path: &Path) -> bool {
+    if ENSEMBLE_MAGIC.iter().any(|magic| bytes.starts_with(magic)) { return false; }
+
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+    extension.eq_ignore_ascii_case("bin")
+        || extension.eq_ignore_ascii_case("hex")
+        || path.with_extension("sym").is_file()
+}
+
+/// Parses the words of a classic program (including its origin), given the file's extension.
+fn parse_words(bytes: &[u8], extension: &str) -> Option<Vec<u16>> {
+    match &*extension.to_lowercase() {
+        "bin" | "hex" => {
+            let radix = if extension.eq_ignore_ascii_case("bin") { 2 } else { 16 };
+            std::str::from_utf8(bytes).ok()?
+                .lines()
+                .map(str::trim)
+                .filter(|l| !l.is_empty())
+                .map(|l| u16::from_str_radix(l, radix).ok())
+                .collect()
+        },
+        _ => {
+            if !bytes.len().is_multiple_of(2) { return None; }
+            Some(bytes.chunks_exact(2).map(|w| u16::from_be_bytes([w[0], w[1]])).collect())
+        },
+    }
+}
+
+/// Parses the labels of a `.sym` file.
+fn parse_symbols(text: &str) -> Vec<(String, u16)> {
+    text.lines()
+        .filter_map(|line| {
+            let line = line.trim_start().strip_prefix("//").unwrap_or(line);
+            match line.split_whitespace().collect::<Vec<_>>()[..] {
+                [name, addr] if is_symbol(name) => Some((name.to_string(), u16::from_str_radix(addr, 16).ok()?)),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Reads a classic program from its `.obj`, `.bin`, or `.hex` file,
+/// along with the labels of its `.sym` file (if there is one next to it, which a `.obj` file needs).
+///
+/// This returns `None` if the file is malformed or isn't a classic program.
+pub(crate) fn read(bytes: &[u8], path: &Path) -> Option<ObjectFile> {
+    if !is_classic(bytes, path) { return None; }
+
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+    let words = parse_words(bytes, extension)?;
+    let (&origin, words) = words.split_first()?;
+    if words.len() > usize::from(u16::MAX) { return None; }
+    let symbols = std::fs::read_to_string(path.with_extension("sym"))
+        .map(|text| parse_symbols(&text))
+        .unwrap_or_default();
+
+    let memory = Chunk::Memory { start: origin, words: words.iter().copied().map(Some).collect() };
+    // The labels aren't in any source:
+    let labels = symbols.into_iter().map(|(name, addr)| Chunk::Label { addr, external: false, src_start: 0, name });
+    let chunks: Vec<_> = std::iter::once(memory).chain(labels).collect();
+
+    from_chunks(&chunks)
+}
+
+/// Writes an object file as a classic program,
+/// returning the `.obj`, `.sym`, `.bin`, and `.hex` files (at paths next to the given `.obj` path) and their contents.
+///
+/// Uninitialized words are written as 0.
+/// This fails if the object file has more than one block (since a classic program can only have one)
+/// or has external labels (since classic programs can't be linked).
+pub(crate) fn write(obj: &ObjectFile, path: &Path) -> Result<Vec<(PathBuf, Vec<u8>)>, &'static str> {
+    if obj.symbol_table().is_some_and(|sym| sym.label_iter().any(|(_, _, external)| external)) {
+        return Err("classic object files can't have external labels");
+    }
+
+    let mut addrs = obj.addr_iter().peekable();
+    let Some(&(origin, _)) = addrs.peek() else {
+        return Err("classic object files must have a block of memory");
+    };
+    let mut words = vec![origin];
+    for (i, (addr, word)) in addrs.enumerate() {
+        if usize::from(addr.wrapping_sub(origin)) != i {
+            return Err("classic object files can only have one block of memory (from a single .ORIG)");
+        }
+        words.push(word.unwrap_or(0));
+    }
+
+    let obj_bytes = words.iter().flat_map(|w| w.to_be_bytes()).collect();
+    let bin = words.iter().map(|w| format!("{w:016b}\n")).collect::<String>();
+    let hex = words.iter().map(|w| format!("{w:04X}\n")).collect::<String>();
+
+    let mut labels: Vec<_> = obj.symbol_table().into_iter()
+        .flat_map(|sym| sym.label_iter())
+        .filter(|&(_, _, external)| !external)
+        .map(|(name, addr, _)| (addr, name))
+        .collect();
+    labels.sort();
+    let mut sym = String::from("// Symbol table\n// Scope level 0:\n//\tSymbol Name       Page Address\n//\t----------------  ------------\n");
+    for (addr, name) in labels {
+        sym.push_str(&format!("//\t{name:16}  {addr:04X}\n"));
+    }
+
+    Ok(vec![
+        (path.with_extension("obj"), obj_bytes),
+        (path.with_extension("sym"), sym.into_bytes()),
+        (path.with_extension("bin"), bin.into_bytes()),
+        (path.with_extension("hex"), hex.into_bytes()),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use lc3_ensemble::asm::encoding::{BinaryFormat, ObjFileFormat, TextFormat};
+
+    use super::*;
+    use crate::asm::tests::assemble;
+    use crate::preproc::tests::temp_dir;
+
+    const PROGRAM: &str = ".ORIG x3000\nMAIN ADD R0, R0, #1\nBRp MAIN\nHALT\nBUF .BLKW 1\n.END\n";
+
+    fn read_file(path: &Path) -> Option<ObjectFile> {
+        crate::read_obj_file(path).expect("file should be read")
+    }
+
+    #[test]
+    fn round_trip() {
+        let obj = assemble(PROGRAM);
+        let dir = temp_dir("classic");
+        let files = write(&obj, &dir.join("prog.obj")).expect("program should be written");
+        for (path, bytes) in &files {
+            std::fs::write(path, bytes).expect("file should be written");
+        }
+        assert_eq!(std::fs::read_to_string(dir.join("prog.hex")).expect("file should be read"), "3000\n1021\n03FE\nF025\n0000\n");
+
+        for extension in ["obj", "bin", "hex"] {
+            let read = read_file(&dir.join("prog").with_extension(extension)).unwrap_or_else(|| panic!(".{extension} should be read"));
+            // Uninitialized words are written as 0:
+            let words: Vec<_> = read.addr_iter().collect();
+            assert_eq!(words, [(0x3000, Some(0x1021)), (0x3001, Some(0x03FE)), (0x3002, Some(0xF025)), (0x3003, Some(0))]);
+
+            let sym = read.symbol_table().expect("labels should be read from the .sym file");
+            assert_eq!(sym.lookup_label("MAIN"), Some(0x3000));
+            assert_eq!(sym.lookup_label("BUF"), Some(0x3003));
+        }
+    }
+
+    #[test]
+    fn rejects_what_cannot_be_written() {
+        let dir = temp_dir("classic-unwritable");
+        assert!(write(&assemble(".ORIG x3000\nHALT\n.END\n.ORIG x4000\nHALT\n.END\n"), &dir.join("a.obj")).is_err());
+        assert!(write(&assemble(".ORIG x3000\n.EXTERNAL F\n.FILL F\n.END\n"), &dir.join("a.obj")).is_err());
+    }
+
+    #[test]
+    fn rejects_corrupt_files() {
+        let dir = temp_dir("classic-corrupt");
+        let obj = assemble(PROGRAM);
+
+        // A corrupted object file (in either of ensemble's formats) isn't read as a classic program,
+        // even if it has a `.sym` file next to it:
+        std::fs::write(dir.join("text.sym"), "").expect("file should be written");
+        std::fs::write(dir.join("binary.sym"), "").expect("file should be written");
+        let mut text = TextFormat::serialize(&obj).into_bytes();
+        text.truncate(text.len() / 2);
+        text.push(b'!');
+        std::fs::write(dir.join("text.obj"), text).expect("file should be written");
+        let mut binary = BinaryFormat::serialize(&obj);
+        binary.truncate(binary.len() - 1);
+        std::fs::write(dir.join("binary.obj"), binary).expect("file should be written");
+        assert!(read_file(&dir.join("text.obj")).is_none());
+        assert!(read_file(&dir.join("binary.obj")).is_none());
+
+        // Without a `.sym` file, a `.obj` file of other bytes isn't either:
+        std::fs::write(dir.join("other.obj"), [0x30, 0x00, 0x12, 0x34]).expect("file should be written");
+        assert!(read_file(&dir.join("other.obj")).is_none());
+        std::fs::write(dir.join("other.sym"), "").expect("file should be written");
+        assert!(read_file(&dir.join("other.obj")).is_some());
+
+        // A classic program has to be made of whole words:
+        std::fs::write(dir.join("odd.obj"), [0x30, 0x00, 0x12]).expect("file should be written");
+        std::fs::write(dir.join("odd.sym"), "").expect("file should be written");
+        assert!(read_file(&dir.join("odd.obj")).is_none());
+        std::fs::write(dir.join("bad.hex"), "3000\n12G4\n").expect("file should be written");
+        assert!(read_file(&dir.join("bad.hex")).is_none());
+        std::fs::write(dir.join("empty.bin"), "").expect("file should be written");
+        assert!(read_file(&dir.join("empty.bin")).is_none());
+    }
+}
@@ -0,0 +1,334 @@
+//! The command-line interface (used by the `lc3` binary).
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use crate::{read_obj_file, read_relocatable_obj_file};
+use crate::archive;
+use crate::asm;
+use crate::dialect::Dialect;
+use crate::diff;
+use crate::disasm;
+use crate::err::Reporter;
+use crate::fmt::{self, Case, FormatOptions, LiteralStyle};
+use crate::image::{self, ImageFormat};
+use crate::link;
+use crate::preproc;
+
+const USAGE: &str = "\
+usage: lc3 <command> [options]
+
+commands:
+    fmt [--write | --check] [--case upper|lower] [--literals preserve|hex|decimal] <files...>
+        Formats assembly files (printing the result unless --write or --check is given).
+    disasm [-o <out.asm>] <file.obj>
+        Disassembles an object file back into assembly source.
+    image --format circuitsim|logisim|verilog [-o <out>] <file.obj>
+        Exports an object file as a memory image for a hardware LC-3.
+    diff [--dialect <name>] <old> <new>
+        Compares two object files (or assembly files) by word and by label, exiting with 1 if they differ.
+    lib -o <out.lib> <files.obj...>
+        Bundles object files into a library, whose object files are only linked if they're needed.
+";
+
+/// An error from parsing the command-line arguments.
+struct UsageError(String);
+
+/// Prints a report to stderr.
+fn eprint_report<E: std::fmt::Display + ?Sized>(mut reporter: Reporter<'_, E>) {
+    let mut buf = vec![];
+    reporter.report(&mut buf);
+    let _ = std::io::stderr().write_all(&buf);
+}
+
+/// Gets the value of an option which requires one.
+fn option_value(args: &mut impl Iterator<Item=String>, name: &str) -> Result<String, UsageError> {
+    args.next().ok_or_else(|| UsageError(format!("{name} requires a value")))
+}
+
+/// What `lc3 fmt` does with the formatted source.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum FmtMode {
+    /// Print it to stdout.
+    Print,
+    /// Overwrite the file with it.
+    Write,
+    /// Fail if it differs from the file.
+    Check,
+}
+
+fn fmt_command(mut args: impl Iterator<Item=String>) -> Result<ExitCode, UsageError> {
+    let mut mode = FmtMode::Print;
+    let mut opts = FormatOptions::default();
+    let mut files = vec![];
+    while let Some(arg) = args.next() {
+        match &*arg {
+            "--write" => mode = FmtMode::Write,
+            "--check" => mode = FmtMode::Check,
+            "--case" => {
+                let value = option_value(&mut args, &arg)?;
+                opts.case = Case::from_name(&value).ok_or_else(|| UsageError(format!("unknown case {value:?}")))?;
+            },
+            "--literals" => {
+                let value = option_value(&mut args, &arg)?;
+                opts.literals = LiteralStyle::from_name(&value).ok_or_else(|| UsageError(format!("unknown literal style {value:?}")))?;
+            },
+            a if a.starts_with("--") => return Err(UsageError(format!("unknown option {a}"))),
+            _ => files.push(PathBuf::from(arg)),
+        }
+    }
+    if files.is_empty() {
+        return Err(UsageError(String::from("no files given")));
+    }
+
+    let mut success = true;
+    for path in &files {
+        success &= format_file(path, opts, mode);
+    }
+
+    Ok(match success {
+        true  => ExitCode::SUCCESS,
+        false => ExitCode::FAILURE,
+    })
+}
+/// Formats a single file, returning whether it succeeded
+/// (and, if checking, whether the file was already formatted).
+fn format_file(path: &Path, opts: FormatOptions, mode: FmtMode) -> bool {
+    let src = match std::fs::read_to_string(path) {
+        Ok(src) => src,
+        Err(e) => {
+            eprint_report(Reporter::io(&e, path));
+            return false;
+        },
+    };
+    let formatted = match fmt::format_source(&src, opts) {
+        Ok(f) => f,
+        Err(e) => {
+            eprint_report(Reporter::ensemble(&e, path, &src).with_code(e.code()));
+            return false;
+        },
+    };
+
+    match mode {
+        FmtMode::Print => print!("{formatted}"),
+        FmtMode::Write if formatted != src => {
+            if let Err(e) = std::fs::write(path, &formatted) {
+                eprint_report(Reporter::io(&e, path));
+                return false;
+            }
+        },
+        FmtMode::Check if formatted != src => {
+            eprintln!("{} is not formatted", path.display());
+            return false;
+        },
+        FmtMode::Write | FmtMode::Check => {},
+    }
+    true
+}
+
+fn disasm_command(mut args: impl Iterator<Item=String>) -> Result<ExitCode, UsageError> {
+    let mut out = None;
+    let mut file = None;
+    while let Some(arg) = args.next() {
+        match &*arg {
+            "-o" => out = Some(PathBuf::from(option_value(&mut args, &arg)?)),
+            a if a.starts_with('-') => return Err(UsageError(format!("unknown option {a}"))),
+            _ if file.is_some() => return Err(UsageError(String::from("only one object file can be disassembled at a time"))),
+            _ => file = Some(PathBuf::from(arg)),
+        }
+    }
+    let Some(path) = file else {
+        return Err(UsageError(String::from("no file given")));
+    };
+
+    let Some(obj) = read_obj_or_report(&path) else {
+        return Ok(ExitCode::FAILURE);
+    };
+    Ok(write_or_print(out.as_deref(), &disasm::disassemble_object(&obj)))
+}
+
+fn image_command(mut args: impl Iterator<Item=String>) -> Result<ExitCode, UsageError> {
+    let mut format = None;
+    let mut out = None;
+    let mut file = None;
+    while let Some(arg) = args.next() {
+        match &*arg {
+            "--format" => {
+                let value = option_value(&mut args, &arg)?;
+                format = Some(ImageFormat::from_name(&value).ok_or_else(|| UsageError(format!("unknown memory image format {value:?}")))?);
+            },
+            "-o" => out = Some(PathBuf::from(option_value(&mut args, &arg)?)),
+            a if a.starts_with('-') => return Err(UsageError(format!("unknown option {a}"))),
+            _ if file.is_some() => return Err(UsageError(String::from("only one object file can be exported at a time"))),
+            _ => file = Some(PathBuf::from(arg)),
+        }
+    }
+    let Some(format) = format else {
+        return Err(UsageError(String::from("no format given")));
+    };
+    let Some(path) = file else {
+        return Err(UsageError(String::from("no file given")));
+    };
+
+    let Some(obj) = read_obj_or_report(&path) else {
+        return Ok(ExitCode::FAILURE);
+    };
+    Ok(write_or_print(out.as_deref(), &image::image(obj.addr_iter(), format)))
+}
+
+fn diff_command(mut args: impl Iterator<Item=String>) -> Result<ExitCode, UsageError> {
+    let mut dialect = Dialect::default();
+    let mut files = vec![];
+    while let Some(arg) = args.next() {
+        match &*arg {
+            "--dialect" => {
+                let value = option_value(&mut args, &arg)?;
+                dialect = Dialect::from_name(&value).ok_or_else(|| UsageError(format!("unknown dialect {value:?}")))?;
+            },
+            a if a.starts_with("--") => return Err(UsageError(format!("unknown option {a}"))),
+            _ => files.push(PathBuf::from(arg)),
+        }
+    }
+    let [old_path, new_path] = &files[..] else {
+        return Err(UsageError(String::from("expected two files to compare")));
+    };
+
+    let (Some(old), Some(new)) = (read_or_assemble(old_path, dialect), read_or_assemble(new_path, dialect)) else {
+        return Ok(ExitCode::FAILURE);
+    };
+    let diff = diff::diff(&old, &new);
+    print!("{}", diff.text());
+    Ok(match diff.is_empty() {
+        true  => ExitCode::SUCCESS,
+        false => ExitCode::FAILURE,
+    })
+}
+/// Reads an object file, or assembles an assembly file (`.asm`) without writing anything,
+/// reporting if either fails.
+fn read_or_assemble(path: &Path, dialect: Dialect) -> Option<lc3_ensemble::asm::ObjectFile> {
+    if !path.extension().is_some_and(|e| e.eq_ignore_ascii_case("asm")) {
+        return read_obj_or_report(path);
+    }
+
+    let src = match std::fs::read_to_string(path) {
+        Ok(src) => src,
+        Err(e) => {
+            eprint_report(Reporter::io(&e, path));
+            return None;
+        },
+    };
+    let (expanded, errors) = preproc::preprocess(&src, Some(path));
+    for e in &errors {
+        eprint_report(Reporter::preprocessed(e, &expanded).with_code(e.code()));
+    }
+    if !errors.is_empty() {
+        return None;
+    }
+    match asm::assemble_all(&expanded, dialect) {
+        Ok((obj, _)) => Some(obj),
+        Err(errors) => {
+            for e in &errors {
+                eprint_report(Reporter::preprocessed(e, &expanded).with_code(e.code()));
+            }
+            None
+        },
+    }
+}
+
+fn lib_command(mut args: impl Iterator<Item=String>) -> Result<ExitCode, UsageError> {
+    let mut out = None;
+    let mut files = vec![];
+    while let Some(arg) = args.next() {
+        match &*arg {
+            "-o" => out = Some(PathBuf::from(option_value(&mut args, &arg)?)),
+            a if a.starts_with('-') => return Err(UsageError(format!("unknown option {a}"))),
+            _ => files.push(PathBuf::from(arg)),
+        }
+    }
+    let Some(out) = out else {
+        return Err(UsageError(String::from("no output given")));
+    };
+    if files.is_empty() {
+        return Err(UsageError(String::from("no files given")));
+    }
+
+    let mut members = vec![];
+    for path in files {
+        match read_relocatable_obj_file(&path) {
+            Ok(Some((obj, relocs))) => members.push((path, obj, relocs)),
+            Ok(None) => {
+                eprint_report(Reporter::io("malformed object file", &path));
+                return Ok(ExitCode::FAILURE);
+            },
+            Err(e) => {
+                eprint_report(Reporter::io(&e, &path));
+                return Ok(ExitCode::FAILURE);
+            },
+        }
+    }
+
+    let objects: Vec<_> = members.iter()
+        .map(|(path, obj, _)| (path.clone(), obj.clone()))
+        .collect();
+    let errors = link::check_library_duplicates(&objects, Path::new(""));
+    if !errors.is_empty() {
+        for e in &errors {
+            eprint_report(e.reporter());
+        }
+        return Ok(ExitCode::FAILURE);
+    }
+    Ok(write_or_print(Some(&out), &archive::write(&members)))
+}
+
+/// Reads an object file, reporting if it can't be read.
+fn read_obj_or_report(path: &Path) -> Option<lc3_ensemble::asm::ObjectFile> {
+    match read_obj_file(path) {
+        Ok(Some(obj)) => Some(obj),
+        Ok(None) => {
+            eprint_report(Reporter::io("malformed object file", path));
+            None
+        },
+        Err(e) => {
+            eprint_report(Reporter::io(&e, path));
+            None
+        },
+    }
+}
+/// Writes a command's output to a file (or prints it if there's no file).
+fn write_or_print(out: Option<&Path>, text: &str) -> ExitCode {
+    match out {
+        Some(out) => if let Err(e) = std::fs::write(out, text) {
+            eprint_report(Reporter::io(&e, out));
+            return ExitCode::FAILURE;
+        },
+        None => print!("{text}"),
+    }
+    ExitCode::SUCCESS
+}
+
+/// Runs the command-line interface with the given arguments (excluding the program name).
+pub fn run(args: impl IntoIterator<Item=String>) -> ExitCode {
+    let mut args = args.into_iter();
+    let result = match args.next().as_deref() {
+        Some("fmt") => fmt_command(args),
+        Some("disasm") => disasm_command(args),
+        Some("image") => image_command(args),
+        Some("diff") => diff_command(args),
+        Some("lib") => lib_command(args),
+        Some("help" | "-h" | "--help") => {
+            print!("{USAGE}");
+            return ExitCode::SUCCESS;
+        },
+        Some(cmd) => Err(UsageError(format!("unknown command {cmd:?}"))),
+        None => Err(UsageError(String::from("no command given"))),
+    };
+
+    match result {
+        Ok(code) => code,
+        Err(UsageError(msg)) => {
+            eprintln!("error: {msg}\n\n{USAGE}");
+            ExitCode::from(2)
+        },
+    }
+}
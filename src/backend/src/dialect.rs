@@ -0,0 +1,451 @@
+//! Compatibility with the dialects of other LC-3 assemblers.
+//!
+//! Course materials and old solutions come from several assemblers, which each accept slightly different source:
+//!
+//! | Dialect        | Labels           | `LABEL:` | Blocks without `.END` | Literals                             |
+//! |----------------|------------------|----------|-----------------------|--------------------------------------|
+//! | `lc3tools`     | case-insensitive | yes      | no                    | `#10`, `10`, `xA`                    |
+//! | `lc3as`        | case-insensitive | no       | no                    | `#10`, `xA`                          |
+//! | `pennsim`      | case-sensitive   | no       | yes                   | `#10`, `xA`                          |
+//! | `lc3tools1`    | case-insensitive | yes      | yes                   | `#10`, `10`, `xA`, `0xA`, `b1010`    |
+//!
+//! Every dialect accepts the textbook's aliases of the TRAP routines (`GETC`, `OUT`, `PUTS`, `IN`, `PUTSP`, and `HALT`),
+//! but only `lc3tools` (this assembler) accepts the `PUTC` alias of `TRAP x21`
+//! and the extensions of this assembler (e.g., constants, expressions, local labels, and the preprocessor).
+//!
+//! Assembling in a dialect accepts source written for it
+//! (e.g., `lc3tools1` accepts `0x3000` and blocks without `.END`),
+//! reports anything in the source which that dialect does not accept as an error (e.g., `.EQU` in `lc3as`),
+//! and warns about anything which another dialect does not accept, so that the source can be made portable.
+//! Since labels are matched case-insensitively, source which defines labels differing only in case
+//! (which PennSim accepts) still does not assemble, and is reported as unsupported (see [`LabelCaseCollision`]).
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::ops::Range;
+
+use lc3_ensemble::err::ErrSpan;
+
+use crate::asm::{label_span, parse_expanded, referenced_label, Parsed};
+use crate::expr::{is_register, is_symbol, operand_kind, parse_char, parse_literal, split_line, LineParts};
+use crate::local::{is_local_ref, local_def};
+use crate::preproc::Expanded;
+
+/// An assembler whose source can be assembled.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub(crate) enum Dialect {
+    /// This assembler.
+    #[default]
+    LC3Tools,
+    /// The `lc3as` assembler which accompanies the textbook.
+    Lc3as,
+    /// PennSim's assembler.
+    PennSim,
+    /// The assembler of LC3Tools 1.x.
+    LC3Tools1,
+}
+impl Dialect {
+    pub(crate) const ALL: [Dialect; 4] = [
+        Dialect::LC3Tools,
+        Dialect::Lc3as,
+        Dialect::PennSim,
+        Dialect::LC3Tools1,
+    ];
+
+    /// The name used to refer to this dialect in options.
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Dialect::LC3Tools  => "lc3tools",
+            Dialect::Lc3as     => "lc3as",
+            Dialect::PennSim   => "pennsim",
+            Dialect::LC3Tools1 => "lc3tools1",
+        }
+    }
+
+    /// The name of this dialect's assembler (as displayed in warnings).
+    fn display_name(self) -> &'static str {
+        match self {
+            Dialect::LC3Tools  => "LC3Tools",
+            Dialect::Lc3as     => "lc3as",
+            Dialect::PennSim   => "PennSim",
+            Dialect::LC3Tools1 => "LC3Tools 1.x",
+        }
+    }
+
+    /// Finds the dialect with the given name.
+    pub(crate) fn from_name(name: &str) -> Option<Dialect> {
+        Dialect::ALL.into_iter().find(|d| d.name().eq_ignore_ascii_case(name))
+    }
+
+    /// Whether labels are case-sensitive.
+    pub(crate) fn case_sensitive(self) -> bool {
+        self == Dialect::PennSim
+    }
+
+    /// Whether every `.ORIG` block has to be closed with `.END`.
+    pub(crate) fn requires_end(self) -> bool {
+        matches!(self, Dialect::LC3Tools | Dialect::Lc3as)
+    }
+
+    /// Parses a numeric literal in a format only this dialect accepts
+    /// (e.g., `0x3000`, `0b101`, or `b101` in LC3Tools 1.x).
+    pub(crate) fn parse_literal(self, token: &str) -> Option<i64> {
+        if self != Dialect::LC3Tools1 { return None; }
+
+        let (negative, token) = match token.strip_prefix('-') {
+            Some(token) => (true, token),
+            None => (false, token),
+        };
+        let (radix, digits) = if let Some(digits) = token.strip_prefix("0x").or_else(|| token.strip_prefix("0X")) {
+            (16, digits)
+        } else if let Some(digits) = ["0b", "0B", "b", "B"].into_iter().find_map(|p| token.strip_prefix(p)) {
+            (2, digits)
+        } else {
+            return None;
+        };
+        // `from_str_radix` would accept a sign after the prefix:
+        if !digits.starts_with(|c: char| c.is_ascii_alphanumeric()) { return None; }
+
+        let value = i64::from_str_radix(digits, radix).ok()?;
+        Some(if negative { -value } else { value })
+    }
+}
+
+/// Something in the source which not every dialect accepts.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Portability {
+    /// A label is defined with a trailing colon.
+    LabelColon,
+    /// A label is referenced in a different case than its definition (which is given).
+    LabelCase(String),
+    /// An `.ORIG` block isn't closed with `.END`.
+    MissingEnd,
+    /// A decimal literal is written without `#` (and the literal is given).
+    UnprefixedDecimal(String),
+    /// A literal is written with `0x`, `0b`, or `b` (and how to write it portably is given).
+    LiteralFormat(String),
+    /// The `PUTC` alias is used.
+    PutcAlias,
+    /// An extension of this assembler is used (and its description is given).
+    Extension(&'static str),
+}
+impl Portability {
+    /// Whether the dialect accepts this.
+    fn accepted_by(&self, dialect: Dialect) -> bool {
+        match self {
+            Portability::LabelColon => matches!(dialect, Dialect::LC3Tools | Dialect::LC3Tools1),
+            Portability::LabelCase(_) => !dialect.case_sensitive(),
+            Portability::MissingEnd => !dialect.requires_end(),
+            Portability::UnprefixedDecimal(_) => matches!(dialect, Dialect::LC3Tools | Dialect::LC3Tools1),
+            Portability::LiteralFormat(_) => dialect == Dialect::LC3Tools1,
+            Portability::PutcAlias | Portability::Extension(_) => dialect == Dialect::LC3Tools,
+        }
+    }
+
+    /// Gets the diagnostic code for this.
+    pub(crate) fn code(&self) -> &'static str {
+        match self {
+            Portability::LabelColon           => "dialect::label-colon",
+            Portability::LabelCase(_)         => "dialect::label-case",
+            Portability::MissingEnd           => "dialect::missing-end",
+            Portability::UnprefixedDecimal(_) => "dialect::unprefixed-decimal",
+            Portability::LiteralFormat(_)     => "dialect::literal-format",
+            Portability::PutcAlias            => "dialect::putc-alias",
+            Portability::Extension(_)         => "dialect::extension",
+        }
+    }
+}
+
+/// A warning about something in the source which another dialect does not accept.
+#[derive(Debug)]
+pub(crate) struct PortabilityWarning {
+    pub(crate) kind: Portability,
+    span: Range<usize>,
+    /// Whether the dialect the source is assembled in doesn't accept this (which makes it an error).
+    pub(crate) rejected: bool,
+}
+impl std::fmt::Display for PortabilityWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.kind {
+            Portability::LabelColon => f.write_str("labels ending with `:`")?,
+            Portability::LabelCase(_) => f.write_str("a label written in a different case than its definition")?,
+            Portability::MissingEnd => f.write_str("a block without `.END`")?,
+            Portability::UnprefixedDecimal(_) => f.write_str("a decimal literal without `#`")?,
+            Portability::LiteralFormat(_) => f.write_str("a literal written with `0x`, `0b`, or `b`")?,
+            Portability::PutcAlias => f.write_str("`PUTC`")?,
+            Portability::Extension(desc) => f.write_str(desc)?,
+        }
+
+        let rejected: Vec<_> = Dialect::ALL.into_iter()
+            .filter(|&d| !self.kind.accepted_by(d))
+            .map(Dialect::display_name)
+            .collect();
+        match rejected.as_slice() {
+            [] => Ok(()),
+            [d] => write!(f, " won't assemble with {d}"),
+            [a, b] => write!(f, " won't assemble with {a} or {b}"),
+            [rest @ .., last] => write!(f, " won't assemble with {}, or {last}", rest.join(", ")),
+        }
+    }
+}
+impl std::error::Error for PortabilityWarning {}
+impl lc3_ensemble::err::Error for PortabilityWarning {
+    fn span(&self) -> Option<ErrSpan> {
+        Some(ErrSpan::from(self.span.clone()))
+    }
+
+    fn help(&self) -> Option<Cow<'_, str>> {
+        match &self.kind {
+            Portability::LabelColon => Some(Cow::Borrowed("remove the `:`")),
+            Portability::LabelCase(def) => Some(Cow::Owned(format!("write it as `{def}`"))),
+            Portability::MissingEnd => Some(Cow::Borrowed("add `.END` at the end of the block")),
+            Portability::UnprefixedDecimal(lit) => Some(Cow::Owned(format!("write it as `#{lit}`"))),
+            Portability::LiteralFormat(lit) => Some(Cow::Owned(format!("write it as `{lit}`"))),
+            Portability::PutcAlias => Some(Cow::Borrowed("write `OUT` (or `TRAP x21`) instead")),
+            Portability::Extension(_) => None,
+        }
+    }
+}
+
+/// Labels which differ only in case, which a case-sensitive dialect accepts as different labels.
+///
+/// This assembler matches labels case-insensitively, so it can't assemble these.
+#[derive(Debug)]
+pub(crate) struct LabelCaseCollision {
+    dialect: Dialect,
+    names: [String; 2],
+    spans: [Range<usize>; 2],
+}
+impl LabelCaseCollision {
+    /// Checks whether the definitions of a label which was defined twice (given by their spans)
+    /// are different labels in the given dialect.
+    pub(crate) fn find(src: &str, dialect: Dialect, spans: &[Range<usize>; 2]) -> Option<Self> {
+        if !dialect.case_sensitive() { return None; }
+
+        let [a, b] = spans.clone().map(|s| src.get(s).unwrap_or_default().to_string());
+        (a != b).then(|| LabelCaseCollision { dialect, names: [a, b], spans: spans.clone() })
+    }
+
+    pub(crate) fn code(&self) -> &'static str {
+        "dialect::unsupported-label-case"
+    }
+}
+impl std::fmt::Display for LabelCaseCollision {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let [a, b] = &self.names;
+        write!(f, "labels `{a}` and `{b}` only differ in case, which is not supported")
+    }
+}
+impl std::error::Error for LabelCaseCollision {}
+impl lc3_ensemble::err::Error for LabelCaseCollision {
+    fn span(&self) -> Option<ErrSpan> {
+        Some(ErrSpan::from(self.spans.clone()))
+    }
+
+    fn help(&self) -> Option<Cow<'_, str>> {
+        Some(Cow::Owned(format!("{} treats these as different labels, but this assembler can't; rename one of them", self.dialect.display_name())))
+    }
+}
+
+/// Finds everything in the source (assembled in the given dialect) which another dialect does not accept.
+///
+/// Anything the given dialect doesn't accept is [`rejected`](PortabilityWarning::rejected).
+/// Nothing is reported for this assembler's own dialect.
+pub(crate) fn check(expanded: &Expanded, dialect: Dialect) -> Vec<PortabilityWarning> {
+    if dialect == Dialect::LC3Tools { return vec![]; }
+
+    let src = expanded.src();
+    let mut warnings = vec![];
+    let mut warn = |kind: Portability, span: Range<usize>| {
+        if kind.accepted_by(Dialect::LC3Tools) || kind.accepted_by(dialect) {
+            let rejected = !kind.accepted_by(dialect);
+            warnings.push(PortabilityWarning { kind, span, rejected });
+        }
+    };
+
+    for (span, line) in expanded.blanked_lines() {
+        let directive = line.split_whitespace().next().unwrap_or("").to_uppercase();
+        if let Some(desc) = match &*directive {
+            ".INCLUDE" => Some("`.INCLUDE`"),
+            ".MACRO" => Some("macros"),
+            ".IF" => Some("conditional assembly"),
+            _ => None,
+        } {
+            let start = span.start + (line.len() - line.trim_start().len());
+            warn(Portability::Extension(desc), start..start + directive.len());
+        }
+    }
+
+    // The `.ORIG` of the block which is currently open:
+    let mut open_orig = None;
+    for line_span in expanded.ordered_lines() {
+        let Some(LineParts { labels, opcode, opcode_span, operands }) = split_line(&src[line_span.clone()]) else { continue };
+        let at = |r: &Range<usize>| line_span.start + r.start .. line_span.start + r.end;
+        let upper = opcode.to_uppercase();
+
+        for (span, word) in &labels {
+            if local_def(word).is_some() {
+                warn(Portability::Extension("local labels"), at(span));
+            } else if word.ends_with(':') {
+                warn(Portability::LabelColon, at(&(span.end - 1..span.end)));
+            }
+        }
+
+        match &*upper {
+            ".ORIG" | ".RELOC" => {
+                if upper == ".RELOC" {
+                    warn(Portability::Extension("`.RELOC`"), at(&opcode_span));
+                }
+                if let Some(orig) = open_orig.replace(at(&opcode_span)) {
+                    warn(Portability::MissingEnd, orig);
+                }
+            },
+            ".END" => open_orig = None,
+            "PUTC" => warn(Portability::PutcAlias, at(&opcode_span)),
+            ".EQU" => warn(Portability::Extension("`.EQU`"), at(&opcode_span)),
+            ".SET" => warn(Portability::Extension("`.SET`"), at(&opcode_span)),
+            ".ASCII" => warn(Portability::Extension("`.ASCII`"), at(&opcode_span)),
+            ".ALIGN" => warn(Portability::Extension("`.ALIGN`"), at(&opcode_span)),
+            ".EXTERNAL" => warn(Portability::Extension("`.EXTERNAL`"), at(&opcode_span)),
+            ".FILL" if operands.len() > 1 => warn(Portability::Extension("`.FILL` with several values"), at(&opcode_span)),
+            ".BLKW" if operands.len() > 1 => warn(Portability::Extension("`.BLKW` with a fill value"), at(&opcode_span)),
+            _ => {},
+        }
+        // Constants are defined with their own operands:
+        if matches!(&*upper, ".EQU" | ".SET") { continue; }
+
+        for (i, (span, text)) in operands.iter().enumerate() {
+            if operand_kind(opcode, i).is_none() { continue; }
+            let span = at(span);
+
+            let kind = if let Some(value) = dialect.parse_literal(text) {
+                let portable = match text.trim_start_matches('-').get(..2) {
+                    Some("0x" | "0X") if value < 0 => format!("x-{:X}", -value),
+                    Some("0x" | "0X") => format!("x{value:X}"),
+                    _ => format!("#{value}"),
+                };
+                Portability::LiteralFormat(portable)
+            } else if text.trim_start_matches('-').starts_with(|c: char| c.is_ascii_digit()) && parse_literal(text).is_some() {
+                Portability::UnprefixedDecimal(text.to_string())
+            } else if is_local_ref(text) {
+                Portability::Extension("local labels")
+            } else if text.starts_with('\'') && parse_char(text).is_some_and(|(_, len)| len == text.len()) {
+                Portability::Extension("character literals")
+            } else if is_register(text) || text.starts_with('"') || parse_literal(text).is_some() || is_symbol(text) {
+                continue;
+            } else if text.strip_prefix('#').is_some_and(is_symbol) {
+                Portability::Extension("constants")
+            } else {
+                Portability::Extension("operand expressions")
+            };
+            warn(kind, span);
+        }
+    }
+    if let Some(orig) = open_orig {
+        warn(Portability::MissingEnd, orig);
+    }
+
+    // Labels have to be referenced in the same case they're defined in for case-sensitive dialects:
+    let Parsed { ast, .. } = parse_expanded(expanded, dialect);
+    let defs: HashMap<String, &str> = ast.iter()
+        .flat_map(|stmt| &stmt.labels)
+        .map(|l| (l.name.to_uppercase(), &*l.name))
+        .collect();
+    for label in ast.iter().filter_map(referenced_label) {
+        let Some(&def) = defs.get(&label.name.to_uppercase()) else { continue };
+        if def != label.name && !label.name.contains('.') {
+            warn(Portability::LabelCase(def.to_string()), label_span(src, label.span()));
+        }
+    }
+
+    warnings.sort_by_key(|w| w.span.start);
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::preproc::preprocess;
+
+    /// The codes of what the source is reported for (and whether each is rejected) in the given dialect.
+    fn findings(src: &str, dialect: Dialect) -> Vec<(&'static str, bool)> {
+        let (expanded, errors) = preprocess(src, None);
+        assert!(errors.is_empty(), "source should preprocess: {errors:?}");
+        check(&expanded, dialect).iter().map(|w| (w.kind.code(), w.rejected)).collect()
+    }
+
+    /// Source using a bit of everything which some dialect doesn't accept.
+    const SOURCE: &str = "\
+.ORIG x3000
+LOOP: ADD R0, R0, 1
+PUTC
+SIZE .EQU 4
+BRp loop
+HALT
+.END
+";
+
+    #[test]
+    fn lc3tools() {
+        assert_eq!(findings(SOURCE, Dialect::LC3Tools), []);
+    }
+
+    #[test]
+    fn lc3as() {
+        assert_eq!(findings(SOURCE, Dialect::Lc3as), [
+            ("dialect::label-colon", true),
+            ("dialect::unprefixed-decimal", true),
+            ("dialect::putc-alias", true),
+            ("dialect::extension", true),
+            // lc3as matches labels case-insensitively (but PennSim doesn't):
+            ("dialect::label-case", false),
+        ]);
+    }
+
+    #[test]
+    fn pennsim() {
+        assert_eq!(findings(SOURCE, Dialect::PennSim), [
+            ("dialect::label-colon", true),
+            ("dialect::unprefixed-decimal", true),
+            ("dialect::putc-alias", true),
+            ("dialect::extension", true),
+            ("dialect::label-case", true),
+        ]);
+        // PennSim doesn't need `.END` (but other dialects do):
+        assert_eq!(findings(".ORIG x3000\nHALT\n", Dialect::PennSim), [("dialect::missing-end", false)]);
+    }
+
+    #[test]
+    fn lc3tools1() {
+        assert_eq!(findings(SOURCE, Dialect::LC3Tools1), [
+            ("dialect::label-colon", false),
+            ("dialect::unprefixed-decimal", false),
+            ("dialect::putc-alias", true),
+            ("dialect::extension", true),
+            ("dialect::label-case", false),
+        ]);
+        assert_eq!(findings(".ORIG 0x3000\nADD R0, R0, b11\nHALT\n", Dialect::LC3Tools1), [
+            ("dialect::missing-end", false),
+            ("dialect::literal-format", false),
+            ("dialect::literal-format", false),
+        ]);
+    }
+
+    #[test]
+    fn trap_aliases() {
+        // Only `PUTC` differs between dialects:
+        let src = ".ORIG x3000\nGETC\nOUT\nPUTS\nIN\nPUTSP\nTRAP x21\nTRAP x25\nHALT\n.END\n";
+        for dialect in Dialect::ALL {
+            assert_eq!(findings(src, dialect), [], "{dialect:?}");
+        }
+    }
+
+    #[test]
+    fn label_case_collisions() {
+        let src = "loop LOOP";
+        let spans = [0..4, 5..9];
+        assert!(LabelCaseCollision::find(src, Dialect::PennSim, &spans).is_some());
+        assert!(LabelCaseCollision::find(src, Dialect::Lc3as, &spans).is_none());
+        assert!(LabelCaseCollision::find("LOOP LOOP", Dialect::PennSim, &spans).is_none());
+    }
+}
@@ -0,0 +1,321 @@
+//! Comparing object files (e.g., a submitted object file with the object file of its source).
+//!
+//! Words are compared by address: a word is added or removed if only one of the object files has it,
+//! and changed if they both have it with different values. Each word is disassembled
+//! (with PC-relative targets named by the labels of its own object file).
+//!
+//! Labels are compared by name: a label is moved if its address changed,
+//! and renamed if it's only in one object file and a label only in the other has its address.
+//! A label which was renamed and moved can't be told apart from one label being removed and another added,
+//! so it's reported as both.
+
+use std::collections::{BTreeMap, HashMap};
+
+use lc3_ensemble::asm::ObjectFile;
+use neon::prelude::*;
+
+use crate::cast::TryIntoJsValue;
+use crate::disasm;
+
+/// A word of one of the object files (`None` if it's uninitialized, as from `.BLKW`).
+#[derive(Debug)]
+struct DiffWord {
+    value: Option<u16>,
+    /// The disassembly of the word.
+    text: String,
+}
+impl std::fmt::Display for DiffWord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.value {
+            Some(value) => write!(f, "x{value:04X}  {}", self.text),
+            None => write!(f, "x????  {}", self.text),
+        }
+    }
+}
+impl TryIntoJsValue for DiffWord {
+    type Value = JsObject;
+
+    fn try_into_js<'a>(self, cx: &mut impl Context<'a>) -> JsResult<'a, Self::Value> {
+        let obj = cx.empty_object();
+
+        let value = self.value.try_into_js(cx)?;
+        obj.set(cx, "value", value)?;
+        let text = cx.string(self.text);
+        obj.set(cx, "text", text)?;
+
+        Ok(obj)
+    }
+}
+
+/// A word which differs between the object files.
+#[derive(Debug)]
+struct WordDiff {
+    addr: u16,
+    /// The word in the first object file (if it has one).
+    old: Option<DiffWord>,
+    /// The word in the second object file (if it has one).
+    new: Option<DiffWord>,
+}
+impl WordDiff {
+    fn kind(&self) -> &'static str {
+        match (&self.old, &self.new) {
+            (None, _) => "added",
+            (_, None) => "removed",
+            _ => "changed",
+        }
+    }
+}
+impl TryIntoJsValue for WordDiff {
+    type Value = JsObject;
+
+    fn try_into_js<'a>(self, cx: &mut impl Context<'a>) -> JsResult<'a, Self::Value> {
+        let obj = cx.empty_object();
+
+        let addr = cx.number(self.addr);
+        obj.set(cx, "addr", addr)?;
+        let kind = cx.string(self.kind());
+        obj.set(cx, "kind", kind)?;
+        let old = self.old.try_into_js(cx)?;
+        obj.set(cx, "old", old)?;
+        let new = self.new.try_into_js(cx)?;
+        obj.set(cx, "new", new)?;
+
+        Ok(obj)
+    }
+}
+
+/// A label which differs between the object files.
+#[derive(Debug)]
+enum LabelDiff {
+    Added { name: String, addr: u16 },
+    Removed { name: String, addr: u16 },
+    Moved { name: String, old_addr: u16, new_addr: u16 },
+    Renamed { old_name: String, new_name: String, addr: u16 },
+}
+impl LabelDiff {
+    /// The name of the label (in the first object file, if it's there).
+    fn name(&self) -> &str {
+        match self {
+            LabelDiff::Added { name, .. } => name,
+            LabelDiff::Removed { name, .. } => name,
+            LabelDiff::Moved { name, .. } => name,
+            LabelDiff::Renamed { old_name, .. } => old_name,
+        }
+    }
+}
+impl std::fmt::Display for LabelDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LabelDiff::Added { name, addr } => write!(f, "label {name}: added at x{addr:04X}"),
+            LabelDiff::Removed { name, addr } => write!(f, "label {name}: removed (was at x{addr:04X})"),
+            LabelDiff::Moved { name, old_addr, new_addr } => write!(f, "label {name}: moved from x{old_addr:04X} to x{new_addr:04X}"),
+            LabelDiff::Renamed { old_name, new_name, addr } => write!(f, "label {old_name}: renamed to {new_name} (at x{addr:04X})"),
+        }
+    }
+}
+impl TryIntoJsValue for LabelDiff {
+    type Value = JsObject;
+
+    fn try_into_js<'a>(self, cx: &mut impl Context<'a>) -> JsResult<'a, Self::Value> {
+        let obj = cx.empty_object();
+
+        let (kind, old_addr, new_addr, new_name) = match &self {
+            LabelDiff::Added { addr, .. } => ("added", None, Some(*addr), None),
+            LabelDiff::Removed { addr, .. } => ("removed", Some(*addr), None, None),
+            LabelDiff::Moved { old_addr, new_addr, .. } => ("moved", Some(*old_addr), Some(*new_addr), None),
+            LabelDiff::Renamed { new_name, addr, .. } => ("renamed", Some(*addr), Some(*addr), Some(new_name.clone())),
+        };
+        let kind = cx.string(kind);
+        obj.set(cx, "kind", kind)?;
+        let name = cx.string(self.name());
+        obj.set(cx, "name", name)?;
+        let old_addr = old_addr.try_into_js(cx)?;
+        obj.set(cx, "oldAddr", old_addr)?;
+        let new_addr = new_addr.try_into_js(cx)?;
+        obj.set(cx, "newAddr", new_addr)?;
+        let new_name = new_name.try_into_js(cx)?;
+        obj.set(cx, "newName", new_name)?;
+
+        Ok(obj)
+    }
+}
+
+/// The differences between two object files.
+#[derive(Debug)]
+pub(crate) struct ObjDiff {
+    /// The words which differ, in order of address.
+    words: Vec<WordDiff>,
+    /// The labels which differ, in order of name.
+    labels: Vec<LabelDiff>,
+}
+impl ObjDiff {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.words.is_empty() && self.labels.is_empty()
+    }
+
+    /// Writes the differences as text, one per line
+    /// (e.g., `~ x3005  x1021  ADD R0, R0, #1  ->  x1022  ADD R0, R0, #2`).
+    pub(crate) fn text(&self) -> String {
+        let mut out = String::new();
+        for w in &self.words {
+            match (&w.old, &w.new) {
+                (Some(old), Some(new)) => out.push_str(&format!("~ x{:04X}  {old}  ->  {new}\n", w.addr)),
+                (Some(old), None) => out.push_str(&format!("- x{:04X}  {old}\n", w.addr)),
+                (None, Some(new)) => out.push_str(&format!("+ x{:04X}  {new}\n", w.addr)),
+                (None, None) => {},
+            }
+        }
+        for l in &self.labels {
+            out.push_str(&format!("{l}\n"));
+        }
+        out
+    }
+}
+impl TryIntoJsValue for ObjDiff {
+    type Value = JsObject;
+
+    fn try_into_js<'a>(self, cx: &mut impl Context<'a>) -> JsResult<'a, Self::Value> {
+        let obj = cx.empty_object();
+
+        let words = self.words.try_into_js(cx)?;
+        obj.set(cx, "words", words)?;
+        let labels = self.labels.try_into_js(cx)?;
+        obj.set(cx, "labels", labels)?;
+
+        Ok(obj)
+    }
+}
+
+/// The labels an object file defines, by name.
+fn labels(obj: &ObjectFile) -> BTreeMap<&str, u16> {
+    obj.symbol_table().into_iter()
+        .flat_map(|sym| sym.label_iter())
+        .filter(|&(_, _, external)| !external)
+        .map(|(name, addr, _)| (name, addr))
+        .collect()
+}
+
+/// The label each address is named by (the first in order of name).
+fn names_by_addr<'o>(labels: &BTreeMap<&'o str, u16>) -> HashMap<u16, &'o str> {
+    let mut names = HashMap::new();
+    for (&name, &addr) in labels {
+        names.entry(addr).or_insert(name);
+    }
+    names
+}
+/// Disassembles a word (naming its PC-relative target by the given labels, if it has one).
+fn disassemble(word: Option<u16>, addr: u16, labels: &HashMap<u16, &str>) -> DiffWord {
+    let text = match word {
+        None => String::from(".BLKW 1"),
+        Some(word) => match disasm::decode(word) {
+            Some(instr) => match disasm::pc_target(&instr, addr).and_then(|(target, _)| labels.get(&target)) {
+                Some(name) => disasm::with_label(instr, name).to_string(),
+                None => instr.to_string(),
+            },
+            None => format!(".FILL x{word:04X}"),
+        },
+    };
+    DiffWord { value: word, text }
+}
+
+/// Compares two object files (see the module docs).
+pub(crate) fn diff(old: &ObjectFile, new: &ObjectFile) -> ObjDiff {
+    let old_labels = labels(old);
+    let new_labels = labels(new);
+    let (old_names, new_names) = (names_by_addr(&old_labels), names_by_addr(&new_labels));
+
+    let old_mem: BTreeMap<u16, Option<u16>> = old.addr_iter().collect();
+    let new_mem: BTreeMap<u16, Option<u16>> = new.addr_iter().collect();
+    let mut addrs: Vec<_> = old_mem.keys().chain(new_mem.keys()).copied().collect();
+    addrs.sort_unstable();
+    addrs.dedup();
+    let words = addrs.into_iter()
+        .filter(|addr| old_mem.get(addr) != new_mem.get(addr))
+        .map(|addr| WordDiff {
+            addr,
+            old: old_mem.get(&addr).map(|&w| disassemble(w, addr, &old_names)),
+            new: new_mem.get(&addr).map(|&w| disassemble(w, addr, &new_names)),
+        })
+        .collect();
+
+    let mut labels = vec![];
+    let mut added: Vec<_> = new_labels.iter()
+        .filter(|(name, _)| !old_labels.contains_key(*name))
+        .map(|(&name, &addr)| (name, addr))
+        .collect();
+    for (&name, &old_addr) in &old_labels {
+        match new_labels.get(name) {
+            Some(&new_addr) if new_addr != old_addr => labels.push(LabelDiff::Moved { name: name.to_string(), old_addr, new_addr }),
+            Some(_) => {},
+            None => match added.iter().position(|&(_, addr)| addr == old_addr) {
+                Some(i) => {
+                    let (new_name, _) = added.remove(i);
+                    labels.push(LabelDiff::Renamed { old_name: name.to_string(), new_name: new_name.to_string(), addr: old_addr });
+                },
+                None => labels.push(LabelDiff::Removed { name: name.to_string(), addr: old_addr }),
+            },
+        }
+    }
+    labels.extend(added.into_iter().map(|(name, addr)| LabelDiff::Added { name: name.to_string(), addr }));
+    labels.sort_by(|a, b| a.name().cmp(b.name()));
+
+    ObjDiff { words, labels }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::tests::assemble;
+
+    const OLD: &str = ".ORIG x3000\nMAIN ADD R0, R0, #1\nBR MAIN\nLOOP HALT\nDATA .FILL x1234\n.END\n";
+
+    /// Compares two sources, as the text of their differences.
+    fn diff_text(old: &str, new: &str) -> String {
+        diff(&assemble(old), &assemble(new)).text()
+    }
+
+    #[test]
+    fn identical_objects_have_no_differences() {
+        assert!(diff(&assemble(OLD), &assemble(OLD)).is_empty());
+        assert_eq!(diff_text(OLD, &OLD.replace("MAIN ADD", "MAIN  ADD")), "");
+    }
+
+    #[test]
+    fn compares_words() {
+        let new = ".ORIG x3000\nMAIN ADD R0, R0, #2\nBR MAIN\nLOOP HALT\n.END\n.ORIG x4000\n.BLKW 1\n.END\n";
+        assert_eq!(diff_text(OLD, new), concat!(
+            "~ x3000  x1021  ADD R0, R0, #1  ->  x1022  ADD R0, R0, #2\n",
+            "- x3003  x1234  ADD R1, R0, #-12\n",
+            "+ x4000  x????  .BLKW 1\n",
+            "label DATA: removed (was at x3003)\n",
+        ));
+
+        // PC-relative targets are named by each object file's own labels:
+        let new = OLD.replace("MAIN ADD", "START ADD").replace("BR MAIN", "BR LOOP");
+        assert_eq!(diff_text(OLD, &new), concat!(
+            "~ x3001  x0FFE  BRnzp MAIN  ->  x0E00  BRnzp LOOP\n",
+            "label MAIN: renamed to START (at x3000)\n",
+        ));
+    }
+
+    #[test]
+    fn compares_labels() {
+        let new = ".ORIG x3000\nMAIN ADD R0, R0, #1\nBR MAIN\nHALT\nLOOP\nDATA .FILL x1234\nEXTRA .BLKW 1\n.END\n";
+        let diff = diff(&assemble(OLD), &assemble(new));
+        assert_eq!(diff.labels.iter().map(ToString::to_string).collect::<Vec<_>>(), [
+            "label EXTRA: added at x3004",
+            "label LOOP: moved from x3002 to x3003",
+        ]);
+        assert_eq!(diff.words.iter().map(WordDiff::kind).collect::<Vec<_>>(), ["added"]);
+    }
+
+    #[test]
+    fn renamed_and_moved_labels_are_removed_and_added() {
+        let new = OLD.replace("DATA .FILL", "NOP\nVALUE .FILL");
+        let diff = diff(&assemble(OLD), &assemble(&new));
+        assert_eq!(diff.labels.iter().map(ToString::to_string).collect::<Vec<_>>(), [
+            "label DATA: removed (was at x3003)",
+            "label VALUE: added at x3004",
+        ]);
+    }
+}
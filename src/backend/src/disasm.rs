@@ -0,0 +1,455 @@
+//! Disassembling object files back into assembly source code
+//! and disassembling regions of the simulator's memory.
+//!
+//! Which words are code is determined by following control flow from the start of each block
+//! (through branches, JSRs, and fall-through). Every other word is treated as data,
+//! which is printed as `.STRINGZ` (for null-terminated runs of text), `.BLKW` (for uninitialized memory),
+//! or `.FILL` (for anything else).
+//!
+//! Labels come from the object file's symbol table (if it has one),
+//! and any other targets of branches, JSRs, and loads/stores are given generated labels.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ops::RangeInclusive;
+
+use lc3_ensemble::asm::ObjectFile;
+use lc3_ensemble::ast::asm::{try_disassemble_line, AsmInstr, Stmt, StmtKind};
+use lc3_ensemble::ast::{Label, PCOffset};
+use lc3_ensemble::sim::Simulator;
+use neon::prelude::*;
+
+use crate::asm::is_unconditional;
+use crate::cast::TryIntoJsValue;
+use crate::fmt::{self, escape_string, FormatOptions};
+
+/// What a PC-relative target is used for.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TargetKind {
+    /// The target of a branch.
+    Branch,
+    /// The target of a JSR.
+    Subroutine,
+    /// The target of a load, store, or LEA.
+    Data,
+}
+impl TargetKind {
+    /// The prefix of labels generated for this kind of target.
+    fn label_prefix(self) -> &'static str {
+        match self {
+            TargetKind::Branch     => "L",
+            TargetKind::Subroutine => "SUB",
+            TargetKind::Data       => "DATA",
+        }
+    }
+}
+
+/// Decodes a word into an instruction (if it is one).
+pub(crate) fn decode(word: u16) -> Option<AsmInstr> {
+    match try_disassemble_line(word)?.nucleus {
+        StmtKind::Instr(instr) => Some(instr),
+        StmtKind::Directive(_) => None,
+    }
+}
+
+/// Gets the absolute address an instruction at `addr` targets with its PC offset
+/// (if it uses one).
+pub(crate) fn pc_target(instr: &AsmInstr, addr: u16) -> Option<(u16, TargetKind)> {
+    let (off, kind) = match instr {
+        AsmInstr::BR(_, PCOffset::Offset(off)) => (off.get(), TargetKind::Branch),
+        AsmInstr::JSR(PCOffset::Offset(off)) => (off.get(), TargetKind::Subroutine),
+        | AsmInstr::LD(_, PCOffset::Offset(off))
+        | AsmInstr::LDI(_, PCOffset::Offset(off))
+        | AsmInstr::LEA(_, PCOffset::Offset(off))
+        | AsmInstr::ST(_, PCOffset::Offset(off))
+        | AsmInstr::STI(_, PCOffset::Offset(off)) => (off.get(), TargetKind::Data),
+        _ => return None,
+    };
+
+    Some((addr.wrapping_add(1).wrapping_add_signed(off), kind))
+}
+
+/// Replaces an instruction's PC offset with a label.
+pub(crate) fn with_label(instr: AsmInstr, name: &str) -> AsmInstr {
+    let label = || Label::new(name.to_string(), 0..name.len());
+    match instr {
+        AsmInstr::BR(cc, _) => AsmInstr::BR(cc, PCOffset::Label(label())),
+        AsmInstr::JSR(_) => AsmInstr::JSR(PCOffset::Label(label())),
+        AsmInstr::LD(r, _) => AsmInstr::LD(r, PCOffset::Label(label())),
+        AsmInstr::LDI(r, _) => AsmInstr::LDI(r, PCOffset::Label(label())),
+        AsmInstr::LEA(r, _) => AsmInstr::LEA(r, PCOffset::Label(label())),
+        AsmInstr::ST(r, _) => AsmInstr::ST(r, PCOffset::Label(label())),
+        AsmInstr::STI(r, _) => AsmInstr::STI(r, PCOffset::Label(label())),
+        instr => instr,
+    }
+}
+
+/// Whether this word is a character that can be written in a `.STRINGZ`.
+fn is_string_char(word: u16) -> bool {
+    matches!(word, 0x20..0x7F | 0x09 | 0x0A | 0x0D)
+}
+
+/// Splits the object file into contiguous blocks of memory.
+fn object_blocks(obj: &ObjectFile) -> Vec<(u16, Vec<Option<u16>>)> {
+    let mut blocks: Vec<(u16, Vec<Option<u16>>)> = vec![];
+    for (addr, value) in obj.addr_iter() {
+        match blocks.last_mut() {
+            Some((start, words)) if addr != 0 && start.wrapping_add(words.len() as u16) == addr => words.push(value),
+            _ => blocks.push((addr, vec![value])),
+        }
+    }
+    blocks
+}
+
+/// Finds the addresses of an object file which are code,
+/// by following control flow from the start of each block.
+pub(crate) fn find_code(obj: &ObjectFile) -> HashSet<u16> {
+    let mem: HashMap<u16, Option<u16>> = obj.addr_iter().collect();
+    let instr_at = |addr: u16| mem.get(&addr).copied().flatten().and_then(decode);
+
+    let mut code = HashSet::new();
+    let mut worklist: Vec<u16> = object_blocks(obj).iter().map(|&(start, _)| start).collect();
+    while let Some(addr) = worklist.pop() {
+        if code.contains(&addr) { continue; }
+        let Some(instr) = instr_at(addr) else { continue };
+        code.insert(addr);
+
+        if let Some((target, TargetKind::Branch | TargetKind::Subroutine)) = pc_target(&instr, addr) {
+            worklist.push(target);
+        }
+        if !is_unconditional(&instr) {
+            worklist.push(addr.wrapping_add(1));
+        }
+    }
+    code
+}
+
+/// Disassembles an object file into assembly source code
+/// which assembles back into the same memory contents.
+pub(crate) fn disassemble_object(obj: &ObjectFile) -> String {
+    let blocks = object_blocks(obj);
+    let mem: HashMap<u16, Option<u16>> = obj.addr_iter().collect();
+    let instr_at = |addr: u16| mem.get(&addr).copied().flatten().and_then(decode);
+    let code = find_code(obj);
+
+    // Label every target in the object file:
+    let mut labels: BTreeMap<u16, Vec<String>> = BTreeMap::new();
+    if let Some(sym) = obj.symbol_table() {
+        for (name, addr, _) in sym.label_iter() {
+            if mem.contains_key(&addr) {
+                labels.entry(addr).or_default().push(name.to_string());
+            }
+        }
+        // The symbol table is unordered, so sort for consistent output:
+        labels.values_mut().for_each(|names| names.sort());
+    }
+    let mut code_addrs: Vec<_> = code.iter().copied().collect();
+    code_addrs.sort_unstable();
+    for &addr in &code_addrs {
+        let Some((target, kind)) = instr_at(addr).and_then(|i| pc_target(&i, addr)) else { continue };
+        if mem.contains_key(&target) {
+            labels.entry(target).or_insert_with(|| vec![format!("{}_{target:04X}", kind.label_prefix())]);
+        }
+    }
+
+    let mut out = String::from("; Disassembled from an object file\n");
+    for (start, words) in &blocks {
+        out.push_str(&format!(".orig x{start:04X}\n"));
+
+        let mut i = 0;
+        while i < words.len() {
+            let addr = start.wrapping_add(i as u16);
+            let label = labels.get(&addr).map_or(String::new(), |names| names.join(" "));
+            // The number of words the next statement takes up:
+            let mut len = 1;
+
+            let stmt = match words[i] {
+                // Uninitialized memory (up to the next label):
+                None => {
+                    while i + len < words.len() && words[i + len].is_none() && !labels.contains_key(&addr.wrapping_add(len as u16)) {
+                        len += 1;
+                    }
+                    format!(".blkw #{len}")
+                },
+                Some(word) if code.contains(&addr) => {
+                    let mut instr = decode(word).unwrap_or_else(|| unreachable!("code should be decodable"));
+                    if let Some(name) = pc_target(&instr, addr).and_then(|(t, _)| labels.get(&t)?.first()) {
+                        instr = with_label(instr, name);
+                    }
+                    Stmt { labels: vec![], nucleus: StmtKind::Instr(instr), span: 0..0 }.to_string()
+                },
+                Some(word) => {
+                    // A null-terminated string (which has no labels in the middle of it):
+                    let chars: String = words[i..].iter()
+                        .enumerate()
+                        .take_while(|&(j, w)| {
+                            let a = addr.wrapping_add(j as u16);
+                            w.is_some_and(is_string_char) && !code.contains(&a) && (j == 0 || !labels.contains_key(&a))
+                        })
+                        .map(|(_, w)| char::from(w.unwrap_or(0) as u8))
+                        .collect();
+                    let end = i + chars.len();
+                    let terminated = words.get(end) == Some(&Some(0))
+                        && !code.contains(&addr.wrapping_add(chars.len() as u16))
+                        && !labels.contains_key(&addr.wrapping_add(chars.len() as u16));
+
+                    if chars.len() >= 2 && terminated {
+                        len = chars.len() + 1;
+                        format!(".stringz {}", escape_string(&chars))
+                    } else {
+                        format!(".fill x{word:04X}")
+                    }
+                },
+            };
+
+            out.push_str(&format!("{label} {stmt}\n"));
+            i += len;
+        }
+
+        out.push_str(".end\n");
+    }
+
+    // Lay out the source nicely (this should always succeed, since the source was just generated):
+    fmt::format_source(&out, FormatOptions::default()).unwrap_or(out)
+}
+
+/// A single disassembled word of memory.
+#[derive(Debug)]
+pub(crate) struct DisasmLine {
+    addr: u16,
+    value: u16,
+    /// The label at this address (if there is one).
+    label: Option<String>,
+    /// The instruction (or `.FILL` directive) the word disassembles to.
+    text: String,
+    /// The absolute address of a PC-relative target
+    /// (or, for data, the address or character the word holds).
+    comment: Option<String>,
+}
+impl TryIntoJsValue for DisasmLine {
+    type Value = JsObject;
+
+    fn try_into_js<'a>(self, cx: &mut impl Context<'a>) -> JsResult<'a, Self::Value> {
+        let obj = cx.empty_object();
+
+        let addr = cx.number(self.addr);
+        obj.set(cx, "addr", addr)?;
+        let value = cx.number(self.value);
+        obj.set(cx, "value", value)?;
+        let label = self.label.try_into_js(cx)?;
+        obj.set(cx, "label", label)?;
+        let text = cx.string(self.text);
+        obj.set(cx, "text", text)?;
+        let comment = self.comment.try_into_js(cx)?;
+        obj.set(cx, "comment", comment)?;
+
+        Ok(obj)
+    }
+}
+
+/// Disassembles a region of the simulator's memory (including the end address),
+/// replacing PC offsets with the labels of their targets (if they have one).
+/// 
+/// Whether a word is code is looked up in `code` (see [`crate::obj::ObjContents::code_map`]),
+/// and words which aren't in it are code if they decode into an instruction.
+/// Data is shown as `.FILL`, annotated with the label it points to (or the character it holds).
+pub(crate) fn disassemble_region<'l>(
+    sim: &Simulator,
+    range: RangeInclusive<u16>,
+    code: &HashMap<u16, bool>,
+    label_of: impl Fn(u16) -> Option<&'l str>
+) -> Vec<DisasmLine> {
+    range.map(|addr| {
+        let value = sim.mem[addr].get();
+        let label = label_of(addr).map(String::from);
+
+        let m_instr = decode(value).filter(|_| code.get(&addr).copied().unwrap_or(true));
+        let (text, comment) = match m_instr {
+            Some(instr) => match pc_target(&instr, addr) {
+                Some((target, _)) => {
+                    let instr = match label_of(target) {
+                        Some(name) => with_label(instr, name),
+                        None => instr,
+                    };
+                    (instr.to_string(), Some(format!("x{target:04X}")))
+                },
+                None => (instr.to_string(), None),
+            },
+            None => match label_of(value) {
+                // This is (probably) a pointer:
+                Some(name) => (format!(".FILL {name}"), Some(format!("x{value:04X}"))),
+                None if is_string_char(value) => (format!(".FILL x{value:04X}"), Some(format!("{:?}", char::from(value as u8)))),
+                None => (format!(".FILL x{value:04X}"), None),
+            },
+        };
+
+        DisasmLine { addr, value, label, text, comment }
+    }).collect()
+}
+
+/// Lays out disassembled lines as a listing
+/// (with columns for the address, value, label, and instruction).
+pub(crate) fn listing(lines: &[DisasmLine]) -> String {
+    let label_width = lines.iter().filter_map(|l| l.label.as_ref()).map(String::len).max().unwrap_or(0);
+    let text_width = lines.iter().map(|l| l.text.len()).max().unwrap_or(0);
+
+    let mut out = String::new();
+    for line in lines {
+        let DisasmLine { addr, value, label, text, comment } = line;
+        let label = label.as_deref().unwrap_or("");
+        let row = match comment {
+            Some(c) => format!("x{addr:04X}  x{value:04X}  {label:label_width$}  {text:text_width$}  ; {c}"),
+            None => format!("x{addr:04X}  x{value:04X}  {label:label_width$}  {text}"),
+        };
+        out.push_str(row.trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::tests::assemble;
+
+    /// Code mixed with data (including data which decodes into an instruction).
+    const PROGRAM: &str = "\
+.ORIG x3000
+MAIN LD R0, COUNT
+LOOP JSR PRINT
+ADD R0, R0, #-1
+BRp LOOP
+HALT
+COUNT .FILL 3
+ADDS .FILL x1021
+MSG .STRINGZ \"a; b, c\"
+BUF .BLKW 2
+PRINT LEA R1, MSG
+ST R1, BUF
+RET
+.END
+";
+
+    /// Disassembles the object file, and checks that it assembles back into the same memory.
+    fn round_trip(obj: &ObjectFile) -> String {
+        let src = disassemble_object(obj);
+        let reassembled = assemble(&src);
+        assert_eq!(reassembled.addr_iter().collect::<Vec<_>>(), obj.addr_iter().collect::<Vec<_>>(), "{src}");
+        src
+    }
+
+    #[test]
+    fn round_trip_with_symbols() {
+        let src = round_trip(&assemble(PROGRAM));
+        assert_eq!(src, "\
+; Disassembled from an object file
+      .ORIG    x3000
+MAIN  LD       R0, COUNT
+LOOP  JSR      PRINT
+      ADD      R0, R0, #-1
+      BRp      LOOP
+      HALT
+COUNT .FILL    x0003
+ADDS  .FILL    x1021
+MSG   .STRINGZ \"a; b, c\"
+BUF   .BLKW    #2
+PRINT LEA      R1, MSG
+      ST       R1, BUF
+      RET
+      .END
+");
+    }
+
+    #[test]
+    fn round_trip_without_symbols() {
+        let obj = crate::obj::strip(&assemble(PROGRAM)).expect("object file should be stripped");
+        assert!(obj.symbol_table().is_none());
+
+        let src = round_trip(&obj);
+        assert_eq!(src, "\
+; Disassembled from an object file
+          .ORIG    x3000
+          LD       R0, DATA_3005
+L_3001    JSR      SUB_3011
+          ADD      R0, R0, #-1
+          BRp      L_3001
+          HALT
+DATA_3005 .FILL    x0003
+          .FILL    x1021
+DATA_3007 .STRINGZ \"a; b, c\"
+DATA_300F .BLKW    #2
+SUB_3011  LEA      R1, DATA_3007
+          ST       R1, DATA_300F
+          RET
+          .END
+");
+    }
+
+    #[test]
+    fn finds_code() {
+        let obj = assemble(PROGRAM);
+        let mut code: Vec<_> = find_code(&obj).into_iter().collect();
+        code.sort_unstable();
+        assert_eq!(code, [0x3000, 0x3001, 0x3002, 0x3003, 0x3004, 0x3011, 0x3012, 0x3013]);
+    }
+
+    /// Code followed by a pointer, a character, and data which decodes into an instruction.
+    const REGION: &str = "\
+.ORIG x3000
+LOOP LD R0, PTR
+BRnzp LOOP
+PTR .FILL LOOP
+CH .FILL x41
+ADDS .FILL x1021
+.END
+";
+
+    /// Disassembles the program's memory, given which words are code.
+    fn region(code: impl Fn(&ObjectFile) -> HashMap<u16, bool>) -> Vec<DisasmLine> {
+        let obj = assemble(REGION);
+        let mut sim = Simulator::new(Default::default());
+        sim.load_obj_file(&obj).expect("program should load");
+        let sym = obj.symbol_table().expect("object file should have a symbol table");
+        disassemble_region(&sim, 0x3000..=0x3004, &code(&obj), |addr| sym.rev_lookup_label(addr))
+    }
+
+    #[test]
+    fn disassembles_region() {
+        let lines = region(|obj| {
+            let code = find_code(obj);
+            obj.addr_iter().map(|(addr, _)| (addr, code.contains(&addr))).collect()
+        });
+        let lines: Vec<_> = lines.iter().map(|l| (l.addr, l.label.as_deref(), l.text.as_str(), l.comment.as_deref())).collect();
+        assert_eq!(lines, [
+            // Targets are labelled (and annotated with their address):
+            (0x3000, Some("LOOP"), "LD R0, PTR", Some("x3002")),
+            (0x3001, None, "BRnzp LOOP", Some("x3000")),
+            // Data is annotated with the label it points to, or the character it holds:
+            (0x3002, Some("PTR"), ".FILL LOOP", Some("x3000")),
+            (0x3003, Some("CH"), ".FILL x0041", Some("'A'")),
+            (0x3004, Some("ADDS"), ".FILL x1021", None),
+        ]);
+    }
+
+    #[test]
+    fn disassembles_region_without_code_map() {
+        // Without knowing which words are code, anything which decodes is an instruction:
+        let lines = region(|_| HashMap::new());
+        assert_eq!(lines[4].text, "ADD R0, R0, #1");
+        assert_eq!(lines[4].comment, None);
+    }
+
+    #[test]
+    fn lays_out_listing() {
+        let lines = region(|obj| {
+            let code = find_code(obj);
+            obj.addr_iter().map(|(addr, _)| (addr, code.contains(&addr))).collect()
+        });
+        assert_eq!(listing(&lines), "\
+x3000  x2001  LOOP  LD R0, PTR   ; x3002
+x3001  x0FFE        BRnzp LOOP   ; x3000
+x3002  x3000  PTR   .FILL LOOP   ; x3000
+x3003  x0041  CH    .FILL x0041  ; 'A'
+x3004  x1021  ADDS  .FILL x1021
+");
+    }
+}
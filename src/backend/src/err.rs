@@ -3,13 +3,17 @@ use std::convert::Infallible;
 use std::path::Path;
 use std::sync::LazyLock;
 
-use lc3_ensemble::err::ErrSpan;
+use lc3_ensemble::asm::{AsmErr, AsmErrKind, SourceInfo};
+use lc3_ensemble::err::{ErrSpan, LexErr, OffsetNewErr, ParseErr};
 use miette::highlighters::{Highlighter, HighlighterState};
 use miette::{Diagnostic, GraphicalReportHandler, GraphicalTheme, NamedSource, Severity, ThemeCharacters, ThemeStyles};
-use neon::context::Context;
+use neon::prelude::*;
 use neon::result::Throw;
 use owo_colors::style;
 
+use crate::cast::TryIntoJsValue;
+use crate::preproc::Expanded;
+
 struct FlatHighlighter(owo_colors::Style);
 impl Highlighter for FlatHighlighter {
     fn start_highlighter_state<'h>(
@@ -63,6 +67,13 @@ impl<'s> ReporterSource<'s> {
             None => ReporterSource::Unlabeled(source),
         }
     }
+
+    fn text(&self) -> &str {
+        match self {
+            ReporterSource::Unlabeled(s) => s,
+            ReporterSource::Labeled(s) => s.inner(),
+        }
+    }
 }
 impl miette::SourceCode for ReporterSource<'_> {
     fn read_span<'a>(
@@ -83,12 +94,18 @@ pub(crate) struct Reporter<'r, E: std::fmt::Display + ?Sized> {
     err: &'r E,
     /// The filename of file where this error occurred (if there is a file)
     filename: Option<&'r str>,
+    /// The full path of the file where this error occurred (if there is a file)
+    path: Option<&'r Path>,
     /// Source code
     source: Option<ReporterSource<'r>>,
     /// Span where error occurred
     span: Option<ErrSpan>,
     /// Relevant help messages
     help: Option<Cow<'r, str>>,
+    /// How severe this report is
+    severity: Severity,
+    /// An identifier for the kind of error
+    code: Option<Cow<'r, str>>,
     /// Whether to include the filename in the error message
     /// (can be false if it'd already appear elsewhere)
     include_name_in_msg: bool
@@ -98,9 +115,12 @@ impl<E: std::fmt::Display + ?Sized> std::fmt::Debug for Reporter<'_, E> {
         f.debug_struct("Reporter")
             .field("err", &self.err.to_string())
             .field("filename", &self.filename)
+            .field("path", &self.path)
             .field("source", &self.source)
             .field("span", &self.span)
             .field("help", &self.help)
+            .field("severity", &self.severity)
+            .field("code", &self.code)
             .field("include_name_in_msg", &self.include_name_in_msg)
             .finish()
     }
@@ -116,7 +136,7 @@ impl<E: std::fmt::Display + ?Sized> std::fmt::Display for Reporter<'_, E> {
 impl<E: std::fmt::Display + ?Sized> std::error::Error for Reporter<'_, E> {}
 impl<E: std::fmt::Display + ?Sized> Diagnostic for Reporter<'_, E> {
     fn severity(&self) -> Option<Severity> {
-        Some(Severity::Error)
+        Some(self.severity)
     }
 
     fn help<'a>(&'a self) -> Option<Box<dyn std::fmt::Display + 'a>> {
@@ -139,9 +159,12 @@ impl<'r, E: std::fmt::Display + ?Sized> Reporter<'r, E> {
         Reporter {
             err,
             filename: None,
+            path: None,
             source: None,
             span: None,
             help: None,
+            severity: Severity::Error,
+            code: None,
             include_name_in_msg: true
         }
     }
@@ -150,9 +173,12 @@ impl<'r, E: std::fmt::Display + ?Sized> Reporter<'r, E> {
         Reporter {
             err,
             filename: fp.file_name().and_then(|s| s.to_str()),
+            path: Some(fp),
             source: None,
             span: None,
             help: None,
+            severity: Severity::Error,
+            code: Some(Cow::Borrowed("io")),
             include_name_in_msg: true
         }
     }
@@ -165,12 +191,95 @@ impl<'r, E: std::fmt::Display + ?Sized> Reporter<'r, E> {
         Reporter {
             err,
             filename,
+            path: Some(fp),
             source: Some(ReporterSource::new(filename, src)),
             span,
             help,
+            severity: Severity::Error,
+            code: None,
+            include_name_in_msg: false,
+        }
+    }
+    /// Like [`Reporter::ensemble`], but for source code which does not come from a file.
+    pub(crate) fn ensemble_unnamed(err: &'r E, src: &'r str) -> Self
+        where E: lc3_ensemble::err::Error
+    {
+        Reporter {
+            err,
+            filename: None,
+            path: None,
+            source: Some(ReporterSource::new(None, src)),
+            span: err.span(),
+            help: err.help(),
+            severity: Severity::Error,
+            code: None,
             include_name_in_msg: false,
         }
     }
+
+    /// Like [`Reporter::ensemble`], but for errors in preprocessed source.
+    ///
+    /// The error is reported in the file it occurred in
+    /// (or at the macro invocation, if it occurred in a macro expansion).
+    pub(crate) fn preprocessed(err: &'r E, expanded: &'r Expanded) -> Self
+        where E: lc3_ensemble::err::Error
+    {
+        let Some(span) = err.span() else {
+            return Reporter::simple(err);
+        };
+
+        let loc = expanded.locate(span.first());
+        // Only the spans in the same file can be shown:
+        let mut spans = span.iter()
+            .map(|s| expanded.locate(s.clone()))
+            .filter(|l| l.path == loc.path && std::ptr::eq(l.text, loc.text))
+            .map(|l| l.span);
+        let mut span = ErrSpan::from(spans.next().unwrap_or(loc.span));
+        span.extend(spans);
+
+        let help = match (err.help(), loc.expanded_from) {
+            (Some(help), Some(name)) => Some(Cow::Owned(format!("{help} (in the expansion of macro {name})"))),
+            (None, Some(name)) => Some(Cow::Owned(format!("in the expansion of macro {name}"))),
+            (help, None) => help,
+        };
+        let filename = loc.path.and_then(|p| p.file_name()).and_then(|s| s.to_str());
+        Reporter {
+            err,
+            filename,
+            path: loc.path,
+            source: Some(ReporterSource::new(filename, loc.text)),
+            span: Some(span),
+            help,
+            severity: Severity::Error,
+            code: None,
+            include_name_in_msg: false,
+        }
+    }
+
+    /// Names the file by its path relative to the given directory (rather than just its file name),
+    /// so that files with the same name in different directories can be told apart.
+    pub(crate) fn relative_to(mut self, dir: &Path) -> Self {
+        let Some(name) = self.path.and_then(|p| p.strip_prefix(dir).ok()).and_then(|p| p.to_str()) else {
+            return self;
+        };
+        self.filename = Some(name);
+        if let Some(ReporterSource::Labeled(src)) = &self.source {
+            self.source = Some(ReporterSource::Labeled(NamedSource::new(name, src.inner().clone())));
+        }
+        self
+    }
+
+    /// Sets how severe this report is.
+    pub(crate) fn with_severity(mut self, severity: Severity) -> Self {
+        self.severity = severity;
+        self
+    }
+
+    /// Sets the identifier for the kind of error.
+    pub(crate) fn with_code(mut self, code: impl Into<Cow<'r, str>>) -> Self {
+        self.code.replace(code.into());
+        self
+    }
 }
 
 impl<E: std::fmt::Display + ?Sized> Reporter<'_, E> {
@@ -182,10 +291,121 @@ impl<E: std::fmt::Display + ?Sized> Reporter<'_, E> {
         writer.extend(report.trim_start().as_bytes());
     }
 
+    /// Creates the structured form of this report.
+    pub(crate) fn diagnostic(&self) -> DiagnosticData {
+        let ranges = match (&self.source, &self.span) {
+            (Some(src), Some(span)) => {
+                let src_info = SourceInfo::new(src.text());
+                span.iter()
+                    .map(|s| {
+                        let (slno, scno) = src_info.get_pos_pair(s.start);
+                        let (elno, ecno) = src_info.get_pos_pair(s.end);
+                        [slno, scno, elno, ecno]
+                    })
+                    .collect()
+            },
+            _ => vec![],
+        };
+
+        DiagnosticData {
+            severity: self.severity,
+            message: self.err.to_string(),
+            file: self.path.map(|p| p.display().to_string()),
+            ranges,
+            help: self.help.as_ref().filter(|s| !s.is_empty()).map(|s| s.to_string()),
+            code: self.code.as_ref().map(|s| s.to_string()),
+        }
+    }
+
     pub(crate) fn report_and_throw<'a>(mut self, writer: &mut Vec<u8>, cx: &mut impl Context<'a>) -> Throw {
         self.report(writer);
+        self.diagnostic().throw(cx)
+    }
+}
+
+/// The structured form of a [`Reporter`]'s report, which can be sent to JS.
+#[derive(Debug, Clone)]
+pub(crate) struct DiagnosticData {
+    severity: Severity,
+    message: String,
+    /// The path of the file where this occurred (if there is a file)
+    file: Option<String>,
+    /// The source ranges where this occurred,
+    /// each in the form `[start line, start col, end line, end col]`
+    ranges: Vec<[usize; 4]>,
+    help: Option<String>,
+    code: Option<String>,
+}
+impl DiagnosticData {
+    /// Throws a JS error with this diagnostic's fields attached to it.
+    pub(crate) fn throw<'a>(self, cx: &mut impl Context<'a>) -> Throw {
+        let result = JsError::error(cx, &self.message)
+            .and_then(|error| {
+                self.write_fields(cx, error)?;
+                cx.throw::<_, Infallible>(error)
+            });
 
-        cx.throw_error::<_, Infallible>(self.err.to_string())
-            .unwrap_err()
+        match result {
+            Err(t) => t,
+            Ok(never) => match never {},
+        }
+    }
+
+    fn write_fields<'a>(self, cx: &mut impl Context<'a>, obj: Handle<'a, impl Object>) -> NeonResult<()> {
+        let severity = cx.string(match self.severity {
+            Severity::Advice  => "info",
+            Severity::Warning => "warning",
+            Severity::Error   => "error",
+        });
+        obj.set(cx, "severity", severity)?;
+        let message = cx.string(self.message);
+        obj.set(cx, "message", message)?;
+        let file = self.file.try_into_js(cx)?;
+        obj.set(cx, "file", file)?;
+        let ranges = self.ranges.try_into_js(cx)?;
+        obj.set(cx, "ranges", ranges)?;
+        let help = self.help.try_into_js(cx)?;
+        obj.set(cx, "help", help)?;
+        let code = self.code.try_into_js(cx)?;
+        obj.set(cx, "code", code)?;
+
+        Ok(())
+    }
+}
+impl TryIntoJsValue for DiagnosticData {
+    type Value = JsObject;
+
+    fn try_into_js<'a>(self, cx: &mut impl Context<'a>) -> JsResult<'a, Self::Value> {
+        let obj = cx.empty_object();
+        self.write_fields(cx, obj)?;
+        Ok(obj)
+    }
+}
+
+/// Gets the diagnostic code for a parse error.
+pub(crate) fn parse_err_code(err: &ParseErr) -> &'static str {
+    use std::error::Error;
+
+    match err.source() {
+        Some(e) if e.is::<LexErr>() => "parse::lex",
+        Some(e) if e.is::<OffsetNewErr>() => "parse::offset-range",
+        _ => "parse::syntax",
+    }
+}
+/// Gets the diagnostic code for an assembler error.
+pub(crate) fn asm_err_code(err: &AsmErr) -> &'static str {
+    match err.kind {
+        AsmErrKind::UndetAddrLabel    => "asm::undetermined-label-address",
+        AsmErrKind::UndetAddrStmt     => "asm::undetermined-statement-address",
+        AsmErrKind::UnclosedOrig      => "asm::unclosed-orig",
+        AsmErrKind::UnopenedOrig      => "asm::unopened-orig",
+        AsmErrKind::OverlappingOrig   => "asm::nested-orig",
+        AsmErrKind::OverlappingLabels => "asm::duplicate-label",
+        AsmErrKind::WrappingBlock     => "asm::wrapping-block",
+        AsmErrKind::BlockInIO         => "asm::block-in-io",
+        AsmErrKind::OverlappingBlocks => "asm::overlapping-blocks",
+        AsmErrKind::OffsetNewErr(_)   => "asm::offset-range",
+        AsmErrKind::OffsetExternal    => "asm::offset-external",
+        AsmErrKind::CouldNotFindLabel => "asm::undefined-label",
     }
 }
\ No newline at end of file
@@ -0,0 +1,1215 @@
+//! Assemble-time constants (`.EQU` and `.SET`) and operand expressions.
+//!
+//! Ensemble's parser only accepts literals and labels as operands,
+//! so before parsing, every operand which is an expression (or a constant) is evaluated
+//! and rewritten into a literal.
+//!
+//! Expressions which use labels need the addresses of those labels, so this takes two passes:
+//! the source is first parsed with a placeholder for each of these expressions (which doesn't change any addresses),
+//! and once the addresses of every label are known, the source is rewritten again with the actual values.
+//!
+//! Expressions can use `+`, `-`, `*`, `/`, parentheses, numeric literals, character literals (`'A'`),
+//! constants, and labels. In PC offset operands (e.g., of `BR` and `LD`), expressions which use labels
+//! are addresses (so `LD R0, BUF+2` loads from the word two after `BUF`).
+//!
+//! Constants are defined with `NAME .EQU value` (or `.EQU NAME, value`).
+//! `.SET` is similar, but can redefine the constant later on.
+//!
+//! Local labels (see [`crate::local`]) are also replaced here, with placeholders which the parser accepts,
+//! as are literals in the formats of other dialects (see [`crate::dialect`]), like `0x3000`.
+//!
+//! The data directives the parser doesn't support (`.FILL` with several values, `.BLKW` with a fill value,
+//! `.ASCII`, and `.ALIGN`) are rewritten into a `.BLKW` of the same size,
+//! and their words are written into the object file after assembling.
+//!
+//! `.RELOC` (which starts a relocatable section, see [`crate::reloc`]) is rewritten into an `.ORIG`
+//! at the address the section is placed at. `.ALIGN` can't be used in a relocatable section,
+//! since its size depends on that address.
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ops::Range;
+
+use lc3_ensemble::ast::asm::{Directive, Stmt, StmtKind};
+use lc3_ensemble::ast::Label;
+use lc3_ensemble::err::ErrSpan;
+
+use crate::asm::{referenced_label_mut, stmt_len};
+use crate::dialect::Dialect;
+use crate::local::{is_local_ref, Locals};
+use crate::preproc::Expanded;
+use crate::reloc::{pack, RELOC_START};
+
+/// The kinds of errors that can occur while evaluating expressions.
+#[derive(Debug)]
+pub(crate) enum ExprErrKind {
+    /// The expression could not be parsed.
+    Syntax(&'static str),
+    /// A constant or label is not defined.
+    Undefined(String),
+    /// The expression uses a label where only constants can be used.
+    NotConstant,
+    /// The expression uses an external label (whose address is not known).
+    External(String),
+    /// A constant defined with `.EQU` was defined again.
+    Redefined(String),
+    DivisionByZero,
+    Overflow,
+    /// A data value does not fit in a word.
+    OutOfRange(i64),
+    /// A string has an unknown escape sequence.
+    InvalidEscape(char),
+    /// The operand depends on where a relocatable section is placed, but isn't a PC offset or data.
+    NotRelocatable,
+    /// The words of a data directive couldn't be written into the object file.
+    UnwritableData,
+}
+
+/// An error from evaluating an expression.
+#[derive(Debug)]
+pub(crate) struct ExprError {
+    kind: ExprErrKind,
+    span: Range<usize>,
+}
+impl ExprError {
+    /// Gets the diagnostic code for this error.
+    pub(crate) fn code(&self) -> &'static str {
+        match self.kind {
+            ExprErrKind::Syntax(_)      => "expr::syntax",
+            ExprErrKind::Undefined(_)   => "expr::undefined-symbol",
+            ExprErrKind::NotConstant    => "expr::not-constant",
+            ExprErrKind::External(_)    => "expr::external-label",
+            ExprErrKind::Redefined(_)   => "expr::redefined-constant",
+            ExprErrKind::DivisionByZero => "expr::division-by-zero",
+            ExprErrKind::Overflow       => "expr::overflow",
+            ExprErrKind::OutOfRange(_)  => "expr::out-of-range",
+            ExprErrKind::InvalidEscape(_) => "expr::invalid-escape",
+            ExprErrKind::NotRelocatable => "expr::not-relocatable",
+            ExprErrKind::UnwritableData => "expr::unwritable-data",
+        }
+    }
+
+    /// Whether the error is in how the expression is written (rather than in its value).
+    pub(crate) fn is_syntax(&self) -> bool {
+        matches!(self.kind, ExprErrKind::Syntax(_) | ExprErrKind::InvalidEscape(_))
+    }
+
+    /// An error for a statement which depends on where a relocatable section is placed, but can't be relocated.
+    pub(crate) fn not_relocatable(span: Range<usize>) -> Self {
+        ExprError { kind: ExprErrKind::NotRelocatable, span }
+    }
+    /// An error for a data directive whose words couldn't be written into the object file.
+    pub(crate) fn unwritable_data(span: Range<usize>) -> Self {
+        ExprError { kind: ExprErrKind::UnwritableData, span }
+    }
+}
+impl std::fmt::Display for ExprError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.kind {
+            ExprErrKind::Syntax(msg) => f.write_str(msg),
+            ExprErrKind::Undefined(name) => write!(f, "`{name}` is not defined"),
+            ExprErrKind::NotConstant => f.write_str("expected a constant expression"),
+            ExprErrKind::External(name) => write!(f, "cannot use external label `{name}` in an expression"),
+            ExprErrKind::Redefined(name) => write!(f, "constant `{name}` is already defined"),
+            ExprErrKind::DivisionByZero => f.write_str("division by zero"),
+            ExprErrKind::Overflow => f.write_str("expression overflowed"),
+            ExprErrKind::OutOfRange(value) => write!(f, "value {value} does not fit in 16 bits"),
+            ExprErrKind::InvalidEscape(c) => write!(f, "unknown escape sequence `\\{c}`"),
+            ExprErrKind::NotRelocatable => f.write_str("this statement depends on where a relocatable section is placed"),
+            ExprErrKind::UnwritableData => f.write_str("the data of this directive could not be written into the object file"),
+        }
+    }
+}
+impl std::error::Error for ExprError {}
+impl lc3_ensemble::err::Error for ExprError {
+    fn span(&self) -> Option<ErrSpan> {
+        Some(ErrSpan::from(self.span.clone()))
+    }
+
+    fn help(&self) -> Option<Cow<'_, str>> {
+        match &self.kind {
+            ExprErrKind::NotConstant => Some(Cow::Borrowed("this operand determines the addresses of labels, so it can only use literals and constants")),
+            ExprErrKind::Undefined(name) if name.starts_with(|c: char| c.is_ascii_digit()) => Some(Cow::Borrowed("`1b` refers to the closest `1:` before it, and `1f` to the closest `1:` after it")),
+            ExprErrKind::Undefined(name) if is_local_ref(name) => Some(Cow::Borrowed("local labels can only be used under the same global label (elsewhere, use their qualified names)")),
+            ExprErrKind::Undefined(_) => Some(Cow::Borrowed("labels can be defined anywhere, but constants have to be defined before they are used")),
+            ExprErrKind::Redefined(_) => Some(Cow::Borrowed("use .set to define constants which can be redefined")),
+            ExprErrKind::OutOfRange(_) => Some(Cow::Borrowed("the range for a word is [-32768, 65535]")),
+            ExprErrKind::InvalidEscape(_) => Some(Cow::Borrowed(r#"the supported escapes are \n, \r, \t, \\, \0, and \""#)),
+            ExprErrKind::NotRelocatable => Some(Cow::Borrowed("only PC offsets (e.g., of BR and LD) and data (e.g., .FILL) can be relocated")),
+            ExprErrKind::UnwritableData => Some(Cow::Borrowed("this is a bug in the assembler, so please report it")),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum BinOp { Add, Sub, Mul, Div }
+
+/// A parsed expression.
+#[derive(Clone, Debug)]
+enum Node {
+    Num(i64),
+    /// A label (or a constant, before constants are substituted).
+    Symbol(String, Range<usize>),
+    Neg(Box<Node>),
+    Bin(BinOp, Box<Node>, Box<Node>),
+}
+impl Node {
+    /// Replaces every constant in this expression with its value.
+    fn substitute(&mut self, constants: &HashMap<String, (i64, bool)>) {
+        match self {
+            Node::Num(_) => {},
+            Node::Symbol(name, _) => if let Some(&(value, _)) = constants.get(&name.to_uppercase()) {
+                *self = Node::Num(value);
+            },
+            Node::Neg(n) => n.substitute(constants),
+            Node::Bin(_, l, r) => {
+                l.substitute(constants);
+                r.substitute(constants);
+            },
+        }
+    }
+
+    /// Replaces every local label in this expression with its qualified name.
+    fn qualify(&mut self, locals: &Locals, line: usize) -> Result<(), ExprError> {
+        match self {
+            Node::Num(_) => Ok(()),
+            Node::Symbol(name, span) => match locals.resolve(line, name) {
+                Some(Some(qualified)) => {
+                    *name = qualified;
+                    Ok(())
+                },
+                Some(None) => Err(ExprError { kind: ExprErrKind::Undefined(name.clone()), span: span.clone() }),
+                None => Ok(()),
+            },
+            Node::Neg(n) => n.qualify(locals, line),
+            Node::Bin(_, l, r) => {
+                l.qualify(locals, line)?;
+                r.qualify(locals, line)
+            },
+        }
+    }
+
+    /// The first label in this expression (after constants are substituted).
+    fn first_label(&self) -> Option<(&str, Range<usize>)> {
+        match self {
+            Node::Num(_) => None,
+            Node::Symbol(name, span) => Some((name, span.clone())),
+            Node::Neg(n) => n.first_label(),
+            Node::Bin(_, l, r) => l.first_label().or_else(|| r.first_label()),
+        }
+    }
+    fn labels(&self, out: &mut Vec<Label>) {
+        match self {
+            Node::Num(_) => {},
+            Node::Symbol(name, span) => out.push(Label::new(name.clone(), span.clone())),
+            Node::Neg(n) => n.labels(out),
+            Node::Bin(_, l, r) => {
+                l.labels(out);
+                r.labels(out);
+            },
+        }
+    }
+
+    /// Evaluates this expression, looking up the addresses of any labels.
+    fn eval(&self, span: &Range<usize>, label: &impl Fn(&str, Range<usize>) -> Result<i64, ExprError>) -> Result<i64, ExprError> {
+        let overflow = || ExprError { kind: ExprErrKind::Overflow, span: span.clone() };
+        match self {
+            Node::Num(n) => Ok(*n),
+            Node::Symbol(name, s) => label(name, s.clone()),
+            Node::Neg(n) => n.eval(span, label)?.checked_neg().ok_or_else(overflow),
+            Node::Bin(op, l, r) => {
+                let (l, r) = (l.eval(span, label)?, r.eval(span, label)?);
+                match op {
+                    BinOp::Add => l.checked_add(r),
+                    BinOp::Sub => l.checked_sub(r),
+                    BinOp::Mul => l.checked_mul(r),
+                    BinOp::Div if r == 0 => return Err(ExprError { kind: ExprErrKind::DivisionByZero, span: span.clone() }),
+                    BinOp::Div => l.checked_div(r),
+                }.ok_or_else(overflow)
+            },
+        }
+    }
+}
+
+/// A recursive descent parser for expressions.
+struct ExprParser<'s> {
+    text: &'s str,
+    /// The offset of the text in source.
+    base: usize,
+    pos: usize,
+    /// The dialect (which can accept other formats of literals).
+    dialect: Dialect,
+}
+impl ExprParser<'_> {
+    fn error(&self, msg: &'static str) -> ExprError {
+        let start = self.base + self.pos;
+        ExprError { kind: ExprErrKind::Syntax(msg), span: start..start + 1 }
+    }
+    fn skip_ws(&mut self) {
+        self.pos += self.rest().len() - self.rest().trim_start().len();
+    }
+    fn rest(&self) -> &str {
+        &self.text[self.pos..]
+    }
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_ws();
+        let ate = self.rest().starts_with(c);
+        if ate { self.pos += c.len_utf8(); }
+        ate
+    }
+
+    fn parse(mut self) -> Result<Node, ExprError> {
+        let node = self.sum()?;
+        self.skip_ws();
+        match self.rest().is_empty() {
+            true => Ok(node),
+            false => Err(self.error("unexpected character in expression")),
+        }
+    }
+    fn sum(&mut self) -> Result<Node, ExprError> {
+        let mut node = self.product()?;
+        loop {
+            let op = match () {
+                _ if self.eat('+') => BinOp::Add,
+                _ if self.eat('-') => BinOp::Sub,
+                _ => return Ok(node),
+            };
+            node = Node::Bin(op, Box::new(node), Box::new(self.product()?));
+        }
+    }
+    fn product(&mut self) -> Result<Node, ExprError> {
+        let mut node = self.unary()?;
+        loop {
+            let op = match () {
+                _ if self.eat('*') => BinOp::Mul,
+                _ if self.eat('/') => BinOp::Div,
+                _ => return Ok(node),
+            };
+            node = Node::Bin(op, Box::new(node), Box::new(self.unary()?));
+        }
+    }
+    fn unary(&mut self) -> Result<Node, ExprError> {
+        if self.eat('-') { return Ok(Node::Neg(Box::new(self.unary()?))); }
+        if self.eat('+') { return self.unary(); }
+        if self.eat('(') {
+            let node = self.sum()?;
+            return match self.eat(')') {
+                true => Ok(node),
+                false => Err(self.error("expected `)`")),
+            };
+        }
+        self.atom()
+    }
+    fn atom(&mut self) -> Result<Node, ExprError> {
+        self.skip_ws();
+        let start = self.pos;
+        let rest = &self.text[start..];
+
+        if rest.starts_with('\'') {
+            let Some((c, len)) = parse_char(rest) else {
+                return Err(self.error("invalid character literal"));
+            };
+            self.pos += len;
+            return Ok(Node::Num(i64::from(c)));
+        }
+
+        // Local labels start with `.`:
+        let lead = usize::from(rest.starts_with('.'));
+        let len = lead + rest[lead..].find(|c: char| !(c.is_alphanumeric() || matches!(c, '_' | '#' | '.'))).unwrap_or(rest.len() - lead);
+        let token = &rest[..len];
+        // A literal can be negative (e.g., `#-5`):
+        let (token, len) = match (token, rest[len..].strip_prefix('-')) {
+            ("#" | "x" | "X", Some(digits)) => {
+                let dlen = digits.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(digits.len());
+                (&rest[..len + 1 + dlen], len + 1 + dlen)
+            },
+            _ => (token, len),
+        };
+        if token.is_empty() {
+            return Err(self.error("expected a number or symbol"));
+        }
+        self.pos += len;
+
+        match parse_literal(token).or_else(|| self.dialect.parse_literal(token)) {
+            Some(n) => Ok(Node::Num(n)),
+            // Constants can also be written like literals (e.g., `#SIZE`):
+            None if is_symbol(token.strip_prefix('#').unwrap_or(token)) => {
+                let name = token.strip_prefix('#').unwrap_or(token);
+                Ok(Node::Symbol(name.to_string(), self.base + self.pos - name.len() .. self.base + self.pos))
+            },
+            None if is_local_ref(token) => Ok(Node::Symbol(token.to_string(), self.base + start .. self.base + self.pos)),
+            None => {
+                self.pos = start;
+                Err(self.error("invalid number"))
+            },
+        }
+    }
+}
+
+/// Evaluates an expression which only uses literals and the given constants (by uppercase name),
+/// like the condition of an `.IF`.
+pub(crate) fn eval_constant(text: &str, constants: &HashMap<String, i64>) -> Option<i64> {
+    let expr = (ExprParser { text, base: 0, pos: 0, dialect: Dialect::default() }).parse().ok()?;
+    let constant = |name: &str, span| constants.get(&name.to_uppercase()).copied()
+        .ok_or_else(|| ExprError { kind: ExprErrKind::Undefined(name.to_string()), span });
+    expr.eval(&(0..text.len()), &constant).ok()
+}
+
+/// Parses a character literal at the start of the text,
+/// returning its value and the length of the literal.
+pub(crate) fn parse_char(text: &str) -> Option<(u16, usize)> {
+    let inner = text.strip_prefix('\'')?;
+    let mut chars = inner.chars();
+    let (c, len) = match chars.next()? {
+        '\\' => {
+            let c = match chars.next()? {
+                'n' => '\n',
+                'r' => '\r',
+                't' => '\t',
+                '0' => '\0',
+                c @ ('\\' | '\'' | '"') => c,
+                _ => return None,
+            };
+            (c, 2)
+        },
+        '\'' => return None,
+        c => (c, c.len_utf8()),
+    };
+    if !c.is_ascii() || !inner[len..].starts_with('\'') { return None; }
+    Some((c as u16, len + 2))
+}
+/// Parses a string literal (including its quotes), returning its bytes.
+///
+/// This accepts the same escapes as `.STRINGZ`.
+fn parse_string(text: &str, base: usize) -> Result<Vec<u8>, ExprError> {
+    let unclosed = || ExprError { kind: ExprErrKind::Syntax("expected a string"), span: base..base + text.len() };
+    let inner = text.strip_prefix('"')
+        .and_then(|t| t.strip_suffix('"'))
+        .filter(|_| text.len() >= 2)
+        .ok_or_else(unclosed)?;
+
+    let mut bytes = vec![];
+    let mut chars = inner.char_indices();
+    while let Some((i, c)) = chars.next() {
+        let c = match c {
+            '\\' => match chars.next() {
+                Some((_, 'n')) => '\n',
+                Some((_, 'r')) => '\r',
+                Some((_, 't')) => '\t',
+                Some((_, '0')) => '\0',
+                Some((_, c @ ('\\' | '"'))) => c,
+                Some((j, c)) => {
+                    let start = base + 1 + i;
+                    return Err(ExprError { kind: ExprErrKind::InvalidEscape(c), span: start..base + 1 + j + c.len_utf8() });
+                },
+                None => return Err(unclosed()),
+            },
+            '"' => return Err(unclosed()),
+            c => c,
+        };
+        bytes.extend(c.encode_utf8(&mut [0; 4]).bytes());
+    }
+    Ok(bytes)
+}
+/// Parses a numeric literal in the forms accepted by the assembler (`#10`, `10`, `xA`, `#-10`, `x-A`).
+pub(crate) fn parse_literal(token: &str) -> Option<i64> {
+    let (radix, digits) = match token.as_bytes().first()? {
+        b'#' => (10, &token[1..]),
+        b'x' | b'X' => (16, &token[1..]),
+        b'0'..=b'9' | b'-' => (10, token),
+        _ => return None,
+    };
+    i64::from_str_radix(digits, radix).ok()
+}
+pub(crate) fn is_symbol(token: &str) -> bool {
+    let mut chars = token.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+pub(crate) fn is_register(token: &str) -> bool {
+    matches!(token.as_bytes(), [b'r' | b'R', b'0'..=b'7'])
+}
+pub(crate) fn is_instruction(token: &str) -> bool {
+    let upper = token.to_uppercase();
+    matches!(&*upper,
+        | "ADD" | "AND" | "NOT" | "JMP" | "JSR" | "JSRR" | "LD" | "LDI" | "LDR" | "LEA"
+        | "ST" | "STI" | "STR" | "TRAP" | "RET" | "RTI" | "NOP"
+        | "GETC" | "OUT" | "PUTC" | "PUTS" | "IN" | "PUTSP" | "HALT"
+    ) || upper.strip_prefix("BR").is_some_and(|cc| cc.chars().all(|c| matches!(c, 'N' | 'Z' | 'P')))
+}
+
+/// The parts of a line of source code.
+pub(crate) struct LineParts<'s> {
+    /// The labels at the start of the line.
+    pub(crate) labels: Vec<(Range<usize>, &'s str)>,
+    /// The instruction or directive.
+    pub(crate) opcode: &'s str,
+    pub(crate) opcode_span: Range<usize>,
+    /// The operands (split at commas).
+    pub(crate) operands: Vec<(Range<usize>, &'s str)>,
+}
+/// Whether a token is a directive (rather than a local label).
+pub(crate) fn is_directive(token: &str) -> bool {
+    matches!(&*token.to_uppercase(),
+        | ".ORIG" | ".FILL" | ".BLKW" | ".STRINGZ" | ".END" | ".EXTERNAL"
+        | ".EQU" | ".SET" | ".ASCII" | ".ALIGN" | ".RELOC"
+    )
+}
+
+/// Finds where the code of a line ends (at the start of its comment, if it has one),
+/// skipping over strings and characters.
+pub(crate) fn code_end(line: &str) -> usize {
+    let mut i = 0;
+    while i < line.len() {
+        let rest = &line[i..];
+        match rest.as_bytes()[0] {
+            b';' => return i,
+            b'"' => {
+                let mut escaped = false;
+                let len = rest[1..].char_indices()
+                    .find(|&(_, c)| {
+                        let close = c == '"' && !escaped;
+                        escaped = c == '\\' && !escaped;
+                        close
+                    })
+                    .map_or(rest.len(), |(j, _)| j + 2);
+                i += len;
+            },
+            b'\'' => i += parse_char(rest).map_or(1, |(_, len)| len),
+            _ => i += rest.chars().next().map_or(1, char::len_utf8),
+        }
+    }
+    line.len()
+}
+
+/// Splits a line of source code into its labels, opcode, and operands
+/// (where every span is relative to the start of the line).
+///
+/// If the line only has labels, the opcode is empty.
+/// This returns `None` if the line is empty.
+pub(crate) fn split_line(line: &str) -> Option<LineParts<'_>> {
+    let code = &line[..code_end(line)];
+
+    // Labels come before the instruction or directive:
+    let mut labels = vec![];
+    let mut pos = 0;
+    let (opcode, opcode_span) = loop {
+        let rest = &code[pos..];
+        let start = pos + (rest.len() - rest.trim_start().len());
+        if start >= code.len() {
+            if labels.is_empty() { return None; }
+            break ("", code.len()..code.len());
+        }
+        let len = code[start..].find(|c: char| c.is_whitespace()).unwrap_or(code.len() - start);
+        let word = &code[start..start + len];
+        pos = start + len;
+
+        if is_directive(word) || is_instruction(word) {
+            break (word, start..pos);
+        }
+        labels.push((start..pos, word));
+    };
+
+    // Operands are separated by commas (outside of strings, characters, and parentheses):
+    let mut operands = vec![];
+    let mut depth = 0;
+    let op_end = opcode_span.end;
+    let mut op_start = op_end;
+    let mut push_operand = |s: usize, e: usize| {
+        let text = &code[s..e];
+        let trimmed = text.trim();
+        if !trimmed.is_empty() {
+            let ts = s + (text.len() - text.trim_start().len());
+            operands.push((ts..ts + trimmed.len(), trimmed));
+        }
+    };
+    let mut i = op_end;
+    while i < code.len() {
+        let rest = &code[i..];
+        match rest.as_bytes()[0] {
+            b'"' => {
+                let mut escaped = false;
+                i += rest[1..].char_indices()
+                    .find(|&(_, c)| {
+                        let close = c == '"' && !escaped;
+                        escaped = c == '\\' && !escaped;
+                        close
+                    })
+                    .map_or(rest.len(), |(j, _)| j + 2);
+                continue;
+            },
+            b'\'' => {
+                i += parse_char(rest).map_or(1, |(_, len)| len);
+                continue;
+            },
+            b'(' => depth += 1,
+            b')' => depth -= 1,
+            b',' if depth == 0 => {
+                push_operand(op_start, i);
+                op_start = i + 1;
+            },
+            _ => {},
+        }
+        i += rest.chars().next().map_or(1, char::len_utf8);
+    }
+    push_operand(op_start, code.len());
+
+    Some(LineParts { labels, opcode, opcode_span, operands })
+}
+
+/// How the value of an operand is used.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum OperandKind {
+    /// A PC offset (where expressions using labels are addresses).
+    PcOffset,
+    /// An immediate value.
+    Value,
+    /// A value which affects the addresses of labels (and so cannot use labels).
+    Constant,
+}
+/// The kind of the operand at the given index of the given instruction or directive
+/// (or `None` if it can't be an expression).
+pub(crate) fn operand_kind(opcode: &str, index: usize) -> Option<OperandKind> {
+    let upper = opcode.to_uppercase();
+    match (&*upper, index) {
+        ("JSR", 0) => Some(OperandKind::PcOffset),
+        (op, 0) if op.starts_with("BR") => Some(OperandKind::PcOffset),
+        ("LD" | "LDI" | "LEA" | "ST" | "STI", 1) => Some(OperandKind::PcOffset),
+        ("ADD" | "AND" | "LDR" | "STR", 2) => Some(OperandKind::Value),
+        ("TRAP" | ".FILL", 0) => Some(OperandKind::Value),
+        (".ORIG" | ".BLKW", 0) => Some(OperandKind::Constant),
+        _ => None,
+    }
+}
+
+/// An operand expression which has to be rewritten into a literal.
+#[derive(Debug)]
+struct Operand {
+    span: Range<usize>,
+    kind: OperandKind,
+    expr: Node,
+    /// The final value of the literal (if it is known).
+    literal: Option<i64>,
+}
+
+/// A word of data, given by an expression.
+#[derive(Clone, Debug)]
+struct Word {
+    span: Range<usize>,
+    expr: Node,
+    /// The value of the word (if it is known).
+    value: Option<u16>,
+}
+
+#[derive(Debug)]
+enum DataKind {
+    /// A list of words (from `.FILL` or `.ASCII`).
+    Words(Vec<Word>),
+    /// A block of words all with the same value (from `.BLKW`).
+    Fill(u16, Word),
+    /// Padding to the next multiple of the alignment (from `.ALIGN`),
+    /// along with the size of the padding (once it is known).
+    Align(u16, Option<u16>),
+}
+/// The words of a data directive which the parser does not support, to be written after assembling.
+#[derive(Debug)]
+pub(crate) struct DataWords {
+    /// The span of the directive and its operands.
+    pub(crate) span: Range<usize>,
+    pub(crate) addr: u16,
+    pub(crate) words: Vec<u16>,
+}
+/// A data directive which the parser does not support.
+#[derive(Debug)]
+struct Data {
+    /// The span of the directive and its operands (which is replaced with a placeholder).
+    span: Range<usize>,
+    kind: DataKind,
+    /// The address of the data (once it is known).
+    addr: Option<u16>,
+}
+impl Data {
+    /// The number of words the data occupies (or `None` if it isn't known yet).
+    fn len(&self) -> Option<u16> {
+        match &self.kind {
+            DataKind::Words(words) => Some(words.len() as u16),
+            DataKind::Fill(n, _) => Some(*n),
+            DataKind::Align(_, padding) => *padding,
+        }
+    }
+    fn words(&self) -> Vec<&Word> {
+        match &self.kind {
+            DataKind::Words(words) => words.iter().collect(),
+            DataKind::Fill(_, word) => vec![word],
+            DataKind::Align(..) => vec![],
+        }
+    }
+    fn words_mut(&mut self) -> Vec<&mut Word> {
+        match &mut self.kind {
+            DataKind::Words(words) => words.iter_mut().collect(),
+            DataKind::Fill(_, word) => vec![word],
+            DataKind::Align(..) => vec![],
+        }
+    }
+}
+
+/// The prefix of the placeholders for local labels (followed by an index into [`Rewritten::names`]).
+const LOCAL_PREFIX: &str = "__LOCAL_";
+
+/// A change to the source, made before parsing.
+#[derive(Debug)]
+struct Edit {
+    /// The span of the original text in the source.
+    orig: Range<usize>,
+    /// The span of the replacement in the rewritten source.
+    new: Range<usize>,
+}
+
+/// Source code where every expression has been rewritten into a literal.
+#[derive(Debug)]
+pub(crate) struct Rewritten {
+    text: String,
+    edits: Vec<Edit>,
+    /// The qualified names of the placeholders for local labels.
+    names: Vec<String>,
+}
+impl Rewritten {
+    pub(crate) fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Maps a span of the rewritten source back to the original source.
+    ///
+    /// A span touching a rewritten expression is expanded to cover the whole expression.
+    pub(crate) fn map_span(&self, span: Range<usize>) -> Range<usize> {
+        let i = self.edits.partition_point(|e| e.new.start <= span.start);
+        let start = match i.checked_sub(1).map(|i| &self.edits[i]) {
+            Some(e) if span.start < e.new.end => e.orig.start,
+            Some(e) => span.start - e.new.end + e.orig.end,
+            None => span.start,
+        };
+        let i = self.edits.partition_point(|e| e.new.start < span.end);
+        let end = match i.checked_sub(1).map(|i| &self.edits[i]) {
+            Some(e) if span.end <= e.new.end => e.orig.end,
+            Some(e) => span.end - e.new.end + e.orig.end,
+            None => span.end,
+        };
+        start..end.max(start)
+    }
+
+    /// Maps the spans of a statement parsed from the rewritten source back to the original source.
+    pub(crate) fn map_stmt(&self, mut stmt: Stmt) -> Stmt {
+        let map_label = |label: &mut Label| {
+            let start = self.map_span(label.span()).start;
+            let name = match label.name.strip_prefix(LOCAL_PREFIX).and_then(|i| i.parse::<usize>().ok()) {
+                Some(i) => self.names[i].clone(),
+                None => std::mem::take(&mut label.name),
+            };
+            let end = start + name.len();
+            *label = Label::new(name, start..end);
+        };
+
+        stmt.span = self.map_span(stmt.span);
+        stmt.labels.iter_mut().for_each(map_label);
+        if let Some(label) = referenced_label_mut(&mut stmt) {
+            map_label(label);
+        }
+        stmt
+    }
+}
+
+/// What an expression can refer to (at a given line).
+struct Context<'a> {
+    /// The constants defined so far (keyed by their uppercase names).
+    constants: &'a HashMap<String, (i64, bool)>,
+    locals: &'a Locals,
+    /// The index of the line (in program order).
+    line: usize,
+}
+
+/// The constants and expressions in preprocessed source code.
+pub(crate) struct Exprs {
+    dialect: Dialect,
+    operands: Vec<Operand>,
+    data: Vec<Data>,
+    /// The local labels (and their qualified names), which are replaced with placeholders.
+    renames: Vec<(Range<usize>, String)>,
+    /// The spans of the lines which define constants (which are removed before parsing).
+    definitions: Vec<Range<usize>>,
+    /// The spans of the `.RELOC` directives and the addresses their sections are placed at.
+    relocs: Vec<(Range<usize>, u16)>,
+    errors: Vec<ExprError>,
+}
+impl Exprs {
+    /// Finds every constant and operand expression in the source,
+    /// evaluating everything which doesn't use labels.
+    pub(crate) fn scan(expanded: &Expanded, dialect: Dialect) -> Self {
+        let src = expanded.src();
+        let mut exprs = Exprs { dialect, operands: vec![], data: vec![], renames: vec![], definitions: vec![], relocs: vec![], errors: vec![] };
+        // Uppercase name to value and whether it was defined with `.SET`.
+        let mut constants: HashMap<String, (i64, bool)> = HashMap::new();
+
+        let lines: Vec<_> = expanded.ordered_lines().into_iter()
+            .filter_map(|span| Some((span.clone(), split_line(&src[span])?)))
+            .collect();
+        let locals = Locals::new(lines.iter().map(|(_, parts)| match &*parts.opcode.to_uppercase() {
+            // Constants aren't labels:
+            ".EQU" | ".SET" => vec![],
+            _ => parts.labels.iter().map(|&(_, word)| word).collect(),
+        }));
+
+        // Whether the current block is relocatable:
+        let mut in_reloc = false;
+        for (line, (line_span, LineParts { labels, opcode, opcode_span, operands })) in lines.into_iter().enumerate() {
+            let at = |r: &Range<usize>| line_span.start + r.start .. line_span.start + r.end;
+
+            let upper = opcode.to_uppercase();
+            match &*upper {
+                ".RELOC" => in_reloc = true,
+                ".ORIG" | ".END" => in_reloc = false,
+                // The padding of .ALIGN depends on where the section is placed, which relocations can't patch:
+                ".ALIGN" if in_reloc => exprs.errors.push(ExprError::not_relocatable(at(&opcode_span))),
+                _ => {},
+            }
+            if upper == ".EQU" || upper == ".SET" {
+                exprs.definitions.push(line_span.clone());
+                exprs.define(&mut constants, upper == ".SET", labels.last().cloned(), &operands, &at);
+                continue;
+            }
+            if upper == ".RELOC" {
+                if let Some((span, _)) = operands.first() {
+                    exprs.errors.push(ExprError { kind: ExprErrKind::Syntax("unexpected operand"), span: at(span) });
+                }
+                let end = operands.last().map_or(opcode_span.end, |(s, _)| s.end);
+                exprs.relocs.push((at(&(opcode_span.start..end)), RELOC_START));
+            }
+
+            for (i, (span, word)) in labels.iter().enumerate() {
+                if let Some(name) = locals.def(line, i) {
+                    let start = at(span).start;
+                    exprs.renames.push((start..start + word.trim_end_matches(':').len(), name.to_string()));
+                }
+            }
+
+            let cx = Context { constants: &constants, locals: &locals, line };
+            let operands: Vec<_> = operands.into_iter().map(|(s, t)| (at(&s), t)).collect();
+            let end = operands.last().map_or(opcode_span.end, |(s, _)| s.end - line_span.start);
+            if let Some(kind) = exprs.scan_data(&upper, &at(&opcode_span), &operands, &cx) {
+                exprs.data.push(Data { span: at(&(opcode_span.start..end)), kind, addr: None });
+                continue;
+            }
+
+            for (i, (span, text)) in operands.iter().enumerate() {
+                let Some(kind) = operand_kind(opcode, i) else { continue };
+                let span = span.clone();
+
+                // Local labels are renamed where the parser accepts labels:
+                let takes_label = kind == OperandKind::PcOffset || opcode.eq_ignore_ascii_case(".FILL");
+                if takes_label && let Some(resolved) = locals.resolve(line, text) {
+                    match resolved {
+                        Some(name) => exprs.renames.push((span, name)),
+                        None => {
+                            exprs.errors.push(ExprError { kind: ExprErrKind::Undefined(text.to_string()), span: span.clone() });
+                            exprs.operands.push(Operand { span, kind, expr: Node::Num(0), literal: None });
+                        },
+                    }
+                    continue;
+                }
+
+                // Leave registers, strings, literals, and labels (where the parser accepts them) to the parser:
+                let is_label = takes_label && is_symbol(text)
+                    && !constants.contains_key(&text.to_uppercase())
+                    && exprs.dialect.parse_literal(text).is_none();
+                if is_register(text) || text.starts_with('"') || parse_literal(text).is_some() || is_label {
+                    continue;
+                }
+
+                let Some(expr) = exprs.parse_expr(text, &span, &cx) else {
+                    // Still replace the operand (with a placeholder), so the parser doesn't report it again:
+                    exprs.operands.push(Operand { span, kind, expr: Node::Num(0), literal: None });
+                    continue;
+                };
+
+                let literal = match expr.first_label() {
+                    None => exprs.eval(&expr, &span),
+                    Some(_) if kind != OperandKind::Constant => None,
+                    Some((_, label_span)) => {
+                        exprs.errors.push(ExprError { kind: ExprErrKind::NotConstant, span: label_span });
+                        None
+                    },
+                };
+                exprs.operands.push(Operand { span, kind, expr, literal });
+            }
+        }
+
+        exprs
+    }
+
+    /// Parses an operand expression, qualifies its local labels, and substitutes its constants
+    /// (recording any error).
+    fn parse_expr(&mut self, text: &str, span: &Range<usize>, cx: &Context) -> Option<Node> {
+        let mut expr = (ExprParser { text, base: span.start, pos: 0, dialect: self.dialect }).parse()
+            .and_then(|mut expr| expr.qualify(cx.locals, cx.line).map(|_| expr))
+            .map_err(|e| self.errors.push(e))
+            .ok()?;
+        expr.substitute(cx.constants);
+        Some(expr)
+    }
+
+    /// Evaluates an expression which can only use constants (recording any error).
+    fn constant(&mut self, text: &str, span: &Range<usize>, cx: &Context) -> Option<i64> {
+        let expr = self.parse_expr(text, span, cx)?;
+        match expr.first_label() {
+            Some((_, label_span)) => {
+                self.errors.push(ExprError { kind: ExprErrKind::NotConstant, span: label_span });
+                None
+            },
+            None => self.eval(&expr, span),
+        }
+    }
+
+    /// Parses a word of data, evaluating it if it doesn't use labels.
+    fn word(&mut self, text: &str, span: &Range<usize>, cx: &Context) -> Word {
+        let expr = self.parse_expr(text, span, cx).unwrap_or(Node::Num(0));
+        let value = match expr.first_label() {
+            Some(_) => None,
+            None => self.eval(&expr, span).and_then(|v| self.check_word(v, span)),
+        };
+        Word { span: span.clone(), expr, value }
+    }
+    /// Checks that a data value fits in a word (recording an error if it doesn't).
+    fn check_word(&mut self, value: i64, span: &Range<usize>) -> Option<u16> {
+        match value {
+            -0x8000..=0xFFFF => Some(value as u16),
+            _ => {
+                self.errors.push(ExprError { kind: ExprErrKind::OutOfRange(value), span: span.clone() });
+                None
+            },
+        }
+    }
+
+    /// Scans a data directive which the parser does not support
+    /// (returning `None` if the directive is something else).
+    fn scan_data(
+        &mut self,
+        opcode: &str,
+        opcode_span: &Range<usize>,
+        operands: &[(Range<usize>, &str)],
+        cx: &Context
+    ) -> Option<DataKind> {
+        let too_many = |span: &Range<usize>| ExprError { kind: ExprErrKind::Syntax("too many operands"), span: span.clone() };
+
+        match (opcode, operands) {
+            (".FILL", [_, _, ..]) => {
+                let words = operands.iter()
+                    .map(|(span, text)| self.word(text, span, cx))
+                    .collect();
+                Some(DataKind::Words(words))
+            },
+            (".BLKW", [(count_span, count), value, rest @ ..]) => {
+                if let Some((span, _)) = rest.first() { self.errors.push(too_many(span)); }
+                let count = self.constant(count, count_span, cx)
+                    .and_then(|n| match n {
+                        1..=0xFFFF => Some(n as u16),
+                        _ => {
+                            self.errors.push(ExprError { kind: ExprErrKind::Syntax("block size must be between 1 and 65535"), span: count_span.clone() });
+                            None
+                        },
+                    })
+                    // Placeholders must keep the addresses of labels the same:
+                    .unwrap_or(1);
+                let (span, text) = value;
+                Some(DataKind::Fill(count, self.word(text, span, cx)))
+            },
+            (".ASCII", [(span, text), rest @ ..]) => {
+                if let Some((span, _)) = rest.first() { self.errors.push(too_many(span)); }
+                let bytes = parse_string(text, span.start)
+                    .map_err(|e| self.errors.push(e))
+                    .unwrap_or_default();
+                let words = bytes.into_iter()
+                    .map(|b| Word { span: span.clone(), expr: Node::Num(i64::from(b)), value: Some(u16::from(b)) })
+                    .collect();
+                Some(DataKind::Words(words))
+            },
+            (".ALIGN", [(span, text), rest @ ..]) => {
+                if let Some((span, _)) = rest.first() { self.errors.push(too_many(span)); }
+                let align = self.constant(text, span, cx)
+                    .and_then(|n| match n {
+                        1..=0x8000 => Some(n as u16),
+                        _ => {
+                            self.errors.push(ExprError { kind: ExprErrKind::Syntax("alignment must be between 1 and 32768"), span: span.clone() });
+                            None
+                        },
+                    })
+                    .unwrap_or(1);
+                Some(DataKind::Align(align, None))
+            },
+            (".ASCII" | ".ALIGN", []) => {
+                self.errors.push(ExprError { kind: ExprErrKind::Syntax("expected an operand"), span: opcode_span.clone() });
+                Some(DataKind::Words(vec![]))
+            },
+            // The parser handles .STRINGZ, but keeps unknown escapes as is:
+            (".STRINGZ", operands) => {
+                for (span, text) in operands {
+                    if let Err(e @ ExprError { kind: ExprErrKind::InvalidEscape(_), .. }) = parse_string(text, span.start) {
+                        self.errors.push(e);
+                    }
+                }
+                None
+            },
+            _ => None,
+        }
+    }
+
+    /// Evaluates an expression which does not use labels (recording any error).
+    fn eval(&mut self, expr: &Node, span: &Range<usize>) -> Option<i64> {
+        let no_labels = |_: &str, _| unreachable!("expression should not have labels");
+        expr.eval(span, &no_labels)
+            .map_err(|e| self.errors.push(e))
+            .ok()
+    }
+
+    /// Defines a constant with `.EQU` or `.SET`.
+    fn define(
+        &mut self,
+        constants: &mut HashMap<String, (i64, bool)>,
+        is_set: bool,
+        label: Option<(Range<usize>, &str)>,
+        operands: &[(Range<usize>, &str)],
+        at: &impl Fn(&Range<usize>) -> Range<usize>
+    ) {
+        // Either `NAME .EQU value` or `.EQU NAME, value`:
+        let (name, name_span, value) = match (&label, operands) {
+            (Some((span, name)), [value]) => (name.trim_end_matches(':'), at(span), value),
+            (None, [(span, name), value]) => (*name, at(span), value),
+            (_, []) | (None, [_]) => {
+                self.errors.push(ExprError { kind: ExprErrKind::Syntax("expected a constant name and value"), span: label.map_or(0..0, |(s, _)| at(&s)) });
+                return;
+            },
+            (_, [.., (span, _)]) => {
+                self.errors.push(ExprError { kind: ExprErrKind::Syntax("too many operands"), span: at(span) });
+                return;
+            },
+        };
+        if !is_symbol(name) {
+            self.errors.push(ExprError { kind: ExprErrKind::Syntax("invalid constant name"), span: name_span });
+            return;
+        }
+
+        let (value_span, value_text) = value;
+        let value_span = at(value_span);
+        let mut expr = match (ExprParser { text: value_text, base: value_span.start, pos: 0, dialect: self.dialect }).parse() {
+            Ok(expr) => expr,
+            Err(e) => return self.errors.push(e),
+        };
+        expr.substitute(constants);
+        if let Some((label, span)) = expr.first_label() {
+            let kind = ExprErrKind::Undefined(label.to_string());
+            return self.errors.push(ExprError { kind, span });
+        }
+        let Some(value) = self.eval(&expr, &value_span) else { return };
+
+        match constants.get(&name.to_uppercase()) {
+            Some(&(_, was_set)) if !(is_set && was_set) => {
+                self.errors.push(ExprError { kind: ExprErrKind::Redefined(name.to_string()), span: name_span });
+            },
+            _ => { constants.insert(name.to_uppercase(), (value, is_set)); },
+        }
+    }
+
+    /// The labels used by expressions (with their spans in source).
+    pub(crate) fn labels(&self) -> Vec<Label> {
+        let mut labels = vec![];
+        for op in &self.operands {
+            op.expr.labels(&mut labels);
+        }
+        for word in self.data.iter().flat_map(Data::words) {
+            word.expr.labels(&mut labels);
+        }
+        labels
+    }
+
+    /// The spans of the `.RELOC` directives.
+    pub(crate) fn reloc_spans(&self) -> Vec<Range<usize>> {
+        self.relocs.iter().map(|(span, _)| span.clone()).collect()
+    }
+
+    /// Places every relocatable section in the first free space from x3000,
+    /// given the statements parsed from the first rewrite of the source.
+    ///
+    /// A word is left free after each section, so that any one of them (`shifted`, along with the number of words)
+    /// can be moved a word later (or earlier) to find its relocations (see [`crate::asm::assemble_all`]).
+    pub(crate) fn place(&mut self, ast: &[Stmt], shifted: Option<(usize, i16)>) {
+        // The fixed sections (as their start and length), and the length of each relocatable section:
+        let mut fixed: Vec<(u16, u16)> = vec![];
+        let mut lens = vec![0; self.relocs.len()];
+        let mut current = None;
+        for stmt in ast {
+            match &stmt.nucleus {
+                StmtKind::Directive(Directive::Orig(addr)) => {
+                    let reloc = self.relocs.iter().position(|(s, _)| stmt.span.start <= s.start && s.end <= stmt.span.end);
+                    current = Some(reloc.ok_or(fixed.len()));
+                    if reloc.is_none() { fixed.push((addr.get(), 0)); }
+                },
+                StmtKind::Directive(Directive::End) => current = None,
+                _ => {},
+            }
+            match current {
+                Some(Ok(i)) => lens[i] += stmt_len(stmt),
+                Some(Err(i)) => fixed[i].1 += stmt_len(stmt),
+                None => {},
+            }
+        }
+
+        let occupied: Vec<_> = fixed.iter().map(|&(start, len)| u32::from(start)..u32::from(start) + u32::from(len)).collect();
+        for (i, ((_, addr), placed)) in self.relocs.iter_mut().zip(pack(&occupied, RELOC_START, &lens, 1)).enumerate() {
+            // If a section doesn't fit, the assembler reports it overlapping:
+            *addr = placed.unwrap_or(RELOC_START);
+            if let Some((_, by)) = shifted.filter(|&(s, _)| s == i) { *addr = addr.wrapping_add_signed(by); }
+        }
+    }
+
+    /// Whether anything depends on the addresses of labels or statements
+    /// (and so can only be evaluated after parsing).
+    pub(crate) fn has_unresolved(&self) -> bool {
+        !self.data.is_empty()
+            || self.operands.iter().any(|op| op.literal.is_none() && op.kind != OperandKind::Constant && op.expr.first_label().is_some())
+    }
+
+    /// Evaluates every expression which uses labels,
+    /// given the statements parsed from the first rewrite of the source.
+    pub(crate) fn resolve(&mut self, ast: &[Stmt]) {
+        // Find the address of every statement and label:
+        let data_at: BTreeMap<_, _> = self.data.iter().enumerate().map(|(i, d)| (d.span.start, i)).collect();
+        let mut stmt_addrs = vec![];
+        let mut labels = HashMap::new();
+        let mut externals = HashSet::new();
+        let mut pc = None;
+        for stmt in ast {
+            match &stmt.nucleus {
+                StmtKind::Directive(Directive::Orig(addr)) => { pc.replace(addr.get()); },
+                StmtKind::Directive(Directive::End) => { pc.take(); },
+                StmtKind::Directive(Directive::External(label)) => { externals.insert(label.name.to_uppercase()); },
+                _ => {},
+            }
+            if let Some(addr) = pc {
+                labels.extend(stmt.labels.iter().map(|l| (l.name.to_uppercase(), addr)));
+                stmt_addrs.push((stmt.span.clone(), addr));
+
+                // The placeholders of data directives don't always have the right size:
+                let len = match data_at.range(stmt.span.clone()).next() {
+                    Some((_, &i)) => {
+                        let data = &mut self.data[i];
+                        data.addr = Some(addr);
+                        if let DataKind::Align(align, padding) = &mut data.kind {
+                            padding.replace((*align - addr % *align) % *align);
+                        }
+                        data.len().unwrap_or(0)
+                    },
+                    None => stmt_len(stmt),
+                };
+                pc = Some(addr.wrapping_add(len));
+            }
+        }
+
+        let label_value = |name: &str, span: Range<usize>| {
+            let upper = name.to_uppercase();
+            match labels.get(&upper) {
+                Some(&addr) => Ok(i64::from(addr)),
+                None if externals.contains(&upper) => Err(ExprError { kind: ExprErrKind::External(name.to_string()), span }),
+                None => Err(ExprError { kind: ExprErrKind::Undefined(name.to_string()), span }),
+            }
+        };
+        for word in self.data.iter_mut().flat_map(Data::words_mut) {
+            if word.value.is_some() || word.expr.first_label().is_none() { continue; }
+            match word.expr.eval(&word.span, &label_value) {
+                Ok(value @ -0x8000..=0xFFFF) => word.value = Some(value as u16),
+                Ok(value) => self.errors.push(ExprError { kind: ExprErrKind::OutOfRange(value), span: word.span.clone() }),
+                Err(e) => self.errors.push(e),
+            }
+        }
+        for op in &mut self.operands {
+            if op.literal.is_some() || op.expr.first_label().is_none() || op.kind == OperandKind::Constant { continue; }
+
+            let value = match op.expr.eval(&op.span, &label_value) {
+                Ok(value) => value,
+                Err(e) => {
+                    self.errors.push(e);
+                    continue;
+                },
+            };
+            op.literal = match op.kind {
+                // The offset from the PC (the address after the statement):
+                OperandKind::PcOffset => {
+                    let Some(&(_, addr)) = stmt_addrs.iter().find(|(s, _)| s.start <= op.span.start && op.span.end <= s.end) else { continue };
+                    Some(value - i64::from(addr) - 1)
+                },
+                OperandKind::Value | OperandKind::Constant => Some(value),
+            };
+        }
+    }
+
+    /// Rewrites the source, replacing every expression with its literal
+    /// (or a placeholder, if its value isn't known yet) and removing constant definitions.
+    pub(crate) fn rewrite(&self, src: &str) -> Rewritten {
+        let mut changes: Vec<(Range<usize>, Cow<str>)> = self.definitions.iter()
+            .map(|s| (s.clone(), Cow::Owned(" ".repeat(s.len()))))
+            .chain(self.operands.iter().map(|op| {
+                let literal = match (op.literal, op.kind) {
+                    (Some(n), _) => format!("#{n}"),
+                    // Placeholders must keep the addresses of labels the same:
+                    (None, OperandKind::Constant) => String::from("#1"),
+                    (None, _) => String::from("#0"),
+                };
+                (op.span.clone(), Cow::Owned(literal))
+            }))
+            .chain(self.renames.iter().enumerate().map(|(i, (span, _))| (span.clone(), Cow::Owned(format!("{LOCAL_PREFIX}{i}")))))
+            .chain(self.relocs.iter().map(|(span, addr)| (span.clone(), Cow::Owned(format!(".ORIG x{addr:04X}")))))
+            .chain(self.data.iter().map(|data| {
+                let placeholder = match data.len() {
+                    // Placeholders must keep the addresses of labels the same:
+                    None => String::from(".BLKW #1"),
+                    Some(0) => " ".repeat(data.span.len()),
+                    Some(n) => format!(".BLKW #{n}"),
+                };
+                (data.span.clone(), Cow::Owned(placeholder))
+            }))
+            .collect();
+        changes.sort_by_key(|(s, _)| s.start);
+
+        let mut text = String::with_capacity(src.len());
+        let mut edits = vec![];
+        let mut last = 0;
+        for (span, replacement) in changes {
+            text.push_str(&src[last..span.start]);
+            let start = text.len();
+            text.push_str(&replacement);
+            edits.push(Edit { orig: span.clone(), new: start..text.len() });
+            last = span.end;
+        }
+        text.push_str(&src[last..]);
+
+        let names = self.renames.iter().map(|(_, name)| name.clone()).collect();
+        Rewritten { text, edits, names }
+    }
+
+    /// The words of every data directive (and their addresses), to be written after assembling.
+    pub(crate) fn data_words(&self) -> Vec<DataWords> {
+        self.data.iter()
+            .filter_map(|data| {
+                let words = match &data.kind {
+                    DataKind::Words(words) => words.iter().map(|w| w.value).collect::<Option<_>>()?,
+                    DataKind::Fill(n, word) => vec![word.value?; usize::from(*n)],
+                    DataKind::Align(..) => return None,
+                };
+                Some(DataWords { span: data.span.clone(), addr: data.addr?, words })
+            })
+            .collect()
+    }
+
+    /// Takes the errors which occurred while evaluating.
+    pub(crate) fn take_errors(&mut self) -> Vec<ExprError> {
+        std::mem::take(&mut self.errors)
+    }
+}
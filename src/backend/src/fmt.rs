@@ -0,0 +1,617 @@
+//! Formatting assembly source code into a canonical layout.
+//!
+//! The formatter parses the source and reprints every statement from its AST,
+//! aligning labels, opcodes, operands, and comments into columns.
+//! Comments and blank lines are kept where they were
+//! (parsing discards them, so they are recovered from the source text by line).
+//!
+//! Ensemble's parser doesn't know about the assembler's extensions (preprocessor directives, macros,
+//! constants, expressions, local labels, and the extra data directives), so lines which use them
+//! are split into columns by the same rules the assembler uses, keeping each label and operand as it was written.
+
+use std::collections::HashSet;
+use std::ops::Range;
+
+use lc3_ensemble::ast::asm::{AsmInstr, Directive, Stmt, StmtKind};
+use lc3_ensemble::ast::{ImmOrReg, PCOffset, Reg};
+use neon::prelude::*;
+
+use crate::asm::{self, AsmError};
+use crate::dialect::Dialect;
+use crate::{expr, preproc};
+
+/// The case that opcodes, directives, and registers are printed in.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub(crate) enum Case {
+    #[default]
+    Upper,
+    Lower,
+}
+/// How numeric literals are printed.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub(crate) enum LiteralStyle {
+    /// Keep the base each literal was written in (normalizing how it is written).
+    #[default]
+    Preserve,
+    /// Print every literal in hex (e.g., `x1F`).
+    Hex,
+    /// Print every literal in decimal (e.g., `#31`).
+    Decimal,
+}
+
+impl Case {
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "upper" => Some(Case::Upper),
+            "lower" => Some(Case::Lower),
+            _ => None,
+        }
+    }
+}
+impl LiteralStyle {
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "preserve" => Some(LiteralStyle::Preserve),
+            "hex" => Some(LiteralStyle::Hex),
+            "decimal" => Some(LiteralStyle::Decimal),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct FormatOptions {
+    pub(crate) case: Case,
+    pub(crate) literals: LiteralStyle,
+}
+impl FormatOptions {
+    /// Reads the options from a JS object (e.g., `{ case: "lower", literals: "hex" }`).
+    pub(crate) fn from_js<'a>(cx: &mut impl Context<'a>, obj: Handle<'a, JsObject>) -> NeonResult<Self> {
+        let mut opts = FormatOptions::default();
+
+        if let Some(case) = obj.get_opt::<JsString, _, _>(cx, "case")? {
+            let case = case.value(cx);
+            opts.case = match Case::from_name(&case) {
+                Some(c) => c,
+                None => return cx.throw_error(format!("unknown case {case:?}")),
+            };
+        }
+        if let Some(literals) = obj.get_opt::<JsString, _, _>(cx, "literals")? {
+            let literals = literals.value(cx);
+            opts.literals = match LiteralStyle::from_name(&literals) {
+                Some(l) => l,
+                None => return cx.throw_error(format!("unknown literal style {literals:?}")),
+            };
+        }
+
+        Ok(opts)
+    }
+}
+
+/// A numeric base.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Base { Hex, Decimal }
+
+/// A line of source, broken into its columns.
+#[derive(Default)]
+struct Line {
+    labels: Vec<String>,
+    opcode: Option<String>,
+    operands: Vec<String>,
+    comment: Option<String>,
+    /// Whether the comment is the only thing on the line and starts at the beginning of the line.
+    flush_comment: bool,
+}
+
+struct Formatter<'a> {
+    src: &'a str,
+    opts: FormatOptions,
+}
+impl Formatter<'_> {
+    fn keyword(&self, s: &str) -> String {
+        match self.opts.case {
+            Case::Upper => s.to_uppercase(),
+            Case::Lower => s.to_lowercase(),
+        }
+    }
+    fn reg(&self, r: Reg) -> String {
+        self.keyword(&r.to_string())
+    }
+
+    /// Prints a numeric literal, using the base of its source token if literals are preserved.
+    fn literal(&self, value: i32, token: Option<&str>, default: Base, hex_width: usize) -> String {
+        let base = match self.opts.literals {
+            LiteralStyle::Hex => Base::Hex,
+            LiteralStyle::Decimal => Base::Decimal,
+            LiteralStyle::Preserve => match token {
+                Some(t) if t.starts_with(['x', 'X']) => Base::Hex,
+                Some(_) => Base::Decimal,
+                None => default,
+            },
+        };
+
+        match base {
+            Base::Hex if value < 0 => format!("x-{:X}", -value),
+            Base::Hex => format!("x{value:0hex_width$X}"),
+            Base::Decimal => format!("#{value}"),
+        }
+    }
+    /// Prints an operand which is either a label or a numeric offset.
+    fn pc_offset<const N: u32>(&self, off: &PCOffset<i16, N>, token: Option<&str>) -> String {
+        match off {
+            PCOffset::Offset(o) => self.literal(i32::from(o.get()), token, Base::Decimal, 0),
+            PCOffset::Label(l) => l.name.clone(),
+        }
+    }
+    fn br_mnemonic(&self, cc: u8, token: &str) -> String {
+        // A lone `BR` stays as it is (rather than being expanded to `BRnzp`).
+        if cc == 0b111 && token.eq_ignore_ascii_case("BR") {
+            return self.keyword("BR");
+        }
+
+        let mut flags = String::new();
+        if cc & 0b100 != 0 { flags.push('n'); }
+        if cc & 0b010 != 0 { flags.push('z'); }
+        if cc & 0b001 != 0 { flags.push('p'); }
+        match self.opts.case {
+            Case::Upper => format!("BR{flags}"),
+            Case::Lower => format!("br{flags}"),
+        }
+    }
+
+    /// Prints a statement's opcode and operands.
+    fn stmt(&self, stmt: &Stmt) -> (String, Vec<String>) {
+        let text = self.src.get(stmt.span.clone()).unwrap_or("");
+        // Every operand (except for strings) is separated by commas or whitespace:
+        let tokens: Vec<_> = text.split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|t| !t.is_empty())
+            .collect();
+        let tok = |i: usize| tokens.get(i).copied();
+        let imm = |v: i16, i: usize| self.literal(i32::from(v), tok(i), Base::Decimal, 0);
+
+        let kw = |s: &str| self.keyword(s);
+        match &stmt.nucleus {
+            StmtKind::Instr(instr) => match instr {
+                AsmInstr::ADD(dr, sr1, sr2) | AsmInstr::AND(dr, sr1, sr2) => {
+                    let op = if matches!(instr, AsmInstr::ADD(..)) { "ADD" } else { "AND" };
+                    let sr2 = match sr2 {
+                        ImmOrReg::Imm(i) => imm(i.get(), 3),
+                        ImmOrReg::Reg(r) => self.reg(*r),
+                    };
+                    (kw(op), vec![self.reg(*dr), self.reg(*sr1), sr2])
+                },
+                AsmInstr::BR(cc, off) => (self.br_mnemonic(*cc, tok(0).unwrap_or("")), vec![self.pc_offset(off, tok(1))]),
+                AsmInstr::JMP(br)  => (kw("JMP"), vec![self.reg(*br)]),
+                AsmInstr::JSR(off) => (kw("JSR"), vec![self.pc_offset(off, tok(1))]),
+                AsmInstr::JSRR(br) => (kw("JSRR"), vec![self.reg(*br)]),
+                AsmInstr::LD(r, off)  => (kw("LD"),  vec![self.reg(*r), self.pc_offset(off, tok(2))]),
+                AsmInstr::LDI(r, off) => (kw("LDI"), vec![self.reg(*r), self.pc_offset(off, tok(2))]),
+                AsmInstr::LEA(r, off) => (kw("LEA"), vec![self.reg(*r), self.pc_offset(off, tok(2))]),
+                AsmInstr::ST(r, off)  => (kw("ST"),  vec![self.reg(*r), self.pc_offset(off, tok(2))]),
+                AsmInstr::STI(r, off) => (kw("STI"), vec![self.reg(*r), self.pc_offset(off, tok(2))]),
+                AsmInstr::LDR(r, br, off) => (kw("LDR"), vec![self.reg(*r), self.reg(*br), imm(off.get(), 3)]),
+                AsmInstr::STR(r, br, off) => (kw("STR"), vec![self.reg(*r), self.reg(*br), imm(off.get(), 3)]),
+                AsmInstr::NOT(dr, sr) => (kw("NOT"), vec![self.reg(*dr), self.reg(*sr)]),
+                AsmInstr::RET => (kw("RET"), vec![]),
+                AsmInstr::RTI => (kw("RTI"), vec![]),
+                // Trap vectors are always written in hex.
+                AsmInstr::TRAP(vect) => (kw("TRAP"), vec![format!("x{:02X}", vect.get())]),
+                AsmInstr::NOP(off) => match off {
+                    PCOffset::Offset(o) if o.get() == 0 => (kw("NOP"), vec![]),
+                    off => (kw("NOP"), vec![self.pc_offset(off, tok(1))]),
+                },
+                AsmInstr::GETC  => (kw("GETC"), vec![]),
+                AsmInstr::OUT   => (kw("OUT"), vec![]),
+                AsmInstr::PUTC  => (kw("PUTC"), vec![]),
+                AsmInstr::PUTS  => (kw("PUTS"), vec![]),
+                AsmInstr::IN    => (kw("IN"), vec![]),
+                AsmInstr::PUTSP => (kw("PUTSP"), vec![]),
+                AsmInstr::HALT  => (kw("HALT"), vec![]),
+            },
+            StmtKind::Directive(directive) => match directive {
+                // Addresses are always written in hex.
+                Directive::Orig(addr) => (kw(".ORIG"), vec![format!("x{:04X}", addr.get())]),
+                Directive::Fill(PCOffset::Label(l)) => (kw(".FILL"), vec![l.name.clone()]),
+                Directive::Fill(PCOffset::Offset(o)) => {
+                    // Negative values are written as such (in either base).
+                    let value = match tok(1) {
+                        Some(t) if t.trim_start_matches(['#', 'x', 'X']).starts_with('-') => i32::from(o.get() as i16),
+                        _ => i32::from(o.get()),
+                    };
+                    (kw(".FILL"), vec![self.literal(value, tok(1), Base::Hex, 4)])
+                },
+                Directive::Blkw(n) => (kw(".BLKW"), vec![self.literal(i32::from(n.get()), tok(1), Base::Decimal, 0)]),
+                Directive::Stringz(s) => (kw(".STRINGZ"), vec![escape_string(s)]),
+                Directive::End => (kw(".END"), vec![]),
+                Directive::External(l) => (kw(".EXTERNAL"), vec![l.name.clone()]),
+            },
+        }
+    }
+}
+
+impl Formatter<'_> {
+    /// Prints an opcode written in a line using the assembler's extensions
+    /// (keeping macro names as they were written).
+    fn opcode(&self, token: &str) -> String {
+        match () {
+            _ if expr::is_instruction(token) && token.len() >= 2 && token[..2].eq_ignore_ascii_case("BR") => {
+                format!("{}{}", self.keyword("BR"), token[2..].to_lowercase())
+            },
+            _ if expr::is_instruction(token) || expr::is_directive(token) || preproc::is_directive(token) => self.keyword(token),
+            _ => token.to_string(),
+        }
+    }
+    /// Prints an operand written in a line using the assembler's extensions.
+    fn operand(&self, token: &str) -> String {
+        match expr::is_register(token) {
+            true  => self.keyword(token),
+            false => token.to_string(),
+        }
+    }
+
+    /// Splits a line using the assembler's extensions into its columns
+    /// (given the names of the macros defined in the source, in uppercase).
+    fn extended_line(&self, text: &str, macros: &HashSet<String>) -> Line {
+        let tokens = preproc::tokenize(text);
+        let is_macro = |i: usize| tokens.get(i).is_some_and(|(_, t)| macros.contains(&t.to_uppercase()));
+
+        let mut line = match () {
+            // Preprocessor directives and macro invocations are read like the preprocessor reads them:
+            _ if tokens.first().is_some_and(|(_, t)| preproc::is_directive(t)) => self.invocation(&tokens, 0),
+            _ if is_macro(0) => self.invocation(&tokens, 0),
+            _ if is_macro(1) => self.invocation(&tokens, 1),
+            _ => match expr::split_line(text) {
+                Some(parts) if !parts.opcode.is_empty() => Line {
+                    labels: parts.labels.iter().map(|(_, l)| l.to_string()).collect(),
+                    opcode: Some(self.opcode(parts.opcode)),
+                    operands: parts.operands.iter().map(|(_, o)| self.operand(o)).collect(),
+                    ..Line::default()
+                },
+                // Anything else with several words is taken to be a macro invocation
+                // (of a macro defined in another file):
+                Some(parts) if parts.labels.len() > 1 => self.invocation(&tokens, 0),
+                Some(parts) => Line { labels: parts.labels.iter().map(|(_, l)| l.to_string()).collect(), ..Line::default() },
+                None => Line::default(),
+            },
+        };
+
+        let comment = text[expr::code_end(text)..].trim_end();
+        if !comment.is_empty() {
+            line.comment = Some(comment.to_string());
+        }
+        line
+    }
+    /// Lays out a preprocessor directive or macro invocation (whose name is the token at `name`),
+    /// with its arguments as operands.
+    fn invocation(&self, tokens: &[(Range<usize>, &str)], name: usize) -> Line {
+        let (_, opcode) = tokens[name];
+        let args = tokens[name + 1..].iter().map(|&(_, t)| t);
+        let operands = match () {
+            // A macro's name is separated from its parameters by a space:
+            _ if opcode.eq_ignore_ascii_case(".MACRO") => {
+                let mut operands: Vec<_> = args.map(String::from).collect();
+                if operands.len() > 1 {
+                    let name = operands.remove(0);
+                    operands[0] = format!("{name} {}", operands[0]);
+                }
+                operands
+            },
+            _ if preproc::is_directive(opcode) => args.map(String::from).collect(),
+            _ => args.map(|a| self.operand(a)).collect(),
+        };
+
+        Line {
+            labels: tokens[..name].iter().map(|(_, t)| t.to_string()).collect(),
+            opcode: Some(self.opcode(opcode)),
+            operands,
+            ..Line::default()
+        }
+    }
+}
+
+/// Quotes a string, escaping any characters that need it.
+pub(crate) fn escape_string(s: &str) -> String {
+    let mut out = String::from('"');
+    for c in s.chars() {
+        match c {
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\\' => out.push_str("\\\\"),
+            '\0' => out.push_str("\\0"),
+            '"'  => out.push_str("\\\""),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// The byte ranges of each line in the source (excluding the line terminator).
+fn line_ranges(src: &str) -> Vec<Range<usize>> {
+    let mut ranges = vec![];
+    let mut start = 0;
+    for line in src.split_inclusive('\n') {
+        let content = line.trim_end_matches(['\n', '\r']);
+        ranges.push(start .. start + content.len());
+        start += line.len();
+    }
+    ranges
+}
+
+/// Checks that the source code parses (with the assembler's extensions).
+///
+/// Included files can't be read here, so lines which may invoke macros defined in them
+/// (lines without a known instruction or directive) aren't checked.
+fn check_syntax(src: &str) -> Result<(), AsmError> {
+    let (expanded, _) = preproc::preprocess(src, None);
+    let parsed = asm::parse_expanded(&expanded, Dialect::default());
+
+    let error = parsed.errors.into_iter()
+        .filter(AsmError::is_syntax)
+        .find(|e| {
+            let Some(span) = e.first_span().filter(|s| s.start < expanded.main_len()) else { return false };
+            let start = src[..span.start].rfind('\n').map_or(0, |i| i + 1);
+            let end = src[span.start..].find('\n').map_or(src.len(), |i| span.start + i);
+            expr::split_line(&src[start..end]).is_some_and(|parts| !parts.opcode.is_empty())
+        });
+    match error {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+/// Formats the source code.
+///
+/// This fails if the source code does not parse.
+pub(crate) fn format_source(src: &str, opts: FormatOptions) -> Result<String, AsmError> {
+    check_syntax(src)?;
+    let (ast, _, _) = asm::parse_recovering(src);
+    let fmt = Formatter { src, opts };
+
+    let ranges = line_ranges(src);
+    let line_of = |offset: usize| ranges.partition_point(|r| r.end < offset);
+
+    let mut lines: Vec<Line> = ranges.iter().map(|_| Line::default()).collect();
+    // The end of the last token on each line (comments start after it).
+    let mut token_ends: Vec<usize> = ranges.iter().map(|r| r.start).collect();
+
+    for stmt in &ast {
+        for label in &stmt.labels {
+            let ln = line_of(label.span().start);
+            lines[ln].labels.push(label.name.clone());
+            token_ends[ln] = token_ends[ln].max(label.span().end);
+        }
+
+        let ln = line_of(stmt.span.start);
+        let (opcode, operands) = fmt.stmt(stmt);
+        lines[ln].opcode = Some(opcode);
+        lines[ln].operands = operands;
+        token_ends[ln] = token_ends[ln].max(stmt.span.end);
+    }
+
+    // Lines which have code but weren't parsed use the assembler's extensions:
+    let macros: HashSet<_> = ranges.iter()
+        .filter_map(|r| match &*preproc::tokenize(&src[r.clone()]) {
+            [(_, directive), (_, name), ..] if directive.eq_ignore_ascii_case(".MACRO") => Some(name.to_uppercase()),
+            _ => None,
+        })
+        .collect();
+    for ((line, range), token_end) in lines.iter_mut().zip(&ranges).zip(&mut token_ends) {
+        let text = &src[range.clone()];
+        if line.labels.is_empty() && line.opcode.is_none() && !text[..expr::code_end(text)].trim().is_empty() {
+            *line = fmt.extended_line(text, &macros);
+            *token_end = range.end;
+        }
+    }
+
+    for ((line, range), token_end) in lines.iter_mut().zip(&ranges).zip(token_ends) {
+        let rest = &src[token_end.min(range.end) .. range.end];
+        if let Some(i) = rest.find(';') {
+            line.comment = Some(rest[i..].trim_end().to_string());
+            line.flush_comment = line.labels.is_empty() && line.opcode.is_none() && token_end + i == range.start;
+        }
+    }
+
+    // Compute the width of each column:
+    let label_width = lines.iter()
+        .map(|l| l.labels.join(" ").len() + 1)
+        .max()
+        .unwrap_or(0)
+        .max(4);
+    let opcode_width = lines.iter()
+        .filter_map(|l| l.opcode.as_ref().map(|o| o.len() + 1))
+        .max()
+        .unwrap_or(0);
+    let comment_col = lines.iter()
+        .filter(|l| l.opcode.is_some())
+        .map(|l| label_width + opcode_width + l.operands.join(", ").len() + 1)
+        .max()
+        .unwrap_or(label_width);
+
+    let mut out = String::new();
+    for line in &lines {
+        let mut buf = String::new();
+        match (&line.opcode, line.labels.is_empty()) {
+            (Some(opcode), _) => {
+                buf.push_str(&format!("{:label_width$}{opcode:opcode_width$}{}", line.labels.join(" "), line.operands.join(", ")));
+            },
+            (None, false) => buf.push_str(&line.labels.join(" ")),
+            (None, true) => {},
+        }
+
+        if let Some(comment) = &line.comment {
+            let col = match () {
+                _ if line.flush_comment => 0,
+                _ if buf.is_empty() => label_width,
+                _ => comment_col,
+            };
+            let min_col = match buf.is_empty() {
+                true  => 0,
+                false => buf.trim_end().len() + 1,
+            };
+            buf = format!("{:width$}{comment}", buf.trim_end(), width = col.max(min_col));
+        }
+
+        out.push_str(buf.trim_end());
+        out.push('\n');
+    }
+
+    // Keep the same number of trailing newlines as the source (at most one):
+    if !src.ends_with('\n') {
+        out.pop();
+    }
+    Ok(out)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble_all;
+
+    /// Source using the assembler's extensions (macros, conditional assembly, constants,
+    /// expressions, local labels, and the extra data directives).
+    const EXTENDED: &str = "\
+; Extensions
+.macro push reg ; saves a register
+add r6,r6,#-1
+str \\reg,r6,#0
+.endm
+SIZE .equ 10
+.IF 1
+.orig x3000
+MAIN push r0
+  add r1,r1,#SIZE-1 ; expression
+  ld r2, BUF+2
+.loop add r1,r1,#-1
+  brp .loop
+  halt
+CH .fill 'A'
+BUF .blkw 10, #-1
+MSG .ascii \"hi; there\"
+  .fill 1,2,3
+.end
+.ENDIF
+";
+
+    #[test]
+    fn formats_extensions() {
+        let formatted = format_source(EXTENDED, FormatOptions::default()).expect("source should format");
+        assert_eq!(formatted, "\
+; Extensions
+      .MACRO push reg        ; saves a register
+      ADD    R6, R6, #-1
+      STR    \\reg, R6, #0
+      .ENDM
+SIZE  .EQU   10
+      .IF    1
+      .ORIG  x3000
+MAIN  push   R0
+      ADD    R1, R1, #SIZE-1 ; expression
+      LD     R2, BUF+2
+.loop ADD    R1, R1, #-1
+      BRp    .loop
+      HALT
+CH    .FILL  'A'
+BUF   .BLKW  10, #-1
+MSG   .ASCII \"hi; there\"
+      .FILL  1, 2, 3
+      .END
+      .ENDIF
+");
+    }
+
+    #[test]
+    fn formatting_is_idempotent() {
+        for case in [Case::Upper, Case::Lower] {
+            let opts = FormatOptions { case, ..FormatOptions::default() };
+            let once = format_source(EXTENDED, opts).expect("source should format");
+            let twice = format_source(&once, opts).expect("formatted source should format");
+            assert_eq!(once, twice);
+        }
+    }
+
+    #[test]
+    fn formatting_keeps_meaning() {
+        let assemble = |src: &str| {
+            let (expanded, _) = preproc::preprocess(src, None);
+            let (obj, _) = assemble_all(&expanded, Dialect::default()).unwrap_or_else(|e| panic!("source should assemble: {e:?}"));
+            obj.addr_iter().collect::<Vec<_>>()
+        };
+
+        let formatted = format_source(EXTENDED, FormatOptions::default()).expect("source should format");
+        assert_eq!(assemble(EXTENDED), assemble(&formatted));
+    }
+
+    /// Source with literals in both bases, including negative ones.
+    const LITERALS: &str = "\
+.orig x3000
+add r0,r0,#-5
+and r1,r1,xF
+ld r2,#-2
+br #-1
+.fill x-10
+.fill #300
+.blkw 2
+.end
+";
+
+    #[test]
+    fn formats_literals() {
+        let format = |literals| format_source(LITERALS, FormatOptions { literals, ..FormatOptions::default() }).expect("source should format");
+
+        assert_eq!(format(LiteralStyle::Preserve), concat!(
+            "    .ORIG x3000\n",
+            "    ADD   R0, R0, #-5\n",
+            "    AND   R1, R1, xF\n",
+            "    LD    R2, #-2\n",
+            "    BR    #-1\n",
+            "    .FILL x-10\n",
+            "    .FILL #300\n",
+            "    .BLKW #2\n",
+            "    .END\n",
+        ));
+        // Negative literals keep their sign in hex (rather than being written in two's complement):
+        assert_eq!(format(LiteralStyle::Hex), concat!(
+            "    .ORIG x3000\n",
+            "    ADD   R0, R0, x-5\n",
+            "    AND   R1, R1, xF\n",
+            "    LD    R2, x-2\n",
+            "    BR    x-1\n",
+            "    .FILL x-10\n",
+            "    .FILL x012C\n",
+            "    .BLKW x2\n",
+            "    .END\n",
+        ));
+        // Addresses stay in hex:
+        assert_eq!(format(LiteralStyle::Decimal), concat!(
+            "    .ORIG x3000\n",
+            "    ADD   R0, R0, #-5\n",
+            "    AND   R1, R1, #15\n",
+            "    LD    R2, #-2\n",
+            "    BR    #-1\n",
+            "    .FILL #-16\n",
+            "    .FILL #300\n",
+            "    .BLKW #2\n",
+            "    .END\n",
+        ));
+    }
+
+    #[test]
+    fn formatting_literals_is_idempotent_and_keeps_meaning() {
+        let assemble = |src: &str| {
+            let (expanded, _) = preproc::preprocess(src, None);
+            let (obj, _) = assemble_all(&expanded, Dialect::default()).unwrap_or_else(|e| panic!("source should assemble: {e:?}"));
+            obj.addr_iter().collect::<Vec<_>>()
+        };
+
+        for literals in [LiteralStyle::Preserve, LiteralStyle::Hex, LiteralStyle::Decimal] {
+            let opts = FormatOptions { case: Case::Lower, literals };
+            let once = format_source(LITERALS, opts).expect("source should format");
+            let twice = format_source(&once, opts).expect("formatted source should format");
+            assert_eq!(once, twice, "{literals:?} formatting should be idempotent");
+            assert_eq!(assemble(LITERALS), assemble(&once), "{literals:?} formatting should keep the meaning");
+        }
+    }
+}
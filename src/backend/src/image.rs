@@ -0,0 +1,136 @@
+//! Memory images, which load a program into the ROM or RAM of a hardware LC-3
+//! (e.g., a datapath built in CircuitSim or Logisim, or written in Verilog).
+//!
+//! Each format is text, with the words of memory written in hex:
+//! - CircuitSim: a word per line, starting from address 0 (the format CircuitSim saves and loads RAM contents in)
+//! - Logisim: a `v2.0 raw` header and the words (starting from address 0), where runs of a word are written as `count*word`
+//! - Verilog: the format read by `$readmemh`, where each block of memory starts with `@address`
+//!
+//! The CircuitSim and Logisim formats can't skip addresses, so they include every word up to the program's last,
+//! with anything uninitialized (or outside the program) written as 0.
+
+/// A format a memory image can be written in.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum ImageFormat {
+    CircuitSim,
+    Logisim,
+    Verilog,
+}
+impl ImageFormat {
+    /// Finds the format with the given name (`circuitsim`, `logisim`, or `verilog`).
+    pub(crate) fn from_name(name: &str) -> Option<ImageFormat> {
+        match &*name.to_lowercase() {
+            "circuitsim" => Some(ImageFormat::CircuitSim),
+            "logisim"    => Some(ImageFormat::Logisim),
+            "verilog"    => Some(ImageFormat::Verilog),
+            _ => None,
+        }
+    }
+}
+
+/// The number of words written on each line of a Logisim image.
+const LOGISIM_LINE_LEN: usize = 8;
+
+/// Writes a memory image, given the words of memory in order of address
+/// (where uninitialized words are `None`).
+pub(crate) fn image(words: impl IntoIterator<Item=(u16, Option<u16>)>, format: ImageFormat) -> String {
+    let words: Vec<_> = words.into_iter().filter_map(|(addr, word)| Some((addr, word?))).collect();
+
+    match format {
+        ImageFormat::CircuitSim => dense(&words).into_iter()
+            .map(|word| format!("{word:04x}\n"))
+            .collect(),
+        ImageFormat::Logisim => {
+            // Run-length encode the words:
+            let mut runs: Vec<(usize, u16)> = vec![];
+            for word in dense(&words) {
+                match runs.last_mut() {
+                    Some((count, last)) if *last == word => *count += 1,
+                    _ => runs.push((1, word)),
+                }
+            }
+
+            let mut out = String::from("v2.0 raw\n");
+            for line in runs.chunks(LOGISIM_LINE_LEN) {
+                let line: Vec<_> = line.iter()
+                    .map(|&(count, word)| match count {
+                        1 => format!("{word:x}"),
+                        _ => format!("{count}*{word:x}"),
+                    })
+                    .collect();
+                out.push_str(&line.join(" "));
+                out.push('\n');
+            }
+            out
+        },
+        ImageFormat::Verilog => {
+            let mut out = String::new();
+            let mut next = None;
+            for &(addr, word) in &words {
+                if next != Some(addr) {
+                    out.push_str(&format!("@{addr:04x}\n"));
+                }
+                out.push_str(&format!("{word:04x}\n"));
+                next = addr.checked_add(1);
+            }
+            out
+        },
+    }
+}
+
+/// Fills in the gaps of the given words (from address 0 to the last word) with 0.
+fn dense(words: &[(u16, u16)]) -> Vec<u16> {
+    let len = words.last().map_or(0, |&(addr, _)| usize::from(addr) + 1);
+    let mut mem = vec![0; len];
+    for &(addr, word) in words {
+        mem[usize::from(addr)] = word;
+    }
+    mem
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Words at x0000-x0002 and x0005, with x0003 uninitialized (and x0004 not given at all).
+    const WORDS: [(u16, Option<u16>); 5] = [
+        (0x0000, Some(0x1234)),
+        (0x0001, Some(0x1234)),
+        (0x0002, Some(0x1234)),
+        (0x0003, None),
+        (0x0005, Some(0x00AB)),
+    ];
+
+    #[test]
+    fn finds_formats_by_name() {
+        assert_eq!(ImageFormat::from_name("circuitsim"), Some(ImageFormat::CircuitSim));
+        assert_eq!(ImageFormat::from_name("Logisim"), Some(ImageFormat::Logisim));
+        assert_eq!(ImageFormat::from_name("VERILOG"), Some(ImageFormat::Verilog));
+        assert_eq!(ImageFormat::from_name("hex"), None);
+    }
+
+    #[test]
+    fn writes_circuitsim_images() {
+        assert_eq!(image(WORDS, ImageFormat::CircuitSim), "1234\n1234\n1234\n0000\n0000\n00ab\n");
+        assert_eq!(image([], ImageFormat::CircuitSim), "");
+    }
+
+    #[test]
+    fn writes_logisim_images() {
+        assert_eq!(image(WORDS, ImageFormat::Logisim), "v2.0 raw\n3*1234 2*0 ab\n");
+        assert_eq!(image([], ImageFormat::Logisim), "v2.0 raw\n");
+
+        // Runs (not words) are wrapped onto lines:
+        let words = (0..10).map(|addr| (addr, Some(addr % 9)));
+        assert_eq!(image(words, ImageFormat::Logisim), "v2.0 raw\n0 1 2 3 4 5 6 7\n8 0\n");
+    }
+
+    #[test]
+    fn writes_verilog_images() {
+        assert_eq!(image(WORDS, ImageFormat::Verilog), "@0000\n1234\n1234\n1234\n@0005\n00ab\n");
+        assert_eq!(image([], ImageFormat::Verilog), "");
+
+        let words = [(0x3000, Some(1)), (0x3001, Some(2)), (0x3002, None), (0x3003, Some(3)), (0xFFFF, Some(4))];
+        assert_eq!(image(words, ImageFormat::Verilog), "@3000\n0001\n0002\n@3003\n0003\n@ffff\n0004\n");
+    }
+}
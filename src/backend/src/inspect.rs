@@ -0,0 +1,305 @@
+//! Inspecting object files (describing what's in them without loading them).
+//!
+//! Whether a section is code or data is estimated the same way the disassembler does it
+//! (see [`crate::disasm`]): words reached by following control flow from the start of each block are code,
+//! and everything else is data.
+
+use std::path::Path;
+
+use lc3_ensemble::asm::ObjectFile;
+use neon::prelude::*;
+
+use crate::cast::TryIntoJsValue;
+use crate::{archive, classic, disasm, link, obj, reloc};
+
+/// A section of an inspected object file.
+#[derive(Debug)]
+struct SectionInfo {
+    origin: u16,
+    len: u16,
+    /// The number of words which are code.
+    code_words: u16,
+    /// The number of words which are data (including uninitialized words).
+    data_words: u16,
+    /// The name of the section, if it's a relocatable section
+    /// (`Some(None)` if it's a relocatable section with no labels).
+    relocatable: Option<Option<String>>,
+}
+impl SectionInfo {
+    /// The estimate of what the section holds.
+    fn kind(&self) -> &'static str {
+        match (self.code_words, self.data_words) {
+            (_, 0) => "code",
+            (0, _) => "data",
+            _ => "mixed",
+        }
+    }
+}
+impl TryIntoJsValue for SectionInfo {
+    type Value = JsObject;
+
+    fn try_into_js<'a>(self, cx: &mut impl Context<'a>) -> JsResult<'a, Self::Value> {
+        let obj = cx.empty_object();
+
+        let origin = cx.number(self.origin);
+        obj.set(cx, "origin", origin)?;
+        let len = cx.number(self.len);
+        obj.set(cx, "length", len)?;
+        let kind = cx.string(self.kind());
+        obj.set(cx, "kind", kind)?;
+        let code_words = cx.number(self.code_words);
+        obj.set(cx, "codeWords", code_words)?;
+        let data_words = cx.number(self.data_words);
+        obj.set(cx, "dataWords", data_words)?;
+        let relocatable = cx.boolean(self.relocatable.is_some());
+        obj.set(cx, "relocatable", relocatable)?;
+        let name = self.relocatable.flatten().try_into_js(cx)?;
+        obj.set(cx, "name", name)?;
+
+        Ok(obj)
+    }
+}
+
+/// A description of an object file.
+#[derive(Debug)]
+pub(crate) struct ObjInfo {
+    /// The format of the object file (`text`, `binary`, or `classic`).
+    format: &'static str,
+    /// The hash of the file's contents (see [`content_hash`]).
+    hash: String,
+    sections: Vec<SectionInfo>,
+    /// The labels the object file defines, in order of address.
+    labels: Vec<(String, u16)>,
+    /// The external labels of the object file, in order of name.
+    externals: Vec<String>,
+    /// Each source line (from 0) with the address it was assembled to, in order of line.
+    lines: Vec<(usize, u16)>,
+    has_source: bool,
+}
+impl TryIntoJsValue for ObjInfo {
+    type Value = JsObject;
+
+    fn try_into_js<'a>(self, cx: &mut impl Context<'a>) -> JsResult<'a, Self::Value> {
+        let obj = cx.empty_object();
+
+        let format = cx.string(self.format);
+        obj.set(cx, "format", format)?;
+        let hash = cx.string(self.hash);
+        obj.set(cx, "hash", hash)?;
+        let sections = self.sections.try_into_js(cx)?;
+        obj.set(cx, "sections", sections)?;
+
+        let labels = cx.empty_array();
+        for (i, (name, addr)) in self.labels.into_iter().enumerate() {
+            let label = cx.empty_object();
+            let name = cx.string(name);
+            label.set(cx, "name", name)?;
+            let addr = cx.number(addr);
+            label.set(cx, "addr", addr)?;
+            labels.set(cx, i as u32, label)?;
+        }
+        obj.set(cx, "labels", labels)?;
+
+        let externals = self.externals.try_into_js(cx)?;
+        obj.set(cx, "externals", externals)?;
+        let lines = self.lines.into_iter()
+            .map(|(lno, addr)| [lno as f64, f64::from(addr)])
+            .collect::<Vec<_>>()
+            .try_into_js(cx)?;
+        obj.set(cx, "lines", lines)?;
+        let has_source = cx.boolean(self.has_source);
+        obj.set(cx, "hasSource", has_source)?;
+
+        Ok(obj)
+    }
+}
+
+/// Hashes the contents of a file with 64-bit FNV-1a, as 16 hex digits.
+///
+/// This is for telling files apart, and isn't meant to resist tampering.
+fn content_hash(bytes: &[u8]) -> String {
+    let hash = bytes.iter().fold(0xcbf2_9ce4_8422_2325u64, |hash, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0000_0100_0000_01b3)
+    });
+    format!("{hash:016x}")
+}
+
+/// An error from inspecting an object file.
+#[derive(Debug)]
+pub(crate) enum InspectErr {
+    /// The file could not be read.
+    Io(std::io::Error),
+    /// The file is a library (see [`crate::archive`]) rather than an object file.
+    Library,
+    /// The file isn't an object file in any format.
+    Malformed,
+}
+impl InspectErr {
+    pub(crate) fn code(&self) -> &'static str {
+        match self {
+            InspectErr::Io(_)     => "io",
+            InspectErr::Library   => "inspect::library",
+            InspectErr::Malformed => "io",
+        }
+    }
+}
+impl std::fmt::Display for InspectErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InspectErr::Io(e) => e.fmt(f),
+            InspectErr::Library => f.write_str("this is a library, not an object file"),
+            InspectErr::Malformed => f.write_str("malformed object file"),
+        }
+    }
+}
+
+/// Inspects the object file at the given path (in any format [`crate::read_obj_file`] reads).
+pub(crate) fn inspect(path: &Path) -> Result<ObjInfo, InspectErr> {
+    // Libraries would otherwise be read as classic object files:
+    if archive::read(path).map_err(InspectErr::Io)?.is_some() {
+        return Err(InspectErr::Library);
+    }
+    let bytes = std::fs::read(path).map_err(InspectErr::Io)?;
+    let (obj_bytes, relocs) = reloc::split(&bytes);
+    let read = match std::str::from_utf8(obj_bytes) {
+        Ok(text) => obj::deserialize_text(text).map(|obj| (obj, "text")),
+        Err(_) => obj::deserialize_binary(obj_bytes).map(|obj| (obj, "binary")),
+    };
+    let Some((obj, format)) = read.or_else(|| Some((classic::read(obj_bytes, path)?, "classic"))) else {
+        return Err(InspectErr::Malformed);
+    };
+
+    Ok(ObjInfo {
+        format,
+        hash: content_hash(&bytes),
+        sections: sections(&obj, &relocs),
+        labels: {
+            let mut labels: Vec<_> = obj.symbol_table().into_iter()
+                .flat_map(|sym| sym.label_iter())
+                .filter(|&(_, _, external)| !external)
+                .map(|(name, addr, _)| (name.to_string(), addr))
+                .collect();
+            labels.sort_by(|(n1, a1), (n2, a2)| (a1, n1).cmp(&(a2, n2)));
+            labels
+        },
+        externals: {
+            let mut externals: Vec<_> = obj.symbol_table().into_iter()
+                .flat_map(|sym| sym.label_iter())
+                .filter(|&(_, _, external)| external)
+                .map(|(name, _, _)| name.to_string())
+                .collect();
+            externals.sort();
+            externals
+        },
+        lines: {
+            let mut lines: Vec<_> = obj.symbol_table().into_iter()
+                .flat_map(|sym| sym.line_iter())
+                .collect();
+            lines.sort();
+            lines
+        },
+        has_source: obj.symbol_table().is_some_and(|sym| sym.source_info().is_some()),
+    })
+}
+
+/// Describes the sections of an object file (with its relocatable sections).
+fn sections(obj: &ObjectFile, relocs: &reloc::Relocs) -> Vec<SectionInfo> {
+    let code = disasm::find_code(obj);
+
+    link::sections(obj).into_iter()
+        .map(|s| {
+            let code_words = (0..s.len)
+                .filter(|&i| code.contains(&s.start.wrapping_add(i)))
+                .count() as u16;
+            let relocatable = relocs.sections.iter()
+                .find(|r| r.start == s.start)
+                .map(|r| r.name.clone());
+            SectionInfo { origin: s.start, len: s.len, code_words, data_words: s.len - code_words, relocatable }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::asm::tests::assemble_relocatable;
+    use crate::obj::{ObjFormat, ObjOutput};
+    use crate::preproc::tests::temp_dir;
+
+    const PROGRAM: &str = ".ORIG x3000\n.EXTERNAL PRINT\nMAIN LDI R0, PTR\nJSRR R0\nHALT\nPTR .FILL PRINT\n.END\n.RELOC\nTABLE .FILL 1\n.FILL 2\n.END\n";
+
+    /// Writes an object file into a new directory, returning its path.
+    fn write(name: &str, src: &str, output: ObjOutput) -> PathBuf {
+        let (obj, relocs) = assemble_relocatable(src);
+        let path = temp_dir(name).join("prog.obj");
+        for (p, bytes) in output.files(&obj, &relocs, &path).expect("object file should be serialized") {
+            std::fs::write(p, bytes).expect("object file should be written");
+        }
+        path
+    }
+
+    #[test]
+    fn inspects_object_files() {
+        let path = write("inspect", PROGRAM, ObjOutput::default());
+        let info = inspect(&path).expect("object file should be inspected");
+        assert_eq!(info.format, "text");
+
+        let sections: Vec<_> = info.sections.iter()
+            .map(|s| (s.origin, s.len, s.kind(), s.code_words, s.relocatable.clone()))
+            .collect();
+        assert_eq!(sections, [
+            (0x3000, 4, "mixed", 3, None),
+            (0x3004, 2, "data", 0, Some(Some(String::from("TABLE")))),
+        ]);
+        assert_eq!(info.labels, [(String::from("MAIN"), 0x3000), (String::from("PTR"), 0x3003), (String::from("TABLE"), 0x3004)]);
+        assert_eq!(info.externals, ["PRINT"]);
+        assert_eq!(info.lines.first(), Some(&(1, 0x3000)));
+        assert!(info.lines.is_sorted());
+        assert!(info.has_source);
+
+        // The hash only depends on the contents of the file:
+        let same = write("inspect-same", PROGRAM, ObjOutput::default());
+        assert_eq!(inspect(&same).expect("object file should be inspected").hash, info.hash);
+        let other = write("inspect-other", &PROGRAM.replace(".FILL 2", ".FILL 3"), ObjOutput::default());
+        assert_ne!(inspect(&other).expect("object file should be inspected").hash, info.hash);
+    }
+
+    #[test]
+    fn inspects_every_format() {
+        const PLAIN: &str = ".ORIG x3000\nMAIN ADD R0, R0, #1\nHALT\n.END\n";
+        for (format, strip, expected) in [
+            (ObjFormat::Text, false, "text"),
+            (ObjFormat::Binary, false, "binary"),
+            (ObjFormat::Binary, true, "binary"),
+            (ObjFormat::Classic, false, "classic"),
+        ] {
+            let path = write(&format!("inspect-{expected}-{strip}"), PLAIN, ObjOutput { format, strip });
+            let info = inspect(&path).unwrap_or_else(|e| panic!("{expected} object file should be inspected: {e}"));
+            assert_eq!(info.format, expected);
+            assert_eq!(info.sections.iter().map(|s| (s.origin, s.len, s.kind())).collect::<Vec<_>>(), [(0x3000, 2, "code")]);
+            // Classic files keep their labels (in the .sym file), but not their source:
+            assert_eq!(info.labels.is_empty(), strip, "{expected}");
+            assert_eq!(info.has_source, format != ObjFormat::Classic && !strip, "{expected}");
+        }
+    }
+
+    #[test]
+    fn libraries_and_malformed_files_are_errors() {
+        let dir = temp_dir("inspect-errors");
+        let (obj, relocs) = assemble_relocatable(PROGRAM);
+        let library = dir.join("prog.lib");
+        std::fs::write(&library, archive::write(&[(dir.join("prog.obj"), obj, relocs)])).expect("library should be written");
+        let e = inspect(&library).expect_err("library should not be inspected");
+        assert!(matches!(e, InspectErr::Library), "{e:?}");
+        assert_eq!(e.code(), "inspect::library");
+        assert_eq!(e.to_string(), "this is a library, not an object file");
+
+        let malformed = dir.join("bad.obj");
+        std::fs::write(&malformed, "LC-3 OBJ FILE\n.TEXT\nnot hex\n").expect("file should be written");
+        assert!(matches!(inspect(&malformed), Err(InspectErr::Malformed)));
+
+        assert!(matches!(inspect(&dir.join("missing.obj")), Err(InspectErr::Io(_))));
+    }
+}
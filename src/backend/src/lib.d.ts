@@ -1,22 +1,460 @@
 declare module "lc3-backend" {
+    /**
+     * A structured report of an error (or warning) from the backend.
+     * 
+     * Errors thrown by the backend also have these fields.
+     */
+    export interface Diagnostic {
+        severity: "error" | "warning" | "info",
+        message: string,
+        /**
+         * The path of the file this diagnostic occurred in (if there is one).
+         */
+        file?: string,
+        /**
+         * The source ranges this diagnostic points to (may be empty).
+         */
+        ranges: [start_lno: number, start_cno: number, end_lno: number, end_cno: number][],
+        help?: string,
+        /**
+         * An identifier for the kind of diagnostic (e.g., `asm::duplicate-label`).
+         */
+        code?: string
+    }
+    /**
+     * A word on the stack.
+     */
+    export interface StackSlot {
+        addr: number,
+        value: number,
+        kind: "local" | "savedFP" | "returnAddr" | "returnValue" | "argument",
+        /**
+         * The argument's name (if it was declared with `setSubroutineDef`).
+         */
+        name?: string
+    }
+    /**
+     * A disassembled word of memory.
+     */
+    export interface DisasmLine {
+        addr: number,
+        value: number,
+        /**
+         * The label at this address (from the loaded object file or the OS).
+         */
+        label?: string,
+        /**
+         * The disassembled instruction, with PC offsets replaced by labels where possible
+         * (e.g., `BRnz LOOP`). Words which are not instructions are shown as `.FILL`s.
+         */
+        text: string,
+        /**
+         * The absolute address of the instruction's PC-relative target (e.g., `x3005`),
+         * or, for data, the address or character the word holds.
+         */
+        comment?: string
+    }
+    /**
+     * A subroutine (or trap) frame on the stack.
+     */
+    export interface StackFrame {
+        /**
+         * The label of the subroutine (or trap handler) this frame belongs to.
+         */
+        label?: string,
+        callerAddr: number,
+        calleeAddr: number,
+        /**
+         * The frame pointer (R5) of this frame, if it could be determined.
+         */
+        framePtr?: number,
+        /**
+         * Whether the saved return address points back to the caller.
+         */
+        raMatches: boolean,
+        /**
+         * The slots of the frame, from the top of the stack (lowest address) downward.
+         */
+        slots: StackSlot[]
+    }
+
+    /**
+     * The lint checks which can be run after assembling.
+     */
+    export type LintName =
+        | "missing-halt"
+        | "unreachable-code"
+        | "unused-label"
+        | "fall-through-data"
+        | "os-overlap"
+        | "offset-near-limit"
+        | "nested-jsr"
+        | "br-no-cc";
+    /**
+     * The assemblers whose source can be assembled:
+     * - `"lc3tools"`: this assembler
+     * - `"lc3as"`: the `lc3as` assembler which accompanies the textbook
+     * - `"pennsim"`: PennSim's assembler (which has case-sensitive labels and doesn't require `.END`)
+     * - `"lc3tools1"`: LC3Tools 1.x (which also accepts `0x`/`b` literals and doesn't require `.END`)
+     * 
+     * Labels which only differ in case (e.g., `loop` and `LOOP`) are different labels in PennSim,
+     * but this assembler can't tell them apart, so they're reported as unsupported (`dialect::unsupported-label-case`).
+     */
+    export type Dialect = "lc3tools" | "lc3as" | "pennsim" | "lc3tools1";
+    export interface ObjectOutputOptions {
+        /**
+         * The format the object file is written in (default: `"text"`).
+         * 
+         * `"classic"` writes the `.obj`, `.sym`, `.bin`, and `.hex` files of `lc3as` (next to the output path),
+         * which can only hold a single `.ORIG` block and can't have external labels.
+         */
+        format?: "text" | "binary" | "classic",
+        /**
+         * Whether to strip the object file of its labels and source code (default: `false`),
+         * e.g., to hand out a program without its source.
+         * 
+         * Stripped object files keep their external labels, so they can still be linked.
+         */
+        strip?: boolean
+    }
+    export interface AssembleOptions extends ObjectOutputOptions {
+        /**
+         * Enables or disables individual lints (all lints are enabled by default).
+         */
+        lints?: Partial<Record<LintName, boolean>>,
+        /**
+         * The dialect of the source (default: `"lc3tools"`).
+         * 
+         * In any other dialect, anything which that dialect doesn't accept (e.g., `.EQU` in `"lc3as"`) is an error,
+         * and anything which another dialect doesn't accept (e.g., `LABEL:` in `"lc3tools1"`) is reported as a warning.
+         */
+        dialect?: Dialect,
+        /**
+         * Whether to also write a listing file (`.lst`) next to the `.obj` file (default: `false`).
+         * 
+         * Each row of the listing has the address, machine code (in hex and binary), label,
+         * and source line of a statement, and the listing ends with the symbol table.
+         */
+        listing?: boolean
+    }
+
     /**
      * Takes a `.asm` file and creates and exports a
      * `.obj` file out of it.
+     * 
+     * The file is preprocessed first, so it can use `.INCLUDE "file.asm"`,
+     * macros (`.MACRO NAME params...` to `.ENDM`), and conditional assembly (`.IF`/`.ELSE`/`.ENDIF`,
+     * whose conditions can use literals and the constants defined before them).
+     * Errors in included files are reported in those files,
+     * and errors in macro expansions are reported at the macro's invocation.
+     * 
+     * Constants can be defined with `.EQU` (or `.SET`, which can be redefined),
+     * and operands can be expressions of literals, character literals, constants, and labels
+     * (e.g., `LD R0, BUFFER+2`, `.FILL END-START`, or `ADD R1, R1, #SIZE-1`).
+     * 
+     * Besides the standard directives, data can be written with `.FILL 1, 2, 3` (several words),
+     * `.BLKW 10, #-1` (a block with a fill value), `.ASCII "text"` (a string without a null terminator),
+     * and `.ALIGN n` (padding to the next multiple of `n`).
+     * 
+     * A block can start with `.RELOC` instead of `.ORIG address` to be relocatable,
+     * in which case {@linkcode link} places it (named by its first label) in memory.
+     * Until then, it's placed in the first free space from x3000.
+     * PC offsets and data (e.g., `.FILL LABEL`) can refer to relocatable blocks,
+     * but other operands (e.g., `ADD R0, R0, LABEL-START`) can only do so within the same block,
+     * and relocatable blocks can't use `.ALIGN` (since its padding depends on where the block is placed).
+     * Relocatable blocks can't be written in the `"classic"` format.
+     * 
+     * Labels starting with `.` (e.g., `.loop`) are local to the last global label before them,
+     * and numeric labels (e.g., `1:`) can be defined repeatedly and referenced with `1b` or `1f`
+     * (the closest definition before or after). Local labels are named by their scope (e.g., `MULTIPLY.loop`).
+     * Labels are case-insensitive, so object files (and their symbol tables) name every label in uppercase,
+     * including the local part of a qualified name (e.g., `.loop` under `Multiply` is `MULTIPLY.LOOP`).
+     * 
+     * Any lint (or portability) warnings are printed to the console
+     * and can be accessed with {@linkcode getDiagnostics}.
      * @param fp The filepath of the `.asm` file
-     * @throws if assembling fails
+     * @param opts Assembler options
+     * @throws if assembling fails (the thrown error has the fields of {@linkcode Diagnostic})
      */
-    export function assemble(fp: string): void;
+    export function assemble(fp: string, opts?: AssembleOptions): void;
     
     /**
      * Takes several `.obj` files and links them.
-     * @param fps The filepaths of the `.obj` files
+     * 
+     * Classic `lc3as` files (`.bin` or `.hex`, or `.obj` with a `.sym` file next to it) are also accepted,
+     * along with the labels of their `.sym` files.
+     * 
+     * Link errors are reported in the source of the object files (if they have source):
+     * overlapping sections at both of their `.ORIG`s, and labels defined by several object files at each definition.
+     * External labels which aren't defined by any of the object files are reported as warnings
+     * (since the linked object file can be linked again later).
+     * 
+     * Relocatable blocks (see {@linkcode assemble}) are placed before linking:
+     * where the linker script places them (see {@linkcode LinkOptions.script}),
+     * or else packed into the first free space from x3000, in order.
+     * PC offsets to or from them which no longer fit are reported at their instructions.
+     * 
+     * Libraries (see {@linkcode createLibrary}) can be given alongside the object files.
+     * Only the members which define an external label that's otherwise undefined are linked
+     * (after the object files, in the order they're needed), and they're named like `cs2110.lib(print.obj)`.
+     * @param fps The filepaths of the `.obj` files (and `.lib` libraries)
      * @param out The output path where the linked object file should be
-     * @throws if linking fails
+     * @param opts How the linked object file is written
+     * @throws if linking fails (the thrown error has the fields of {@linkcode Diagnostic})
+     */
+    export function link(fps: string[], out: string, opts?: LinkOptions): void;
+    export interface LinkOptions extends ObjectOutputOptions {
+        /**
+         * Whether to also write a linker map (default: `false`), which is written next to the linked object file
+         * (with the extension `.map`).
+         * 
+         * The map lists every section with its origin, length, and object file,
+         * every label with its address and the object file which defines it,
+         * and every external label with the object file which resolved it and the addresses which reference it.
+         */
+        map?: boolean,
+        /**
+         * The path of a linker script, which places relocatable blocks at fixed addresses.
+         * 
+         * Each line is either `NAME address` (placing the block whose first label is `NAME`)
+         * or `* address` (where the other blocks are packed from, instead of x3000).
+         * `;` starts a comment.
+         */
+        script?: string,
+        /**
+         * Whether external labels which aren't defined by the object files or libraries
+         * are resolved by the standard library (default: `true`).
+         * 
+         * The standard library has these routines, which take their arguments in `R0` and `R1`,
+         * return their results in `R0` (and `R1`), and preserve every other register
+         * (saving them in their own memory, so `R6` doesn't have to point to a stack):
+         * - `PRINTDEC`: prints `R0` as a signed decimal number
+         * - `READDEC`: reads a signed decimal number from the keyboard (echoing it) into `R0`
+         * - `PRINTHEX`: prints `R0` in hexadecimal (e.g., `x3000`)
+         * - `MULT`: `R0 = R0 * R1`
+         * - `DIVMOD`: `R0 = R0 / R1` and `R1 = R0 % R1` (signed, rounding toward zero)
+         * - `STRLEN`: `R0` = the length of the string at `R0`
+         * - `STRCMP`: compares the strings at `R0` and `R1`,
+         *   setting `R0` (and the condition codes) to the difference of the first characters which differ
+         * 
+         * They're linked with their source, so the debugger can step into them.
+         * 
+         * Since external labels can't be PC offsets (so `JSR PRINTDEC` doesn't assemble),
+         * a routine is called through a pointer to it:
+         * ```
+         *         .EXTERNAL PRINTDEC
+         *         LD R6, P_PRINTDEC
+         *         JSRR R6
+         *         ...
+         * P_PRINTDEC .FILL PRINTDEC
+         * ```
+         */
+        stdlib?: boolean
+    }
+    /**
+     * Bundles several `.obj` files into a library, along with an index of the labels each of them defines.
+     * 
+     * When a library is linked (see {@linkcode link}), only the object files which are needed are linked.
+     * Relocatable blocks (see {@linkcode assemble}) are kept, so they're placed when the program is linked.
+     * @param fps The filepaths of the `.obj` files
+     * @param out The output path where the library should be (usually with the extension `.lib`)
+     * @throws if an object file can't be read, or if a label is defined by several of them
+     * (the thrown error has the fields of {@linkcode Diagnostic})
+     */
+    export function createLibrary(fps: string[], out: string): void;
+
+    export interface BuildResult {
+        /** The path of the linked object file. */
+        output: string,
+        /** The address of the project's entry point (if it has one). */
+        entry?: number,
+        /** The sources which were assembled (sources whose object files are up to date are not). */
+        assembled: string[],
+        /** The paths of the project's test specs. */
+        tests: string[]
+    }
+    /**
+     * Builds a multi-file program from its project file (`lc3project.json`), which is a JSON object like:
+     * ```json
+     * {
+     *     "sources": ["main.asm", "lib/print.asm"],
+     *     "output": "build/program.obj",
+     *     "entry": "MAIN",
+     *     "lints": { "unused-label": false },
+     *     "tests": ["tests/print.json"]
+     * }
+     * ```
+     * 
+     * Each `.asm` source is assembled into an object file next to it
+     * (unless that object file is newer than the source, the files it includes, and the project file),
+     * and other sources are read as object files (or libraries, see {@linkcode createLibrary}).
+     * The sources are then linked in order, and every external label must be defined by one of them
+     * (or by the standard library, unless the project file sets `"stdlib": false`, see {@linkcode LinkOptions.stdlib}).
+     * If any source fails to assemble, the errors of every source are reported (see {@linkcode getDiagnostics})
+     * and nothing is linked.
+     * 
+     * The project file can also have the `dialect` option of {@linkcode AssembleOptions},
+     * and the {@linkcode LinkOptions} of the linked object file.
+     * Every path is relative to the project file, and diagnostics name files by their paths relative to it.
+     * @param projectPath The project file, or the directory containing it
+     * @returns where the program was built, its entry point, and its test specs
+     * @throws if the project file is invalid, or if assembling or linking fails
+     * (the thrown error has the fields of {@linkcode Diagnostic})
+     */
+    export function build(projectPath: string): BuildResult;
+
+    export interface FormatOptions {
+        /**
+         * The case of opcodes, directives, and registers (default: `"upper"`).
+         */
+        case?: "upper" | "lower",
+        /**
+         * How numeric literals are written (default: `"preserve"`, which keeps each literal's base).
+         */
+        literals?: "preserve" | "hex" | "decimal"
+    }
+    /**
+     * Formats assembly source code into a canonical layout
+     * (aligning labels, opcodes, operands, and comments into columns).
+     * 
+     * Lines using the assembler's extensions (e.g., macros, `.EQU`, expressions, and local labels)
+     * are aligned the same way, but their labels and operands are kept as they were written.
+     * @param text The source code
+     * @param opts Formatting options
+     * @returns the formatted source code
+     * @throws if the source code does not parse (the thrown error has the fields of {@linkcode Diagnostic})
+     */
+    export function formatSource(text: string, opts?: FormatOptions): string;
+    /**
+     * Disassembles an object file back into assembly source code.
+     *
+     * Labels are taken from the object file's symbol table if it has one.
+     * Other branch, subroutine, and data targets are given generated labels (e.g., `L_3005`).
+     * @param fp The object file to disassemble (which can also be a classic `lc3as` file)
+     * @returns source code which assembles back into the same memory contents
+     * @throws if the file could not be read or is not a valid object file
+     */
+    export function disassembleObject(fp: string): string;
+    /**
+     * A section of an object file (see {@linkcode inspectObject}).
+     */
+    export interface ObjectSection {
+        origin: number,
+        length: number,
+        /**
+         * An estimate of what the section holds.
+         * 
+         * Words reached by following control flow from the start of each block are code,
+         * and everything else (including uninitialized words) is data.
+         */
+        kind: "code" | "data" | "mixed",
+        codeWords: number,
+        dataWords: number,
+        /**
+         * Whether this is a relocatable block (see {@linkcode assemble}), which the linker can move.
+         */
+        relocatable: boolean,
+        /**
+         * The name of the relocatable block (its first label), if it has one.
+         */
+        name?: string
+    }
+    /**
+     * What's in an object file (see {@linkcode inspectObject}).
+     */
+    export interface ObjectInfo {
+        format: "text" | "binary" | "classic",
+        /**
+         * A hash of the file's contents (64-bit FNV-1a, as 16 hex digits),
+         * for telling files apart (it isn't meant to detect tampering).
+         */
+        hash: string,
+        /** The sections of the object file, in order of origin. */
+        sections: ObjectSection[],
+        /** The labels the object file defines, in order of address. */
+        labels: { name: string, addr: number }[],
+        /** The external labels the object file uses (which it must be linked with), in order of name. */
+        externals: string[],
+        /** Each source line (from 0) with the address it was assembled to, in order of line. */
+        lines: [lno: number, addr: number][],
+        /** Whether the object file has its source code (and so can be debugged at the source level). */
+        hasSource: boolean
+    }
+    /**
+     * Describes what's in an object file without loading it.
+     * @param fp The object file to inspect (which can also be a classic `lc3as` file)
+     * @returns the object file's sections, symbols, and format
+     * @throws if the file could not be read or is not a valid object file (including if it's a library)
+     */
+    export function inspectObject(fp: string): ObjectInfo;
+    /**
+     * A word of an object file (see {@linkcode diffObjects}).
+     */
+    export interface DiffWord {
+        /** The value of the word (undefined if it's uninitialized, as from `.BLKW`). */
+        value?: number,
+        /** The disassembly of the word, with PC-relative targets named by the object file's labels. */
+        text: string
+    }
+    /**
+     * The differences between two object files (see {@linkcode diffObjects}).
+     */
+    export interface ObjectDiff {
+        /**
+         * The words which differ, in order of address:
+         * words only the new object file has are `"added"`, words only the old one has are `"removed"`,
+         * and words they both have with different values are `"changed"`.
+         */
+        words: { addr: number, kind: "added" | "removed" | "changed", old?: DiffWord, new?: DiffWord }[],
+        /**
+         * The labels which differ, in order of name:
+         * labels whose address changed are `"moved"`, and labels only one object file has are `"added"` or `"removed"`
+         * (or `"renamed"`, if a label only the other object file has is at the same address).
+         * A label which was both renamed and moved is `"removed"` under its old name and `"added"` under its new one.
+         */
+        labels: { kind: "added" | "removed" | "moved" | "renamed", name: string, oldAddr?: number, newAddr?: number, newName?: string }[]
+    }
+    /**
+     * Compares two object files by word and by label
+     * (e.g., to check that an object file was assembled from a source file).
+     * 
+     * Either file can be an assembly file (`.asm`), which is assembled first (without writing anything).
+     * @param oldFp The first object (or assembly) file
+     * @param newFp The second object (or assembly) file
+     * @param opts The dialect assembly files are assembled in (see {@linkcode AssembleOptions})
+     * @returns the differences, which are empty if the object files have the same contents and labels
+     * @throws if a file could not be read, is not a valid object file, or does not assemble
+     * (the thrown error has the fields of {@linkcode Diagnostic})
+     */
+    export function diffObjects(oldFp: string, newFp: string, opts?: Pick<AssembleOptions, "dialect">): ObjectDiff;
+    /**
+     * The formats of memory images, which load a program into the memory of a hardware LC-3:
+     * - `"circuitsim"`: CircuitSim RAM/ROM contents (a hex word per line, starting from address 0)
+     * - `"logisim"`: Logisim's `v2.0 raw` format
+     * - `"verilog"`: the hex format read by Verilog's `$readmemh` (with an `@address` before each block)
+     * 
+     * The CircuitSim and Logisim formats write every word up to the last one, filling the gaps with 0.
+     */
+    export type ImageFormat = "circuitsim" | "logisim" | "verilog";
+    /**
+     * Exports an object file as a memory image (see {@linkcode ImageFormat}).
+     * @param fp The object file to export (which can also be a classic `lc3as` file)
+     * @param out The path of the memory image to write
+     * @param format The format of the memory image
+     * @throws if the object file could not be read or the format is unknown
+     */
+    export function exportObjectImage(fp: string, out: string, format: ImageFormat): void;
+
+    /**
+     * Gets the diagnostics reported by the last call to
+     * `assemble`, `link`, or `loadObjectFile`.
      */
-    export function link(fps: string[], out: string): void;
+    export function getDiagnostics(): Diagnostic[];
 
     /**
      * Gets the symbol table, mapping each memory address to a label.
+     * 
+     * Local labels are included by their qualified names, which are uppercase like every other label (e.g., `MULTIPLY.LOOP`).
      */
     export function getCurrSymTable(): {[addr: number]: string};
     
@@ -33,9 +471,17 @@ declare module "lc3-backend" {
     export function setPauseOnFatalTrap(status: boolean): void;
     
     /**
-     * Loads an object file into the simulator,
+     * Loads an object file (in either format) into the simulator,
      * as well as clearing any state from the previous run.
      * 
+     * Classic `lc3as` files (`.bin` or `.hex`, or `.obj` with a `.sym` file next to it) can also be loaded,
+     * in which case the labels of the `.sym` file next to it are loaded too.
+     * 
+     * Object files with external labels that haven't been linked can't be loaded.
+     * 
+     * If the object file has no source code (e.g., it was stripped),
+     * its memory lines are disassembled instead.
+     * 
      * @param fp The `.obj` file to load to the simulator
      */
     export function loadObjectFile(fp: string): void;
@@ -114,6 +560,30 @@ declare module "lc3-backend" {
      * @param addr The memory location to read the line of.
      */
     export function getMemLine(addr: number): string;
+    /**
+     * Disassembles a region of memory, resolving PC-relative targets
+     * to their absolute addresses and labels.
+     * @param start The first address of the region.
+     * @param end The last address of the region (inclusive).
+     */
+    export function disassembleRegion(start: number, end: number): DisasmLine[];
+    /**
+     * Disassembles a region of memory (as in {@linkcode disassembleRegion})
+     * and writes it to a listing file.
+     * @param start The first address of the region.
+     * @param end The last address of the region (inclusive).
+     * @param fp The path of the listing file to write.
+     */
+    export function exportDisassembly(start: number, end: number, fp: string): void;
+    /**
+     * Exports a region of the simulator's memory as a memory image (see {@linkcode ImageFormat}),
+     * so that its current state can be loaded into a hardware LC-3.
+     * @param start The first address of the region.
+     * @param end The last address of the region (inclusive).
+     * @param fp The path of the memory image to write.
+     * @param format The format of the memory image.
+     */
+    export function exportMemoryImage(start: number, end: number, fp: string, format: ImageFormat): void;
 
     /**
      * Accesses the list of memory changes that occurred last execution.
@@ -172,6 +642,20 @@ declare module "lc3-backend" {
      * Gets the frame number (number of calls deep) from the engine.
      */
     export function getFrameNumber(): number;
+    /**
+     * Walks the stack (from R6/R5) following the LC-3 calling convention,
+     * returning the active frames from innermost to outermost.
+     */
+    export function getStackFrames(): StackFrame[];
+    /**
+     * Declares the arguments of a subroutine which uses the LC-3 calling convention,
+     * so that its argument slots can be labeled by `getStackFrames`.
+     * 
+     * This must be called after the object file is loaded.
+     * @param addr The address of the start of the subroutine.
+     * @param params The names of the subroutine's arguments.
+     */
+    export function setSubroutineDef(addr: number, params: string[]): void;
     /**
      * Checks if the simulator is currently running.
      */
@@ -179,12 +663,18 @@ declare module "lc3-backend" {
 
     /**
      * Gets the span in source code that corresponds to a given label.
+     * 
+     * The span is in the file it came from (e.g., a file included with `.INCLUDE`, or the invocation of a macro),
+     * given by `path`. This can only be found if the loaded object file's source is next to it (with the `.asm` extension)
+     * and hasn't changed since it was assembled; otherwise, `path` is `undefined`
+     * and the span is in the preprocessed source stored in the object file.
      */
-    export function getLabelSourceRange(label: string): [start_lno: number, start_cno: number, end_lno: number, end_cno: number] | undefined;
+    export function getLabelSourceRange(label: string): [start_lno: number, start_cno: number, end_lno: number, end_cno: number, path: string | undefined] | undefined;
     /**
-     * Gets the span in source code that corresponds to a given memory address.
+     * Gets the span in source code that corresponds to a given memory address
+     * (in the file it came from, as with {@link getLabelSourceRange}).
      */
-    export function getAddrSourceRange(addr: number): [start_lno: number, start_cno: number, end_lno: number, end_cno: number] | undefined;
+    export function getAddrSourceRange(addr: number): [start_lno: number, start_cno: number, end_lno: number, end_cno: number, path: string | undefined] | undefined;
 
     /**
      * Gets the amount of instructions remaining on the timer device until next interrupt.
@@ -1,32 +1,62 @@
+mod archive;
+mod asm;
+mod dialect;
+mod diff;
 mod err;
+mod expr;
+mod fmt;
+mod image;
+mod inspect;
+mod link;
+mod lint;
+mod listing;
+mod local;
+pub mod lsp;
 mod sim;
 mod cast;
+mod classic;
+mod disasm;
+pub mod cli;
 mod obj;
+mod preproc;
+mod project;
+mod reloc;
+mod stack;
+mod stdlib;
 
 use std::collections::HashMap;
 use std::io::Write;
-use std::ops::{Range, RangeBounds};
+use std::ops::RangeBounds;
 use std::path::{Path, PathBuf};
 use std::sync::atomic::Ordering;
 use std::sync::{LazyLock, Mutex, MutexGuard};
 
 use cast::{IntoJsValue, ResultExtJs, TryIntoJsValue};
-use lc3_ensemble::asm::encoding::{BinaryFormat, ObjFileFormat, TextFormat};
-use lc3_ensemble::asm::{assemble_debug, ObjectFile};
+use lc3_ensemble::asm::ObjectFile;
 use lc3_ensemble::ast::Reg::{R0, R1, R2, R3, R4, R5, R6, R7};
-use lc3_ensemble::parse::parse_ast;
 use lc3_ensemble::sim::debug::Breakpoint;
 use lc3_ensemble::sim::device::ExternalDevice;
+use lc3_ensemble::sim::frame::ParameterList;
 use lc3_ensemble::sim::mem::MachineInitStrategy;
 use lc3_ensemble::sim::{SimErr, Simulator};
 use neon::prelude::*;
-use err::Reporter;
-use obj::ObjContents;
+use neon::result::Throw;
+use dialect::Dialect;
+use err::{asm_err_code, DiagnosticData, Reporter};
+use fmt::FormatOptions;
+use image::ImageFormat;
+use lint::LintConfig;
+use miette::Severity;
+use obj::{ObjContents, ObjOutput, SourceRange};
+use preproc::Expanded;
+use project::{BuildResult, Project};
+use reloc::Relocs;
 use owo_colors::OwoColorize;
 use sim::SimController;
 
 static CONTROLLER: LazyLock<Mutex<SimController>> = LazyLock::new(Mutex::default);
 static SIM_CONTENTS: LazyLock<Mutex<ObjContents>> = LazyLock::new(Mutex::default);
+static DIAGNOSTICS: LazyLock<Mutex<Vec<DiagnosticData>>> = LazyLock::new(Mutex::default);
 
 fn obj_contents() -> MutexGuard<'static, ObjContents> {
     SIM_CONTENTS.lock().unwrap_or_else(|e| e.into_inner())
@@ -34,12 +64,59 @@ fn obj_contents() -> MutexGuard<'static, ObjContents> {
 fn controller() -> MutexGuard<'static, SimController> {
     CONTROLLER.lock().unwrap_or_else(|e| e.into_inner())
 }
+fn diagnostics() -> MutexGuard<'static, Vec<DiagnosticData>> {
+    DIAGNOSTICS.lock().unwrap_or_else(|e| e.into_inner())
+}
+/// Reports every error to the console and throws the first one,
+/// keeping their diagnostics so that they can be accessed with `getDiagnostics`.
+fn report_all_and_throw<'r, 'a, E: std::fmt::Display + ?Sized + 'r>(
+    reporters: impl IntoIterator<Item=Reporter<'r, E>>,
+    cx: &mut impl Context<'a>
+) -> Throw {
+    let mut first = None;
+    for reporter in reporters {
+        first.get_or_insert_with(|| reporter.diagnostic());
+        report(reporter);
+    }
+
+    match first {
+        Some(diagnostic) => diagnostic.throw(cx),
+        None => cx.throw_error::<_, std::convert::Infallible>("assembling failed").unwrap_err(),
+    }
+}
+/// Reports to the console (without throwing),
+/// keeping its diagnostic so that it can be accessed with `getDiagnostics`.
+fn report<E: std::fmt::Display + ?Sized>(mut reporter: Reporter<'_, E>) {
+    diagnostics().push(reporter.diagnostic());
+    reporter.report(&mut controller().output_buf());
+}
+/// Reports an error to the console and throws it,
+/// keeping its diagnostic so that it can be accessed with `getDiagnostics`.
+fn report_and_throw<'a, E: std::fmt::Display + ?Sized>(reporter: Reporter<'_, E>, cx: &mut impl Context<'a>) -> Throw {
+    diagnostics().push(reporter.diagnostic());
+    reporter.report_and_throw(&mut controller().output_buf(), cx)
+}
 pub fn deserialize_obj_file(bytes: Vec<u8>) -> Option<ObjectFile> {
     match String::from_utf8(bytes) {
-        Ok(s) => TextFormat::deserialize(&s),
-        Err(e) => BinaryFormat::deserialize(e.as_bytes()),
+        Ok(s) => obj::deserialize_text(&s),
+        Err(e) => obj::deserialize_binary(e.as_bytes()),
     }
 }
+/// Reads an object file in any supported format:
+/// ensemble's text or binary formats, or a classic `lc3as` file (`.bin` or `.hex`, or `.obj` with its `.sym`).
+///
+/// This returns `None` if the file is malformed.
+pub fn read_obj_file(path: &Path) -> std::io::Result<Option<ObjectFile>> {
+    Ok(read_relocatable_obj_file(path)?.map(|(obj, _)| obj))
+}
+/// Reads an object file in any supported format (see [`read_obj_file`]),
+/// along with its relocatable sections (see [`reloc`]).
+pub(crate) fn read_relocatable_obj_file(path: &Path) -> std::io::Result<Option<(ObjectFile, Relocs)>> {
+    let bytes = std::fs::read(path)?;
+    let (bytes, relocs) = reloc::split(&bytes);
+    let obj = deserialize_obj_file(bytes.to_vec()).or_else(|| classic::read(bytes, path));
+    Ok(obj.map(|obj| (obj, relocs)))
+}
 
 /// Get the common ancestor of all listed paths.
 fn common_ancestor<'p>(p: impl IntoIterator<Item=&'p Path>) -> &'p Path {
@@ -70,7 +147,7 @@ fn reset_machine(zeroed: bool) {
 
     obj_contents().clear();
 }
-fn load_obj_file(obj: ObjectFile) -> Result<(), SimErr> {
+fn load_obj_file(obj: ObjectFile, expanded: Option<Expanded>) -> Result<(), SimErr> {
     reset_machine(false);
     let mut controller = controller();
     
@@ -78,9 +155,21 @@ fn load_obj_file(obj: ObjectFile) -> Result<(), SimErr> {
         .unwrap_or_else(|_| panic!("simulator should've been idle after reset"))
         .load_obj_file(&obj)?;
 
-    obj_contents().load_contents(obj);
+    obj_contents().load_contents(obj, expanded);
     Ok(())
 }
+/// Preprocesses the source of an object file again, so that spans of its source can be mapped to the files they came from.
+///
+/// The source file has to be next to the object file and unchanged since it was assembled
+/// (so this is `None` for linked or stripped object files).
+fn reexpand_source(obj_path: &Path, obj: &ObjectFile) -> Option<Expanded> {
+    let src_info = obj.symbol_table()?.source_info()?;
+    let src_path = obj_path.with_extension("asm");
+    let src = std::fs::read_to_string(&src_path).ok()?;
+
+    let (expanded, _) = preproc::preprocess(&src, Some(&src_path));
+    (expanded.src() == src_info.source()).then_some(expanded)
+}
 //--------- CONFIG FUNCTIONS ---------//
 fn set_ignore_privilege(mut cx: FunctionContext) -> JsResult<JsUndefined> {
     // fn(enable: bool) -> Result<()>
@@ -116,64 +205,498 @@ fn clear_output(mut cx: FunctionContext) -> JsResult<JsUndefined> {
 }
 
 //--------- EDITOR/ASSEMBLER FUNCTIONS ---------//
+/// How a file is assembled (the options of `assemble`).
+#[derive(Default)]
+struct AssembleConfig {
+    lints: LintConfig,
+    dialect: Dialect,
+    listing: bool,
+    output: ObjOutput,
+}
+/// Assembles a file, reporting its warnings and writing its object file (and listing, if enabled).
+///
+/// This returns the object file and its relocatable sections.
+/// Reports name files by their paths relative to `dir`.
+fn assemble_file(cx: &mut FunctionContext, in_path: &Path, out_path: &Path, config: &AssembleConfig, dir: &Path) -> NeonResult<(ObjectFile, Relocs)> {
+    // should be unreachable cause frontend validates IO
+    let src = std::fs::read_to_string(in_path).or_throw(cx)?;
+
+    let (expanded, errors) = preproc::preprocess(&src, Some(in_path));
+    if !errors.is_empty() {
+        let reporters = errors.iter()
+            .map(|e| Reporter::preprocessed(e, &expanded).with_code(e.code()).relative_to(dir));
+        return Err(report_all_and_throw(reporters, cx));
+    }
+    let (obj, relocs) = asm::assemble_all(&expanded, config.dialect)
+        .map_err(|errors| {
+            let reporters = errors.iter()
+                .map(|e| Reporter::preprocessed(e, &expanded).with_code(e.code()).relative_to(dir));
+            report_all_and_throw(reporters, cx)
+        })?;
+
+    // Anything the dialect doesn't accept is an error:
+    let (rejected, portability): (Vec<_>, Vec<_>) = dialect::check(&expanded, config.dialect)
+        .into_iter()
+        .partition(|w| w.rejected);
+    if !rejected.is_empty() {
+        let reporters = rejected.iter()
+            .map(|w| Reporter::preprocessed(w, &expanded).with_code(w.kind.code()).relative_to(dir));
+        return Err(report_all_and_throw(reporters, cx));
+    }
+    for warning in portability {
+        report({
+            Reporter::preprocessed(&warning, &expanded)
+                .with_code(warning.kind.code())
+                .with_severity(Severity::Warning)
+                .relative_to(dir)
+        });
+    }
+    for warning in lint::lint(&expanded, config.dialect, &config.lints) {
+        report({
+            Reporter::preprocessed(&warning, &expanded)
+                .with_code(warning.lint.code())
+                .with_severity(Severity::Warning)
+                .relative_to(dir)
+        });
+    }
+    
+    let files = config.output.files(&obj, &relocs, out_path)
+        .map_err(|e| report_and_throw(Reporter::io(e, out_path), cx))?;
+    for (path, bytes) in files {
+        std::fs::write(&path, bytes)
+            .map_err(|e| report_and_throw(Reporter::io(&e, &path), cx))?;
+    }
+    if config.listing {
+        let listing_path = in_path.with_extension("lst");
+        std::fs::write(&listing_path, listing::listing(&expanded, config.dialect, &obj))
+            .map_err(|e| report_and_throw(Reporter::io(&e, &listing_path), cx))?;
+    }
+
+    Ok((obj, relocs))
+}
 fn assemble(mut cx: FunctionContext) -> JsResult<JsUndefined> {
-    // fn (fp: String) -> Result<()>
+    // fn (fp: String, options?: { lints?: Object, dialect?: String, listing?: bool, format?: String, strip?: bool }) -> Result<()>
     let in_path: PathBuf = cx.argument::<JsString>(0)?.value(&mut cx).into();
     let out_path = in_path.with_extension("obj");
+    let mut config = AssembleConfig::default();
+    if let Some(opts) = cx.argument_opt(1) {
+        let opts = opts.downcast_or_throw::<JsObject, _>(&mut cx)?;
+        config.output = ObjOutput::from_js(&mut cx, opts)?;
+        if let Some(lints) = opts.get_opt::<JsObject, _, _>(&mut cx, "lints")? {
+            config.lints = LintConfig::from_js(&mut cx, lints)?;
+        }
+        if let Some(name) = opts.get_opt::<JsString, _, _>(&mut cx, "dialect")? {
+            let name = name.value(&mut cx);
+            config.dialect = match Dialect::from_name(&name) {
+                Some(dialect) => dialect,
+                None => return cx.throw_error(format!("unknown dialect {name:?}")),
+            };
+        }
+        config.listing = opts.get_opt::<JsBoolean, _, _>(&mut cx, "listing")?.is_some_and(|b| b.value(&mut cx));
+    }
     
-    // should be unreachable cause frontend validates IO
-    let src = std::fs::read_to_string(&in_path).or_throw(&mut cx)?;
-
-    let ast = parse_ast(&src)
-        .map_err(|e| Reporter::ensemble(&e, &in_path, &src).report_and_throw(&mut controller().output_buf(), &mut cx))?;
-    let obj = assemble_debug(ast, &src)
-        .map_err(|e| Reporter::ensemble(&e, &in_path, &src).report_and_throw(&mut controller().output_buf(), &mut cx))?;
-    
-    std::fs::write(&out_path, TextFormat::serialize(&obj))
-        .map_err(|e| Reporter::io(&e, &out_path).report_and_throw(&mut controller().output_buf(), &mut cx))?;
+    diagnostics().clear();
+    assemble_file(&mut cx, &in_path, &out_path, &config, in_path.parent().unwrap_or(Path::new("")))?;
 
     let ancestor = common_ancestor([&*in_path, &*out_path]);
     let rel_in = display_path(&in_path, ancestor);
     let rel_out = display_path(&out_path, ancestor);
-    writeln!(controller().output_buf(), "successfully assembled {} into {}", rel_in.underline(), rel_out.underline())
-        .unwrap();
+    match config.listing {
+        true => {
+            let listing_path = in_path.with_extension("lst");
+            let rel_lst = display_path(&listing_path, ancestor);
+            writeln!(controller().output_buf(), "successfully assembled {} into {} (listing in {})", rel_in.underline(), rel_out.underline(), rel_lst.underline())
+        },
+        false => writeln!(controller().output_buf(), "successfully assembled {} into {}", rel_in.underline(), rel_out.underline()),
+    }.unwrap();
     Ok(cx.undefined())
 }
 
+/// Places the relocatable sections of object files (see [`link::place`]),
+/// with the linker script at the given path (if there is one).
+///
+/// Reports name files by their paths relative to `dir`.
+fn place_sections(cx: &mut FunctionContext, inputs: &[(PathBuf, ObjectFile, Relocs)], script_path: Option<&Path>, dir: &Path) -> NeonResult<Vec<(PathBuf, ObjectFile)>> {
+    let script_src = match script_path {
+        Some(path) => Some({
+            std::fs::read_to_string(path)
+                .map_err(|e| report_and_throw(Reporter::io(&e, path).relative_to(dir), cx))?
+        }),
+        None => None,
+    };
+    let script = match (script_path, &script_src) {
+        (Some(path), Some(src)) => Some({
+            link::LinkScript::parse(path, src)
+                .map_err(|errors| report_all_and_throw(errors.iter().map(|e| e.reporter().relative_to(dir)), cx))?
+        }),
+        _ => None,
+    };
+
+    link::place(inputs, script.as_ref())
+        .map_err(|errors| report_all_and_throw(errors.iter().map(|e| e.reporter().relative_to(dir)), cx))
+}
+/// Links object files in order
+/// (checking that their sections don't overlap and that their labels are only defined once).
+///
+/// If the linked program must be complete, external labels which aren't resolved are errors
+/// (and otherwise, they're warnings).
+/// Reports name files by their paths relative to `dir`.
+fn link_objects(cx: &mut FunctionContext, inputs: &[(PathBuf, ObjectFile)], dir: &Path, complete: bool) -> NeonResult<ObjectFile> {
+    let mut errors = link::check_overlaps(inputs, dir);
+    if errors.is_empty() {
+        errors = link::check_duplicates(inputs, dir);
+    }
+    if !errors.is_empty() {
+        return Err(report_all_and_throw(errors.iter().map(|e| e.reporter().relative_to(dir)), cx));
+    }
+
+    let unresolved = link::check_unresolved(inputs);
+    match complete {
+        true if !unresolved.is_empty() => {
+            return Err(report_all_and_throw(unresolved.iter().map(|e| e.reporter().relative_to(dir)), cx));
+        },
+        _ => for e in &unresolved {
+            report(e.reporter().relative_to(dir).with_severity(Severity::Warning));
+        },
+    }
+
+    let mut result_obj = ObjectFile::empty();
+    for (_, obj) in inputs {
+        result_obj = ObjectFile::link(result_obj, obj.clone())
+            .map_err(|e| report_and_throw(Reporter::simple(&e).with_code(asm_err_code(&e)), cx))?;
+    }
+    Ok(result_obj)
+}
 fn link(mut cx: FunctionContext) -> JsResult<JsUndefined> {
-    // fn (fp: String[], out: String) -> Result<()>
+    // fn (fp: String[], out: String, options?: { format?: String, strip?: bool, map?: bool, script?: String, stdlib?: bool }) -> Result<()>
     let out: PathBuf = cx.argument::<JsString>(1)?.value(&mut cx).into();
+    let mut output = ObjOutput::default();
+    let mut map_path = None;
+    let mut script_path = None;
+    let mut use_stdlib = true;
+    if let Some(opts) = cx.argument_opt(2) {
+        let opts = opts.downcast_or_throw::<JsObject, _>(&mut cx)?;
+        output = ObjOutput::from_js(&mut cx, opts)?;
+        if opts.get_opt::<JsBoolean, _, _>(&mut cx, "map")?.is_some_and(|b| b.value(&mut cx)) {
+            map_path = Some(out.with_extension("map"));
+        }
+        if let Some(script) = opts.get_opt::<JsString, _, _>(&mut cx, "script")? {
+            script_path = Some(PathBuf::from(script.value(&mut cx)));
+        }
+        if let Some(stdlib) = opts.get_opt::<JsBoolean, _, _>(&mut cx, "stdlib")? {
+            use_stdlib = stdlib.value(&mut cx);
+        }
+    }
 
     let file_paths: Vec<PathBuf> = cx.argument::<JsArray>(0)?
         .to_vec(&mut cx)?
         .into_iter()
         .map(|e| e.downcast_or_throw::<JsString, _>(&mut cx).map(|s| s.value(&mut cx).into()))
         .collect::<Result<_, _>>()?;
-
-    let mut result_obj = ObjectFile::empty();
-    for fp in &file_paths {
+    let ancestor = common_ancestor(file_paths.iter().chain([&out]).map(|p| &**p)).to_path_buf();
+
+    diagnostics().clear();
+    let mut inputs = vec![];
+    let mut libraries = vec![];
+    for fp in file_paths {
+        if let Some(members) = archive::read(&fp).or_throw(&mut cx)? {
+            libraries.push((fp, members));
+            continue;
+        }
         // Parse object file:
-        let src = std::fs::read_to_string(fp).or_throw(&mut cx)?;
-        let obj = deserialize_obj_file(src.into_bytes())
+        let (obj, relocs) = read_relocatable_obj_file(&fp).or_throw(&mut cx)?
+            .ok_or_else(|| {
+                report_and_throw(Reporter::io("cannot deserialize object file", &fp), &mut cx)
+            })?;
+        inputs.push((fp, obj, relocs));
+    }
+    if use_stdlib {
+        libraries.push(stdlib::library());
+    }
+    let members = archive::select(&inputs, libraries);
+    inputs.extend(members);
+    let inputs = place_sections(&mut cx, &inputs, script_path.as_deref(), &ancestor)?;
+    let result_obj = link_objects(&mut cx, &inputs, &ancestor, false)?;
+
+    let files = output.files(&result_obj, &Relocs::default(), &out)
+        .map_err(|e| report_and_throw(Reporter::io(e, &out), &mut cx))?;
+    for (path, bytes) in files {
+        std::fs::write(&path, bytes).or_throw(&mut cx)?;
+    }
+    if let Some(map_path) = &map_path {
+        std::fs::write(map_path, link::map(&inputs, &ancestor))
+            .map_err(|e| report_and_throw(Reporter::io(&e, map_path), &mut cx))?;
+    }
+
+    let in_fs = inputs.iter()
+        .map(|(p, _)| display_path(p, &ancestor).underline().to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    let out_f = display_path(&out, &ancestor);
+    match &map_path {
+        Some(map_path) => writeln!(controller().output_buf(), "successfully linked object files [{}] to {} (map in {})", in_fs.underline(), out_f.underline(), display_path(map_path, &ancestor).underline()),
+        None => writeln!(controller().output_buf(), "successfully linked object files [{}] to {}", in_fs.underline(), out_f.underline()),
+    }.unwrap();
+
+    Ok(cx.undefined())
+}
+fn create_library(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    // fn (fp: String[], out: String) -> Result<()>
+    let out: PathBuf = cx.argument::<JsString>(1)?.value(&mut cx).into();
+    let file_paths: Vec<PathBuf> = cx.argument::<JsArray>(0)?
+        .to_vec(&mut cx)?
+        .into_iter()
+        .map(|e| e.downcast_or_throw::<JsString, _>(&mut cx).map(|s| s.value(&mut cx).into()))
+        .collect::<Result<_, _>>()?;
+    let ancestor = common_ancestor(file_paths.iter().chain([&out]).map(|p| &**p)).to_path_buf();
+
+    diagnostics().clear();
+    let mut members = vec![];
+    for fp in file_paths {
+        let (obj, relocs) = read_relocatable_obj_file(&fp).or_throw(&mut cx)?
             .ok_or_else(|| {
-                Reporter::io("cannot deserialize object file", fp)
-                    .report_and_throw(&mut controller().output_buf(), &mut cx)
+                report_and_throw(Reporter::io("cannot deserialize object file", &fp), &mut cx)
             })?;
+        members.push((fp, obj, relocs));
+    }
 
-        // Link to current result obj:
-        result_obj = ObjectFile::link(result_obj, obj)
-            .map_err(|e| Reporter::simple(&e).report_and_throw(&mut controller().output_buf(), &mut cx))?;
+    let objects: Vec<_> = members.iter()
+        .map(|(path, obj, _)| (path.clone(), obj.clone()))
+        .collect();
+    let errors = link::check_library_duplicates(&objects, &ancestor);
+    if !errors.is_empty() {
+        return Err(report_all_and_throw(errors.iter().map(|e| e.reporter().relative_to(&ancestor)), &mut cx));
     }
-    std::fs::write(&out, TextFormat::serialize(&result_obj)).or_throw(&mut cx)?;
 
-    let ancestor = common_ancestor(file_paths.iter().chain([&out]).map(|p| &**p));
-    let in_fs = file_paths.iter()
-        .map(|p| display_path(p, ancestor).underline().to_string())
+    std::fs::write(&out, archive::write(&members))
+        .map_err(|e| report_and_throw(Reporter::io(&e, &out), &mut cx))?;
+
+    let in_fs = members.iter()
+        .map(|(p, _, _)| display_path(p, &ancestor).underline().to_string())
         .collect::<Vec<_>>()
         .join(", ");
-    let out_f = display_path(&out, ancestor);
+    let out_f = display_path(&out, &ancestor);
+    writeln!(controller().output_buf(), "successfully created library {} from object files [{}]", out_f.underline(), in_fs).unwrap();
+
+    Ok(cx.undefined())
+}
+fn build(mut cx: FunctionContext) -> JsResult<JsObject> {
+    // fn(projectPath: String) -> Result<BuildResult>
+    let project_path = project::project_file(Path::new(&cx.argument::<JsString>(0)?.value(&mut cx)));
+
+    diagnostics().clear();
+    let project = Project::read(&project_path)
+        .map_err(|e| report_and_throw(Reporter::io(&e, &project_path).with_code(e.code()), &mut cx))?;
+    let dir = project.dir().to_path_buf();
+    let config = AssembleConfig {
+        lints: project.lints.clone(),
+        dialect: project.dialect,
+        ..Default::default()
+    };
 
-    writeln!(controller().output_buf(), "successfully linked object files [{}] to {}", in_fs.underline(), out_f.underline()).unwrap();
+    // Assemble each changed source, and link everything in order
+    // (reporting the errors of every source before failing):
+    let mut assembled = vec![];
+    let mut inputs = vec![];
+    let mut libraries = vec![];
+    let mut failed = None;
+    for src in &project.sources {
+        let is_asm = src.extension().is_some_and(|e| e.eq_ignore_ascii_case("asm"));
+        let input = match is_asm {
+            true => {
+                let obj_path = src.with_extension("obj");
+                let (obj, relocs) = match project::built_object(src, &obj_path, &project.path) {
+                    Some(built) => built,
+                    None => {
+                        assembled.push(src.clone());
+                        match cx.try_catch(|cx| assemble_file(cx, src, &obj_path, &config, &dir)) {
+                            Ok(built) => built,
+                            Err(e) => {
+                                failed.get_or_insert(e);
+                                continue;
+                            },
+                        }
+                    },
+                };
+                (obj_path, obj, relocs)
+            },
+            false => {
+                let library = archive::read(src)
+                    .map_err(|e| report_and_throw(Reporter::io(&e, src).relative_to(&dir), &mut cx))?;
+                if let Some(members) = library {
+                    libraries.push((src.clone(), members));
+                    continue;
+                }
+                let (obj, relocs) = read_relocatable_obj_file(src)
+                    .map_err(|e| report_and_throw(Reporter::io(&e, src).relative_to(&dir), &mut cx))?
+                    .ok_or_else(|| report_and_throw(Reporter::io("cannot deserialize object file", src).relative_to(&dir), &mut cx))?;
+                (src.clone(), obj, relocs)
+            },
+        };
+        inputs.push(input);
+    }
+    if let Some(e) = failed {
+        return cx.throw(e);
+    }
+    if project.stdlib {
+        libraries.push(stdlib::library());
+    }
+    let members = archive::select(&inputs, libraries);
+    inputs.extend(members);
+    let inputs = place_sections(&mut cx, &inputs, project.script.as_deref(), &dir)?;
+    let result_obj = link_objects(&mut cx, &inputs, &dir, true)?;
+
+    let entry = match &project.entry {
+        Some(label) => {
+            let addr = result_obj.symbol_table()
+                .and_then(|sym| sym.label_iter().find(|&(name, _, external)| !external && name.eq_ignore_ascii_case(label)))
+                .map(|(_, addr, _)| addr);
+            match addr {
+                Some(addr) => Some(addr),
+                None => {
+                    let msg = format!("entry point {label} is not defined by any source");
+                    return Err(report_and_throw(Reporter::io(&msg, &project.path).with_code("project::undefined-entry"), &mut cx));
+                },
+            }
+        },
+        None => None,
+    };
+
+    if let Some(parent) = project.output.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| report_and_throw(Reporter::io(&e, &project.output).relative_to(&dir), &mut cx))?;
+    }
+    let files = project.obj_output.files(&result_obj, &Relocs::default(), &project.output)
+        .map_err(|e| report_and_throw(Reporter::io(e, &project.output).relative_to(&dir), &mut cx))?;
+    for (path, bytes) in files {
+        std::fs::write(&path, bytes)
+            .map_err(|e| report_and_throw(Reporter::io(&e, &path).relative_to(&dir), &mut cx))?;
+    }
+    if project.map {
+        let map_path = project.output.with_extension("map");
+        std::fs::write(&map_path, link::map(&inputs, &dir))
+            .map_err(|e| report_and_throw(Reporter::io(&e, &map_path).relative_to(&dir), &mut cx))?;
+    }
+
+    writeln!(
+        controller().output_buf(),
+        "successfully built {} into {} (assembled {} of {} sources)",
+        display_path(&project.path, &dir).underline(),
+        display_path(&project.output, &dir).underline(),
+        assembled.len(),
+        project.sources.len()
+    ).unwrap();
+
+    BuildResult { output: project.output, entry, assembled, tests: project.tests }
+        .try_into_js(&mut cx)
+}
+fn get_diagnostics(mut cx: FunctionContext) -> JsResult<JsArray> {
+    // fn() -> Result<Diagnostic[]>
+    diagnostics().clone().try_into_js(&mut cx)
+}
+fn format_source(mut cx: FunctionContext) -> JsResult<JsString> {
+    // fn(text: String, options?: FormatOptions) -> Result<String>
+    let text = cx.argument::<JsString>(0)?.value(&mut cx);
+    let opts = match cx.argument_opt(1) {
+        Some(opts) => {
+            let opts = opts.downcast_or_throw::<JsObject, _>(&mut cx)?;
+            FormatOptions::from_js(&mut cx, opts)?
+        },
+        None => FormatOptions::default(),
+    };
+
+    match fmt::format_source(&text, opts) {
+        Ok(formatted) => Ok(cx.string(formatted)),
+        Err(e) => Err(Reporter::ensemble_unnamed(&e, &text).with_code(e.code()).diagnostic().throw(&mut cx)),
+    }
+}
+fn disassemble_object(mut cx: FunctionContext) -> JsResult<JsString> {
+    // fn(fp: String) -> Result<String>
+    let in_path: PathBuf = cx.argument::<JsString>(0)?.value(&mut cx).into();
+
+    let Some(obj) = read_obj_file(&in_path).or_throw(&mut cx)? else {
+        return Err(Reporter::io("malformed object file", &in_path).diagnostic().throw(&mut cx));
+    };
+
+    Ok(cx.string(disasm::disassemble_object(&obj)))
+}
+fn inspect_object(mut cx: FunctionContext) -> JsResult<JsObject> {
+    // fn(fp: String) -> Result<ObjectInfo>
+    let in_path: PathBuf = cx.argument::<JsString>(0)?.value(&mut cx).into();
+
+    match inspect::inspect(&in_path) {
+        Ok(info) => info.try_into_js(&mut cx),
+        Err(e) => Err(Reporter::io(&e, &in_path).with_code(e.code()).diagnostic().throw(&mut cx)),
+    }
+}
+/// Reads one side of a diff: an object file, or an assembly file (`.asm`),
+/// which is assembled without writing anything.
+///
+/// Reports name files by their paths relative to `dir`.
+fn read_diff_input(cx: &mut FunctionContext, path: &Path, dialect: Dialect, dir: &Path) -> NeonResult<ObjectFile> {
+    if path.extension().is_some_and(|e| e.eq_ignore_ascii_case("asm")) {
+        let src = std::fs::read_to_string(path)
+            .map_err(|e| report_and_throw(Reporter::io(&e, path).relative_to(dir), cx))?;
+        let (expanded, errors) = preproc::preprocess(&src, Some(path));
+        if !errors.is_empty() {
+            let reporters = errors.iter()
+                .map(|e| Reporter::preprocessed(e, &expanded).with_code(e.code()).relative_to(dir));
+            return Err(report_all_and_throw(reporters, cx));
+        }
+        let (obj, _) = asm::assemble_all(&expanded, dialect)
+            .map_err(|errors| {
+                let reporters = errors.iter()
+                    .map(|e| Reporter::preprocessed(e, &expanded).with_code(e.code()).relative_to(dir));
+                report_all_and_throw(reporters, cx)
+            })?;
+        return Ok(obj);
+    }
+
+    read_obj_file(path)
+        .map_err(|e| report_and_throw(Reporter::io(&e, path).relative_to(dir), cx))?
+        .ok_or_else(|| report_and_throw(Reporter::io("malformed object file", path).relative_to(dir), cx))
+}
+fn diff_objects(mut cx: FunctionContext) -> JsResult<JsObject> {
+    // fn(old: String, new: String, options?: { dialect?: String }) -> Result<ObjectDiff>
+    let old_path: PathBuf = cx.argument::<JsString>(0)?.value(&mut cx).into();
+    let new_path: PathBuf = cx.argument::<JsString>(1)?.value(&mut cx).into();
+    let mut dialect = Dialect::default();
+    if let Some(opts) = cx.argument_opt(2) {
+        let opts = opts.downcast_or_throw::<JsObject, _>(&mut cx)?;
+        if let Some(name) = opts.get_opt::<JsString, _, _>(&mut cx, "dialect")? {
+            let name = name.value(&mut cx);
+            dialect = match Dialect::from_name(&name) {
+                Some(dialect) => dialect,
+                None => return cx.throw_error(format!("unknown dialect {name:?}")),
+            };
+        }
+    }
+    let ancestor = common_ancestor([&*old_path, &*new_path]).to_path_buf();
+
+    diagnostics().clear();
+    let old = read_diff_input(&mut cx, &old_path, dialect, &ancestor)?;
+    let new = read_diff_input(&mut cx, &new_path, dialect, &ancestor)?;
+    diff::diff(&old, &new).try_into_js(&mut cx)
+}
+/// Reads a memory image format argument, throwing if it isn't a known format.
+fn image_format_arg(cx: &mut FunctionContext, i: usize) -> NeonResult<ImageFormat> {
+    let name = cx.argument::<JsString>(i)?.value(cx);
+    match ImageFormat::from_name(&name) {
+        Some(format) => Ok(format),
+        None => cx.throw_error(format!("unknown memory image format {name:?}")),
+    }
+}
+fn export_object_image(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    // fn(fp: String, out: String, format: String) -> Result<()>
+    let in_path: PathBuf = cx.argument::<JsString>(0)?.value(&mut cx).into();
+    let out_path: PathBuf = cx.argument::<JsString>(1)?.value(&mut cx).into();
+    let format = image_format_arg(&mut cx, 2)?;
+
+    let Some(obj) = read_obj_file(&in_path).or_throw(&mut cx)? else {
+        return Err(Reporter::io("malformed object file", &in_path).diagnostic().throw(&mut cx));
+    };
+    std::fs::write(&out_path, image::image(obj.addr_iter(), format))
+        .map_err(|e| report_and_throw(Reporter::io(&e, &out_path), &mut cx))?;
 
     Ok(cx.undefined())
 }
@@ -185,7 +708,7 @@ fn get_curr_sym_table(mut cx: FunctionContext) -> JsResult<JsObject> {
     let contents = obj_contents();
     let mut map = HashMap::new();
 
-    if let Some((sym, _)) = contents.get_sym_source() {
+    if let Some(sym) = contents.get_sym_table() {
         map.extend(sym.label_iter().map(|(label, addr, _)| (addr, label)));
     }
 
@@ -196,18 +719,27 @@ fn load_object_file(mut cx: FunctionContext) -> JsResult<JsUndefined> {
     let in_path: PathBuf = cx.argument::<JsString>(0)?.value(&mut cx).into();
     
     // should be unreachable cause frontend validates IO
-    let bytes = std::fs::read(&in_path).or_throw(&mut cx)?;
+    let obj = read_obj_file(&in_path).or_throw(&mut cx)?;
+    diagnostics().clear();
     
-    let Some(obj) = deserialize_obj_file(bytes) else {
+    let Some(obj) = obj else {
         return Err(
-            Reporter::io("malformed object file", &in_path)
-                .report_and_throw(&mut controller().output_buf(), &mut cx)
+            report_and_throw(Reporter::io("malformed object file", &in_path), &mut cx)
         );
     };
+
+    // The simulator can't load external labels which weren't linked:
+    let inputs = [(in_path, obj)];
+    let unresolved = link::check_unresolved(&inputs);
+    if !unresolved.is_empty() {
+        return Err(report_all_and_throw(unresolved.iter().map(|e| e.reporter()), &mut cx));
+    }
+    let [(in_path, obj)] = inputs;
+    let expanded = reexpand_source(&in_path, &obj);
     
-    match load_obj_file(obj) {
+    match load_obj_file(obj, expanded) {
         Ok(_) => Ok(cx.undefined()),
-        Err(e) => Err(Reporter::simple(&e).report_and_throw(&mut controller().output_buf(), &mut cx)),
+        Err(e) => Err(report_and_throw(Reporter::simple(&e).with_code("sim"), &mut cx)),
     }
 }
 fn reinitialize_machine(mut cx: FunctionContext) -> JsResult<JsUndefined> {
@@ -400,6 +932,50 @@ fn get_mem_line(mut cx: FunctionContext) -> JsResult<JsString> {
     
     Ok(cx.string(string))
 }
+fn disassemble_region(mut cx: FunctionContext) -> JsResult<JsArray> {
+    // fn(start: u16, end: u16) -> Result<DisasmLine[]>
+    let start = cx.argument::<JsNumber>(0)?.value(&mut cx) as u16;
+    let end   = cx.argument::<JsNumber>(1)?.value(&mut cx) as u16;
+
+    let mut controller = controller();
+    let sim = controller.simulator().or_throw(&mut cx)?;
+
+    let contents = obj_contents();
+    disasm::disassemble_region(sim, start..=end, &contents.code_map(), |addr| contents.get_label(addr))
+        .try_into_js(&mut cx)
+}
+fn export_disassembly(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    // fn(start: u16, end: u16, fp: String) -> Result<()>
+    let start = cx.argument::<JsNumber>(0)?.value(&mut cx) as u16;
+    let end   = cx.argument::<JsNumber>(1)?.value(&mut cx) as u16;
+    let out_path: PathBuf = cx.argument::<JsString>(2)?.value(&mut cx).into();
+
+    let mut controller = controller();
+    let sim = controller.simulator().or_throw(&mut cx)?;
+
+    let contents = obj_contents();
+    let lines = disasm::disassemble_region(sim, start..=end, &contents.code_map(), |addr| contents.get_label(addr));
+    std::fs::write(&out_path, disasm::listing(&lines))
+        .map_err(|e| report_and_throw(Reporter::io(&e, &out_path), &mut cx))?;
+
+    Ok(cx.undefined())
+}
+fn export_memory_image(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    // fn(start: u16, end: u16, fp: String, format: String) -> Result<()>
+    let start = cx.argument::<JsNumber>(0)?.value(&mut cx) as u16;
+    let end   = cx.argument::<JsNumber>(1)?.value(&mut cx) as u16;
+    let out_path: PathBuf = cx.argument::<JsString>(2)?.value(&mut cx).into();
+    let format = image_format_arg(&mut cx, 3)?;
+
+    let mut controller = controller();
+    let sim = controller.simulator().or_throw(&mut cx)?;
+
+    let words = (start..=end).map(|addr| (addr, Some(sim.mem[addr].get())));
+    std::fs::write(&out_path, image::image(words, format))
+        .map_err(|e| report_and_throw(Reporter::io(&e, &out_path), &mut cx))?;
+
+    Ok(cx.undefined())
+}
 fn clear_input(mut cx: FunctionContext) -> JsResult<JsUndefined> {
     // fn() -> ()
     controller().input_buf().clear();
@@ -479,46 +1055,78 @@ fn get_frame_number(mut cx: FunctionContext) -> JsResult<JsNumber> {
     fno.try_into_js(&mut cx)
 }
 
+fn get_stack_frames(mut cx: FunctionContext) -> JsResult<JsArray> {
+    // fn() -> Result<StackFrame[]>
+    let mut controller = controller();
+    let sim = controller.simulator().or_throw(&mut cx)?;
+
+    let contents = obj_contents();
+    stack::walk_stack(sim, |addr| contents.get_label(addr))
+        .try_into_js(&mut cx)
+}
+fn set_subroutine_def(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    // fn(addr: u16, params: String[]) -> Result<()>
+    let addr = cx.argument::<JsNumber>(0)?.value(&mut cx) as u16;
+    let params: Vec<String> = cx.argument::<JsArray>(1)?
+        .to_vec(&mut cx)?
+        .into_iter()
+        .map(|e| e.downcast_or_throw::<JsString, _>(&mut cx).map(|s| s.value(&mut cx)))
+        .collect::<Result<_, _>>()?;
+    let params: Vec<_> = params.iter().map(String::as_str).collect();
+
+    let mut controller = controller();
+    controller.simulator()
+        .or_throw(&mut cx)?
+        .frame_stack
+        .set_subroutine_def(addr, ParameterList::with_calling_convention(&params));
+
+    Ok(cx.undefined())
+}
+
 fn is_sim_running(mut cx: FunctionContext) -> JsResult<JsBoolean> {
     // fn() -> bool
     Ok(cx.boolean(controller().is_running()))
 }
 fn get_label_source_range(mut cx: FunctionContext) -> JsResult<JsValue> {
-    // fn(label: String) -> Result<Option<usize>>
+    // fn(label: String) -> Result<Option<[usize, usize, usize, usize, Option<String>]>>
     let label = cx.argument::<JsString>(0)?.value(&mut cx);
     
     let contents = obj_contents();
     'get_line: {
         let Some((sym, src_info)) = contents.get_sym_source() else { break 'get_line };
-        let Some(Range { start, end }) = sym.get_label_source(&label) else { break 'get_line };
-        let (slno, scno) = src_info.get_pos_pair(start);
-        let (elno, ecno) = src_info.get_pos_pair(end);
+        let Some(span) = sym.get_label_source(&label) else { break 'get_line };
+        let span = asm::label_span(src_info.source(), span);
+        let Some(range) = contents.source_range(span) else { break 'get_line };
 
-        return [slno, scno, elno, ecno]
-            .try_into_js(&mut cx)
-            .map(|e| e.upcast());
+        return source_range_to_js(range, &mut cx);
     }
     Ok(cx.undefined().upcast())
 
 }
 fn get_addr_source_range(mut cx: FunctionContext) -> JsResult<JsValue> {
-    // fn(addr: u16) -> Result<Option<usize>>
+    // fn(addr: u16) -> Result<Option<[usize, usize, usize, usize, Option<String>]>>
     let addr = cx.argument::<JsNumber>(0)?.value(&mut cx) as u16;
 
     let contents = obj_contents();
     'get_line: {
         let Some((sym, src_info)) = contents.get_sym_source() else { break 'get_line };
         let Some(lno) = sym.rev_lookup_line(addr) else { break 'get_line };
-        let Some(Range { start, end }) = src_info.line_span(lno) else { break 'get_line };
-        let (slno, scno) = src_info.get_pos_pair(start);
-        let (elno, ecno) = src_info.get_pos_pair(end);
+        let Some(span) = src_info.line_span(lno) else { break 'get_line };
+        let Some(range) = contents.source_range(span) else { break 'get_line };
 
-        return [slno, scno, elno, ecno]
-            .try_into_js(&mut cx)
-            .map(|e| e.upcast());
+        return source_range_to_js(range, &mut cx);
     }
     Ok(cx.undefined().upcast())
 }
+/// Converts a source range to `[start_lno, start_cno, end_lno, end_cno, path]`.
+fn source_range_to_js<'a>(range: SourceRange, cx: &mut impl Context<'a>) -> JsResult<'a, JsValue> {
+    let SourceRange { path, start: (slno, scno), end: (elno, ecno) } = range;
+
+    let arr = [slno, scno, elno, ecno].try_into_js(cx)?;
+    let path = path.map(|p| p.to_string_lossy().into_owned()).try_into_js(cx)?;
+    arr.set(cx, 4, path)?;
+    Ok(arr.upcast())
+}
 
 fn get_timer_remaining(mut cx: FunctionContext) -> JsResult<JsNumber> {
     // fn() -> u32
@@ -600,6 +1208,14 @@ fn set_timer_max(mut cx: FunctionContext) -> JsResult<JsUndefined> {
 fn main(mut cx: ModuleContext) -> NeonResult<()> {
     cx.export_function("assemble", assemble)?;
     cx.export_function("link", link)?;
+    cx.export_function("createLibrary", create_library)?;
+    cx.export_function("build", build)?;
+    cx.export_function("getDiagnostics", get_diagnostics)?;
+    cx.export_function("formatSource", format_source)?;
+    cx.export_function("disassembleObject", disassemble_object)?;
+    cx.export_function("inspectObject", inspect_object)?;
+    cx.export_function("diffObjects", diff_objects)?;
+    cx.export_function("exportObjectImage", export_object_image)?;
     cx.export_function("getCurrSymTable", get_curr_sym_table)?;
     cx.export_function("setIgnorePrivilege", set_ignore_privilege)?;
     cx.export_function("setPauseOnFatalTrap", set_pause_on_fatal_trap)?;
@@ -617,6 +1233,9 @@ fn main(mut cx: ModuleContext) -> NeonResult<()> {
     cx.export_function("setMemValue", set_mem_value)?;
     cx.export_function("getMemLine", get_mem_line)?;
     cx.export_function("takeMemChanges", take_mem_changes)?;
+    cx.export_function("disassembleRegion", disassemble_region)?;
+    cx.export_function("exportDisassembly", export_disassembly)?;
+    cx.export_function("exportMemoryImage", export_memory_image)?;
     cx.export_function("clearInput", clear_input)?;
     cx.export_function("addInput", add_input)?;
     cx.export_function("getAndClearOutput", get_and_clear_output)?;
@@ -626,6 +1245,8 @@ fn main(mut cx: ModuleContext) -> NeonResult<()> {
     cx.export_function("removeBreakpoint", remove_breakpoint)?;
     cx.export_function("didHitBreakpoint", did_hit_breakpoint)?;
     cx.export_function("getFrameNumber", get_frame_number)?;
+    cx.export_function("getStackFrames", get_stack_frames)?;
+    cx.export_function("setSubroutineDef", set_subroutine_def)?;
     cx.export_function("isSimRunning", is_sim_running)?;
     cx.export_function("getLabelSourceRange", get_label_source_range)?;
     cx.export_function("getAddrSourceRange", get_addr_source_range)?;
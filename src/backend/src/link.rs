@@ -0,0 +1,687 @@
+//! Linking object files, and the map of where everything in a linked program came from.
+//!
+//! Ensemble's linker doesn't know which object file its errors come from, so they are found here beforehand
+//! (where it's still known which object file each section and label is from) and reported in the source of
+//! the object files: overlapping sections at their `.ORIG`s, labels defined by several object files at each definition,
+//! and unresolved external labels at their `.EXTERNAL`s.
+//!
+//! Relocatable sections (see [`crate::reloc`]) are placed before linking: at their address in the linker script (if there is one),
+//! or else packed into the first free space, in order. A linker script has a line for each section it places,
+//! `NAME address` (where a section is named by its first label), and can have a `* address` line,
+//! the address the rest of the sections are packed from (x3000 by default). `;` starts a comment.
+//!
+//! A linker map (`.map`) lists:
+//! - every section (the block of memory from an `.ORIG`) with its origin, length, and object file
+//! - every label with its address and the object file which defines it
+//! - every external label with the object file which resolved it and where it is referenced
+
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use lc3_ensemble::asm::ObjectFile;
+use lc3_ensemble::err::ErrSpan;
+use miette::Severity;
+
+use crate::err::Reporter;
+use crate::expr::parse_literal;
+use crate::obj::{to_chunks, Chunk};
+use crate::reloc::{self, RelocateErr, Relocs, RELOC_START};
+
+/// A section of an object file (the block of memory from an `.ORIG`).
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) struct Section {
+    pub(crate) start: u16,
+    pub(crate) len: u16,
+}
+impl Section {
+    /// The range of addresses of this section.
+    pub(crate) fn range(self) -> Range<u32> {
+        u32::from(self.start)..u32::from(self.start) + u32::from(self.len)
+    }
+}
+impl std::fmt::Display for Section {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let end = self.start.wrapping_add(self.len.saturating_sub(1));
+        write!(f, "x{:04X}-x{end:04X}", self.start)
+    }
+}
+
+/// The sections of an object file, in order of address.
+pub(crate) fn sections(obj: &ObjectFile) -> Vec<Section> {
+    let mut sections: Vec<_> = to_chunks(obj).into_iter()
+        .filter_map(|c| match c {
+            Chunk::Memory { start, words } => Some(Section { start, len: words.len() as u16 }),
+            _ => None,
+        })
+        .filter(|s| s.len > 0)
+        .collect();
+    sections.sort_by_key(|s| s.start);
+    sections
+}
+/// The relocations of an object file (the addresses which refer to an external label, and the label).
+fn relocations(obj: &ObjectFile) -> Vec<(u16, String)> {
+    let mut relocations: Vec<_> = to_chunks(obj).into_iter()
+        .filter_map(|c| match c {
+            Chunk::Relocation { addr, name } => Some((addr, name)),
+            _ => None,
+        })
+        .collect();
+    relocations.sort();
+    relocations
+}
+
+/// The kinds of errors from linking.
+#[derive(Debug)]
+pub(crate) enum LinkErrKind {
+    /// A section overlaps with a section of another object file.
+    OverlappingSections { section: Section, other: Section, other_file: String },
+    /// The section which another section overlapped with (reported alongside [`LinkErrKind::OverlappingSections`]).
+    OverlappedSection { section: Section },
+    /// A label is defined by another object file too.
+    DuplicateLabel { name: String, other_file: String },
+    /// The other definition of a label (reported alongside [`LinkErrKind::DuplicateLabel`]).
+    OtherDefinition { name: String },
+    /// An external label isn't defined by any object file.
+    UnresolvedExternal { name: String },
+    /// A relocatable section doesn't fit anywhere in free memory.
+    NoSpace { len: u16 },
+    /// A PC offset to or from a relocatable section doesn't fit once the section is placed.
+    RelocatedOffset { offset: i32, bits: u8 },
+    /// An object file can't be written back once its relocatable sections are placed.
+    Unrelocatable,
+    /// A line of a linker script is invalid.
+    InvalidScript(&'static str),
+    /// A linker script places a section which none of the object files have.
+    UnknownSection { name: String },
+}
+/// An error from linking, in the source of one of the object files.
+#[derive(Debug)]
+pub(crate) struct LinkErr {
+    pub(crate) kind: LinkErrKind,
+    span: Option<Range<usize>>,
+}
+impl LinkErr {
+    pub(crate) fn code(&self) -> &'static str {
+        match self.kind {
+            LinkErrKind::OverlappingSections { .. } => "link::overlapping-sections",
+            LinkErrKind::OverlappedSection { .. }   => "link::overlapping-sections",
+            LinkErrKind::DuplicateLabel { .. }      => "link::duplicate-label",
+            LinkErrKind::OtherDefinition { .. }     => "link::duplicate-label",
+            LinkErrKind::UnresolvedExternal { .. }  => "link::unresolved-external",
+            LinkErrKind::NoSpace { .. }             => "link::no-space",
+            LinkErrKind::RelocatedOffset { .. }     => "link::offset-range",
+            LinkErrKind::Unrelocatable              => "link::unrelocatable",
+            LinkErrKind::InvalidScript(_)           => "link::invalid-script",
+            LinkErrKind::UnknownSection { .. }      => "link::unknown-section",
+        }
+    }
+    pub(crate) fn severity(&self) -> Severity {
+        match self.kind {
+            LinkErrKind::OverlappingSections { .. } => Severity::Error,
+            LinkErrKind::OverlappedSection { .. }   => Severity::Advice,
+            LinkErrKind::DuplicateLabel { .. }      => Severity::Error,
+            LinkErrKind::OtherDefinition { .. }     => Severity::Advice,
+            LinkErrKind::UnresolvedExternal { .. }  => Severity::Error,
+            LinkErrKind::NoSpace { .. }             => Severity::Error,
+            LinkErrKind::RelocatedOffset { .. }     => Severity::Error,
+            LinkErrKind::Unrelocatable              => Severity::Error,
+            LinkErrKind::InvalidScript(_)           => Severity::Error,
+            LinkErrKind::UnknownSection { .. }      => Severity::Error,
+        }
+    }
+}
+impl std::fmt::Display for LinkErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.kind {
+            LinkErrKind::OverlappingSections { section, other, other_file } => write!(f, "section {section} overlaps with section {other} of {other_file}"),
+            LinkErrKind::OverlappedSection { section } => write!(f, "section {section} is overlapped by another section"),
+            LinkErrKind::DuplicateLabel { name, other_file } => write!(f, "label {name} is also defined in {other_file}"),
+            LinkErrKind::OtherDefinition { name } => write!(f, "label {name} is first defined here"),
+            LinkErrKind::UnresolvedExternal { name } => write!(f, "external label {name} is not defined by any object file"),
+            LinkErrKind::NoSpace { len } => write!(f, "no free space for relocatable section of {len} words"),
+            LinkErrKind::RelocatedOffset { offset, bits } => write!(f, "relocated PC offset {offset} does not fit in {bits} bits"),
+            LinkErrKind::Unrelocatable => f.write_str("object file could not be written with its relocatable sections placed"),
+            LinkErrKind::InvalidScript(msg) => f.write_str(msg),
+            LinkErrKind::UnknownSection { name } => write!(f, "no relocatable section is named {name}"),
+        }
+    }
+}
+impl std::error::Error for LinkErr {}
+impl lc3_ensemble::err::Error for LinkErr {
+    fn span(&self) -> Option<ErrSpan> {
+        self.span.clone().map(ErrSpan::from)
+    }
+
+    fn help(&self) -> Option<Cow<'_, str>> {
+        match self.kind {
+            LinkErrKind::OverlappingSections { .. } => Some(Cow::Borrowed("change the .ORIG of one of the sections so that they don't share any addresses")),
+            LinkErrKind::DuplicateLabel { .. } => Some(Cow::Borrowed("labels must be unique across linked files, try renaming one of the labels")),
+            LinkErrKind::UnresolvedExternal { .. } => Some(Cow::Borrowed("link with the object file which defines the label")),
+            LinkErrKind::NoSpace { .. } => Some(Cow::Borrowed("place the section in a linker script, or move sections with a .ORIG out of the way")),
+            LinkErrKind::RelocatedOffset { .. } => Some(Cow::Borrowed("place the sections closer together (e.g., with a linker script), or reach the label through a .FILL")),
+            LinkErrKind::InvalidScript(_) => Some(Cow::Borrowed("each line of a linker script is `NAME address` or `* address`")),
+            LinkErrKind::UnknownSection { .. } => Some(Cow::Borrowed("relocatable sections are named by their first label")),
+            | LinkErrKind::OverlappedSection { .. }
+            | LinkErrKind::OtherDefinition { .. }
+            | LinkErrKind::Unrelocatable => None,
+        }
+    }
+}
+
+/// A link error along with the object file it's in.
+pub(crate) struct LocatedErr<'o> {
+    pub(crate) err: LinkErr,
+    /// The file the error is reported in.
+    path: PathBuf,
+    /// The source of the object file (if it has any).
+    src: Option<&'o str>,
+}
+impl<'o> LocatedErr<'o> {
+    /// Creates an error in an object file, at a span in its source (if it has any).
+    fn new(obj_path: &Path, kind: LinkErrKind, located: Option<(&'o str, Range<usize>)>) -> Self {
+        let (src, span) = located.unzip();
+        LocatedErr { err: LinkErr { kind, span }, path: source_path(obj_path), src }
+    }
+
+
+    pub(crate) fn reporter(&self) -> Reporter<'_, LinkErr> {
+        let reporter = match self.src {
+            Some(src) => Reporter::ensemble(&self.err, &self.path, src),
+            None => Reporter::io(&self.err, &self.path),
+        };
+        reporter.with_code(self.err.code()).with_severity(self.err.severity())
+    }
+}
+
+/// The file errors in an object file's source are reported in:
+/// the source file next to it (if there is one), or else the object file itself.
+fn source_path(obj_path: &Path) -> PathBuf {
+    let asm = obj_path.with_extension("asm");
+    match asm.is_file() {
+        true  => asm,
+        false => obj_path.to_path_buf(),
+    }
+}
+/// Finds the `.ORIG` of a section in the source of its object file.
+fn orig_span(obj: &ObjectFile, section: Section) -> Option<(&str, Range<usize>)> {
+    let sym = obj.symbol_table()?;
+    let info = sym.source_info()?;
+    let first_line = sym.line_iter()
+        .filter(|&(_, addr)| addr == section.start)
+        .map(|(lno, _)| lno)
+        .min()?;
+    let is_orig = |lno: usize| info.read_line(lno)
+        .and_then(|line| line.split_whitespace().next())
+        .is_some_and(|word| word.eq_ignore_ascii_case(".ORIG") || word.eq_ignore_ascii_case(".RELOC"));
+    let lno = (0..=first_line).rev().find(|&lno| is_orig(lno)).unwrap_or(first_line);
+
+    let span = info.line_span(lno)?;
+    let line = info.source().get(span.clone())?;
+    let start = span.start + (line.len() - line.trim_start().len());
+    let end = span.start + line.trim_end().len();
+    Some((info.source(), start..end))
+}
+/// Finds the line of the statement at an address in the source of its object file.
+fn addr_span(obj: &ObjectFile, addr: u16) -> Option<(&str, Range<usize>)> {
+    let sym = obj.symbol_table()?;
+    let info = sym.source_info()?;
+    let (lno, _) = sym.line_iter().find(|&(_, a)| a == addr)?;
+
+    let span = info.line_span(lno)?;
+    let line = info.source().get(span.clone())?;
+    let start = span.start + (line.len() - line.trim_start().len());
+    Some((info.source(), start..span.start + line.trim_end().len()))
+}
+/// Finds the definition of a label (or its `.EXTERNAL`) in the source of its object file.
+fn label_span<'o>(obj: &'o ObjectFile, name: &str) -> Option<(&'o str, Range<usize>)> {
+    let sym = obj.symbol_table()?;
+    let src = sym.source_info()?.source();
+    let span = sym.get_label_source(name).filter(|s| s.end <= src.len())?;
+    Some((src, span))
+}
+
+/// A linker script, which places relocatable sections at fixed addresses.
+pub(crate) struct LinkScript<'s> {
+    path: PathBuf,
+    src: &'s str,
+    /// Each section's name and address (and the span of its line).
+    placements: Vec<(&'s str, u16, Range<usize>)>,
+    /// The address the rest of the sections are packed from.
+    rest: u16,
+}
+impl<'s> LinkScript<'s> {
+    /// Parses a linker script (from the file at the given path).
+    pub(crate) fn parse(path: &Path, src: &'s str) -> Result<Self, Vec<LocatedErr<'s>>> {
+        let mut script = LinkScript { path: path.to_path_buf(), src, placements: vec![], rest: RELOC_START };
+        let mut errors = vec![];
+        let mut start = 0;
+        for line in src.split_inclusive('\n') {
+            let code = line.split(';').next().unwrap_or_default();
+            let span = start + (code.len() - code.trim_start().len())..start + code.trim_end().len();
+            start += line.len();
+
+            let addr = |word: &str| parse_literal(word).and_then(|a| u16::try_from(a).ok());
+            match code.split_whitespace().collect::<Vec<_>>()[..] {
+                [] => {},
+                ["*", addr_word] => match addr(addr_word) {
+                    Some(addr) => script.rest = addr,
+                    None => errors.push(script.error(LinkErrKind::InvalidScript("invalid address"), span)),
+                },
+                [name, addr_word] => match addr(addr_word) {
+                    Some(addr) => script.placements.push((name, addr, span)),
+                    None => errors.push(script.error(LinkErrKind::InvalidScript("invalid address"), span)),
+                },
+                _ => errors.push(script.error(LinkErrKind::InvalidScript("expected a section name and an address"), span)),
+            }
+        }
+
+        match errors.is_empty() {
+            true  => Ok(script),
+            false => Err(errors),
+        }
+    }
+
+    /// An error at a span of the linker script.
+    fn error(&self, kind: LinkErrKind, span: Range<usize>) -> LocatedErr<'s> {
+        LocatedErr { err: LinkErr { kind, span: Some(span) }, path: self.path.clone(), src: Some(self.src) }
+    }
+}
+
+/// Places the relocatable sections of the object files (given with their paths and relocatable sections):
+/// at their address in the linker script (if there is one), or else packed into the first free space, in order.
+///
+/// This returns the object files with their sections moved, or errors for sections which don't fit anywhere
+/// and PC offsets which no longer fit.
+pub(crate) fn place<'o>(inputs: &'o [(PathBuf, ObjectFile, Relocs)], script: Option<&LinkScript<'o>>) -> Result<Vec<(PathBuf, ObjectFile)>, Vec<LocatedErr<'o>>> {
+    let mut errors = vec![];
+
+    // Everything with a .ORIG stays where it is:
+    let mut occupied: Vec<_> = inputs.iter()
+        .flat_map(|(_, obj, relocs)| {
+            sections(obj).into_iter().filter(|s| !relocs.sections.iter().any(|r| r.start == s.start))
+        })
+        .map(Section::range)
+        .collect();
+    let mut starts: Vec<Vec<Option<u16>>> = inputs.iter().map(|(_, _, relocs)| vec![None; relocs.sections.len()]).collect();
+
+    if let Some(script) = script {
+        for (name, addr, span) in &script.placements {
+            let found = inputs.iter().enumerate().find_map(|(i, (_, _, relocs))| {
+                let j = relocs.sections.iter().position(|s| s.name.as_ref().is_some_and(|n| n.eq_ignore_ascii_case(name)))?;
+                Some((i, j))
+            });
+            match found {
+                Some((i, j)) => {
+                    starts[i][j] = Some(*addr);
+                    occupied.push(Section { start: *addr, len: inputs[i].2.sections[j].len }.range());
+                },
+                None => errors.push(script.error(LinkErrKind::UnknownSection { name: name.to_string() }, span.clone())),
+            }
+        }
+    }
+
+    let unplaced: Vec<_> = starts.iter().enumerate()
+        .flat_map(|(i, s)| s.iter().enumerate().filter(|(_, s)| s.is_none()).map(move |(j, _)| (i, j)))
+        .collect();
+    let lens: Vec<_> = unplaced.iter().map(|&(i, j)| inputs[i].2.sections[j].len).collect();
+    let from = script.map_or(RELOC_START, |s| s.rest);
+    for (&(i, j), placed) in unplaced.iter().zip(reloc::pack(&occupied, from, &lens, 0)) {
+        match placed {
+            Some(addr) => starts[i][j] = Some(addr),
+            None => {
+                let (path, obj, relocs) = &inputs[i];
+                let section = &relocs.sections[j];
+                let located = orig_span(obj, Section { start: section.start, len: section.len });
+                errors.push(LocatedErr::new(path, LinkErrKind::NoSpace { len: section.len }, located));
+            },
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let mut placed = vec![];
+    for ((path, obj, relocs), starts) in inputs.iter().zip(starts) {
+        let starts: Vec<_> = starts.into_iter().flatten().collect();
+        match reloc::relocate(obj, relocs, &starts) {
+            Ok(obj) => placed.push((path.clone(), obj)),
+            Err(RelocateErr::Overflows(overflows)) => errors.extend({
+                overflows.into_iter()
+                    .map(|o| LocatedErr::new(path, LinkErrKind::RelocatedOffset { offset: o.offset, bits: o.bits }, addr_span(obj, o.addr)))
+            }),
+            Err(RelocateErr::Unreadable) => errors.push(LocatedErr::new(path, LinkErrKind::Unrelocatable, None)),
+        }
+    }
+    match errors.is_empty() {
+        true  => Ok(placed),
+        false => Err(errors),
+    }
+}
+
+/// Finds the first pair of sections (from different object files) which overlap,
+/// returning an error for each (at their `.ORIG`s, if the object files have source).
+///
+/// Object files are given with their paths, and are named relative to `dir` in the errors.
+pub(crate) fn check_overlaps<'o>(inputs: &'o [(PathBuf, ObjectFile)], dir: &Path) -> Vec<LocatedErr<'o>> {
+    let mut all: Vec<_> = inputs.iter()
+        .enumerate()
+        .flat_map(|(i, (_, obj))| sections(obj).into_iter().map(move |s| (i, s)))
+        .collect();
+    all.sort_by_key(|&(i, s)| (s.start, i));
+
+    let overlap = all.iter()
+        .enumerate()
+        .flat_map(|(n, a)| all[n + 1..].iter().map(move |b| (a, b)))
+        .find(|&(&(ai, a), &(bi, b))| ai != bi && a.range().end > b.range().start);
+    let Some((&(ai, a), &(bi, b))) = overlap else { return vec![] };
+
+    // Report the error in the later object file:
+    let ((first, first_section), (second, second_section)) = match ai < bi {
+        true  => ((ai, a), (bi, b)),
+        false => ((bi, b), (ai, a)),
+    };
+    let locate = |i: usize, section: Section, kind: LinkErrKind| {
+        let (path, obj) = &inputs[i];
+        LocatedErr::new(path, kind, orig_span(obj, section))
+    };
+    vec![
+        locate(second, second_section, LinkErrKind::OverlappingSections {
+            section: second_section,
+            other: first_section,
+            other_file: display(&inputs[first].0, dir),
+        }),
+        locate(first, first_section, LinkErrKind::OverlappedSection { section: first_section }),
+    ]
+}
+
+/// Finds the labels which are defined by several object files,
+/// returning an error at each later definition (along with a note at the first).
+///
+/// Object files are given with their paths, and are named relative to `dir` in the errors.
+pub(crate) fn check_duplicates<'o>(inputs: &'o [(PathBuf, ObjectFile)], dir: &Path) -> Vec<LocatedErr<'o>> {
+    find_duplicates(inputs, dir, true)
+}
+/// Finds the labels which are defined by several members of a library (see [`check_duplicates`]).
+///
+/// Unlike when linking, labels defined at the same address are also errors,
+/// since the library's index can only name one member for each label.
+pub(crate) fn check_library_duplicates<'o>(inputs: &'o [(PathBuf, ObjectFile)], dir: &Path) -> Vec<LocatedErr<'o>> {
+    find_duplicates(inputs, dir, false)
+}
+fn find_duplicates<'o>(inputs: &'o [(PathBuf, ObjectFile)], dir: &Path, allow_same_addr: bool) -> Vec<LocatedErr<'o>> {
+    let mut first_defs: HashMap<&str, (usize, u16)> = HashMap::new();
+    let mut errors = vec![];
+    for (i, (path, obj)) in inputs.iter().enumerate() {
+        let mut labels: Vec<_> = defined_labels(obj).collect();
+        labels.sort();
+        for (name, addr) in labels {
+            match first_defs.get(name) {
+                // Ensemble allows labels to be defined several times at the same address:
+                Some(&(j, first_addr)) if !allow_same_addr || first_addr != addr => {
+                    let (first_path, first_obj) = &inputs[j];
+                    errors.push(LocatedErr::new(path, LinkErrKind::DuplicateLabel {
+                        name: name.to_string(),
+                        other_file: display(first_path, dir),
+                    }, label_span(obj, name)));
+                    errors.push(LocatedErr::new(first_path, LinkErrKind::OtherDefinition {
+                        name: name.to_string(),
+                    }, label_span(first_obj, name)));
+                },
+                Some(_) => {},
+                None => { first_defs.insert(name, (i, addr)); },
+            }
+        }
+    }
+    errors
+}
+/// Finds the external labels which aren't defined by any of the object files,
+/// returning an error at each of their `.EXTERNAL`s.
+pub(crate) fn check_unresolved(inputs: &[(PathBuf, ObjectFile)]) -> Vec<LocatedErr<'_>> {
+    let defined: HashSet<&str> = inputs.iter()
+        .flat_map(|(_, obj)| defined_labels(obj).map(|(name, _)| name))
+        .collect();
+
+    let mut errors = vec![];
+    for (path, obj) in inputs {
+        let mut externals: Vec<_> = obj.symbol_table().into_iter()
+            .flat_map(|sym| sym.label_iter())
+            .filter(|&(name, _, external)| external && !defined.contains(name))
+            .map(|(name, _, _)| name)
+            .collect();
+        externals.sort();
+        errors.extend({
+            externals.into_iter()
+                .map(|name| LocatedErr::new(path, LinkErrKind::UnresolvedExternal { name: name.to_string() }, label_span(obj, name)))
+        });
+    }
+    errors
+}
+/// The labels an object file defines (rather than its external labels) and their addresses.
+fn defined_labels(obj: &ObjectFile) -> impl Iterator<Item=(&str, u16)> {
+    obj.symbol_table().into_iter()
+        .flat_map(|sym| sym.label_iter())
+        .filter(|&(_, _, external)| !external)
+        .map(|(name, addr, _)| (name, addr))
+}
+
+/// A path relative to `dir` (if it is in `dir`).
+fn display(path: &Path, dir: &Path) -> String {
+    path.strip_prefix(dir).unwrap_or(path).display().to_string()
+}
+
+/// Creates the linker map of the given object files (which are named relative to `dir`).
+pub(crate) fn map(inputs: &[(PathBuf, ObjectFile)], dir: &Path) -> String {
+    let names: Vec<_> = inputs.iter().map(|(path, _)| display(path, dir)).collect();
+
+    let mut section_rows = vec![];
+    let mut symbol_rows = vec![];
+    let mut externals: Vec<(String, usize)> = vec![];
+    let mut references: Vec<(String, usize, u16)> = vec![];
+    for (i, (_, obj)) in inputs.iter().enumerate() {
+        for s in sections(obj) {
+            let end = s.start.wrapping_add(s.len - 1);
+            section_rows.push([format!("x{:04X}", s.start), format!("x{end:04X}"), s.len.to_string(), names[i].clone()]);
+        }
+        for (name, addr, external) in obj.symbol_table().into_iter().flat_map(|sym| sym.label_iter()) {
+            match external {
+                true  => externals.push((name.to_string(), i)),
+                false => symbol_rows.push((addr, [name.to_string(), format!("x{addr:04X}"), names[i].clone()])),
+            }
+        }
+        references.extend(relocations(obj).into_iter().map(|(addr, name)| (name, i, addr)));
+    }
+    section_rows.sort();
+    symbol_rows.sort();
+    externals.sort();
+    externals.dedup();
+
+    let mut external_rows = vec![];
+    for (name, i) in externals {
+        let definition = inputs.iter()
+            .enumerate()
+            .find_map(|(j, (_, obj))| {
+                let (_, addr, _) = obj.symbol_table()?.label_iter().find(|&(n, _, external)| !external && n == name)?;
+                Some((j, addr))
+            });
+        let (addr, resolved_by) = match definition {
+            Some((j, addr)) => (format!("x{addr:04X}"), names[j].clone()),
+            None => (String::from("-"), String::from("(unresolved)")),
+        };
+        let referenced_at: Vec<_> = references.iter()
+            .filter(|(n, j, _)| *n == name && *j == i)
+            .map(|(_, _, addr)| format!("x{addr:04X}"))
+            .collect();
+        let referenced_by = match referenced_at.is_empty() {
+            true  => names[i].clone(),
+            false => format!("{} ({})", names[i], referenced_at.join(", ")),
+        };
+        external_rows.push([name, addr, resolved_by, referenced_by]);
+    }
+
+    let mut out = String::new();
+    out.push_str(&table("sections", ["origin", "end", "length", "object"], section_rows));
+    out.push('\n');
+    out.push_str(&table("symbols", ["name", "address", "object"], symbol_rows.into_iter().map(|(_, row)| row).collect()));
+    out.push('\n');
+    out.push_str(&table("external references", ["name", "address", "resolved by", "referenced by"], external_rows));
+    out
+}
+
+/// Writes a titled table with aligned columns.
+fn table<const N: usize>(title: &str, header: [&str; N], rows: Vec<[String; N]>) -> String {
+    let mut widths = header.map(str::len);
+    for row in &rows {
+        for (w, cell) in widths.iter_mut().zip(row) {
+            *w = (*w).max(cell.len());
+        }
+    }
+
+    let mut out = format!("{title}\n");
+    let lines = std::iter::once(header.map(String::from)).chain(rows);
+    for row in lines {
+        let line: Vec<_> = row.iter().zip(widths).map(|(cell, w)| format!("{cell:w$}")).collect();
+        out.push_str("  ");
+        out.push_str(line.join("  ").trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::tests::assemble;
+
+    /// Assembles object files, as if they were in the directory `/project`.
+    fn objects(srcs: &[(&str, &str)]) -> Vec<(PathBuf, ObjectFile)> {
+        srcs.iter()
+            .map(|&(name, src)| (Path::new("/project").join(name), assemble(src)))
+            .collect()
+    }
+
+    #[test]
+    fn maps_sections_symbols_and_externals() {
+        let inputs = objects(&[
+            ("main.obj", ".ORIG x3000\n.EXTERNAL PRINT\n.EXTERNAL MISSING\nMAIN LDI R0, PTR\nHALT\nPTR .FILL PRINT\n.FILL MISSING\n.END\n"),
+            ("lib/print.obj", ".ORIG x4000\nPRINT ADD R0, R0, #0\nRET\n.END\n.ORIG x3800\nBUFFER .BLKW 16\n.END\n"),
+        ]);
+        let expected = concat!(
+            "sections\n",
+            "  origin  end    length  object\n",
+            "  x3000   x3003  4       main.obj\n",
+            "  x3800   x380F  16      lib/print.obj\n",
+            "  x4000   x4001  2       lib/print.obj\n",
+            "\n",
+            "symbols\n",
+            "  name    address  object\n",
+            "  MAIN    x3000    main.obj\n",
+            "  PTR     x3002    main.obj\n",
+            "  BUFFER  x3800    lib/print.obj\n",
+            "  PRINT   x4000    lib/print.obj\n",
+            "\n",
+            "external references\n",
+            "  name     address  resolved by    referenced by\n",
+            "  MISSING  -        (unresolved)   main.obj (x3003)\n",
+            "  PRINT    x4000    lib/print.obj  main.obj (x3002)\n",
+        );
+        assert_eq!(map(&inputs, Path::new("/project")), expected);
+    }
+
+    #[test]
+    fn reports_overlaps_across_files() {
+        let inputs = objects(&[
+            ("a.obj", ".ORIG x3000\nHALT\n.END\n.ORIG x3002\n.BLKW 2\n.END\n"),
+            ("b.obj", ".ORIG x4000\nHALT\n.END\n"),
+            ("c.obj", "; comment\n  .ORIG x3001 ; start\n.BLKW 2\n.END\n"),
+        ]);
+        let errors = check_overlaps(&inputs, Path::new("/project"));
+        let [overlapping, overlapped] = &errors[..] else { panic!("expected an error and a note, got {} errors", errors.len()) };
+
+        // The error is in the later file, even though its section starts first:
+        assert_eq!(overlapping.path, Path::new("/project/c.obj"));
+        assert_eq!(overlapping.err.code(), "link::overlapping-sections");
+        assert_eq!(overlapping.err.severity(), Severity::Error);
+        assert_eq!(overlapping.err.to_string(), "section x3001-x3002 overlaps with section x3002-x3003 of a.obj");
+        let span = overlapping.err.span.clone().expect("error should have a span");
+        assert_eq!(&overlapping.src.expect("error should have source")[span], ".ORIG x3001 ; start");
+
+        assert_eq!(overlapped.path, Path::new("/project/a.obj"));
+        assert_eq!(overlapped.err.severity(), Severity::Advice);
+        assert_eq!(overlapped.err.to_string(), "section x3002-x3003 is overlapped by another section");
+        let span = overlapped.err.span.clone().expect("note should have a span");
+        assert_eq!(&overlapped.src.expect("note should have source")[span], ".ORIG x3002");
+
+        // Adjacent sections don't overlap:
+        let inputs = objects(&[
+            ("a.obj", ".ORIG x3000\nHALT\n.END\n"),
+            ("b.obj", ".ORIG x3001\nHALT\n.END\n"),
+        ]);
+        assert!(check_overlaps(&inputs, Path::new("/project")).is_empty());
+    }
+
+    /// The text an error's span covers.
+    fn spanned<'o>(e: &LocatedErr<'o>) -> &'o str {
+        let span = e.err.span.clone().expect("error should have a span");
+        &e.src.expect("error should have source")[span]
+    }
+
+    #[test]
+    fn reports_duplicates_at_each_definition() {
+        let inputs = objects(&[
+            ("a.obj", ".ORIG x3000\nMAIN HALT\nSHARED .FILL 1\n.END\n"),
+            ("b.obj", ".ORIG x4000\nNOP\nSHARED .FILL 2\nMAIN HALT\n.END\n"),
+        ]);
+        let errors = check_duplicates(&inputs, Path::new("/project"));
+        let messages: Vec<_> = errors.iter()
+            .map(|e| (e.path.display().to_string(), e.err.to_string(), spanned(e)))
+            .collect();
+        assert_eq!(messages, [
+            (String::from("/project/b.obj"), String::from("label MAIN is also defined in a.obj"), "MAIN"),
+            (String::from("/project/a.obj"), String::from("label MAIN is first defined here"), "MAIN"),
+            (String::from("/project/b.obj"), String::from("label SHARED is also defined in a.obj"), "SHARED"),
+            (String::from("/project/a.obj"), String::from("label SHARED is first defined here"), "SHARED"),
+        ]);
+        assert!(errors.iter().all(|e| e.err.code() == "link::duplicate-label"));
+        assert_eq!(errors.iter().map(|e| e.err.severity()).collect::<Vec<_>>(), [Severity::Error, Severity::Advice, Severity::Error, Severity::Advice]);
+    }
+
+    #[test]
+    fn duplicates_at_the_same_address_are_allowed() {
+        let inputs = objects(&[
+            ("a.obj", ".ORIG x3000\nMAIN HALT\n.END\n"),
+            ("b.obj", ".ORIG x3000\nMAIN HALT\n.END\n"),
+        ]);
+        assert!(check_duplicates(&inputs, Path::new("/project")).is_empty());
+
+        // ...except in a library, whose index names one member for each label:
+        let errors = check_library_duplicates(&inputs, Path::new("/project"));
+        assert_eq!(errors.iter().map(|e| e.err.to_string()).collect::<Vec<_>>(), [
+            "label MAIN is also defined in a.obj",
+            "label MAIN is first defined here",
+        ]);
+    }
+
+    #[test]
+    fn reports_unresolved_externals_at_their_declarations() {
+        let inputs = objects(&[
+            ("a.obj", ".ORIG x3000\n.EXTERNAL PRINT\n.EXTERNAL SCAN\n.FILL PRINT\n.FILL SCAN\n.END\n"),
+            ("b.obj", ".ORIG x4000\nPRINT RET\n.EXTERNAL SCAN\n.FILL SCAN\n.END\n"),
+        ]);
+        let errors = check_unresolved(&inputs);
+        let messages: Vec<_> = errors.iter()
+            .map(|e| (e.path.display().to_string(), e.err.to_string(), spanned(e)))
+            .collect();
+        assert_eq!(messages, [
+            (String::from("/project/a.obj"), String::from("external label SCAN is not defined by any object file"), "SCAN"),
+            (String::from("/project/b.obj"), String::from("external label SCAN is not defined by any object file"), "SCAN"),
+        ]);
+        assert!(errors.iter().all(|e| e.err.code() == "link::unresolved-external"));
+
+        // The span is the label's `.EXTERNAL`, not its use:
+        let src = errors[0].src.expect("error should have source");
+        let span = errors[0].err.span.clone().expect("error should have a span");
+        assert_eq!(&src[..span.start], ".ORIG x3000\n.EXTERNAL PRINT\n.EXTERNAL ");
+    }
+}
@@ -0,0 +1,517 @@
+//! Lint checks, which warn about code that assembles but is likely to be a mistake.
+//!
+//! Lints are run on the AST of a file which has already assembled successfully,
+//! and each lint can be individually enabled or disabled with a [`LintConfig`].
+
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+use lc3_ensemble::ast::asm::{AsmInstr, Directive, Stmt, StmtKind};
+use lc3_ensemble::ast::{PCOffset, Reg};
+use lc3_ensemble::err::ErrSpan;
+use neon::prelude::*;
+
+use crate::asm::{is_unconditional, label_span, parse_expanded, referenced_label, stmt_len, Parsed};
+use crate::dialect::Dialect;
+use crate::preproc::Expanded;
+
+/// A lint check.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub(crate) enum Lint {
+    /// Execution can run off the end of a block (e.g., there's no HALT).
+    MissingHalt,
+    /// An instruction follows an unconditional branch and has no label.
+    UnreachableCode,
+    /// A label is never referenced.
+    UnusedLabel,
+    /// Execution falls through from an instruction into data.
+    FallThroughData,
+    /// A block overlaps memory used by the operating system.
+    OsOverlap,
+    /// A PC offset to a label is close to the limit of its range.
+    OffsetNearLimit,
+    /// A subroutine calls another subroutine (or trap) without saving R7 first.
+    NestedJsr,
+    /// A `BR` is written without condition codes.
+    BrNoCc,
+}
+impl Lint {
+    pub(crate) const ALL: [Lint; 8] = [
+        Lint::MissingHalt,
+        Lint::UnreachableCode,
+        Lint::UnusedLabel,
+        Lint::FallThroughData,
+        Lint::OsOverlap,
+        Lint::OffsetNearLimit,
+        Lint::NestedJsr,
+        Lint::BrNoCc,
+    ];
+
+    /// The name used to refer to this lint in configuration.
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Lint::MissingHalt     => "missing-halt",
+            Lint::UnreachableCode => "unreachable-code",
+            Lint::UnusedLabel     => "unused-label",
+            Lint::FallThroughData => "fall-through-data",
+            Lint::OsOverlap       => "os-overlap",
+            Lint::OffsetNearLimit => "offset-near-limit",
+            Lint::NestedJsr       => "nested-jsr",
+            Lint::BrNoCc          => "br-no-cc",
+        }
+    }
+
+    /// The diagnostic code of this lint's warnings.
+    pub(crate) fn code(self) -> &'static str {
+        match self {
+            Lint::MissingHalt     => "lint::missing-halt",
+            Lint::UnreachableCode => "lint::unreachable-code",
+            Lint::UnusedLabel     => "lint::unused-label",
+            Lint::FallThroughData => "lint::fall-through-data",
+            Lint::OsOverlap       => "lint::os-overlap",
+            Lint::OffsetNearLimit => "lint::offset-near-limit",
+            Lint::NestedJsr       => "lint::nested-jsr",
+            Lint::BrNoCc          => "lint::br-no-cc",
+        }
+    }
+
+    /// Finds the lint with the given name (with or without the `lint::` prefix).
+    pub(crate) fn from_name(name: &str) -> Option<Lint> {
+        let name = name.strip_prefix("lint::").unwrap_or(name);
+        Lint::ALL.into_iter().find(|l| l.name() == name)
+    }
+}
+
+/// Which lints are enabled.
+///
+/// By default, every lint is enabled.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct LintConfig {
+    disabled: HashSet<Lint>,
+}
+impl LintConfig {
+    pub(crate) fn is_enabled(&self, lint: Lint) -> bool {
+        !self.disabled.contains(&lint)
+    }
+
+    pub(crate) fn set_enabled(&mut self, lint: Lint, enabled: bool) {
+        match enabled {
+            true  => self.disabled.remove(&lint),
+            false => self.disabled.insert(lint),
+        };
+    }
+
+    /// Reads the config from a JS object mapping lint names to whether they are enabled
+    /// (e.g., `{ "unused-label": false }`).
+    pub(crate) fn from_js<'a>(cx: &mut impl Context<'a>, obj: Handle<'a, JsObject>) -> NeonResult<Self> {
+        let mut config = LintConfig::default();
+
+        let keys = obj.get_own_property_names(cx)?.to_vec(cx)?;
+        for key in keys {
+            let name = key.downcast_or_throw::<JsString, _>(cx)?.value(cx);
+            let Some(lint) = Lint::from_name(&name) else {
+                return cx.throw_error(format!("unknown lint {name:?}"));
+            };
+            let enabled = obj.get::<JsBoolean, _, _>(cx, &*name)?.value(cx);
+            config.set_enabled(lint, enabled);
+        }
+
+        Ok(config)
+    }
+}
+
+/// A warning produced by a lint.
+#[derive(Debug)]
+pub(crate) struct LintWarning {
+    pub(crate) lint: Lint,
+    message: String,
+    span: Range<usize>,
+    help: Option<Cow<'static, str>>,
+}
+impl std::fmt::Display for LintWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+impl std::error::Error for LintWarning {}
+impl lc3_ensemble::err::Error for LintWarning {
+    fn span(&self) -> Option<ErrSpan> {
+        Some(ErrSpan::from(self.span.clone()))
+    }
+
+    fn help(&self) -> Option<Cow<'_, str>> {
+        self.help.clone()
+    }
+}
+
+/// Whether this instruction overwrites R7 with a return address.
+fn overwrites_r7(instr: &AsmInstr) -> bool {
+    matches!(instr,
+        | AsmInstr::JSR(_) | AsmInstr::JSRR(_) | AsmInstr::TRAP(_)
+        | AsmInstr::GETC | AsmInstr::OUT | AsmInstr::PUTC
+        | AsmInstr::PUTS | AsmInstr::IN | AsmInstr::PUTSP
+    )
+}
+/// Whether this instruction saves R7 somewhere (to memory or another register).
+fn saves_r7(instr: &AsmInstr) -> bool {
+    matches!(instr,
+        | AsmInstr::ST(Reg::R7, _) | AsmInstr::STI(Reg::R7, _) | AsmInstr::STR(Reg::R7, _, _)
+        | AsmInstr::ADD(_, Reg::R7, _) | AsmInstr::AND(_, Reg::R7, _)
+    )
+}
+/// The label this instruction references by PC offset, along with the size of the offset.
+fn pc_offset_label(instr: &AsmInstr) -> Option<(&str, Range<usize>, u32)> {
+    let (label, bits) = match instr {
+        | AsmInstr::BR(_, PCOffset::Label(l))
+        | AsmInstr::LD(_, PCOffset::Label(l))
+        | AsmInstr::LDI(_, PCOffset::Label(l))
+        | AsmInstr::LEA(_, PCOffset::Label(l))
+        | AsmInstr::ST(_, PCOffset::Label(l))
+        | AsmInstr::STI(_, PCOffset::Label(l))
+        | AsmInstr::NOP(PCOffset::Label(l)) => (l, 9),
+        AsmInstr::JSR(PCOffset::Label(l)) => (l, 11),
+        _ => return None,
+    };
+
+    Some((&label.name, label.span(), bits))
+}
+
+/// An `.orig` block.
+#[derive(Clone)]
+struct Block {
+    /// The index of the `.orig` statement.
+    orig: usize,
+    /// The statements in the block (excluding `.orig` and `.end`).
+    stmts: Range<usize>,
+    /// The addresses the block occupies.
+    addrs: Range<u16>,
+}
+
+struct Linter<'a> {
+    src: &'a str,
+    ast: &'a [Stmt],
+    /// The (uppercase) labels used in operand expressions.
+    expr_labels: &'a HashSet<String>,
+    config: &'a LintConfig,
+    /// The address of each statement (if it is in a block).
+    addrs: Vec<Option<u16>>,
+    blocks: Vec<Block>,
+    /// The address of each label (keyed by its uppercase name).
+    labels: HashMap<String, u16>,
+    warnings: Vec<LintWarning>,
+}
+impl<'a> Linter<'a> {
+    fn new(src: &'a str, ast: &'a [Stmt], expr_labels: &'a HashSet<String>, config: &'a LintConfig) -> Self {
+        let mut addrs = Vec::with_capacity(ast.len());
+        let mut blocks = vec![];
+        let mut labels = HashMap::new();
+
+        let mut pc = None;
+        let mut block_start: Option<(usize, u16)> = None;
+        for (i, stmt) in ast.iter().enumerate() {
+            match &stmt.nucleus {
+                StmtKind::Directive(Directive::Orig(addr)) => {
+                    pc.replace(addr.get());
+                    block_start.replace((i, addr.get()));
+                },
+                StmtKind::Directive(Directive::End) => {
+                    let end = pc.take();
+                    if let (Some((orig, start)), Some(end)) = (block_start.take(), end) {
+                        blocks.push(Block { orig, stmts: orig + 1 .. i, addrs: start..end });
+                    }
+                },
+                _ => {},
+            }
+
+            addrs.push(pc);
+            if let Some(addr) = pc {
+                labels.extend(stmt.labels.iter().map(|l| (l.name.to_uppercase(), addr)));
+                pc.replace(addr.wrapping_add(stmt_len(stmt)));
+            }
+        }
+
+        Linter { src, ast, expr_labels, config, addrs, blocks, labels, warnings: vec![] }
+    }
+
+    fn warn(&mut self, lint: Lint, span: Range<usize>, message: String, help: &'static str) {
+        if self.config.is_enabled(lint) {
+            self.warnings.push(LintWarning { lint, message, span, help: Some(Cow::Borrowed(help)) });
+        }
+    }
+
+    /// Checks for unreachable code, data reached by fall-through,
+    /// and execution running past the end of a block.
+    fn check_control_flow(&mut self) {
+        for Block { stmts: block, .. } in self.blocks.clone() {
+            // Whether execution can continue from the previous statement into this one.
+            let mut falls_through = false;
+            // Whether unreachable code has already been reported for the current run of statements.
+            let mut reported_unreachable = false;
+
+            for (i, stmt) in self.ast[block.clone()].iter().enumerate() {
+                let reachable = i == 0 || falls_through || !stmt.labels.is_empty();
+                if !stmt.labels.is_empty() {
+                    reported_unreachable = false;
+                }
+
+                match &stmt.nucleus {
+                    StmtKind::Instr(instr) => {
+                        if !reachable && !reported_unreachable {
+                            reported_unreachable = true;
+                            self.warn(Lint::UnreachableCode, stmt.span.clone(),
+                                String::from("this instruction can never be executed"),
+                                "nothing branches here, add a label if something should"
+                            );
+                        }
+                        falls_through = reachable && !is_unconditional(instr);
+                    },
+                    StmtKind::Directive(Directive::Fill(_) | Directive::Blkw(_) | Directive::Stringz(_)) => {
+                        if falls_through {
+                            self.warn(Lint::FallThroughData, stmt.span.clone(),
+                                String::from("execution falls through from the previous instruction into data"),
+                                "add a HALT or branch before this data"
+                            );
+                        }
+                        falls_through = false;
+                    },
+                    StmtKind::Directive(_) => {},
+                }
+            }
+
+            if falls_through {
+                let span = self.ast[block.end - 1].span.clone();
+                self.warn(Lint::MissingHalt, span,
+                    String::from("execution can run past the end of this block"),
+                    "add a HALT (or a branch) at the end of the program"
+                );
+            }
+        }
+    }
+
+    /// Checks for labels which are never referenced.
+    fn check_unused_labels(&mut self) {
+        let used: HashSet<_> = self.ast.iter()
+            .filter_map(referenced_label)
+            .map(|l| l.name.to_uppercase())
+            .chain(self.expr_labels.iter().cloned())
+            .collect();
+
+        for stmt in self.ast {
+            for label in &stmt.labels {
+                if !used.contains(&label.name.to_uppercase()) {
+                    self.warn(Lint::UnusedLabel, label_span(self.src, label.span()),
+                        format!("label `{}` is never used", label.name),
+                        "remove the label, or disable this lint if the label is read externally"
+                    );
+                }
+            }
+        }
+    }
+
+    /// Checks for blocks which overlap the operating system.
+    fn check_os_overlap(&mut self) {
+        let os_addrs: HashSet<_> = lc3_ensemble::sim::_os_obj_file()
+            .addr_iter()
+            .filter_map(|(addr, val)| val.map(|_| addr))
+            .collect();
+
+        for Block { orig, addrs: Range { start, end }, .. } in self.blocks.clone() {
+            let len = end.wrapping_sub(start);
+
+            if (0..len).any(|i| os_addrs.contains(&start.wrapping_add(i))) {
+                self.warn(Lint::OsOverlap, self.ast[orig].span.clone(),
+                    format!("block at x{start:04X}-x{:04X} overlaps memory used by the operating system", end.wrapping_sub(1)),
+                    "user programs typically start at x3000"
+                );
+            }
+        }
+    }
+
+    /// Checks for PC offsets which are close to the limits of their range.
+    fn check_offsets(&mut self) {
+        for (stmt, addr) in self.ast.iter().zip(self.addrs.clone()) {
+            let (StmtKind::Instr(instr), Some(addr)) = (&stmt.nucleus, addr) else { continue };
+            let Some((label, span, bits)) = pc_offset_label(instr) else { continue };
+            let Some(&target) = self.labels.get(&label.to_uppercase()) else { continue };
+
+            let offset = target.wrapping_sub(addr.wrapping_add(1)) as i16 as i32;
+            let max = (1 << (bits - 1)) - 1;
+            let min = -(1 << (bits - 1));
+            // Warn within 1/8 of the limit.
+            let margin = 1 << (bits - 4);
+
+            if offset > max - margin || offset < min + margin {
+                self.warn(Lint::OffsetNearLimit, label_span(self.src, span),
+                    format!("offset to `{label}` is {offset}, close to the limit of the {bits}-bit range [{min}, {max}]"),
+                    "adding code between this instruction and the label may make it unreachable"
+                );
+            }
+        }
+    }
+
+    /// Checks for subroutines which call other subroutines without saving R7.
+    fn check_nested_jsr(&mut self) {
+        let entries: HashSet<_> = self.ast.iter()
+            .filter_map(|stmt| match &stmt.nucleus {
+                StmtKind::Instr(AsmInstr::JSR(PCOffset::Label(l))) => Some(l.name.to_uppercase()),
+                _ => None,
+            })
+            .collect();
+
+        for Block { stmts: block, .. } in self.blocks.clone() {
+            for start in block.clone() {
+                let Some(name) = self.ast[start].labels.iter().find(|l| entries.contains(&l.name.to_uppercase())) else { continue };
+                let name = name.name.clone();
+
+                let mut saved = false;
+                for (i, stmt) in self.ast[start..block.end].iter().enumerate() {
+                    // Stop at the start of the next subroutine.
+                    if i != 0 && stmt.labels.iter().any(|l| entries.contains(&l.name.to_uppercase())) { break; }
+                    let StmtKind::Instr(instr) = &stmt.nucleus else { continue };
+
+                    if saves_r7(instr) {
+                        saved = true;
+                    } else if overwrites_r7(instr) && !saved {
+                        self.warn(Lint::NestedJsr, stmt.span.clone(),
+                            format!("this call overwrites R7 before subroutine `{name}` has saved its return address"),
+                            "save R7 (e.g., on the stack) before this call and restore it before returning"
+                        );
+                        break;
+                    } else if matches!(instr, AsmInstr::RET | AsmInstr::JMP(Reg::R7) | AsmInstr::HALT) {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Checks for `BR` written without condition codes.
+    fn check_br_no_cc(&mut self) {
+        for stmt in self.ast {
+            let StmtKind::Instr(AsmInstr::BR(..)) = stmt.nucleus else { continue };
+            let Some(text) = self.src.get(stmt.span.clone()) else { continue };
+
+            if text.split_whitespace().next().is_some_and(|m| m.eq_ignore_ascii_case("BR")) {
+                self.warn(Lint::BrNoCc, stmt.span.clone(),
+                    String::from("`BR` without condition codes always branches"),
+                    "write `BRnzp` to make the unconditional branch explicit"
+                );
+            }
+        }
+    }
+}
+
+/// Runs the enabled lints on the (preprocessed) source code, assembled in the given dialect.
+///
+/// This returns no warnings if the source does not parse.
+/// The warnings are sorted by where they occur in source.
+pub(crate) fn lint(expanded: &Expanded, dialect: Dialect, config: &LintConfig) -> Vec<LintWarning> {
+    let Parsed { ast, errors, expr_labels, .. } = parse_expanded(expanded, dialect);
+    if !errors.is_empty() { return vec![]; }
+    let expr_labels = expr_labels.iter().map(|l| l.name.to_uppercase()).collect();
+
+    let mut linter = Linter::new(expanded.src(), &ast, &expr_labels, config);
+    linter.check_control_flow();
+    linter.check_unused_labels();
+    linter.check_os_overlap();
+    linter.check_offsets();
+    linter.check_nested_jsr();
+    linter.check_br_no_cc();
+
+    let mut warnings = linter.warnings;
+    warnings.sort_by_key(|w| w.span.start);
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::preproc::preprocess;
+
+    /// The lints which warn about a source (with the source they point at).
+    fn lints_with(src: &str, config: &LintConfig) -> Vec<(Lint, String)> {
+        let (expanded, _) = preprocess(src, None);
+        lint(&expanded, Dialect::default(), config).into_iter()
+            .map(|w| (w.lint, expanded.src()[w.span].to_string()))
+            .collect()
+    }
+    fn lints(src: &str) -> Vec<(Lint, String)> {
+        lints_with(src, &LintConfig::default())
+    }
+    const NONE: [&str; 0] = [];
+
+    fn warns(src: &str, lint: Lint) -> Vec<String> {
+        lints(src).into_iter().filter(|&(l, _)| l == lint).map(|(_, s)| s).collect()
+    }
+
+    #[test]
+    fn clean_program() {
+        assert_eq!(lints(".ORIG x3000\nLD R0, N\nBRz DONE\nADD R0, R0, #-1\nDONE HALT\nN .FILL 3\n.END\n"), []);
+    }
+
+    #[test]
+    fn missing_halt() {
+        assert_eq!(warns(".ORIG x3000\nADD R0, R0, #1\n.END\n", Lint::MissingHalt), ["ADD R0, R0, #1"]);
+        assert_eq!(warns(".ORIG x3000\nADD R0, R0, #1\nHALT\n.END\n", Lint::MissingHalt), NONE);
+    }
+
+    #[test]
+    fn unreachable_code() {
+        assert_eq!(warns(".ORIG x3000\nHALT\nADD R0, R0, #1\nADD R0, R0, #2\n.END\n", Lint::UnreachableCode), ["ADD R0, R0, #1"]);
+        assert_eq!(warns(".ORIG x3000\nBRnzp L\nHALT\nL HALT\n.END\n", Lint::UnreachableCode), ["HALT"]);
+        assert_eq!(warns(".ORIG x3000\nBRz L\nHALT\nL HALT\n.END\n", Lint::UnreachableCode), NONE);
+    }
+
+    #[test]
+    fn unused_label() {
+        assert_eq!(warns(".ORIG x3000\nSTART HALT\n.END\n", Lint::UnusedLabel), ["START"]);
+        // Labels used by operands, data, and expressions are used:
+        assert_eq!(warns(".ORIG x3000\nLEA R0, BUF+1\nLD R1, P\nHALT\nP .FILL Q\nQ .FILL 0\nBUF .BLKW 2\n.END\n", Lint::UnusedLabel), NONE);
+    }
+
+    #[test]
+    fn fall_through_data() {
+        assert_eq!(warns(".ORIG x3000\nADD R0, R0, #1\nN .FILL 1\nHALT\n.END\n", Lint::FallThroughData), [".FILL 1"]);
+        assert_eq!(warns(".ORIG x3000\nHALT\n.FILL 1\n.END\n", Lint::FallThroughData), NONE);
+    }
+
+    #[test]
+    fn os_overlap() {
+        assert_eq!(warns(".ORIG x0200\nHALT\n.END\n", Lint::OsOverlap), [".ORIG x0200"]);
+        assert_eq!(warns(".ORIG x3000\nHALT\n.END\n", Lint::OsOverlap), NONE);
+    }
+
+    #[test]
+    fn offset_near_limit() {
+        assert_eq!(warns(".ORIG x3000\nLD R0, N\nHALT\n.BLKW 250\nN .FILL 1\n.END\n", Lint::OffsetNearLimit), ["N"]);
+        assert_eq!(warns(".ORIG x3000\nLD R0, N\nHALT\n.BLKW 200\nN .FILL 1\n.END\n", Lint::OffsetNearLimit), NONE);
+    }
+
+    #[test]
+    fn nested_jsr() {
+        let src = ".ORIG x3000\nJSR F\nHALT\nF JSR G\nRET\nG RET\n.END\n";
+        assert_eq!(warns(src, Lint::NestedJsr), ["JSR G"]);
+        let src = ".ORIG x3000\nJSR F\nHALT\nF ST R7, SAVE\nJSR G\nLD R7, SAVE\nRET\nG OUT\nRET\nSAVE .BLKW 1\n.END\n";
+        assert_eq!(warns(src, Lint::NestedJsr), ["OUT"]);
+    }
+
+    #[test]
+    fn br_no_cc() {
+        assert_eq!(warns(".ORIG x3000\nL BR L\n.END\n", Lint::BrNoCc), ["BR L"]);
+        assert_eq!(warns(".ORIG x3000\nL BRnzp L\n.END\n", Lint::BrNoCc), NONE);
+    }
+
+    #[test]
+    fn disabled_lints() {
+        let src = ".ORIG x3000\nSTART ADD R0, R0, #1\n.END\n";
+        assert_eq!(lints(src).into_iter().map(|(l, _)| l).collect::<Vec<_>>(), [Lint::UnusedLabel, Lint::MissingHalt]);
+
+        let mut config = LintConfig::default();
+        config.set_enabled(Lint::from_name("lint::unused-label").expect("lint should exist"), false);
+        assert_eq!(lints_with(src, &config).into_iter().map(|(l, _)| l).collect::<Vec<_>>(), [Lint::MissingHalt]);
+        config.set_enabled(Lint::UnusedLabel, true);
+        config.set_enabled(Lint::MissingHalt, false);
+        assert_eq!(lints_with(src, &config).into_iter().map(|(l, _)| l).collect::<Vec<_>>(), [Lint::UnusedLabel]);
+    }
+}
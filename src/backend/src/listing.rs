@@ -0,0 +1,193 @@
+//! Listing files (`.lst`), which show what each line of source assembled into.
+//!
+//! Each row of a listing has the address, the machine code (in hex and binary), the label, and the source line
+//! of a statement. Statements which occupy several words (e.g., `.BLKW` and `.STRINGZ`) have a row for each word,
+//! and lines which don't assemble into anything (e.g., comments) only have their source.
+//! Preprocessor lines (e.g., `.INCLUDE` and macro invocations) are shown as they were written,
+//! followed by the lines they include or expand into.
+//! The listing ends with the symbol table.
+
+use std::collections::HashMap;
+
+use lc3_ensemble::asm::ObjectFile;
+use lc3_ensemble::ast::asm::{Directive, StmtKind};
+
+use crate::asm::{parse_expanded, stmt_len, Parsed};
+use crate::dialect::Dialect;
+use crate::preproc::Expanded;
+
+/// A row of the listing.
+struct Row<'s> {
+    /// The address and word (if it is initialized).
+    word: Option<(u16, Option<u16>)>,
+    label: String,
+    source: &'s str,
+}
+
+/// Creates the listing of source (assembled in the given dialect) which assembled into the given object file.
+pub(crate) fn listing(expanded: &Expanded, dialect: Dialect, obj: &ObjectFile) -> String {
+    let src = expanded.src();
+    let Parsed { ast, .. } = parse_expanded(expanded, dialect);
+    let words: HashMap<u16, Option<u16>> = obj.addr_iter().collect();
+
+    // The statements on each line (keyed by the start of the line):
+    let mut line_starts: Vec<usize> = expanded.ordered_lines().into_iter().map(|l| l.start).collect();
+    line_starts.sort_unstable();
+    let mut stmts_by_line: HashMap<usize, Vec<(u16, usize)>> = HashMap::new();
+    let mut symbols = vec![];
+    let mut addr = 0u16;
+    for (i, stmt) in ast.iter().enumerate() {
+        match &stmt.nucleus {
+            StmtKind::Directive(Directive::Orig(start)) => addr = start.get(),
+            StmtKind::Directive(Directive::External(label)) => symbols.push((label.name.clone(), None)),
+            _ => {},
+        }
+        symbols.extend(stmt.labels.iter().map(|l| (l.name.clone(), Some(addr))));
+
+        let line = line_starts[line_starts.partition_point(|&s| s <= stmt.span.start).saturating_sub(1)];
+        stmts_by_line.entry(line).or_default().push((addr, i));
+        addr = addr.wrapping_add(stmt_len(stmt));
+    }
+
+    // The original text of lines which were blanked out by the preprocessor:
+    let blanked: HashMap<usize, &str> = expanded.blanked_lines().into_iter().map(|(span, text)| (span.start, text)).collect();
+
+    let mut rows = vec![];
+    for line in expanded.ordered_lines() {
+        let source = blanked.get(&line.start).copied().unwrap_or(&src[line.clone()]).trim_end();
+        let stmts = stmts_by_line.remove(&line.start).unwrap_or_default();
+        let mut source = Some(source);
+
+        for (addr, i) in stmts {
+            let stmt = &ast[i];
+            let mut label = stmt.labels.iter().map(|l| &*l.name).collect::<Vec<_>>().join(" ");
+            for offset in 0..stmt_len(stmt) {
+                let addr = addr.wrapping_add(offset);
+                let word = words.get(&addr).copied().flatten();
+                rows.push(Row { word: Some((addr, word)), label: std::mem::take(&mut label), source: source.take().unwrap_or("") });
+            }
+        }
+        // Lines which don't occupy memory (and blank lines, unless they're in the middle of the program):
+        if let Some(source) = source && (!source.is_empty() || !rows.is_empty()) {
+            rows.push(Row { word: None, label: String::new(), source });
+        }
+    }
+    while rows.last().is_some_and(|r| r.word.is_none() && r.source.is_empty()) {
+        rows.pop();
+    }
+
+    let label_width = rows.iter().map(|r| r.label.len()).max().unwrap_or(0).max("label".len());
+    let mut out = format!("{:5}  {:5}  {:16}  {:label_width$}  source\n", "addr", "hex", "binary", "label");
+    for Row { word, label, source } in rows {
+        let (addr, hex, bin) = match word {
+            Some((addr, Some(word))) => (format!("x{addr:04X}"), format!("x{word:04X}"), format!("{word:016b}")),
+            Some((addr, None)) => (format!("x{addr:04X}"), String::new(), String::new()),
+            None => Default::default(),
+        };
+        let row = format!("{addr:5}  {hex:5}  {bin:16}  {label:label_width$}  {source}");
+        out.push_str(row.trim_end());
+        out.push('\n');
+    }
+
+    symbols.sort_by(|(a_name, a_addr), (b_name, b_addr)| (a_addr.is_none(), a_addr, a_name).cmp(&(b_addr.is_none(), b_addr, b_name)));
+    let name_width = symbols.iter().map(|(name, _)| name.len()).max().unwrap_or(0).max("symbol".len());
+    out.push_str(&format!("\n{:name_width$}  address\n", "symbol"));
+    for (name, addr) in symbols {
+        match addr {
+            Some(addr) => out.push_str(&format!("{name:name_width$}  x{addr:04X}\n")),
+            None => out.push_str(&format!("{name:name_width$}  (external)\n")),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble_all;
+    use crate::preproc::preprocess;
+    use crate::preproc::tests::temp_dir;
+
+    /// The listing of the source (from the file at the given path, if it is from a file).
+    fn listing_of(src: &str, path: Option<&std::path::Path>) -> String {
+        let (expanded, errors) = preprocess(src, path);
+        assert!(errors.is_empty(), "source should preprocess: {errors:?}");
+        let (obj, _) = assemble_all(&expanded, Dialect::default()).unwrap_or_else(|e| panic!("source should assemble: {e:?}"));
+        listing(&expanded, Dialect::default(), &obj)
+    }
+
+    #[test]
+    fn lists_words_and_comments() {
+        let src = "\
+; Prints a message
+.ORIG x3000
+.EXTERNAL PRINT
+MAIN LEA R0, MSG ; the message
+
+  LDI R1, PTR
+  JSRR R1
+  HALT
+MSG .STRINGZ \"hi\"
+BUF .BLKW 2
+PTR .FILL PRINT
+.END
+";
+        assert_eq!(listing_of(src, None), r#"addr   hex    binary            label  source
+                                       ; Prints a message
+                                       .ORIG x3000
+                                       .EXTERNAL PRINT
+x3000  xE003  1110000000000011  MAIN   MAIN LEA R0, MSG ; the message
+
+x3001  xA207  1010001000000111           LDI R1, PTR
+x3002  x4040  0100000001000000           JSRR R1
+x3003  xF025  1111000000100101           HALT
+x3004  x0068  0000000001101000  MSG    MSG .STRINGZ "hi"
+x3005  x0069  0000000001101001
+x3006  x0000  0000000000000000
+x3007                           BUF    BUF .BLKW 2
+x3008
+x3009  x0000  0000000000000000  PTR    PTR .FILL PRINT
+                                       .END
+
+symbol  address
+MAIN    x3000
+MSG     x3004
+BUF     x3007
+PTR     x3009
+PRINT   (external)
+"#);
+    }
+
+    #[test]
+    fn lists_included_and_expanded_lines() {
+        let dir = temp_dir("listing");
+        std::fs::write(dir.join("data.asm"), "DATA .FILL 7\n").expect("file should be written");
+        let src = "\
+.MACRO CLEAR reg
+AND \\reg, \\reg, #0
+.ENDM
+.ORIG x3000
+START CLEAR R1
+  HALT
+.INCLUDE \"data.asm\"
+.END
+";
+        assert_eq!(listing_of(src, Some(&dir.join("main.asm"))), r#"addr   hex    binary            label  source
+                                       .MACRO CLEAR reg
+                                       AND \reg, \reg, #0
+                                       .ENDM
+                                       .ORIG x3000
+                                       START CLEAR R1
+                                       START
+x3000  x5260  0101001001100000  START  AND R1, R1, #0
+x3001  xF025  1111000000100101           HALT
+                                       .INCLUDE "data.asm"
+x3002  x0007  0000000000000111  DATA   DATA .FILL 7
+                                       .END
+
+symbol  address
+START   x3000
+DATA    x3002
+"#);
+    }
+}
@@ -0,0 +1,211 @@
+//! Local labels, which are scoped to the last global label before them.
+//!
+//! A label starting with `.` (e.g., `.loop`) is local to the last global label,
+//! and its qualified name includes that label (e.g., `.loop` under `MULTIPLY` is `MULTIPLY.loop`).
+//!
+//! Numeric labels (e.g., `1:`) can be defined any number of times,
+//! and are referenced with `1b` (the closest definition at or before the reference)
+//! or `1f` (the closest definition after the reference).
+//! Their qualified names are also scoped (e.g., `MULTIPLY.1`), with a suffix if they're defined more than once
+//! in the same scope (e.g., `MULTIPLY.1.2` for the second).
+//!
+//! Local labels can also be referenced outside of their scope by their qualified names.
+//! Like every other label, these are case-insensitive, and ensemble's symbol table stores them in uppercase
+//! (so `.loop` under `Multiply` is `MULTIPLY.LOOP` in the object file).
+//!
+//! Ensemble's parser doesn't accept these names, so they're replaced with placeholders before parsing
+//! and renamed to their qualified names afterwards.
+
+use std::collections::HashMap;
+
+use crate::expr::is_symbol;
+
+/// The name of a local label definition (without any trailing colon),
+/// or `None` if the word is not a local label definition.
+pub(crate) fn local_def(word: &str) -> Option<&str> {
+    if let Some(name) = word.strip_suffix(':').filter(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit())) {
+        return Some(name);
+    }
+    let name = word.strip_suffix(':').unwrap_or(word);
+    name.strip_prefix('.').is_some_and(is_symbol).then_some(name)
+}
+
+/// Whether a token is a reference to a local label
+/// (e.g., `.loop`, `1b`, or `1f`, or a qualified name like `MULTIPLY.loop`).
+pub(crate) fn is_local_ref(token: &str) -> bool {
+    if let Some(name) = token.strip_prefix('.') {
+        return is_symbol(name);
+    }
+    if let Some((scope, name)) = token.split_once('.') {
+        return is_symbol(scope) && !name.is_empty() && name.bytes().all(|b| b.is_ascii_alphanumeric() || matches!(b, b'_' | b'.'));
+    }
+    match token.as_bytes() {
+        [digits @ .., b'b' | b'B' | b'f' | b'F'] => !digits.is_empty() && digits.iter().all(u8::is_ascii_digit),
+        _ => false,
+    }
+}
+
+/// The local labels of the source.
+pub(crate) struct Locals {
+    /// The scope (the last global label) after each line.
+    scopes: Vec<Option<String>>,
+    /// The qualified names of local label definitions, keyed by their line and their index in the line.
+    defs: HashMap<(usize, usize), String>,
+    /// The numeric label definitions, in order (with their line, number, and qualified name).
+    numeric: Vec<(usize, u32, String)>,
+}
+impl Locals {
+    /// Finds the local labels, given the labels of each line (in program order).
+    pub(crate) fn new<'a>(lines: impl IntoIterator<Item = Vec<&'a str>>) -> Self {
+        let mut locals = Locals { scopes: vec![], defs: HashMap::new(), numeric: vec![] };
+        let mut scope: Option<String> = None;
+        // The numeric definitions of each scope and number (to detect duplicates).
+        let mut numeric_defs: Vec<(usize, usize, Option<String>, u32)> = vec![];
+
+        for (i, labels) in lines.into_iter().enumerate() {
+            for (j, word) in labels.into_iter().enumerate() {
+                match local_def(word) {
+                    Some(name) => match name.parse::<u32>() {
+                        Ok(n) => numeric_defs.push((i, j, scope.clone(), n)),
+                        Err(_) => {
+                            let qualified = format!("{}{name}", scope.as_deref().unwrap_or(""));
+                            locals.defs.insert((i, j), qualified);
+                        },
+                    },
+                    None => { scope.replace(word.trim_end_matches(':').to_string()); },
+                }
+            }
+            locals.scopes.push(scope.clone());
+        }
+
+        let mut counts: HashMap<(Option<String>, u32), usize> = HashMap::new();
+        for (_, _, scope, n) in &numeric_defs {
+            *counts.entry((scope.as_ref().map(|s| s.to_uppercase()), *n)).or_default() += 1;
+        }
+        let mut seen: HashMap<(Option<String>, u32), usize> = HashMap::new();
+        for (i, j, scope, n) in numeric_defs {
+            let key = (scope.as_ref().map(|s| s.to_uppercase()), n);
+            let k = seen.entry(key.clone()).or_default();
+            *k += 1;
+
+            let mut qualified = format!("{}.{n}", scope.as_deref().unwrap_or(""));
+            if counts[&key] > 1 {
+                qualified.push_str(&format!(".{k}"));
+            }
+            locals.defs.insert((i, j), qualified.clone());
+            locals.numeric.push((i, n, qualified));
+        }
+
+        locals
+    }
+
+    /// The qualified name of the label at the given index of the given line
+    /// (or `None` if the label is not local).
+    pub(crate) fn def(&self, line: usize, index: usize) -> Option<&str> {
+        self.defs.get(&(line, index)).map(String::as_str)
+    }
+
+    /// Resolves a reference to a local label on the given line.
+    ///
+    /// This returns `None` if the token is not a local label,
+    /// and `Some(None)` if it is, but no such label is defined.
+    pub(crate) fn resolve(&self, line: usize, token: &str) -> Option<Option<String>> {
+        if !is_local_ref(token) { return None; }
+        if token.contains('.') && !token.starts_with('.') {
+            return Some(Some(token.to_string()));
+        }
+        if token.starts_with('.') {
+            let scope = self.scopes.get(line).cloned().flatten().unwrap_or_default();
+            return Some(Some(format!("{scope}{token}")));
+        }
+
+        let (digits, dir) = token.split_at(token.len() - 1);
+        let Ok(n) = digits.parse::<u32>() else { return Some(None) };
+        let found = match dir {
+            "b" | "B" => self.numeric.iter().rev().find(|&&(l, m, _)| l <= line && m == n),
+            "f" | "F" => self.numeric.iter().find(|&&(l, m, _)| l > line && m == n),
+            _ => return None,
+        };
+        Some(found.map(|(_, _, name)| name.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::tests::assemble;
+
+    const PROGRAM: &str = "\
+.ORIG x3000
+Multiply AND R2, R2, #0
+.loop ADD R2, R2, R0
+ADD R1, R1, #-1
+BRp .loop
+1: BRz 1f
+BR 1b
+1: RET
+DIVIDE
+.loop BR .loop
+JSR multiply.LOOP
+BR 1b
+.END
+";
+
+    /// The locals of lines with the given labels.
+    fn locals(lines: &[&[&'static str]]) -> Locals {
+        Locals::new(lines.iter().map(|labels| labels.to_vec()))
+    }
+
+    #[test]
+    fn scopes_dot_labels() {
+        let locals = locals(&[&["F"], &[".loop"], &[], &["G"], &[".loop", ".end"]]);
+        assert_eq!(locals.def(1, 0), Some("F.loop"));
+        assert_eq!(locals.def(4, 0), Some("G.loop"));
+        assert_eq!(locals.def(4, 1), Some("G.end"));
+        assert_eq!(locals.def(0, 0), None);
+
+        assert_eq!(locals.resolve(2, ".loop"), Some(Some(String::from("F.loop"))));
+        assert_eq!(locals.resolve(4, ".loop"), Some(Some(String::from("G.loop"))));
+        // Qualified names can be used anywhere:
+        assert_eq!(locals.resolve(4, "F.loop"), Some(Some(String::from("F.loop"))));
+        // Global labels aren't local:
+        assert_eq!(locals.resolve(2, "G"), None);
+    }
+
+    #[test]
+    fn numbers_duplicate_labels() {
+        let locals = locals(&[&["F"], &["1:"], &["1:"], &["2:"], &["G"], &["1:"]]);
+        assert_eq!(locals.def(1, 0), Some("F.1.1"));
+        assert_eq!(locals.def(2, 0), Some("F.1.2"));
+        assert_eq!(locals.def(3, 0), Some("F.2"));
+        assert_eq!(locals.def(5, 0), Some("G.1"));
+
+        // `1b` is the closest definition at or before the line, and `1f` the closest one after it:
+        assert_eq!(locals.resolve(2, "1b"), Some(Some(String::from("F.1.2"))));
+        assert_eq!(locals.resolve(3, "1B"), Some(Some(String::from("F.1.2"))));
+        assert_eq!(locals.resolve(1, "1f"), Some(Some(String::from("F.1.2"))));
+        assert_eq!(locals.resolve(3, "1f"), Some(Some(String::from("G.1"))));
+        assert_eq!(locals.resolve(0, "1b"), Some(None));
+        assert_eq!(locals.resolve(5, "2f"), Some(None));
+    }
+
+    #[test]
+    fn assembles_local_labels() {
+        let obj = assemble(PROGRAM);
+        let sym = obj.symbol_table().expect("object file should have a symbol table");
+
+        // Qualified names are stored in uppercase:
+        assert_eq!(sym.lookup_label("MULTIPLY.LOOP"), Some(0x3001));
+        assert_eq!(sym.lookup_label("MULTIPLY.1.1"), Some(0x3004));
+        assert_eq!(sym.lookup_label("MULTIPLY.1.2"), Some(0x3006));
+        assert_eq!(sym.lookup_label("DIVIDE.LOOP"), Some(0x3007));
+
+        let words: Vec<_> = obj.addr_iter().filter_map(|(_, w)| w).collect();
+        assert_eq!(words[3], 0x03FD); // BRp .loop (to x3001)
+        assert_eq!(words[4], 0x0401); // BRz 1f (to x3006)
+        assert_eq!(words[5], 0x0FFE); // BR 1b (to x3004)
+        assert_eq!(words[7], 0x0FFF); // BR .loop (to x3007)
+        assert_eq!(words[8], 0x4FF8); // JSR multiply.LOOP (to x3001)
+        assert_eq!(words[9], 0x0FFC); // BR 1b (to x3006)
+    }
+}
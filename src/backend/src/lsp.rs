@@ -0,0 +1,736 @@
+//! A language server for LC-3 assembly (used by the `lc3-lsp` binary).
+//!
+//! This speaks the Language Server Protocol over stdio and provides:
+//! - diagnostics (assembler errors and lint warnings)
+//! - go-to-definition, find-references, and rename for labels
+//! - hover information (label addresses, instruction encodings, and TRAP documentation)
+//! - completions and document symbols
+//!
+//! Documents are preprocessed (relative to their path, for `.INCLUDE`),
+//! and errors in included files or macro expansions are shown where they were included or invoked.
+//!
+//! Lints can be configured through the `lints` field of the client's initialization options
+//! (e.g., `{ "lints": { "unused-label": false } }`),
+//! and documents can be assembled in another assembler's dialect through the `dialect` field
+//! (e.g., `{ "dialect": "pennsim" }`), which also reports anything that isn't portable.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::ops::Range;
+use std::path::PathBuf;
+
+use lc3_ensemble::asm::ObjectFile;
+use lc3_ensemble::ast::asm::{AsmInstr, Directive, Stmt, StmtKind};
+use lc3_ensemble::ast::{Label, PCOffset};
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::notification::{DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, Notification as _, PublishDiagnostics};
+use lsp_types::request::{Completion, DocumentSymbolRequest, GotoDefinition, HoverRequest, References, Rename, Request as _};
+use lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionOptions, CompletionParams, CompletionResponse,
+    Diagnostic, DiagnosticRelatedInformation, DiagnosticSeverity, DocumentSymbol, DocumentSymbolParams,
+    DocumentSymbolResponse, GotoDefinitionParams, GotoDefinitionResponse, Hover, HoverContents,
+    HoverParams, HoverProviderCapability, InitializeParams, Location, MarkupContent, MarkupKind,
+    NumberOrString, OneOf, Position, PublishDiagnosticsParams, ReferenceParams, RenameParams,
+    ServerCapabilities, SymbolKind, TextDocumentPositionParams, TextDocumentSyncCapability,
+    TextDocumentSyncKind, TextEdit, Uri, WorkspaceEdit,
+};
+
+use crate::asm::{self, label_span, referenced_label, AsmError};
+use crate::dialect::{self, Dialect};
+use crate::lint::{self, Lint, LintConfig};
+use crate::preproc::{self, Expanded, PreprocError};
+
+type BoxError = Box<dyn Error + Send + Sync>;
+
+const INSTRUCTIONS: &[&str] = &[
+    "ADD", "AND", "NOT",
+    "BR", "BRn", "BRz", "BRp", "BRnz", "BRnp", "BRzp", "BRnzp",
+    "JMP", "JSR", "JSRR", "RET", "RTI",
+    "LD", "LDI", "LDR", "LEA", "ST", "STI", "STR",
+    "TRAP", "NOP",
+    "GETC", "OUT", "PUTC", "PUTS", "IN", "PUTSP", "HALT",
+];
+const DIRECTIVES: &[&str] = &[
+    ".orig", ".fill", ".blkw", ".stringz", ".ascii", ".align", ".end", ".external",
+    ".include", ".macro", ".endm", ".if", ".else", ".endif",
+    ".equ", ".set",
+];
+const REGISTERS: &[&str] = &["R0", "R1", "R2", "R3", "R4", "R5", "R6", "R7"];
+
+/// Documentation for the standard TRAP routines.
+fn trap_doc(vect: u16) -> Option<&'static str> {
+    match vect {
+        0x20 => Some("**GETC** (`TRAP x20`): Reads a character from the keyboard into R0 (without echoing it)."),
+        0x21 => Some("**OUT** (`TRAP x21`): Writes the character in R0[7:0] to the console."),
+        0x22 => Some("**PUTS** (`TRAP x22`): Writes the null-terminated string starting at the address in R0 to the console, one character per word."),
+        0x23 => Some("**IN** (`TRAP x23`): Prompts for a character, reads it from the keyboard into R0, and echoes it to the console."),
+        0x24 => Some("**PUTSP** (`TRAP x24`): Writes the null-terminated string starting at the address in R0 to the console, two characters per word (low byte first)."),
+        0x25 => Some("**HALT** (`TRAP x25`): Stops execution of the program."),
+        _ => None,
+    }
+}
+/// The trap vector an instruction invokes (if it is a TRAP).
+fn trap_vect(instr: &AsmInstr) -> Option<u16> {
+    match instr {
+        AsmInstr::TRAP(vect) => Some(vect.get()),
+        AsmInstr::GETC  => Some(0x20),
+        AsmInstr::OUT   => Some(0x21),
+        AsmInstr::PUTC  => Some(0x21),
+        AsmInstr::PUTS  => Some(0x22),
+        AsmInstr::IN    => Some(0x23),
+        AsmInstr::PUTSP => Some(0x24),
+        AsmInstr::HALT  => Some(0x25),
+        _ => None,
+    }
+}
+
+/// Converts a byte offset in the text into an LSP position (which uses UTF-16 columns).
+fn position_of(text: &str, offset: usize) -> Position {
+    let mut offset = offset.min(text.len());
+    while !text.is_char_boundary(offset) {
+        offset -= 1;
+    }
+
+    let line_start = text[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let line = text[..line_start].matches('\n').count();
+    let character = text[line_start..offset].encode_utf16().count();
+    Position::new(line as u32, character as u32)
+}
+/// Converts an LSP position into a byte offset in the text.
+fn offset_of(text: &str, pos: Position) -> usize {
+    let line_start = match pos.line {
+        0 => 0,
+        n => match text.match_indices('\n').nth(n as usize - 1) {
+            Some((i, _)) => i + 1,
+            None => return text.len(),
+        },
+    };
+
+    let mut col = 0;
+    for (i, c) in text[line_start..].char_indices() {
+        if c == '\n' || col >= pos.character as usize {
+            return line_start + i;
+        }
+        col += c.len_utf16();
+    }
+    text.len()
+}
+fn range_of(text: &str, span: &Range<usize>) -> lsp_types::Range {
+    lsp_types::Range::new(position_of(text, span.start), position_of(text, span.end))
+}
+
+/// Whether this is a valid label name.
+fn is_label_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// An occurrence of a label in source.
+struct LabelSpan {
+    name: String,
+    span: Range<usize>,
+    /// Whether this is where the label is defined.
+    is_def: bool,
+}
+
+/// Gets the path of a `file:` URI.
+///
+/// Windows paths are written as `file:///c:/dir/file.asm` (with the colon often percent-encoded),
+/// and network paths as `file://server/share/file.asm`.
+fn file_path(uri: &Uri) -> Option<PathBuf> {
+    let rest = uri.as_str().strip_prefix("file://")?;
+    let rest = rest.split(['?', '#']).next().unwrap_or(rest);
+    let (host, path) = rest.split_at(rest.find('/')?);
+
+    // Decode any percent-encoded bytes:
+    let mut bytes = vec![];
+    let mut rest = path.as_bytes();
+    while let Some((&b, r)) = rest.split_first() {
+        let decoded = match (b, r) {
+            (b'%', [hi, lo, ..]) => std::str::from_utf8(&[*hi, *lo]).ok().and_then(|h| u8::from_str_radix(h, 16).ok()),
+            _ => None,
+        };
+        match decoded {
+            Some(d) => { bytes.push(d); rest = &r[2..]; },
+            None => { bytes.push(b); rest = r; },
+        }
+    }
+    let path = String::from_utf8(bytes).ok()?;
+
+    let path = match path.as_bytes() {
+        // A drive letter (`/c:/...`) doesn't keep the leading slash:
+        [b'/', drive, b':', ..] if drive.is_ascii_alphabetic() => path[1..].to_string(),
+        _ if host.is_empty() || host.eq_ignore_ascii_case("localhost") => path,
+        _ => format!("//{host}{path}"),
+    };
+    Some(PathBuf::from(path))
+}
+
+/// An open document and its analysis.
+struct Document {
+    text: String,
+    /// The document after preprocessing.
+    expanded: Expanded,
+    /// The dialect the document is assembled in.
+    dialect: Dialect,
+    /// The statements in the document which could be parsed.
+    ast: Vec<Stmt>,
+    /// The assembled object file (if assembling succeeded).
+    obj: Option<ObjectFile>,
+    /// The errors from preprocessing.
+    preproc_errors: Vec<PreprocError>,
+    /// The errors from assembling (if assembling failed).
+    errors: Vec<AsmError>,
+    labels: Vec<LabelSpan>,
+}
+impl Document {
+    fn new(text: String, path: Option<PathBuf>, dialect: Dialect) -> Self {
+        let (expanded, preproc_errors) = preproc::preprocess(&text, path.as_deref());
+        // Only the statements (and expressions) in this document are needed for navigation:
+        let parsed = asm::parse_expanded(&expanded, dialect);
+        let ast: Vec<_> = parsed.ast.into_iter()
+            .filter(|s| s.span.end <= expanded.main_len())
+            .collect();
+        let (obj, errors) = match preproc_errors.is_empty() {
+            true => match asm::assemble_all(&expanded, dialect) {
+                Ok((obj, _)) => (Some(obj), vec![]),
+                Err(errors) => (None, errors),
+            },
+            false => (None, vec![]),
+        };
+
+        let mut labels = vec![];
+        for stmt in &ast {
+            let span = |l: &Label| label_span(&text, l.span());
+            labels.extend(stmt.labels.iter().map(|l| LabelSpan { name: l.name.clone(), span: span(l), is_def: true }));
+            labels.extend(referenced_label(stmt).map(|l| LabelSpan { name: l.name.clone(), span: span(l), is_def: false }));
+        }
+        // Labels used in expressions (e.g., `LEA R0, BUF+2`) were replaced with their values before parsing:
+        for label in parsed.expr_labels.iter().filter(|l| l.span().end <= expanded.main_len()) {
+            let span = label_span(&text, label.span());
+            if labels.iter().all(|l| l.span != span) {
+                labels.push(LabelSpan { name: label.name.clone(), span, is_def: false });
+            }
+        }
+        labels.sort_by_key(|l| l.span.start);
+
+        Document { text, expanded, dialect, ast, obj, preproc_errors, errors, labels }
+    }
+
+    fn diagnostics(&self, uri: &Uri, lint_config: &LintConfig) -> Vec<Diagnostic> {
+        match self.obj {
+            Some(_) => {
+                let portability = dialect::check(&self.expanded, self.dialect);
+                let portability = portability.iter()
+                    .map(|w| {
+                        let severity = if w.rejected { DiagnosticSeverity::ERROR } else { DiagnosticSeverity::WARNING };
+                        self.diagnostic(uri, w, severity, w.kind.code())
+                    });
+                let lints = lint::lint(&self.expanded, self.dialect, lint_config);
+                let lints = lints.iter()
+                    .map(|w| self.diagnostic(uri, w, DiagnosticSeverity::WARNING, w.lint.code()));
+                portability.chain(lints).collect()
+            },
+            None => {
+                let preproc_errors = self.preproc_errors.iter()
+                    .map(|e| self.diagnostic(uri, e, DiagnosticSeverity::ERROR, e.code()));
+                let errors = self.errors.iter()
+                    .map(|e| self.diagnostic(uri, e, DiagnosticSeverity::ERROR, e.code()));
+                preproc_errors.chain(errors).collect()
+            },
+        }
+    }
+    fn diagnostic(&self, uri: &Uri, err: &impl lc3_ensemble::err::Error, severity: DiagnosticSeverity, code: &str) -> Diagnostic {
+        // Errors outside of this document are shown where they were included or expanded from:
+        let spans: Vec<_> = err.span().iter()
+            .flat_map(|s| s.iter().map(|s| self.expanded.main_span(s.clone())).collect::<Vec<_>>())
+            .collect();
+        let range = spans.first().map_or_else(Default::default, |s| range_of(&self.text, s));
+        // Any other spans are reported as related locations:
+        let related_information = (spans.len() > 1).then(|| {
+            spans[1..].iter()
+                .map(|s| DiagnosticRelatedInformation {
+                    location: Location::new(uri.clone(), range_of(&self.text, s)),
+                    message: String::from("also here"),
+                })
+                .collect()
+        });
+
+        let mut message = err.to_string();
+        if let Some(help) = err.help().filter(|h| !h.is_empty()) {
+            message.push_str("\nhelp: ");
+            message.push_str(&help);
+        }
+
+        Diagnostic {
+            range,
+            severity: Some(severity),
+            code: Some(NumberOrString::String(code.to_string())),
+            source: Some(String::from("lc3")),
+            message,
+            related_information,
+            ..Default::default()
+        }
+    }
+
+    /// Finds the label occurrence at the given position.
+    fn label_at(&self, pos: Position) -> Option<&LabelSpan> {
+        let offset = offset_of(&self.text, pos);
+        self.labels.iter().find(|l| l.span.start <= offset && offset <= l.span.end)
+    }
+    /// Finds every occurrence of the given label.
+    fn occurrences<'a>(&'a self, name: &'a str) -> impl Iterator<Item=&'a LabelSpan> + 'a {
+        self.labels.iter().filter(move |l| l.name.eq_ignore_ascii_case(name))
+    }
+    fn label_addr(&self, name: &str) -> Option<u16> {
+        self.obj.as_ref()?.symbol_table()?.lookup_label(name)
+    }
+    fn word_at(&self, addr: u16) -> Option<u16> {
+        self.obj.as_ref()?.addr_iter().find(|&(a, _)| a == addr)?.1
+    }
+}
+
+struct Server {
+    connection: Connection,
+    documents: HashMap<Uri, Document>,
+    lint_config: LintConfig,
+    dialect: Dialect,
+}
+impl Server {
+    fn publish_diagnostics(&self, uri: &Uri) -> Result<(), BoxError> {
+        let diagnostics = match self.documents.get(uri) {
+            Some(doc) => doc.diagnostics(uri, &self.lint_config),
+            None => vec![],
+        };
+
+        let params = PublishDiagnosticsParams::new(uri.clone(), diagnostics, None);
+        let not = Notification::new(PublishDiagnostics::METHOD.to_string(), params);
+        self.connection.sender.send(Message::Notification(not))?;
+        Ok(())
+    }
+
+    fn handle_notification(&mut self, not: Notification) -> Result<(), BoxError> {
+        match &*not.method {
+            DidOpenTextDocument::METHOD => {
+                let params: lsp_types::DidOpenTextDocumentParams = serde_json::from_value(not.params)?;
+                let uri = params.text_document.uri;
+                self.documents.insert(uri.clone(), Document::new(params.text_document.text, file_path(&uri), self.dialect));
+                self.publish_diagnostics(&uri)?;
+            },
+            DidChangeTextDocument::METHOD => {
+                let params: lsp_types::DidChangeTextDocumentParams = serde_json::from_value(not.params)?;
+                let uri = params.text_document.uri;
+                // The full document is synced, so only the last change matters.
+                if let Some(change) = params.content_changes.into_iter().last() {
+                    self.documents.insert(uri.clone(), Document::new(change.text, file_path(&uri), self.dialect));
+                    self.publish_diagnostics(&uri)?;
+                }
+            },
+            DidCloseTextDocument::METHOD => {
+                let params: lsp_types::DidCloseTextDocumentParams = serde_json::from_value(not.params)?;
+                self.documents.remove(&params.text_document.uri);
+                self.publish_diagnostics(&params.text_document.uri)?;
+            },
+            _ => {},
+        }
+
+        Ok(())
+    }
+
+    fn handle_request(&self, req: Request) -> Response {
+        match &*req.method {
+            GotoDefinition::METHOD => self.dispatch::<GotoDefinition>(req, Server::definition),
+            References::METHOD => self.dispatch::<References>(req, Server::references),
+            HoverRequest::METHOD => self.dispatch::<HoverRequest>(req, Server::hover),
+            Completion::METHOD => self.dispatch::<Completion>(req, Server::completion),
+            DocumentSymbolRequest::METHOD => self.dispatch::<DocumentSymbolRequest>(req, Server::document_symbols),
+            Rename::METHOD => self.dispatch::<Rename>(req, Server::rename),
+            _ => Response::new_err(req.id, ErrorCode::MethodNotFound as i32, format!("unsupported request {}", req.method)),
+        }
+    }
+    fn dispatch<R: lsp_types::request::Request>(
+        &self,
+        req: Request,
+        handler: impl FnOnce(&Self, R::Params) -> Result<R::Result, String>
+    ) -> Response {
+        let id = req.id.clone();
+        match req.extract::<R::Params>(R::METHOD) {
+            Ok((id, params)) => match handler(self, params) {
+                Ok(result) => Response::new_ok(id, result),
+                Err(msg) => Response::new_err(id, ErrorCode::InvalidParams as i32, msg),
+            },
+            Err(e) => Response::new_err(id, ErrorCode::InvalidParams as i32, e.to_string()),
+        }
+    }
+
+    fn document(&self, uri: &Uri) -> Result<&Document, String> {
+        self.documents.get(uri).ok_or_else(|| format!("document {} is not open", uri.as_str()))
+    }
+    fn document_at(&self, params: &TextDocumentPositionParams) -> Result<&Document, String> {
+        self.document(&params.text_document.uri)
+    }
+
+    fn definition(&self, params: GotoDefinitionParams) -> Result<Option<GotoDefinitionResponse>, String> {
+        let uri = &params.text_document_position_params.text_document.uri;
+        let doc = self.document_at(&params.text_document_position_params)?;
+
+        let Some(label) = doc.label_at(params.text_document_position_params.position) else { return Ok(None) };
+        let def = doc.occurrences(&label.name).find(|l| l.is_def);
+        Ok(def.map(|l| GotoDefinitionResponse::Scalar(Location::new(uri.clone(), range_of(&doc.text, &l.span)))))
+    }
+
+    fn references(&self, params: ReferenceParams) -> Result<Option<Vec<Location>>, String> {
+        let uri = &params.text_document_position.text_document.uri;
+        let doc = self.document_at(&params.text_document_position)?;
+
+        let Some(label) = doc.label_at(params.text_document_position.position) else { return Ok(None) };
+        let locations = doc.occurrences(&label.name)
+            .filter(|l| params.context.include_declaration || !l.is_def)
+            .map(|l| Location::new(uri.clone(), range_of(&doc.text, &l.span)))
+            .collect();
+        Ok(Some(locations))
+    }
+
+    fn hover(&self, params: HoverParams) -> Result<Option<Hover>, String> {
+        let doc = self.document_at(&params.text_document_position_params)?;
+        let pos = params.text_document_position_params.position;
+
+        // Hovering over a label shows its address:
+        if let Some(label) = doc.label_at(pos) {
+            let value = match doc.label_addr(&label.name) {
+                Some(addr) => format!("`{}`: label at `x{addr:04X}`", label.name),
+                None => format!("`{}`: label", label.name),
+            };
+            return Ok(Some(Hover {
+                contents: HoverContents::Markup(MarkupContent { kind: MarkupKind::Markdown, value }),
+                range: Some(range_of(&doc.text, &label.span)),
+            }));
+        }
+
+        // Hovering over a statement shows its encoding (and documentation for TRAPs):
+        let offset = offset_of(&doc.text, pos);
+        let Some(stmt) = doc.ast.iter().find(|s| s.span.start <= offset && offset <= s.span.end) else { return Ok(None) };
+
+        let mut sections = vec![];
+        let addr = doc.obj.as_ref()
+            .and_then(|o| o.symbol_table())
+            .and_then(|sym| sym.lookup_line(pos.line as usize));
+        if let Some(addr) = addr {
+            match doc.word_at(addr) {
+                Some(word) => sections.push(format!(
+                    "`x{addr:04X}`: `x{word:04X}` (`{:04b} {:04b} {:04b} {:04b}`)",
+                    word >> 12, (word >> 8) & 0xF, (word >> 4) & 0xF, word & 0xF
+                )),
+                None => sections.push(format!("`x{addr:04X}`")),
+            }
+        }
+        if let StmtKind::Instr(instr) = &stmt.nucleus {
+            sections.extend(trap_vect(instr).and_then(trap_doc).map(String::from));
+        }
+
+        if sections.is_empty() { return Ok(None) }
+        Ok(Some(Hover {
+            contents: HoverContents::Markup(MarkupContent { kind: MarkupKind::Markdown, value: sections.join("\n\n") }),
+            range: Some(range_of(&doc.text, &stmt.span)),
+        }))
+    }
+
+    fn completion(&self, params: CompletionParams) -> Result<Option<CompletionResponse>, String> {
+        let doc = self.document_at(&params.text_document_position)?;
+
+        let keyword = |label: &str, kind| CompletionItem { label: label.to_string(), kind: Some(kind), ..Default::default() };
+        let mut items: Vec<_> = INSTRUCTIONS.iter().map(|s| keyword(s, CompletionItemKind::KEYWORD))
+            .chain(DIRECTIVES.iter().map(|s| keyword(s, CompletionItemKind::KEYWORD)))
+            .chain(REGISTERS.iter().map(|s| keyword(s, CompletionItemKind::VARIABLE)))
+            .collect();
+
+        for label in doc.labels.iter().filter(|l| l.is_def) {
+            items.push(CompletionItem {
+                label: label.name.clone(),
+                kind: Some(CompletionItemKind::REFERENCE),
+                detail: doc.label_addr(&label.name).map(|addr| format!("x{addr:04X}")),
+                ..Default::default()
+            });
+        }
+
+        Ok(Some(CompletionResponse::Array(items)))
+    }
+
+    fn document_symbols(&self, params: DocumentSymbolParams) -> Result<Option<DocumentSymbolResponse>, String> {
+        let doc = self.document(&params.text_document.uri)?;
+
+        let subroutines: Vec<_> = doc.ast.iter()
+            .filter_map(|s| match &s.nucleus {
+                StmtKind::Instr(AsmInstr::JSR(PCOffset::Label(l))) => Some(l.name.to_uppercase()),
+                _ => None,
+            })
+            .collect();
+
+        let mut symbols = vec![];
+        for stmt in &doc.ast {
+            for label in &stmt.labels {
+                let kind = match &stmt.nucleus {
+                    _ if subroutines.contains(&label.name.to_uppercase()) => SymbolKind::FUNCTION,
+                    StmtKind::Directive(Directive::Fill(_) | Directive::Blkw(_) | Directive::Stringz(_)) => SymbolKind::VARIABLE,
+                    _ => SymbolKind::KEY,
+                };
+
+                #[allow(deprecated)]
+                symbols.push(DocumentSymbol {
+                    name: label.name.clone(),
+                    detail: doc.label_addr(&label.name).map(|addr| format!("x{addr:04X}")),
+                    kind,
+                    tags: None,
+                    deprecated: None,
+                    range: range_of(&doc.text, &(label.span().start..stmt.span.end)),
+                    selection_range: range_of(&doc.text, &label_span(&doc.text, label.span())),
+                    children: None,
+                });
+            }
+        }
+
+        Ok(Some(DocumentSymbolResponse::Nested(symbols)))
+    }
+
+    fn rename(&self, params: RenameParams) -> Result<Option<WorkspaceEdit>, String> {
+        let uri = &params.text_document_position.text_document.uri;
+        let doc = self.document_at(&params.text_document_position)?;
+
+        if !is_label_name(&params.new_name) {
+            return Err(format!("{:?} is not a valid label name", params.new_name));
+        }
+        let Some(label) = doc.label_at(params.text_document_position.position) else { return Ok(None) };
+
+        let edits = doc.occurrences(&label.name)
+            .map(|l| TextEdit::new(range_of(&doc.text, &l.span), params.new_name.clone()))
+            .collect();
+        Ok(Some(WorkspaceEdit::new(HashMap::from([(uri.clone(), edits)]))))
+    }
+}
+
+fn capabilities() -> ServerCapabilities {
+    ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        definition_provider: Some(OneOf::Left(true)),
+        references_provider: Some(OneOf::Left(true)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        completion_provider: Some(CompletionOptions {
+            trigger_characters: Some(vec![String::from(".")]),
+            ..Default::default()
+        }),
+        document_symbol_provider: Some(OneOf::Left(true)),
+        rename_provider: Some(OneOf::Left(true)),
+        ..Default::default()
+    }
+}
+
+/// Reads the lint config from the client's initialization options.
+fn lint_config(init: &InitializeParams) -> LintConfig {
+    let mut config = LintConfig::default();
+
+    let lints = init.initialization_options.as_ref()
+        .and_then(|o| o.get("lints"))
+        .and_then(|l| l.as_object());
+    for (name, enabled) in lints.into_iter().flatten() {
+        if let (Some(lint), Some(enabled)) = (Lint::from_name(name), enabled.as_bool()) {
+            config.set_enabled(lint, enabled);
+        }
+    }
+
+    config
+}
+
+/// Reads the dialect from the client's initialization options.
+fn dialect(init: &InitializeParams) -> Dialect {
+    init.initialization_options.as_ref()
+        .and_then(|o| o.get("dialect"))
+        .and_then(|d| d.as_str())
+        .and_then(Dialect::from_name)
+        .unwrap_or_default()
+}
+
+/// Runs the language server over stdio until the client shuts it down.
+pub fn run() -> Result<(), BoxError> {
+    let (connection, io_threads) = Connection::stdio();
+
+    let init = connection.initialize(serde_json::to_value(capabilities())?)?;
+    let init: InitializeParams = serde_json::from_value(init)?;
+
+    let mut server = Server { connection, documents: HashMap::new(), lint_config: lint_config(&init), dialect: dialect(&init) };
+    while let Ok(msg) = server.connection.receiver.recv() {
+        match msg {
+            Message::Request(req) => {
+                if server.connection.handle_shutdown(&req)? { break; }
+                let resp = server.handle_request(req);
+                server.connection.sender.send(Message::Response(resp))?;
+            },
+            Message::Notification(not) => server.handle_notification(not)?,
+            Message::Response(_) => {},
+        }
+    }
+
+    drop(server);
+    io_threads.join()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A server (not connected to a client) with a document open.
+    fn server(text: &str) -> (Server, Uri) {
+        let uri: Uri = "file:///tmp/test.asm".parse().expect("URI should parse");
+        let (connection, _client) = Connection::memory();
+        let mut server = Server { connection, documents: HashMap::new(), lint_config: LintConfig::default(), dialect: Dialect::default() };
+        server.documents.insert(uri.clone(), Document::new(text.to_string(), None, Dialect::default()));
+        (server, uri)
+    }
+    /// The position of the `n`th occurrence of `needle` in the text.
+    fn pos(text: &str, needle: &str, n: usize) -> Position {
+        let (offset, _) = text.match_indices(needle).nth(n).unwrap_or_else(|| panic!("{needle:?} should occur {} times", n + 1));
+        position_of(text, offset)
+    }
+    fn position_params(uri: &Uri, position: Position) -> TextDocumentPositionParams {
+        TextDocumentPositionParams::new(lsp_types::TextDocumentIdentifier::new(uri.clone()), position)
+    }
+
+    fn path_of(uri: &str) -> Option<PathBuf> {
+        file_path(&uri.parse().expect("URI should parse"))
+    }
+
+    #[test]
+    fn file_paths() {
+        assert_eq!(path_of("file:///home/x/a.asm"), Some(PathBuf::from("/home/x/a.asm")));
+        assert_eq!(path_of("file://localhost/home/x/a.asm"), Some(PathBuf::from("/home/x/a.asm")));
+        assert_eq!(path_of("file:///home/x/my%20file.asm"), Some(PathBuf::from("/home/x/my file.asm")));
+        assert_eq!(path_of("file:///c%3A/Users/x/a.asm"), Some(PathBuf::from("c:/Users/x/a.asm")));
+        assert_eq!(path_of("file:///C:/Users/x/a.asm"), Some(PathBuf::from("C:/Users/x/a.asm")));
+        assert_eq!(path_of("file://server/share/a.asm"), Some(PathBuf::from("//server/share/a.asm")));
+        assert_eq!(path_of("untitled:Untitled-1"), None);
+        assert_eq!(path_of("file:///home/x/%FF.asm"), None);
+    }
+
+    const PROGRAM: &str = ".ORIG x3000\nLOOP JSR SUB\nBR LOOP\nSUB ADD R0, R0, #1\nRET\nUNUSED .FILL 0\n.END\n";
+
+    #[test]
+    fn labels() {
+        let doc = Document::new(PROGRAM.to_string(), None, Dialect::default());
+        let labels: Vec<_> = doc.labels.iter().map(|l| (l.name.as_str(), &PROGRAM[l.span.clone()], l.is_def)).collect();
+        assert_eq!(labels, [
+            ("LOOP", "LOOP", true), ("SUB", "SUB", false), ("LOOP", "LOOP", false),
+            ("SUB", "SUB", true), ("UNUSED", "UNUSED", true),
+        ]);
+        assert_eq!(doc.label_addr("sub"), Some(0x3002));
+    }
+
+    #[test]
+    fn definition() {
+        let (server, uri) = server(PROGRAM);
+        let params = GotoDefinitionParams {
+            text_document_position_params: position_params(&uri, pos(PROGRAM, "LOOP", 1)),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        let Some(GotoDefinitionResponse::Scalar(def)) = server.definition(params).expect("definition should be found") else {
+            panic!("LOOP should have a definition");
+        };
+        assert_eq!(def.range.start, pos(PROGRAM, "LOOP", 0));
+    }
+
+    #[test]
+    fn hover() {
+        let (server, uri) = server(PROGRAM);
+        let hover = |position| {
+            let params = HoverParams { text_document_position_params: position_params(&uri, position), work_done_progress_params: Default::default() };
+            match server.hover(params).expect("hover should succeed") {
+                Some(Hover { contents: HoverContents::Markup(m), .. }) => Some(m.value),
+                _ => None,
+            }
+        };
+        assert_eq!(hover(pos(PROGRAM, "SUB", 0)).as_deref(), Some("`SUB`: label at `x3002`"));
+        assert_eq!(hover(pos(PROGRAM, "ADD", 0)).as_deref(), Some("`x3002`: `x1021` (`0001 0000 0010 0001`)"));
+    }
+
+    #[test]
+    fn document_symbols() {
+        let (server, uri) = server(PROGRAM);
+        let params = DocumentSymbolParams {
+            text_document: lsp_types::TextDocumentIdentifier::new(uri),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        let Some(DocumentSymbolResponse::Nested(symbols)) = server.document_symbols(params).expect("symbols should be found") else {
+            panic!("symbols should be nested");
+        };
+        let symbols: Vec<_> = symbols.iter().map(|s| (s.name.as_str(), s.kind, s.detail.as_deref())).collect();
+        assert_eq!(symbols, [
+            ("LOOP", SymbolKind::KEY, Some("x3000")),
+            ("SUB", SymbolKind::FUNCTION, Some("x3002")),
+            ("UNUSED", SymbolKind::VARIABLE, Some("x3004")),
+        ]);
+    }
+
+    #[test]
+    fn diagnostics() {
+        let uri: Uri = "file:///tmp/test.asm".parse().expect("URI should parse");
+
+        // A document which assembles gets warnings:
+        let doc = Document::new(PROGRAM.to_string(), None, Dialect::default());
+        let diagnostics = doc.diagnostics(&uri, &LintConfig::default());
+        let unused = diagnostics.iter()
+            .find(|d| d.code == Some(NumberOrString::String(String::from("lint::unused-label"))))
+            .expect("UNUSED should be reported");
+        assert_eq!(unused.severity, Some(DiagnosticSeverity::WARNING));
+        assert_eq!(unused.range.start.line, 5);
+        assert!(diagnostics.iter().all(|d| d.severity == Some(DiagnosticSeverity::WARNING)));
+
+        // A document which doesn't gets errors instead:
+        let text = ".ORIG x3000\nBR NOWHERE\nHALT\n.END\n";
+        let doc = Document::new(text.to_string(), None, Dialect::default());
+        let diagnostics = doc.diagnostics(&uri, &LintConfig::default());
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Some(DiagnosticSeverity::ERROR));
+        assert_eq!(diagnostics[0].range.start.line, 1);
+    }
+
+    const EXPRS: &str = "SIZE .EQU 4\n.ORIG x3000\nLEA R0, BUF+2\nLD R1, BUF\nADD R2, R2, #SIZE-1\nHALT\nTABLE .FILL BUF, BUF+1\nBUF .BLKW SIZE\n.END\n";
+
+    #[test]
+    fn references_in_expressions() {
+        let (server, uri) = server(EXPRS);
+        let params = ReferenceParams {
+            text_document_position: position_params(&uri, pos(EXPRS, "BUF", 4)),
+            context: lsp_types::ReferenceContext { include_declaration: true },
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        let refs = server.references(params).expect("references should be found").expect("BUF should be a label");
+        let expected: Vec<_> = (0..5).map(|n| pos(EXPRS, "BUF", n)).collect();
+        assert_eq!(refs.iter().map(|l| l.range.start).collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn rename_in_expressions() {
+        let (server, uri) = server(EXPRS);
+        assert!(server.documents[&uri].obj.is_some());
+        let params = RenameParams {
+            text_document_position: position_params(&uri, pos(EXPRS, "BUF", 0)),
+            new_name: String::from("BUFFER"),
+            work_done_progress_params: Default::default(),
+        };
+        let edit = server.rename(params).expect("rename should succeed").expect("BUF should be a label");
+        let mut edits = edit.changes.expect("rename should have changes").remove(&uri).expect("rename should edit the document");
+
+        // Applying the edits (from the end) renames every use, so the document still assembles:
+        edits.sort_by_key(|e| std::cmp::Reverse(offset_of(EXPRS, e.range.start)));
+        let mut text = EXPRS.to_string();
+        for e in &edits {
+            text.replace_range(offset_of(EXPRS, e.range.start)..offset_of(EXPRS, e.range.end), &e.new_text);
+        }
+        assert_eq!(edits.len(), 5);
+        assert!(!text.contains("BUF,") && !text.contains("BUF+"));
+        assert!(Document::new(text, None, Dialect::default()).obj.is_some());
+    }
+}
@@ -1,7 +1,300 @@
 use std::collections::HashMap;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 
+use lc3_ensemble::asm::encoding::{BinaryFormat, ObjFileFormat, TextFormat};
 use lc3_ensemble::asm::{ObjectFile, SourceInfo, SymbolTable};
 use lc3_ensemble::ast::asm::try_disassemble_line;
+use neon::prelude::*;
+
+use crate::preproc::Expanded;
+use crate::reloc::{self, Relocs};
+
+/// The format object files are written in.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub(crate) enum ObjFormat {
+    /// Ensemble's text format.
+    #[default]
+    Text,
+    /// Ensemble's binary format.
+    Binary,
+    /// The `.obj`, `.sym`, `.bin`, and `.hex` files of `lc3as` (see [`crate::classic`]).
+    Classic,
+}
+impl ObjFormat {
+    /// Finds the format with the given name (`text`, `binary`, or `classic`).
+    pub(crate) fn from_name(name: &str) -> Option<ObjFormat> {
+        match name {
+            "text"    => Some(ObjFormat::Text),
+            "binary"  => Some(ObjFormat::Binary),
+            "classic" => Some(ObjFormat::Classic),
+            _ => None,
+        }
+    }
+}
+
+/// How object files are written.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct ObjOutput {
+    pub(crate) format: ObjFormat,
+    /// Whether to strip the symbol table and source code (see [`strip`]).
+    pub(crate) strip: bool,
+}
+impl ObjOutput {
+    /// Reads the `format` and `strip` fields of a JS options object.
+    pub(crate) fn from_js<'a>(cx: &mut impl Context<'a>, opts: Handle<'a, JsObject>) -> NeonResult<Self> {
+        let mut output = ObjOutput::default();
+        if let Some(name) = opts.get_opt::<JsString, _, _>(cx, "format")? {
+            let name = name.value(cx);
+            output.format = match ObjFormat::from_name(&name) {
+                Some(format) => format,
+                None => return cx.throw_error(format!("unknown object file format {name:?}")),
+            };
+        }
+        if let Some(strip) = opts.get_opt::<JsBoolean, _, _>(cx, "strip")? {
+            output.strip = strip.value(cx);
+        }
+        Ok(output)
+    }
+
+    /// Serializes an object file (and its relocatable sections, see [`crate::reloc`]) which is written to the given path,
+    /// returning every file to write (the classic format has several) and their contents.
+    ///
+    /// This fails if the object file can't be written in the format.
+    pub(crate) fn files(self, obj: &ObjectFile, relocs: &Relocs, path: &Path) -> Result<Vec<(PathBuf, Vec<u8>)>, &'static str> {
+        let stripped;
+        let obj = match self.strip {
+            true => { stripped = strip(obj).ok_or("object file could not be stripped")?; &stripped },
+            false => obj,
+        };
+        let mut bytes = match self.format {
+            ObjFormat::Text => {
+                let mut text = TextFormat::serialize(obj);
+                // Ensemble writes a `.DEBUG` section even without any source, which it then can't read back:
+                if self.strip && let Some(i) = text.find("\n.DEBUG\n") {
+                    text.truncate(i + 1);
+                }
+                text.into_bytes()
+            },
+            ObjFormat::Binary => BinaryFormat::serialize(obj),
+            ObjFormat::Classic if !relocs.is_empty() => return Err("relocatable sections cannot be written in the classic format"),
+            ObjFormat::Classic => return crate::classic::write(obj, path),
+        };
+        reloc::append(&mut bytes, relocs, self.format == ObjFormat::Text);
+        Ok(vec![(path.to_path_buf(), bytes)])
+    }
+}
+
+/// How ensemble's binary format for object files starts (followed by a 2-byte version).
+pub(crate) const BINARY_MAGIC: &[u8] = b"obj\x21\x10";
+
+/// A chunk of ensemble's binary format for object files.
+///
+/// Ensemble's object files can't be inspected or modified directly, so they are split into these chunks
+/// (see [`to_chunks`]) to get at their blocks, labels, and relocations, and built back from them (see [`from_chunks`]).
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub(crate) enum Chunk {
+    /// A block of memory, with each word (or `None` if it's uninitialized).
+    Memory { start: u16, words: Vec<Option<u16>> },
+    /// A label, with where its definition starts in the source.
+    Label { addr: u16, external: bool, src_start: u64, name: String },
+    /// The addresses of consecutive source lines (from `first`), which must be in order.
+    Lines { first: u64, addrs: Vec<u16> },
+    /// Source code.
+    Source(String),
+    /// A relocation (an address which refers to an external label).
+    Relocation { addr: u16, name: String },
+}
+impl Chunk {
+    /// Writes the chunk as ensemble reads it.
+    fn write(&self, out: &mut Vec<u8>) {
+        match self {
+            Chunk::Memory { start, words } => {
+                out.push(0x00);
+                out.extend(start.to_le_bytes());
+                out.extend((words.len() as u16).to_le_bytes());
+                for word in words {
+                    match word {
+                        Some(word) => { out.push(0xFF); out.extend(word.to_le_bytes()); },
+                        None => out.extend([0; 3]),
+                    }
+                }
+            },
+            Chunk::Label { addr, external, src_start, name } => {
+                out.push(0x01);
+                out.extend(addr.to_le_bytes());
+                out.push(u8::from(*external));
+                out.extend(src_start.to_le_bytes());
+                out.extend((name.len() as u64).to_le_bytes());
+                out.extend(name.as_bytes());
+            },
+            // Ensemble writes consecutive lines at the same address (e.g., a label on its own line) in one block,
+            // but can only read blocks whose addresses are strictly increasing, so those blocks are split:
+            Chunk::Lines { first, addrs } => {
+                let mut lno = *first;
+                for block in addrs.chunk_by(|a, b| a < b) {
+                    out.push(0x02);
+                    out.extend(lno.to_le_bytes());
+                    out.extend((block.len() as u16).to_le_bytes());
+                    out.extend(block.iter().flat_map(|a| a.to_le_bytes()));
+                    lno += block.len() as u64;
+                }
+            },
+            Chunk::Source(src) => {
+                out.push(0x03);
+                out.extend((src.len() as u64).to_le_bytes());
+                out.extend(src.as_bytes());
+            },
+            Chunk::Relocation { addr, name } => {
+                out.push(0x04);
+                out.extend(addr.to_le_bytes());
+                out.extend((name.len() as u64).to_le_bytes());
+                out.extend(name.as_bytes());
+            },
+        }
+    }
+}
+
+/// Reads the chunks of ensemble's binary format for object files (after its magic number and version),
+/// returning them along with anything after the last chunk which could be read.
+///
+/// This returns `None` if the bytes aren't in the binary format.
+///
+/// Ensemble writes the address of each relocation in big-endian, but reads it back in little-endian,
+/// so those addresses are read in big-endian here (and written in little-endian by [`from_chunks`]).
+pub(crate) fn read_chunks(bytes: &[u8]) -> Option<(Vec<Chunk>, &[u8])> {
+    fn take<'b>(rest: &mut &'b [u8], n: usize) -> Option<&'b [u8]> {
+        let (taken, tail) = rest.split_at_checked(n)?;
+        *rest = tail;
+        Some(taken)
+    }
+    fn take_u16(rest: &mut &[u8]) -> Option<u16> {
+        take(rest, 2).map(|b| u16::from_le_bytes([b[0], b[1]]))
+    }
+    fn take_u64(rest: &mut &[u8]) -> Option<u64> {
+        take(rest, 8).and_then(|b| b.try_into().ok()).map(u64::from_le_bytes)
+    }
+    fn take_str(rest: &mut &[u8], len: u64) -> Option<String> {
+        let bytes = take(rest, usize::try_from(len).ok()?)?;
+        String::from_utf8(bytes.to_vec()).ok()
+    }
+    fn chunk(rest: &mut &[u8]) -> Option<Chunk> {
+        let chunk = match take(rest, 1)?[0] {
+            0x00 => {
+                let start = take_u16(rest)?;
+                let len = take_u16(rest)?;
+                let words = take(rest, 3 * usize::from(len))?
+                    .chunks_exact(3)
+                    .map(|w| (w[0] == 0xFF).then(|| u16::from_le_bytes([w[1], w[2]])))
+                    .collect();
+                Chunk::Memory { start, words }
+            },
+            0x01 => {
+                let addr = take_u16(rest)?;
+                let external = take(rest, 1)?[0] != 0;
+                let src_start = take_u64(rest)?;
+                let len = take_u64(rest)?;
+                Chunk::Label { addr, external, src_start, name: take_str(rest, len)? }
+            },
+            0x02 => {
+                let first = take_u64(rest)?;
+                let len = take_u16(rest)?;
+                let addrs = (0..len).map(|_| take_u16(rest)).collect::<Option<_>>()?;
+                Chunk::Lines { first, addrs }
+            },
+            0x03 => {
+                let len = take_u64(rest)?;
+                Chunk::Source(take_str(rest, len)?)
+            },
+            0x04 => {
+                let addr = take_u16(rest)?.swap_bytes();
+                let len = take_u64(rest)?;
+                Chunk::Relocation { addr, name: take_str(rest, len)? }
+            },
+            _ => return None,
+        };
+        Some(chunk)
+    }
+
+    let mut rest = bytes.strip_prefix(BINARY_MAGIC)?.get(2..)?;
+    let mut chunks = vec![];
+    loop {
+        let mut tail = rest;
+        match chunk(&mut tail) {
+            Some(c) => { chunks.push(c); rest = tail; },
+            None => break Some((chunks, rest)),
+        }
+    }
+}
+/// Splits an object file into the chunks of ensemble's binary format.
+pub(crate) fn to_chunks(obj: &ObjectFile) -> Vec<Chunk> {
+    // Ensemble can always read what it writes:
+    read_chunks(&BinaryFormat::serialize(obj)).map_or(vec![], |(chunks, _)| chunks)
+}
+/// Builds an object file from the chunks of ensemble's binary format,
+/// returning `None` if ensemble can't read them (e.g., if line numbers are out of order).
+pub(crate) fn from_chunks<'c>(chunks: impl IntoIterator<Item=&'c Chunk>) -> Option<ObjectFile> {
+    // The magic number and version of an empty object file:
+    let mut bytes = BinaryFormat::serialize(&ObjectFile::empty());
+    for chunk in chunks {
+        chunk.write(&mut bytes);
+    }
+    BinaryFormat::deserialize(&bytes)
+}
+/// Reads ensemble's binary format for object files.
+pub(crate) fn deserialize_binary(bytes: &[u8]) -> Option<ObjectFile> {
+    match read_chunks(bytes)? {
+        (chunks, []) => from_chunks(&chunks),
+        _ => None,
+    }
+}
+/// Reads ensemble's text format for object files.
+///
+/// Ensemble panics on a `.DEBUG` section whose only divider (`====`) is its last line
+/// (e.g., in a file which was cut off), so those are rejected before ensemble reads them.
+pub(crate) fn deserialize_text(text: &str) -> Option<ObjectFile> {
+    // The non-empty lines of each section (as ensemble reads them):
+    let mut sections: Vec<(&str, Vec<&str>)> = vec![];
+    for line in text.trim().lines().filter(|l| !l.starts_with('#') && !l.trim().is_empty()) {
+        match sections.last_mut() {
+            _ if line.starts_with('.') => sections.push((line, vec![])),
+            Some((_, rest)) => rest.push(line),
+            None => {},
+        }
+    }
+    let cut_off = sections.iter().any(|(header, rest)| {
+        *header == ".DEBUG" && !rest.is_empty() && rest.iter().filter(|l| l.starts_with('=')).count() < 2
+    });
+    if cut_off { return None; }
+
+    TextFormat::deserialize(text)
+}
+/// Removes the source code, the line numbers, and the labels of an object file,
+/// keeping only what is needed to load and link it (its memory, its external labels, and their relocations).
+///
+/// This returns `None` if ensemble can't read the stripped object file back.
+pub(crate) fn strip(obj: &ObjectFile) -> Option<ObjectFile> {
+    let chunks = to_chunks(obj);
+    from_chunks(chunks.iter().filter(|c| match c {
+        Chunk::Memory { .. } | Chunk::Relocation { .. } => true,
+        Chunk::Label { external, .. } => *external,
+        Chunk::Lines { .. } | Chunk::Source(_) => false,
+    }))
+}
+
+/// The memory line of a word which has no source (its character, or its disassembly).
+fn disassembled_line(value: u16) -> String {
+    if (0x0020..0x007F).contains(&value) {
+        // ASCII
+        char::from(value as u8).to_string()
+    } else {
+        // Disassemble logic
+        match try_disassemble_line(value) {
+            Some(s) => format!("*{s}"),
+            None => String::new(),
+        }
+    }
+}
 
 // Symbol access stuff
 fn get_sym_source_from_obj(obj: &ObjectFile) -> Option<(&SymbolTable, &SourceInfo)> {
@@ -11,39 +304,65 @@ fn get_sym_source_from_obj(obj: &ObjectFile) -> Option<(&SymbolTable, &SourceInf
     Some((sym, src))
 }
 fn add_mem_lines_from_obj(mem_lines: &mut HashMap<u16, String>, obj: &ObjectFile) {
-    if let Some((sym, src_info)) = get_sym_source_from_obj(obj) {
-        // For each source line in the object file,
-        // if it maps to an address, add the mapping (addr, source line) to mem_lines.
+    let Some((sym, src_info)) = get_sym_source_from_obj(obj) else {
+        // Without source (e.g., a stripped object file), each word is disassembled instead:
         mem_lines.extend({
-            sym.line_iter()
-                .filter_map(|(lno, addr)| {
-                    let span = src_info.line_span(lno)?;
-                    let text = src_info.source().get(span)?.to_string();
-                    Some((addr, text))
-                })
+            obj.addr_iter()
+                .filter_map(|(addr, value)| Some((addr, disassembled_line(value?))))
         });
+        return;
+    };
+    // For each source line in the object file,
+    // if it maps to an address, add the mapping (addr, source line) to mem_lines.
+    mem_lines.extend({
+        sym.line_iter()
+            .filter_map(|(lno, addr)| {
+                let span = src_info.line_span(lno)?;
+                let text = src_info.source().get(span)?.to_string();
+                Some((addr, text))
+            })
+    });
 
-        // Update sources to better handle .stringz:
-        let labels = obj.addr_iter()
-            .filter_map(|(addr, m_val)| match m_val {
-                Some(val @ 0x0020..0x007F) => Some((addr, char::from(val as u8).to_string())),
+    // Update sources to better handle string data (.stringz and .ascii),
+    // showing escapes for control characters and a string's null terminator:
+    let mut prev_char = false;
+    let labels = obj.addr_iter()
+        .filter_map(|(addr, m_val)| {
+            let label = match m_val {
+                Some(val @ 0x0020..0x007F) => Some(char::from(val as u8).to_string()),
+                Some(0x0A) => Some(String::from("\\n")),
+                Some(0x09) => Some(String::from("\\t")),
+                Some(0x0D) => Some(String::from("\\r")),
+                Some(0x00) if prev_char => Some(String::from("\\0")),
                 _ => None
-            });
-
-        for (addr, label) in labels {
-            let new_label = match mem_lines.get(&addr) {
-                Some(orig_label) => format!("{orig_label} ({label})"),
-                None => label,
             };
-            mem_lines.insert(addr, new_label);
-        }
+            prev_char = label.is_some();
+            Some((addr, label?))
+        });
+
+    for (addr, label) in labels {
+        let new_label = match mem_lines.get(&addr) {
+            Some(orig_label) => format!("{orig_label} ({label})"),
+            None => label,
+        };
+        mem_lines.insert(addr, new_label);
     }
 }
 //
 
+/// Where a span of the loaded object file's source is, as lines and columns (from 0).
+pub(crate) struct SourceRange<'c> {
+    /// The file the span is in (if it's known).
+    pub(crate) path: Option<&'c Path>,
+    pub(crate) start: (usize, usize),
+    pub(crate) end: (usize, usize),
+}
+
 #[derive(Default)]
 pub(crate) struct ObjContents {
     obj_file: Option<ObjectFile>,
+    /// The preprocessed source of the object file (if its source files could be found).
+    expanded: Option<Expanded>,
     mem_lines: HashMap<u16, String>,
 }
 impl ObjContents {
@@ -51,35 +370,177 @@ impl ObjContents {
         self.mem_lines.get(&addr).map_or("", |s| s)
     }
     pub(crate) fn set_mem_line(&mut self, addr: u16, value: u16) {
-        let string = if (0x0020..0x007F).contains(&value) {
-            // ASCII
-            char::from(value as u8).to_string()
-        } else {
-            // Disassemble logic
-            match try_disassemble_line(value) {
-                Some(s) => format!("*{s}"),
-                None => String::new(),
-            }
-        };
-    
-        self.mem_lines.insert(addr, string);
+        self.mem_lines.insert(addr, disassembled_line(value));
     }
 
-    pub(crate) fn load_contents(&mut self, obj: ObjectFile) {
+    pub(crate) fn load_contents(&mut self, obj: ObjectFile, expanded: Option<Expanded>) {
         // Set mem lines:
         add_mem_lines_from_obj(&mut self.mem_lines, lc3_ensemble::sim::_os_obj_file());
         add_mem_lines_from_obj(&mut self.mem_lines, &obj);
         //
 
         self.obj_file.replace(obj);
+        self.expanded = expanded;
     }
 
     pub(crate) fn clear(&mut self) {
         self.obj_file.take();
+        self.expanded.take();
         self.mem_lines.clear();
     }
     
+    /// Gets the label at a given address, checking the loaded object file and then the OS.
+    pub(crate) fn get_label(&self, addr: u16) -> Option<&str> {
+        self.obj_file.as_ref()
+            .and_then(|obj| obj.symbol_table()?.rev_lookup_label(addr))
+            .or_else(|| lc3_ensemble::sim::_os_obj_file().symbol_table()?.rev_lookup_label(addr))
+    }
+
+    /// Finds whether each address of the OS and the loaded object file is code (see [`crate::disasm::find_code`]).
+    pub(crate) fn code_map(&self) -> HashMap<u16, bool> {
+        std::iter::once(lc3_ensemble::sim::_os_obj_file())
+            .chain(self.obj_file.as_ref())
+            .flat_map(|obj| {
+                let code = crate::disasm::find_code(obj);
+                obj.addr_iter().map(move |(addr, _)| (addr, code.contains(&addr)))
+            })
+            .collect()
+    }
+
+    /// Gets the symbol table of the loaded object file (which may not have source info).
+    pub(crate) fn get_sym_table(&self) -> Option<&SymbolTable> {
+        self.obj_file.as_ref()?.symbol_table()
+    }
+
     pub(crate) fn get_sym_source(&self) -> Option<(&SymbolTable, &SourceInfo)> {
         get_sym_source_from_obj(self.obj_file.as_ref()?)
     }
-}
\ No newline at end of file
+
+    /// Finds where a span of the loaded object file's source is.
+    ///
+    /// If the object file's preprocessed source is known, the span is mapped to the file it came from
+    /// (see [`Expanded::locate`]). Otherwise, the span is in the object file's source, which has no path.
+    pub(crate) fn source_range(&self, span: Range<usize>) -> Option<SourceRange<'_>> {
+        let (_, src_info) = self.get_sym_source()?;
+        let range = match &self.expanded {
+            Some(expanded) => {
+                let loc = expanded.locate(span);
+                let info = SourceInfo::from(loc.text);
+                SourceRange { path: loc.path, start: info.get_pos_pair(loc.span.start), end: info.get_pos_pair(loc.span.end) }
+            },
+            None => SourceRange { path: None, start: src_info.get_pos_pair(span.start), end: src_info.get_pos_pair(span.end) },
+        };
+        Some(range)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use lc3_ensemble::sim::Simulator;
+
+    use super::*;
+    use crate::asm::tests::{assemble, assemble_relocatable};
+    use crate::preproc::tests::temp_dir;
+
+    const PROGRAM: &str = ".ORIG x3000\nMAIN ADD R0, R0, #1\nHALT\nMSG .STRINGZ \"hi\"\n.END\n";
+
+    /// Writes the object file in the given way, and reads it back.
+    fn write_and_read(name: &str, src: &str, output: ObjOutput) -> (ObjectFile, Relocs) {
+        let (obj, relocs) = assemble_relocatable(src);
+        let path = temp_dir(name).join("out.obj");
+        let files = output.files(&obj, &relocs, &path).expect("object file should be written");
+        for (path, bytes) in files {
+            std::fs::write(path, bytes).expect("file should be written");
+        }
+        crate::read_relocatable_obj_file(&path)
+            .expect("object file should be read")
+            .expect("object file should be well-formed")
+    }
+
+    #[test]
+    fn chunks_round_trip() {
+        let obj = assemble(".ORIG x3000\n.EXTERNAL PRINT\nMAIN LDI R0, PTR\nHALT\nPTR .FILL PRINT\nBUF .BLKW 1\n.END\n");
+        let chunks = to_chunks(&obj);
+        assert!(chunks.contains(&Chunk::Memory { start: 0x3000, words: vec![Some(0xA001), Some(0xF025), Some(0), None] }));
+        assert!(chunks.contains(&Chunk::Relocation { addr: 0x3002, name: String::from("PRINT") }));
+        assert!(chunks.iter().any(|c| matches!(c, Chunk::Label { addr: 0x3003, external: false, name, .. } if name == "BUF")));
+
+        // This has two lines at x3000 (`.EXTERNAL` and `MAIN`), which ensemble can't read back by itself:
+        assert!(BinaryFormat::deserialize(&BinaryFormat::serialize(&obj)).is_none());
+        let read = from_chunks(&chunks).expect("chunks should be read back");
+        assert_eq!(read.addr_iter().collect::<Vec<_>>(), obj.addr_iter().collect::<Vec<_>>());
+        let lines = |obj: &ObjectFile| obj.symbol_table().map(|sym| sym.line_iter().collect::<Vec<_>>());
+        assert_eq!(lines(&read), lines(&obj));
+        assert_eq!(read.symbol_table().and_then(|sym| sym.lookup_label("BUF")), Some(0x3003));
+        assert!(to_chunks(&read).contains(&Chunk::Relocation { addr: 0x3002, name: String::from("PRINT") }));
+
+        // Anything after the chunks isn't part of the object file:
+        let mut bytes = BinaryFormat::serialize(&obj);
+        bytes.push(0x80);
+        assert!(read_chunks(&bytes).is_some_and(|(_, rest)| rest == [0x80]));
+        assert!(deserialize_binary(&bytes).is_none());
+    }
+
+    #[test]
+    fn reads_back_every_format() {
+        let obj = assemble(PROGRAM);
+        let formats = [ObjFormat::Text, ObjFormat::Binary, ObjFormat::Classic];
+        for (format, strip) in formats.into_iter().flat_map(|f| [(f, false), (f, true)]) {
+            let name = format!("obj-{format:?}-{strip}");
+            let (read, _) = write_and_read(&name, PROGRAM, ObjOutput { format, strip });
+            assert_eq!(read.addr_iter().collect::<Vec<_>>(), obj.addr_iter().collect::<Vec<_>>(), "{name}");
+
+            let label = read.symbol_table().and_then(|sym| sym.lookup_label("MSG"));
+            let source = read.symbol_table().and_then(|sym| sym.source_info());
+            match strip {
+                true => assert!(label.is_none() && source.is_none(), "{name} should be stripped"),
+                false => assert_eq!(label, Some(0x3002), "{name} should keep its labels"),
+            }
+        }
+    }
+
+    #[test]
+    fn stripping_keeps_externals_and_relocations() {
+        let src = ".EXTERNAL PRINT\n.RELOC\nMAIN LD R0, PTR\nHALT\nPTR .FILL PRINT\nSELF .FILL MAIN\n.END\n";
+        for format in [ObjFormat::Text, ObjFormat::Binary] {
+            let (obj, relocs) = write_and_read(&format!("obj-strip-{format:?}"), src, ObjOutput { format, strip: true });
+            assert!(!relocs.is_empty(), "{format:?} should keep the relocatable section");
+            // The external label is still unresolved, so the object file can't be loaded until it is linked:
+            assert!(Simulator::new(Default::default()).load_obj_file(&obj).is_err(), "{format:?} should keep the external label");
+        }
+    }
+
+    #[test]
+    fn memory_lines_of_stripped_objects_are_disassembled() {
+        let obj = assemble(PROGRAM);
+
+        let mut contents = ObjContents::default();
+        contents.load_contents(obj.clone(), None);
+        assert_eq!(contents.get_mem_line(0x3000), "MAIN ADD R0, R0, #1");
+        assert_eq!(contents.get_mem_line(0x3002), "MSG .STRINGZ \"hi\" (h)");
+        assert_eq!(contents.get_mem_line(0x3004), "\\0");
+
+        let mut contents = ObjContents::default();
+        contents.load_contents(strip(&obj).expect("object file should be stripped"), None);
+        assert_eq!(contents.get_mem_line(0x3000), "*ADD R0, R0, #1");
+        assert_eq!(contents.get_mem_line(0x3001), "*HALT");
+        assert_eq!(contents.get_mem_line(0x3002), "h");
+        assert_eq!(contents.get_mem_line(0x3004), "");
+    }
+
+    #[test]
+    fn cut_off_object_files_are_malformed() {
+        let obj = assemble(PROGRAM);
+        let text = TextFormat::serialize(&obj);
+        let binary = BinaryFormat::serialize(&obj);
+
+        // Reading part of an object file shouldn't panic:
+        for end in 0..text.len() {
+            let _ = text.get(..end).map(deserialize_text);
+        }
+        for end in 0..binary.len() {
+            let _ = deserialize_binary(&binary[..end]);
+        }
+        assert!(deserialize_text(&text).is_some());
+        assert!(deserialize_binary(&binary).is_some());
+    }
+}
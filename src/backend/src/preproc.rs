@@ -0,0 +1,797 @@
+//! A preprocessor for `.INCLUDE`, macros (`.MACRO`/`.ENDM`), and conditional assembly (`.IF`/`.ELSE`/`.ENDIF`).
+//!
+//! Ensemble's assembler (and the `SourceInfo` it stores in object files) only knows about a single source string,
+//! so the preprocessor builds one out of chunks:
+//! - The main file comes first, with every preprocessor line blanked out
+//!   (so the offsets of every other line are unchanged).
+//! - Each included file and macro expansion is appended after it as its own chunk
+//!   (separated by an empty line).
+//!
+//! Statements are parsed from this combined source and then reordered
+//! so that each chunk's statements are placed where it was included or invoked.
+//! As such, debug symbols point into the included or macro-expanded source,
+//! and errors can be mapped back to the file they occurred in.
+//!
+//! Macros are defined with:
+//! ```text
+//! .MACRO PUSH reg
+//!     ADD R6, R6, #-1
+//!     STR \reg, R6, #0
+//! .ENDM
+//! ```
+//! and invoked like instructions (e.g., `PUSH R0`, which can also be labeled).
+//! In a macro's body, `\param` is replaced with the argument given for that parameter
+//! and `\@` is replaced with a number unique to each expansion (for labels local to the expansion).
+//!
+//! The condition of an `.IF` is an expression of literals and constants (see [`crate::expr`])
+//! which is true if it isn't 0. Only constants defined (with `.EQU` or `.SET`) before the `.IF`
+//! and outside of any excluded region can be used, since conditions are evaluated while preprocessing.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use lc3_ensemble::ast::asm::Stmt;
+use lc3_ensemble::err::ErrSpan;
+
+use crate::expr;
+
+/// How deeply includes and macro expansions can nest
+/// (so that recursive macros are rejected instead of overflowing the stack).
+const MAX_DEPTH: usize = 64;
+
+/// The kinds of errors that can occur while preprocessing.
+#[derive(Debug)]
+pub(crate) enum PreprocErrKind {
+    /// `.INCLUDE` was not given a quoted path.
+    ExpectedPath,
+    /// An included file could not be read.
+    CannotInclude(String),
+    /// A file (indirectly) includes itself.
+    IncludeCycle,
+    /// `.INCLUDE` was used in source which does not come from a file.
+    IncludeWithoutFile,
+    /// `.MACRO` was not given a name.
+    ExpectedMacroName,
+    /// `.MACRO` has no matching `.ENDM`.
+    UnclosedMacro,
+    /// `.ENDM` has no matching `.MACRO`.
+    UnopenedMacro,
+    /// A macro was invoked with the wrong number of arguments.
+    WrongArgCount { expected: usize, found: usize },
+    /// Includes or macro expansions nested too deeply.
+    TooDeep,
+    /// `.IF` has no matching `.ENDIF`.
+    UnclosedIf,
+    /// `.ELSE` or `.ENDIF` has no matching `.IF`.
+    UnopenedIf,
+    /// `.IF` has more than one `.ELSE`.
+    DuplicateElse,
+    /// The condition of an `.IF` is not a constant expression.
+    InvalidCondition,
+}
+
+/// An error from preprocessing.
+#[derive(Debug)]
+pub(crate) struct PreprocError {
+    kind: PreprocErrKind,
+    span: Range<usize>,
+}
+impl PreprocError {
+    /// Gets the diagnostic code for this error.
+    pub(crate) fn code(&self) -> &'static str {
+        match self.kind {
+            PreprocErrKind::ExpectedPath             => "preproc::expected-path",
+            PreprocErrKind::CannotInclude(_)         => "preproc::cannot-include",
+            PreprocErrKind::IncludeCycle             => "preproc::include-cycle",
+            PreprocErrKind::IncludeWithoutFile       => "preproc::include-without-file",
+            PreprocErrKind::ExpectedMacroName        => "preproc::expected-macro-name",
+            PreprocErrKind::UnclosedMacro            => "preproc::unclosed-macro",
+            PreprocErrKind::UnopenedMacro            => "preproc::unopened-macro",
+            PreprocErrKind::WrongArgCount { .. }     => "preproc::macro-args",
+            PreprocErrKind::TooDeep                  => "preproc::too-deep",
+            PreprocErrKind::UnclosedIf               => "preproc::unclosed-if",
+            PreprocErrKind::UnopenedIf               => "preproc::unopened-if",
+            PreprocErrKind::DuplicateElse            => "preproc::duplicate-else",
+            PreprocErrKind::InvalidCondition         => "preproc::invalid-condition",
+        }
+    }
+}
+impl std::fmt::Display for PreprocError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.kind {
+            PreprocErrKind::ExpectedPath => f.write_str("expected a quoted file path"),
+            PreprocErrKind::CannotInclude(e) => write!(f, "cannot include file: {e}"),
+            PreprocErrKind::IncludeCycle => f.write_str("file includes itself"),
+            PreprocErrKind::IncludeWithoutFile => f.write_str("cannot include files from source which is not in a file"),
+            PreprocErrKind::ExpectedMacroName => f.write_str("expected macro name"),
+            PreprocErrKind::UnclosedMacro => f.write_str(".macro has no matching .endm"),
+            PreprocErrKind::UnopenedMacro => f.write_str(".endm has no matching .macro"),
+            PreprocErrKind::WrongArgCount { expected, found } => write!(f, "macro expects {expected} argument(s), but {found} were given"),
+            PreprocErrKind::TooDeep => f.write_str("includes or macro expansions are nested too deeply"),
+            PreprocErrKind::UnclosedIf => f.write_str(".if has no matching .endif"),
+            PreprocErrKind::UnopenedIf => f.write_str("no matching .if"),
+            PreprocErrKind::DuplicateElse => f.write_str(".if already has an .else"),
+            PreprocErrKind::InvalidCondition => f.write_str("expected a constant expression"),
+        }
+    }
+}
+impl std::error::Error for PreprocError {}
+impl lc3_ensemble::err::Error for PreprocError {
+    fn span(&self) -> Option<ErrSpan> {
+        Some(ErrSpan::from(self.span.clone()))
+    }
+
+    fn help(&self) -> Option<Cow<'_, str>> {
+        match self.kind {
+            PreprocErrKind::IncludeCycle => Some(Cow::Borrowed("included files cannot include any file that includes them")),
+            PreprocErrKind::TooDeep => Some(Cow::Borrowed("check for macros which invoke themselves")),
+            PreprocErrKind::InvalidCondition => Some(Cow::Borrowed("conditions can only use literals and constants defined before them, where 0 is false and anything else is true")),
+            _ => None,
+        }
+    }
+}
+
+/// Where a chunk of the expanded source came from.
+#[derive(Debug)]
+enum ChunkOrigin {
+    /// A source file (which is only `None` for a main file which isn't saved).
+    File(Option<PathBuf>),
+    /// An expansion of the macro with the given name.
+    Macro(String),
+}
+#[derive(Debug)]
+struct Chunk {
+    /// Where this chunk is in the expanded source.
+    range: Range<usize>,
+    /// This chunk's text before any preprocessor lines were blanked out.
+    text: String,
+    origin: ChunkOrigin,
+    /// The chunk this was included or expanded from
+    /// and the span of the `.INCLUDE` or macro invocation which did so.
+    parent: Option<(usize, Range<usize>)>,
+}
+
+/// Where a span of the expanded source came from.
+pub(crate) struct SourceLocation<'e> {
+    /// The file the span is in (if it is in a file).
+    pub(crate) path: Option<&'e Path>,
+    /// The text of that file.
+    pub(crate) text: &'e str,
+    /// The span within that file.
+    pub(crate) span: Range<usize>,
+    /// The macro (if any) whose expansion the span was in.
+    pub(crate) expanded_from: Option<&'e str>,
+}
+
+/// Preprocessed source code.
+#[derive(Debug)]
+pub(crate) struct Expanded {
+    src: String,
+    /// The chunks of the source, ordered by where they are in the source
+    /// (with the main file always first).
+    chunks: Vec<Chunk>,
+}
+impl Expanded {
+    /// The expanded source code.
+    pub(crate) fn src(&self) -> &str {
+        &self.src
+    }
+    /// The length of the main file (whose offsets are the same in the expanded source).
+    pub(crate) fn main_len(&self) -> usize {
+        self.chunks[0].range.end
+    }
+
+    fn chunk_at(&self, offset: usize) -> usize {
+        self.chunks.partition_point(|c| c.range.start <= offset).saturating_sub(1)
+    }
+
+    /// Reorders statements parsed from the expanded source
+    /// so that every chunk's statements are where the chunk was included or invoked.
+    pub(crate) fn order(&self, ast: Vec<Stmt>) -> Vec<Stmt> {
+        if self.chunks.len() <= 1 { return ast; }
+
+        let mut by_chunk: Vec<Vec<Stmt>> = self.chunks.iter().map(|_| vec![]).collect();
+        for stmt in ast {
+            let start = stmt.labels.iter().map(|l| l.span().start).fold(stmt.span.start, usize::min);
+            by_chunk[self.chunk_at(start)].push(stmt);
+        }
+
+        let mut out = vec![];
+        self.order_chunk(0, &mut by_chunk, &mut out);
+        out
+    }
+    fn order_chunk(&self, chunk: usize, by_chunk: &mut [Vec<Stmt>], out: &mut Vec<Stmt>) {
+        let mut stmts = std::mem::take(&mut by_chunk[chunk]).into_iter().peekable();
+        let children = self.chunks.iter()
+            .enumerate()
+            .filter_map(|(i, c)| match &c.parent {
+                Some((p, span)) if *p == chunk => Some((span.start, i)),
+                _ => None,
+            });
+
+        for (at, child) in children {
+            out.extend(std::iter::from_fn(|| stmts.next_if(|s| s.span.start < at)));
+            self.order_chunk(child, by_chunk, out);
+        }
+        out.extend(stmts);
+    }
+
+    /// The spans of every line of the expanded source, in program order
+    /// (so every chunk's lines are where the chunk was included or invoked).
+    pub(crate) fn ordered_lines(&self) -> Vec<Range<usize>> {
+        let mut out = vec![];
+        self.chunk_lines(0, &mut out);
+        out
+    }
+    fn chunk_lines(&self, chunk: usize, out: &mut Vec<Range<usize>>) {
+        let range = self.chunks[chunk].range.clone();
+        let mut lines = self.src[range.clone()]
+            .split_inclusive('\n')
+            .scan(range.start, |start, line| {
+                let span = *start..*start + line.trim_end_matches(['\r', '\n']).len();
+                *start += line.len();
+                Some(span)
+            })
+            .peekable();
+        let children = self.chunks.iter()
+            .enumerate()
+            .filter_map(|(i, c)| match &c.parent {
+                Some((p, span)) if *p == chunk => Some((span.start, i)),
+                _ => None,
+            });
+
+        // The line which includes or invokes a chunk comes before the chunk's lines:
+        for (at, child) in children {
+            out.extend(std::iter::from_fn(|| lines.next_if(|l| l.start <= at)));
+            self.chunk_lines(child, out);
+        }
+        out.extend(lines);
+    }
+
+    /// The lines which were blanked out of the expanded source
+    /// (preprocessor lines, macro definitions, and lines excluded by `.IF`),
+    /// as their spans in the expanded source and their original text.
+    pub(crate) fn blanked_lines(&self) -> Vec<(Range<usize>, &str)> {
+        let mut out = vec![];
+        for chunk in &self.chunks {
+            let mut start = chunk.range.start;
+            let lines = self.src[chunk.range.clone()].split_inclusive('\n')
+                .zip(chunk.text.split_inclusive('\n'));
+            for (line, orig) in lines {
+                if line.trim().is_empty() && !orig.trim().is_empty() {
+                    let orig = orig.trim_end_matches(['\r', '\n']);
+                    out.push((start..start + orig.len(), orig));
+                }
+                start += line.len();
+            }
+        }
+        out
+    }
+
+    /// The files the source came from (the main file, if it is saved, and every file it included).
+    pub(crate) fn files(&self) -> impl Iterator<Item=&Path> {
+        self.chunks.iter().filter_map(|c| match &c.origin {
+            ChunkOrigin::File(path) => path.as_deref(),
+            ChunkOrigin::Macro(_) => None,
+        })
+    }
+
+    /// Finds where a span of the expanded source came from.
+    ///
+    /// Spans in macro expansions are mapped to the invocation of the macro.
+    pub(crate) fn locate(&self, span: Range<usize>) -> SourceLocation<'_> {
+        let mut chunk = self.chunk_at(span.start);
+        let mut span = span;
+        let mut expanded_from = None;
+
+        while let Chunk { origin: ChunkOrigin::Macro(name), parent: Some((p, call)), .. } = &self.chunks[chunk] {
+            expanded_from.get_or_insert(name.as_str());
+            chunk = *p;
+            span = call.clone();
+        }
+
+        let Chunk { range, text, origin, .. } = &self.chunks[chunk];
+        let path = match origin {
+            ChunkOrigin::File(path) => path.as_deref(),
+            ChunkOrigin::Macro(_) => None,
+        };
+        let start = span.start.saturating_sub(range.start).min(text.len());
+        let end = span.end.saturating_sub(range.start).clamp(start, text.len());
+        SourceLocation { path, text, span: start..end, expanded_from }
+    }
+
+    /// Maps a span of the expanded source to the main file
+    /// (to the `.INCLUDE` or macro invocation it came from, if it came from elsewhere).
+    pub(crate) fn main_span(&self, span: Range<usize>) -> Range<usize> {
+        let mut chunk = self.chunk_at(span.start);
+        let mut span = span;
+        while let Some((p, call)) = &self.chunks[chunk].parent {
+            chunk = *p;
+            span = call.clone();
+        }
+        span
+    }
+}
+
+/// A macro definition.
+struct Macro {
+    params: Vec<String>,
+    body: String,
+}
+
+/// An `.IF` which has not yet been closed.
+struct OpenIf {
+    span: Range<usize>,
+    /// Whether the lines in the current branch are assembled.
+    active: bool,
+    /// Whether a previous branch was taken (or the `.IF` is itself in an inactive region).
+    taken: bool,
+    seen_else: bool,
+}
+
+/// A macro which is currently being defined.
+struct OpenMacro {
+    span: Range<usize>,
+    name: String,
+    params: Vec<String>,
+    body: String,
+}
+
+/// Splits a line into its tokens (separated by whitespace and commas and ending at a comment),
+/// along with their spans within the line.
+///
+/// Quoted strings are kept as a single token.
+pub(crate) fn tokenize(line: &str) -> Vec<(Range<usize>, &str)> {
+    let mut tokens = vec![];
+    let mut chars = line.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        match c {
+            ';' => break,
+            c if c.is_whitespace() || c == ',' => { chars.next(); },
+            '"' => {
+                chars.next();
+                let mut end = line.len();
+                let mut escaped = false;
+                for (i, c) in chars.by_ref() {
+                    match c {
+                        '\\' if !escaped => escaped = true,
+                        '"' if !escaped => { end = i + 1; break; },
+                        _ => escaped = false,
+                    }
+                }
+                tokens.push((start..end, &line[start..end]));
+            },
+            _ => {
+                let mut end = line.len();
+                while let Some(&(i, c)) = chars.peek() {
+                    if c.is_whitespace() || matches!(c, ',' | ';' | '"') { end = i; break; }
+                    chars.next();
+                }
+                tokens.push((start..end, &line[start..end]));
+            }
+        }
+    }
+    tokens
+}
+
+/// Whether a token is a preprocessor directive (which has to be the first token of its line).
+pub(crate) fn is_directive(token: &str) -> bool {
+    matches!(&*token.to_uppercase(), ".INCLUDE" | ".MACRO" | ".ENDM" | ".IF" | ".ELSE" | ".ENDIF")
+}
+
+/// Replaces parameter references (`\param`) and `\@` in a macro's body.
+fn substitute(body: &str, params: &[String], args: &[&str], id: usize) -> String {
+    let mut out = String::with_capacity(body.len());
+    let mut rest = body;
+    while let Some(i) = rest.find('\\') {
+        out.push_str(&rest[..i]);
+        rest = &rest[i + 1..];
+
+        if let Some(r) = rest.strip_prefix('@') {
+            out.push_str(&id.to_string());
+            rest = r;
+            continue;
+        }
+        let len = rest.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(rest.len());
+        match params.iter().position(|p| p.eq_ignore_ascii_case(&rest[..len])) {
+            Some(p) if len > 0 => {
+                out.push_str(args[p]);
+                rest = &rest[len..];
+            },
+            _ => out.push('\\'),
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+struct Preprocessor {
+    src: String,
+    chunks: Vec<Chunk>,
+    /// The defined macros (by uppercase name).
+    macros: HashMap<String, Macro>,
+    errors: Vec<PreprocError>,
+    /// The files which are currently being included (to detect include cycles).
+    including: Vec<PathBuf>,
+    /// The number of macro expansions so far (used to substitute `\@`).
+    expansions: usize,
+    /// The constants defined so far (by uppercase name), for the conditions of `.IF`.
+    constants: HashMap<String, i64>,
+}
+impl Preprocessor {
+    fn error(&mut self, kind: PreprocErrKind, span: Range<usize>) {
+        self.errors.push(PreprocError { kind, span });
+    }
+
+    /// The file the chunk came from (or the file it was expanded in).
+    fn chunk_file(&self, mut chunk: usize) -> Option<&Path> {
+        loop {
+            match &self.chunks[chunk] {
+                Chunk { origin: ChunkOrigin::File(path), .. } => return path.as_deref(),
+                Chunk { parent: Some((p, _)), .. } => chunk = *p,
+                Chunk { parent: None, .. } => return None,
+            }
+        }
+    }
+
+    /// Appends a chunk to the expanded source and preprocesses it.
+    fn add_chunk(&mut self, text: String, origin: ChunkOrigin, parent: Option<(usize, Range<usize>)>) {
+        let depth = {
+            let mut depth = 0;
+            let mut p = parent.as_ref().map(|(p, _)| *p);
+            while let Some(c) = p {
+                depth += 1;
+                p = self.chunks[c].parent.as_ref().map(|(p, _)| *p);
+            }
+            depth
+        };
+        if depth > MAX_DEPTH {
+            let span = parent.map_or(0..0, |(_, s)| s);
+            self.error(PreprocErrKind::TooDeep, span);
+            return;
+        }
+
+        // Separate the chunk from the previous chunk with an empty line
+        // (so that the debug symbols of the two chunks don't run together).
+        if !self.chunks.is_empty() {
+            if !self.src.ends_with('\n') { self.src.push('\n'); }
+            self.src.push('\n');
+        }
+        let start = self.src.len();
+        self.src.push_str(&text);
+        let index = self.chunks.len();
+        self.chunks.push(Chunk { range: start..self.src.len(), text, origin, parent });
+
+        self.process_chunk(index);
+    }
+
+    /// Blanks out a line of the expanded source, keeping the offsets of all other characters the same.
+    fn blank(&mut self, line: Range<usize>) {
+        let blanked = " ".repeat(line.len());
+        self.src.replace_range(line, &blanked);
+    }
+
+    fn process_chunk(&mut self, index: usize) {
+        let Range { start, end } = self.chunks[index].range.clone();
+        let mut ifs: Vec<OpenIf> = vec![];
+        let mut defining: Option<OpenMacro> = None;
+
+        let mut pos = start;
+        while pos < end {
+            let line_end = self.src[pos..end].find('\n').map_or(end, |i| pos + i);
+            let line = self.src[pos..line_end].trim_end_matches('\r').to_string();
+            let line_span = pos..pos + line.len();
+            pos = line_end + 1;
+
+            let tokens = tokenize(&line);
+            let span_of = |r: &Range<usize>| line_span.start + r.start .. line_span.start + r.end;
+            let directive = tokens.first()
+                .filter(|(_, t)| t.starts_with('.'))
+                .map(|(_, t)| t.to_uppercase());
+            let active = ifs.last().is_none_or(|f| f.active);
+
+            // Lines in a macro definition are collected into its body:
+            if let Some(m) = &mut defining {
+                if directive.as_deref() == Some(".ENDM") {
+                    let OpenMacro { name, params, body, .. } = defining.take().unwrap_or_else(|| unreachable!());
+                    self.macros.insert(name.to_uppercase(), Macro { params, body });
+                } else {
+                    m.body.push_str(&line);
+                    m.body.push('\n');
+                }
+                self.blank(line_span);
+                continue;
+            }
+
+            match directive.as_deref() {
+                Some(".IF") => {
+                    let taken = match active {
+                        true => match tokens.get(1).and_then(|(r, _)| expr::eval_constant(&line[r.start..expr::code_end(&line)], &self.constants)) {
+                            Some(n) => n != 0,
+                            _ => {
+                                self.error(PreprocErrKind::InvalidCondition, span_of(&tokens[0].0));
+                                false
+                            },
+                        },
+                        false => false,
+                    };
+                    ifs.push(OpenIf { span: span_of(&tokens[0].0), active: taken, taken: taken || !active, seen_else: false });
+                },
+                Some(".ELSE") => match ifs.last_mut() {
+                    Some(f) if f.seen_else => self.error(PreprocErrKind::DuplicateElse, span_of(&tokens[0].0)),
+                    Some(f) => {
+                        f.active = !f.taken;
+                        f.taken = true;
+                        f.seen_else = true;
+                    },
+                    None => self.error(PreprocErrKind::UnopenedIf, span_of(&tokens[0].0)),
+                },
+                Some(".ENDIF") => if ifs.pop().is_none() {
+                    self.error(PreprocErrKind::UnopenedIf, span_of(&tokens[0].0));
+                },
+                _ if !active => {},
+                Some(".INCLUDE") => {
+                    self.blank(line_span.clone());
+                    self.include(index, &tokens, span_of(&(tokens[0].0.start..tokens.last().map_or(0, |(r, _)| r.end))));
+                    continue;
+                },
+                Some(".MACRO") => match tokens.get(1) {
+                    Some((_, name)) => defining = Some(OpenMacro {
+                        span: span_of(&tokens[0].0),
+                        name: name.to_string(),
+                        params: tokens[2..].iter().map(|(_, p)| p.trim_start_matches('\\').to_string()).collect(),
+                        body: String::new(),
+                    }),
+                    None => self.error(PreprocErrKind::ExpectedMacroName, span_of(&tokens[0].0)),
+                },
+                Some(".ENDM") => self.error(PreprocErrKind::UnopenedMacro, span_of(&tokens[0].0)),
+                _ => {
+                    self.define(&line);
+
+                    // Macro invocations (which may be labeled):
+                    let is_macro = |i: usize| tokens.get(i).is_some_and(|(_, t)| self.macros.contains_key(&t.to_uppercase()));
+                    let name_index = match () {
+                        _ if is_macro(0) => 0,
+                        _ if is_macro(1) => 1,
+                        _ => continue,
+                    };
+
+                    self.blank(line_span.clone());
+                    let label = (name_index == 1).then(|| tokens[0].1);
+                    let args: Vec<_> = tokens[name_index + 1..].iter().map(|(_, t)| *t).collect();
+                    let call_span = span_of(&(tokens[0].0.start..tokens.last().map_or(0, |(r, _)| r.end)));
+                    self.expand(index, tokens[name_index].1, label, &args, call_span);
+                    continue;
+                },
+            }
+
+            // This line is a preprocessor directive or is in an inactive region:
+            self.blank(line_span);
+        }
+
+        if let Some(m) = defining {
+            self.error(PreprocErrKind::UnclosedMacro, m.span);
+        }
+        for f in ifs {
+            self.error(PreprocErrKind::UnclosedIf, f.span);
+        }
+    }
+
+    /// Records a constant defined on this line (if it is defined with a value that can be evaluated),
+    /// so that it can be used in the conditions of `.IF`.
+    ///
+    /// Any errors in the definition are reported by the assembler.
+    fn define(&mut self, line: &str) {
+        let Some(parts) = expr::split_line(line) else { return };
+        if !matches!(&*parts.opcode.to_uppercase(), ".EQU" | ".SET") { return }
+
+        // Either `NAME .EQU value` or `.EQU NAME, value`:
+        let (name, value) = match (parts.labels.last(), &parts.operands[..]) {
+            (Some((_, name)), [(_, value)]) => (name.trim_end_matches(':'), *value),
+            (None, [(_, name), (_, value)]) => (*name, *value),
+            _ => return,
+        };
+        if let Some(value) = expr::eval_constant(value, &self.constants) {
+            self.constants.insert(name.to_uppercase(), value);
+        }
+    }
+
+    fn include(&mut self, chunk: usize, tokens: &[(Range<usize>, &str)], span: Range<usize>) {
+        let Some(path) = tokens.get(1).and_then(|(_, t)| t.strip_prefix('"')?.strip_suffix('"')) else {
+            self.error(PreprocErrKind::ExpectedPath, span);
+            return;
+        };
+        let Some(current) = self.chunk_file(chunk) else {
+            self.error(PreprocErrKind::IncludeWithoutFile, span);
+            return;
+        };
+        let path = current.parent().unwrap_or(Path::new("")).join(path);
+
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) => {
+                self.error(PreprocErrKind::CannotInclude(format!("{}: {e}", path.display())), span);
+                return;
+            },
+        };
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.clone());
+        if self.including.contains(&canonical) {
+            self.error(PreprocErrKind::IncludeCycle, span);
+            return;
+        }
+
+        self.including.push(canonical);
+        self.add_chunk(text, ChunkOrigin::File(Some(path)), Some((chunk, span)));
+        self.including.pop();
+    }
+
+    fn expand(&mut self, chunk: usize, name: &str, label: Option<&str>, args: &[&str], span: Range<usize>) {
+        let Some(m) = self.macros.get(&name.to_uppercase()) else { return };
+        if m.params.len() != args.len() {
+            let kind = PreprocErrKind::WrongArgCount { expected: m.params.len(), found: args.len() };
+            self.error(kind, span);
+            return;
+        }
+
+        self.expansions += 1;
+        let mut text = String::new();
+        // The label is put on its own line, so that it labels the first statement of the expansion:
+        if let Some(label) = label {
+            text.push_str(label);
+            text.push('\n');
+        }
+        text.push_str(&substitute(&m.body, &m.params, args, self.expansions));
+
+        self.add_chunk(text, ChunkOrigin::Macro(name.to_string()), Some((chunk, span)));
+    }
+}
+
+/// Preprocesses source code (from the file at the given path, if it is from a file).
+///
+/// This always produces the expanded source, along with any errors which occurred.
+pub(crate) fn preprocess(src: &str, path: Option<&Path>) -> (Expanded, Vec<PreprocError>) {
+    let mut pp = Preprocessor {
+        src: String::new(),
+        chunks: vec![],
+        macros: HashMap::new(),
+        errors: vec![],
+        including: path.map(|p| p.canonicalize().unwrap_or_else(|_| p.to_path_buf())).into_iter().collect(),
+        expansions: 0,
+        constants: HashMap::new(),
+    };
+    pp.add_chunk(src.to_string(), ChunkOrigin::File(path.map(Path::to_path_buf)), None);
+
+    pp.errors.sort_by_key(|e| e.span.start);
+    (Expanded { src: pp.src, chunks: pp.chunks }, pp.errors)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A new empty directory for a test's files.
+    pub(crate) fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("lc3-test-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).expect("temporary directory should be created");
+        dir
+    }
+
+    /// The lines which are assembled (in order), along with the codes of any errors.
+    fn preprocessed(src: &str, path: Option<&Path>) -> (Vec<String>, Vec<&'static str>) {
+        let (expanded, errors) = preprocess(src, path);
+        let lines = expanded.ordered_lines().into_iter()
+            .map(|span| expanded.src()[span].trim().to_string())
+            .filter(|line| !line.is_empty())
+            .collect();
+        (lines, errors.iter().map(PreprocError::code).collect())
+    }
+
+    #[test]
+    fn substitutes_macro_arguments() {
+        let src = "\
+.MACRO PUSH reg
+ADD R6, R6, #-1
+STR \\reg, R6, #0
+.ENDM
+.macro spin
+L\\@ BR L\\@
+.endm
+MAIN PUSH R0
+spin
+SPIN
+";
+        let (lines, errors) = preprocessed(src, None);
+        assert_eq!(errors, [] as [&str; 0]);
+        assert_eq!(lines, ["MAIN", "ADD R6, R6, #-1", "STR R0, R6, #0", "L2 BR L2", "L3 BR L3"]);
+
+        let (_, errors) = preprocessed(".MACRO PUSH reg\n.ENDM\nPUSH R0, R1\n", None);
+        assert_eq!(errors, ["preproc::macro-args"]);
+    }
+
+    #[test]
+    fn limits_nesting_depth() {
+        let (_, errors) = preprocessed(".MACRO FOREVER\nADD R0, R0, #1\nFOREVER\n.ENDM\nFOREVER\n", None);
+        assert_eq!(errors, ["preproc::too-deep"]);
+    }
+
+    #[test]
+    fn evaluates_conditions() {
+        let src = "\
+DEBUG .EQU 1
+.SET LEVEL, 2
+.IF DEBUG
+A
+.ELSE
+B
+.ENDIF
+.IF (LEVEL - 2) * 4
+C
+.ELSE
+D
+.ENDIF
+.IF 0
+E .EQU 1
+  .IF 1
+F
+  .ENDIF
+.ELSE
+G
+.ENDIF
+LEVEL .SET 0
+.IF LEVEL
+H
+.ENDIF
+";
+        let (lines, errors) = preprocessed(src, None);
+        assert_eq!(errors, [] as [&str; 0]);
+        assert_eq!(lines, ["DEBUG .EQU 1", ".SET LEVEL, 2", "A", "D", "G", "LEVEL .SET 0"]);
+    }
+
+    #[test]
+    fn reports_condition_errors() {
+        let errors = |src| preprocessed(src, None).1;
+        // Constants have to be defined before the condition (and not in an excluded region):
+        assert_eq!(errors(".IF LATER\n.ENDIF\nLATER .EQU 1\n"), ["preproc::invalid-condition"]);
+        assert_eq!(errors(".IF 0\nX .EQU 1\n.ENDIF\n.IF X\n.ENDIF\n"), ["preproc::invalid-condition"]);
+        assert_eq!(errors(".IF\n.ENDIF\n"), ["preproc::invalid-condition"]);
+
+        assert_eq!(errors(".IF 1\n"), ["preproc::unclosed-if"]);
+        assert_eq!(errors(".ENDIF\n"), ["preproc::unopened-if"]);
+        assert_eq!(errors(".IF 1\n.ELSE\n.ELSE\n.ENDIF\n"), ["preproc::duplicate-else"]);
+    }
+
+    #[test]
+    fn includes_files() {
+        let dir = temp_dir("include");
+        std::fs::write(dir.join("a.asm"), "A1\n.INCLUDE \"b.asm\"\nA2\n").expect("file should be written");
+        std::fs::write(dir.join("b.asm"), "B1\n").expect("file should be written");
+
+        let (lines, errors) = preprocessed(".INCLUDE \"a.asm\"\nMAIN\n", Some(&dir.join("main.asm")));
+        assert_eq!(errors, [] as [&str; 0]);
+        assert_eq!(lines, ["A1", "B1", "A2", "MAIN"]);
+
+        let (_, errors) = preprocessed(".INCLUDE \"a.asm\"\n", None);
+        assert_eq!(errors, ["preproc::include-without-file"]);
+        let (_, errors) = preprocessed(".INCLUDE \"missing.asm\"\n.INCLUDE a.asm\n", Some(&dir.join("main.asm")));
+        assert_eq!(errors, ["preproc::cannot-include", "preproc::expected-path"]);
+    }
+
+    #[test]
+    fn rejects_include_cycles() {
+        let dir = temp_dir("include-cycle");
+        std::fs::write(dir.join("a.asm"), ".INCLUDE \"b.asm\"\n").expect("file should be written");
+        std::fs::write(dir.join("b.asm"), ".INCLUDE \"a.asm\"\n").expect("file should be written");
+
+        let main = dir.join("main.asm");
+        let (_, errors) = preprocessed(".INCLUDE \"a.asm\"\n", Some(&main));
+        assert_eq!(errors, ["preproc::include-cycle"]);
+
+        // A file can't include itself either (even if it is the main file):
+        std::fs::write(&main, ".INCLUDE \"main.asm\"\n").expect("file should be written");
+        let (_, errors) = preprocessed(".INCLUDE \"main.asm\"\n", Some(&main));
+        assert_eq!(errors, ["preproc::include-cycle"]);
+    }
+}
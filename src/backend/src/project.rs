@@ -0,0 +1,369 @@
+//! Project files (`lc3project.json`), which describe how to build a program from several files.
+//!
+//! A project file is a JSON object with the fields:
+//! - `sources` (required): the files of the program, in the order they are linked.
+//!   Assembly files (`.asm`) are assembled into an object file next to them, and other files are read as object files
+//!   (or libraries, whose members are only linked if they're needed, see [`crate::archive`]).
+//! - `output` (required): where the linked object file is written
+//! - `entry`: the label the program starts at (which must be defined by one of the sources)
+//! - `dialect`, `lints`, `format`, and `strip`: the same as the options of `assemble`
+//!   (`format` and `strip` only apply to the linked object file)
+//! - `map`: whether to write a linker map next to the linked object file (see [`crate::link`])
+//! - `script`: the linker script which places relocatable sections (see [`crate::link`])
+//! - `stdlib`: whether external labels can be resolved by the standard library (see [`crate::stdlib`], default `true`)
+//! - `tests`: the test specs of the program
+//!
+//! Every path is relative to the directory of the project file.
+
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use lc3_ensemble::asm::ObjectFile;
+use neon::prelude::*;
+use serde_json::{Map, Value};
+
+use crate::cast::TryIntoJsValue;
+use crate::dialect::Dialect;
+use crate::lint::{Lint, LintConfig};
+use crate::obj::{ObjFormat, ObjOutput};
+use crate::preproc;
+use crate::reloc::Relocs;
+
+/// The name of a project file.
+pub(crate) const PROJECT_FILE: &str = "lc3project.json";
+
+/// Finds the project file, given the project file or the directory containing it.
+pub(crate) fn project_file(path: &Path) -> PathBuf {
+    match path.is_dir() {
+        true  => path.join(PROJECT_FILE),
+        false => path.to_path_buf(),
+    }
+}
+
+/// An error from reading a project file.
+#[derive(Debug)]
+pub(crate) enum ProjectErr {
+    /// The project file could not be read.
+    Io(std::io::Error),
+    /// The project file is not valid JSON.
+    Json(serde_json::Error),
+    /// A field of the project file is missing or invalid.
+    Field(&'static str, String),
+    /// The project file has a field which isn't known.
+    UnknownField(String),
+}
+impl ProjectErr {
+    pub(crate) fn code(&self) -> &'static str {
+        match self {
+            ProjectErr::Io(_)           => "io",
+            ProjectErr::Json(_)         => "project::invalid-json",
+            ProjectErr::Field(..)       => "project::invalid-field",
+            ProjectErr::UnknownField(_) => "project::unknown-field",
+        }
+    }
+}
+impl std::fmt::Display for ProjectErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProjectErr::Io(e) => e.fmt(f),
+            ProjectErr::Json(e) => write!(f, "invalid project file: {e}"),
+            ProjectErr::Field(field, reason) => write!(f, "invalid field {field:?}: {reason}"),
+            ProjectErr::UnknownField(field) => write!(f, "unknown field {field:?}"),
+        }
+    }
+}
+
+/// A parsed project file.
+#[derive(Debug)]
+pub(crate) struct Project {
+    /// The path of the project file.
+    pub(crate) path: PathBuf,
+    pub(crate) sources: Vec<PathBuf>,
+    pub(crate) output: PathBuf,
+    pub(crate) entry: Option<String>,
+    pub(crate) dialect: Dialect,
+    pub(crate) lints: LintConfig,
+    pub(crate) obj_output: ObjOutput,
+    pub(crate) map: bool,
+    pub(crate) script: Option<PathBuf>,
+    pub(crate) stdlib: bool,
+    pub(crate) tests: Vec<PathBuf>,
+}
+impl Project {
+    /// Reads a project file.
+    pub(crate) fn read(path: &Path) -> Result<Project, ProjectErr> {
+        let text = std::fs::read_to_string(path).map_err(ProjectErr::Io)?;
+        let value: Value = serde_json::from_str(&text).map_err(ProjectErr::Json)?;
+        let Value::Object(fields) = value else {
+            return Err(ProjectErr::Field("", String::from("expected an object")));
+        };
+        let dir = path.parent().unwrap_or(Path::new("")).to_path_buf();
+
+        if let Some(field) = fields.keys().find(|k| !["sources", "output", "entry", "dialect", "lints", "format", "strip", "map", "script", "stdlib", "tests"].contains(&&***k)) {
+            return Err(ProjectErr::UnknownField(field.clone()));
+        }
+
+        let sources = paths(&fields, "sources", &dir)?;
+        if sources.is_empty() {
+            return Err(ProjectErr::Field("sources", String::from("expected at least one source file")));
+        }
+        let output = match fields.get("output") {
+            Some(Value::String(p)) => dir.join(p),
+            Some(_) => return Err(ProjectErr::Field("output", String::from("expected a path"))),
+            None => return Err(ProjectErr::Field("output", String::from("expected the path of the linked object file"))),
+        };
+        let entry = match fields.get("entry") {
+            Some(Value::String(label)) => Some(label.clone()),
+            Some(_) => return Err(ProjectErr::Field("entry", String::from("expected a label"))),
+            None => None,
+        };
+        let dialect = match fields.get("dialect") {
+            Some(Value::String(name)) => Dialect::from_name(name)
+                .ok_or_else(|| ProjectErr::Field("dialect", format!("unknown dialect {name:?}")))?,
+            Some(_) => return Err(ProjectErr::Field("dialect", String::from("expected the name of a dialect"))),
+            None => Dialect::default(),
+        };
+
+        let mut lints = LintConfig::default();
+        match fields.get("lints") {
+            Some(Value::Object(map)) => for (name, enabled) in map {
+                let Some(lint) = Lint::from_name(name) else {
+                    return Err(ProjectErr::Field("lints", format!("unknown lint {name:?}")));
+                };
+                let Some(enabled) = enabled.as_bool() else {
+                    return Err(ProjectErr::Field("lints", format!("expected {name:?} to be true or false")));
+                };
+                lints.set_enabled(lint, enabled);
+            },
+            Some(_) => return Err(ProjectErr::Field("lints", String::from("expected an object mapping lint names to true or false"))),
+            None => {},
+        }
+
+        let mut obj_output = ObjOutput::default();
+        match fields.get("format") {
+            Some(Value::String(name)) => obj_output.format = ObjFormat::from_name(name)
+                .ok_or_else(|| ProjectErr::Field("format", format!("unknown object file format {name:?}")))?,
+            Some(_) => return Err(ProjectErr::Field("format", String::from("expected the name of an object file format"))),
+            None => {},
+        }
+        match fields.get("strip") {
+            Some(Value::Bool(strip)) => obj_output.strip = *strip,
+            Some(_) => return Err(ProjectErr::Field("strip", String::from("expected true or false"))),
+            None => {},
+        }
+
+        let map = match fields.get("map") {
+            Some(Value::Bool(map)) => *map,
+            Some(_) => return Err(ProjectErr::Field("map", String::from("expected true or false"))),
+            None => false,
+        };
+        let script = match fields.get("script") {
+            Some(Value::String(p)) => Some(dir.join(p)),
+            Some(_) => return Err(ProjectErr::Field("script", String::from("expected a path"))),
+            None => None,
+        };
+        let stdlib = match fields.get("stdlib") {
+            Some(Value::Bool(stdlib)) => *stdlib,
+            Some(_) => return Err(ProjectErr::Field("stdlib", String::from("expected true or false"))),
+            None => true,
+        };
+        let tests = match fields.contains_key("tests") {
+            true  => paths(&fields, "tests", &dir)?,
+            false => vec![],
+        };
+
+        Ok(Project { path: path.to_path_buf(), sources, output, entry, dialect, lints, obj_output, map, script, stdlib, tests })
+    }
+
+    /// The directory of the project (which its paths are relative to).
+    pub(crate) fn dir(&self) -> &Path {
+        self.path.parent().unwrap_or(Path::new(""))
+    }
+}
+
+/// Reads a field which is a list of paths (relative to the given directory).
+fn paths(fields: &Map<String, Value>, field: &'static str, dir: &Path) -> Result<Vec<PathBuf>, ProjectErr> {
+    let invalid = || ProjectErr::Field(field, String::from("expected a list of paths"));
+    fields.get(field)
+        .and_then(Value::as_array)
+        .ok_or_else(invalid)?
+        .iter()
+        .map(|p| p.as_str().map(|p| dir.join(p)).ok_or_else(invalid))
+        .collect()
+}
+
+/// Reads the object file previously assembled from a source file,
+/// if it is newer than the source, the files it includes, and the project file
+/// (so that the source doesn't need to be assembled again), along with its relocatable sections.
+pub(crate) fn built_object(src: &Path, obj_path: &Path, project_path: &Path) -> Option<(ObjectFile, Relocs)> {
+    let text = std::fs::read_to_string(src).ok()?;
+    let (expanded, errors) = preproc::preprocess(&text, Some(src));
+    if !errors.is_empty() { return None; }
+
+    let modified = |p: &Path| std::fs::metadata(p).and_then(|m| m.modified()).ok();
+    let built: SystemTime = modified(obj_path)?;
+    let up_to_date = expanded.files()
+        .chain([project_path])
+        .all(|p| modified(p).is_some_and(|m| m <= built));
+
+    match up_to_date {
+        true  => crate::read_relocatable_obj_file(obj_path).ok().flatten(),
+        false => None,
+    }
+}
+
+/// The result of building a project.
+pub(crate) struct BuildResult {
+    /// The path of the linked object file.
+    pub(crate) output: PathBuf,
+    /// The address of the entry point (if the project has one).
+    pub(crate) entry: Option<u16>,
+    /// The sources which were assembled (rather than being up to date).
+    pub(crate) assembled: Vec<PathBuf>,
+    /// The paths of the project's test specs.
+    pub(crate) tests: Vec<PathBuf>,
+}
+impl TryIntoJsValue for BuildResult {
+    type Value = JsObject;
+
+    fn try_into_js<'a>(self, cx: &mut impl Context<'a>) -> JsResult<'a, Self::Value> {
+        let path_strings = |paths: Vec<PathBuf>| paths.into_iter().map(|p| p.display().to_string()).collect::<Vec<_>>();
+        let obj = cx.empty_object();
+
+        let output = cx.string(self.output.display().to_string());
+        obj.set(cx, "output", output)?;
+        let entry = self.entry.try_into_js(cx)?;
+        obj.set(cx, "entry", entry)?;
+        let assembled = path_strings(self.assembled).try_into_js(cx)?;
+        obj.set(cx, "assembled", assembled)?;
+        let tests = path_strings(self.tests).try_into_js(cx)?;
+        obj.set(cx, "tests", tests)?;
+
+        Ok(obj)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::preproc::tests::temp_dir;
+
+    /// Writes a project file into a new directory, returning its path.
+    fn write_project(name: &str, json: &str) -> PathBuf {
+        let dir = temp_dir(name);
+        let path = dir.join(PROJECT_FILE);
+        std::fs::write(&path, json).expect("project file should be written");
+        path
+    }
+
+    /// Sets when a file was last modified.
+    fn touch(path: &Path, time: SystemTime) {
+        std::fs::File::options().write(true).open(path)
+            .and_then(|f| f.set_modified(time))
+            .expect("modification time should be set");
+    }
+
+    #[test]
+    fn reads_projects() {
+        let path = write_project("project-read", r#"{
+            "sources": ["main.asm", "lib/util.lib"],
+            "output": "out/program.obj",
+            "entry": "MAIN",
+            "lints": { "unused-label": false },
+            "format": "binary",
+            "strip": true,
+            "map": true,
+            "stdlib": false,
+            "tests": ["tests/spec.json"]
+        }"#);
+        let dir = path.parent().expect("project file should have a directory");
+
+        let project = Project::read(&path).expect("project should be read");
+        assert_eq!(project.dir(), dir);
+        assert_eq!(project.sources, [dir.join("main.asm"), dir.join("lib/util.lib")]);
+        assert_eq!(project.output, dir.join("out/program.obj"));
+        assert_eq!(project.entry.as_deref(), Some("MAIN"));
+        assert_eq!(project.obj_output.format, ObjFormat::Binary);
+        assert!(project.obj_output.strip);
+        assert!(project.map);
+        assert!(!project.stdlib);
+        assert_eq!(project.script, None);
+        assert_eq!(project.tests, [dir.join("tests/spec.json")]);
+
+        // Everything but the sources and output is optional:
+        let path = write_project("project-read-minimal", r#"{ "sources": ["main.asm"], "output": "main.obj" }"#);
+        let project = Project::read(&path).expect("project should be read");
+        assert_eq!(project.entry, None);
+        assert_eq!(project.dialect, Dialect::default());
+        assert!(!project.map);
+        assert!(project.stdlib);
+        assert!(project.tests.is_empty());
+    }
+
+    #[test]
+    fn rejects_unknown_and_invalid_fields() {
+        let read = |json: &str| Project::read(&write_project("project-invalid", json)).expect_err("project should be rejected");
+
+        let e = read(r#"{ "sources": ["main.asm"], "output": "main.obj", "ouptut": "main.obj" }"#);
+        assert!(matches!(&e, ProjectErr::UnknownField(f) if f == "ouptut"), "{e:?}");
+        assert_eq!(e.code(), "project::unknown-field");
+
+        for (json, field) in [
+            (r#"["main.asm"]"#, ""),
+            (r#"{ "output": "main.obj" }"#, "sources"),
+            (r#"{ "sources": [], "output": "main.obj" }"#, "sources"),
+            (r#"{ "sources": ["main.asm", 1], "output": "main.obj" }"#, "sources"),
+            (r#"{ "sources": ["main.asm"] }"#, "output"),
+            (r#"{ "sources": ["main.asm"], "output": "main.obj", "entry": 12288 }"#, "entry"),
+            (r#"{ "sources": ["main.asm"], "output": "main.obj", "dialect": "lc4" }"#, "dialect"),
+            (r#"{ "sources": ["main.asm"], "output": "main.obj", "lints": { "no-such-lint": true } }"#, "lints"),
+            (r#"{ "sources": ["main.asm"], "output": "main.obj", "lints": { "unused-label": "off" } }"#, "lints"),
+            (r#"{ "sources": ["main.asm"], "output": "main.obj", "format": "elf" }"#, "format"),
+            (r#"{ "sources": ["main.asm"], "output": "main.obj", "strip": "yes" }"#, "strip"),
+            (r#"{ "sources": ["main.asm"], "output": "main.obj", "tests": "spec.json" }"#, "tests"),
+        ] {
+            let e = read(json);
+            assert!(matches!(e, ProjectErr::Field(f, _) if f == field), "expected {field:?} to be invalid in {json}, got {e:?}");
+            assert_eq!(e.code(), "project::invalid-field");
+        }
+
+        assert_eq!(read("{ sources: [] }").code(), "project::invalid-json");
+        assert_eq!(Project::read(&temp_dir("project-missing").join(PROJECT_FILE)).expect_err("project should be missing").code(), "io");
+    }
+
+    #[test]
+    fn built_objects_track_includes_and_the_project_file() {
+        let path = write_project("project-built", r#"{ "sources": ["main.asm"], "output": "main.obj" }"#);
+        let dir = path.parent().expect("project file should have a directory");
+        let (src, inc, obj_path) = (dir.join("main.asm"), dir.join("util.inc"), dir.join("main.obj"));
+
+        std::fs::write(&src, ".ORIG x3000\n.INCLUDE \"util.inc\"\nHALT\n.END\n").expect("source should be written");
+        std::fs::write(&inc, "ADD R0, R0, #1\n").expect("include should be written");
+        let (expanded, errors) = preproc::preprocess(&std::fs::read_to_string(&src).expect("source should be read"), Some(&src));
+        assert!(errors.is_empty(), "source should preprocess: {errors:?}");
+        let (obj, relocs) = crate::asm::assemble_all(&expanded, Dialect::default()).expect("source should assemble");
+        for (p, bytes) in ObjOutput::default().files(&obj, &relocs, &obj_path).expect("object file should be serialized") {
+            std::fs::write(p, bytes).expect("object file should be written");
+        }
+
+        let before = SystemTime::now() - Duration::from_secs(60);
+        let after = SystemTime::now() + Duration::from_secs(60);
+        for p in [&src, &inc, &path] {
+            touch(p, before);
+        }
+        assert!(built_object(&src, &obj_path, &path).is_some(), "object file should be up to date");
+
+        touch(&inc, after);
+        assert!(built_object(&src, &obj_path, &path).is_none(), "object file should be older than the include");
+        touch(&inc, before);
+
+        touch(&path, after);
+        assert!(built_object(&src, &obj_path, &path).is_none(), "object file should be older than the project file");
+        touch(&path, before);
+
+        std::fs::remove_file(&inc).expect("include should be removed");
+        assert!(built_object(&src, &obj_path, &path).is_none(), "source should fail to preprocess");
+    }
+}
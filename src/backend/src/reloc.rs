@@ -0,0 +1,254 @@
+//! Relocatable sections, which are placed in memory by the linker rather than by a fixed `.ORIG`.
+//!
+//! A relocatable section starts with `.RELOC` (instead of `.ORIG address`) and ends with `.END`.
+//! When it's assembled, it's placed in the first free space from x3000 (so the object file can still be loaded by itself),
+//! and the object file records:
+//! - each relocatable section: where it was placed, its length, and its name (its first label)
+//! - each relocation: a word whose value depends on where relocatable sections are placed
+//!   (an address in data, or a PC offset to or from a relocatable section)
+//!
+//! Ensemble's object files can't hold these, so they're written after the object file:
+//! in the text format, as `#` lines (which ensemble skips over, reading the sections where they were placed),
+//! and in the binary format, as a chunk of those same lines (which only this crate can read).
+//!
+//! When linking, each relocatable section is moved to its new address, and every relocation is patched
+//! (see [`relocate`]). PC offsets which no longer fit are errors.
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+use lc3_ensemble::asm::ObjectFile;
+
+use crate::obj::{from_chunks, read_chunks, to_chunks, Chunk};
+
+/// The identifying byte of the relocation chunk at the end of a binary object file.
+const RELOC_CHUNK: u8 = 0x80;
+/// The first address relocatable sections are placed at (by default).
+pub(crate) const RELOC_START: u16 = 0x3000;
+/// The end of the memory relocatable sections can be placed in (the start of the IO region).
+const RELOC_END: u32 = 0xFE00;
+
+/// A relocatable section of an object file.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub(crate) struct RelocSection {
+    /// The first label of the section (if it has any).
+    pub(crate) name: Option<String>,
+    /// Where the section is currently placed.
+    pub(crate) start: u16,
+    pub(crate) len: u16,
+}
+impl RelocSection {
+    fn contains(&self, addr: u16) -> bool {
+        addr.wrapping_sub(self.start) < self.len
+    }
+}
+
+/// A word whose value depends on where relocatable sections are placed.
+///
+/// When the sections are moved, the field (the low `bits` bits of the word) changes by the sum of each term,
+/// which is the distance a section moved times a coefficient (1 or -1):
+/// an address in a section moves with it (+1), and a PC offset from a section moves against it (-1).
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub(crate) struct Relocation {
+    pub(crate) addr: u16,
+    /// The size of the field (16 for data, or the size of a PC offset).
+    pub(crate) bits: u8,
+    /// Each section (by index) and its coefficient.
+    pub(crate) terms: Vec<(usize, i8)>,
+}
+
+/// The relocatable sections of an object file and their relocations
+/// (empty if the object file has no relocatable sections).
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub(crate) struct Relocs {
+    pub(crate) sections: Vec<RelocSection>,
+    pub(crate) relocations: Vec<Relocation>,
+}
+impl Relocs {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.sections.is_empty()
+    }
+
+    /// Writes the relocatable sections and relocations as `#` lines
+    /// (e.g., `# SECTION 3000 0012 PRINT` and `# RELOCATION 3005 9 +0 -1`).
+    fn to_text(&self) -> String {
+        let mut out = String::new();
+        for s in &self.sections {
+            out.push_str(&format!("# SECTION {:04X} {:04X} {}\n", s.start, s.len, s.name.as_deref().unwrap_or("-")));
+        }
+        for r in &self.relocations {
+            let terms: Vec<_> = r.terms.iter()
+                .map(|&(i, c)| format!("{}{i}", if c < 0 { '-' } else { '+' }))
+                .collect();
+            out.push_str(&format!("# RELOCATION {:04X} {} {}\n", r.addr, r.bits, terms.join(" ")));
+        }
+        out
+    }
+
+    /// Reads the `#` lines written by [`Relocs::to_text`] (ignoring anything else).
+    fn from_text(text: &str) -> Relocs {
+        let hex = |s: &str| u16::from_str_radix(s, 16).ok();
+        let mut relocs = Relocs::default();
+        for line in text.lines() {
+            let fields: Vec<_> = line.split_whitespace().collect();
+            match fields[..] {
+                ["#", "SECTION", start, len, name] => {
+                    let (Some(start), Some(len)) = (hex(start), hex(len)) else { continue };
+                    let name = (name != "-").then(|| name.to_string());
+                    relocs.sections.push(RelocSection { name, start, len });
+                },
+                ["#", "RELOCATION", addr, bits, ref terms @ ..] => {
+                    let (Some(addr), Ok(bits)) = (hex(addr), bits.parse()) else { continue };
+                    let terms = terms.iter()
+                        .filter_map(|t| {
+                            let (sign, index) = t.split_at_checked(1)?;
+                            let coeff = match sign { "+" => 1, "-" => -1, _ => return None };
+                            Some((index.parse().ok()?, coeff))
+                        })
+                        .collect();
+                    relocs.relocations.push(Relocation { addr, bits, terms });
+                },
+                _ => {},
+            }
+        }
+        relocs
+    }
+}
+
+/// Adds the relocatable sections and relocations to the end of a serialized object file
+/// (in the text format if `text`, and otherwise in the binary format).
+pub(crate) fn append(bytes: &mut Vec<u8>, relocs: &Relocs, text: bool) {
+    if relocs.is_empty() { return; }
+
+    let lines = relocs.to_text();
+    if !text {
+        bytes.push(RELOC_CHUNK);
+        bytes.extend_from_slice(&(lines.len() as u64).to_le_bytes());
+    }
+    bytes.extend_from_slice(lines.as_bytes());
+}
+
+/// Splits a serialized object file into the object file (which ensemble can read)
+/// and its relocatable sections and relocations.
+pub(crate) fn split(bytes: &[u8]) -> (&[u8], Relocs) {
+    if let Ok(text) = std::str::from_utf8(bytes) {
+        return (bytes, Relocs::from_text(text));
+    }
+    match read_chunks(bytes) {
+        Some((_, rest @ [RELOC_CHUNK, lines @ ..])) => {
+            let lines = lines.get(8..).and_then(|l| std::str::from_utf8(l).ok()).unwrap_or_default();
+            (&bytes[..bytes.len() - rest.len()], Relocs::from_text(lines))
+        },
+        _ => (bytes, Relocs::default()),
+    }
+}
+
+/// Places sections of the given lengths (in order) in the first free space from `start`,
+/// leaving `gap` words free after each of them.
+///
+/// Free space is everything not in `occupied` (and not in the IO region).
+/// This returns `None` for each section which doesn't fit anywhere.
+pub(crate) fn pack(occupied: &[Range<u32>], start: u16, lens: &[u16], gap: u16) -> Vec<Option<u16>> {
+    let mut occupied = occupied.to_vec();
+    lens.iter()
+        .map(|&len| {
+            let len = u32::from(len) + u32::from(gap);
+            let mut addr = u32::from(start);
+            loop {
+                if addr + len > RELOC_END { return None; }
+                match occupied.iter().filter(|r| r.start < addr + len && addr < r.end).map(|r| r.end).max() {
+                    Some(end) => addr = end,
+                    None => break,
+                }
+            }
+            occupied.push(addr..addr + len);
+            Some(addr as u16)
+        })
+        .collect()
+}
+
+/// A relocated PC offset which no longer fits in its instruction.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct OffsetOverflow {
+    /// The address of the instruction (before it was moved).
+    pub(crate) addr: u16,
+    pub(crate) offset: i32,
+    pub(crate) bits: u8,
+}
+
+/// An error from moving the relocatable sections of an object file.
+#[derive(Debug)]
+pub(crate) enum RelocateErr {
+    /// Every PC offset which no longer fits.
+    Overflows(Vec<OffsetOverflow>),
+    /// The moved object file can't be read back by ensemble.
+    Unreadable,
+}
+
+/// Moves each relocatable section of an object file to the given address (in the order of [`Relocs::sections`]),
+/// patching every relocation and moving the section's labels and line numbers along with it.
+///
+/// This fails with every PC offset which no longer fits.
+pub(crate) fn relocate(obj: &ObjectFile, relocs: &Relocs, starts: &[u16]) -> Result<ObjectFile, RelocateErr> {
+    let deltas: Vec<i32> = relocs.sections.iter()
+        .zip(starts)
+        .map(|(s, &start)| i32::from(start) - i32::from(s.start))
+        .collect();
+    let moved = |addr: u16| match relocs.sections.iter().position(|s| s.contains(addr)) {
+        Some(i) => addr.wrapping_add(deltas[i] as u16),
+        None => addr,
+    };
+
+    // Patch the words of each relocation:
+    let mut patched_words = HashMap::new();
+    let mut overflows = vec![];
+    let values: HashMap<_, _> = obj.addr_iter().collect();
+    for r in &relocs.relocations {
+        let Some(&Some(word)) = values.get(&r.addr) else { continue };
+        let delta: i32 = r.terms.iter().map(|&(i, c)| i32::from(c) * deltas.get(i).copied().unwrap_or(0)).sum();
+
+        let patched = match r.bits {
+            16 => word.wrapping_add(delta as u16),
+            bits => {
+                let mask = (1u16 << bits) - 1;
+                let shift = 16 - u32::from(bits);
+                let offset = i32::from(((word & mask) << shift) as i16 >> shift) + delta;
+                if !(-(1 << (bits - 1))..1 << (bits - 1)).contains(&offset) {
+                    overflows.push(OffsetOverflow { addr: r.addr, offset, bits });
+                    continue;
+                }
+                (word & !mask) | (offset as u16 & mask)
+            },
+        };
+        patched_words.insert(r.addr, patched);
+    }
+    if !overflows.is_empty() {
+        return Err(RelocateErr::Overflows(overflows));
+    }
+
+    // Rewrite the object file with everything moved:
+    let mut chunks = vec![];
+    let mut lines = vec![];
+    for mut chunk in to_chunks(obj) {
+        match &mut chunk {
+            Chunk::Memory { start, words } => {
+                for (i, word) in words.iter_mut().enumerate() {
+                    if let Some(&patched) = patched_words.get(&start.wrapping_add(i as u16)) { *word = Some(patched); }
+                }
+                *start = moved(*start);
+            },
+            Chunk::Label { addr, .. } | Chunk::Relocation { addr, .. } => *addr = moved(*addr),
+            // Each block of line numbers has to be sorted by address, which moving sections can undo,
+            // so every line gets its own block:
+            Chunk::Lines { first, addrs } => {
+                lines.extend((*first..).zip(addrs.iter()).map(|(lno, &addr)| Chunk::Lines { first: lno, addrs: vec![moved(addr)] }));
+                continue;
+            },
+            Chunk::Source(_) => {},
+        }
+        chunks.push(chunk);
+    }
+    chunks.extend(lines);
+
+    from_chunks(&chunks).ok_or(RelocateErr::Unreadable)
+}
@@ -39,7 +39,8 @@ pub(crate) struct SimController {
 }
 impl SimController {
     pub(crate) fn new() -> Self {
-        let flags = Default::default();
+        // Frames are tracked so the stack can be displayed.
+        let flags = SimFlags { debug_frames: true, ..Default::default() };
         let mut sim = Simulator::new(flags);
 
         let mcr = Arc::clone(sim.mcr());
@@ -0,0 +1,469 @@
+//! Stack frame reconstruction following the CS 2110 LC-3 calling convention.
+//!
+//! Under this convention, once a subroutine has executed its prologue,
+//! its frame pointer (R5) points at the first local variable and the frame looks like:
+//!
+//! ```text
+//! FP - n | local n (and any saved registers)
+//! ...    | ...
+//! FP + 0 | local 1
+//! FP + 1 | old frame pointer (caller's R5)
+//! FP + 2 | return address (R7)
+//! FP + 3 | return value
+//! FP + 4 | argument 1
+//! ...    | ...
+//! ```
+//!
+//! The frames are walked from R5/R6 outward (following the saved R5 chain)
+//! and paired with the simulator's `frame_stack` entries,
+//! so that each slot can be attributed to the subroutine that owns it.
+//!
+//! Traps and interrupts switch R6 to the supervisor stack, so the frames outside of one
+//! are only attributed through their frame pointers (and only their first local is known).
+
+use lc3_ensemble::ast::Reg::{R5, R6};
+use lc3_ensemble::sim::frame::{Frame, FrameType, ParameterList};
+use lc3_ensemble::sim::Simulator;
+use neon::prelude::*;
+
+use crate::cast::TryIntoJsValue;
+
+/// The largest number of words a single frame can occupy before
+/// the stack walk considers the frame pointer chain corrupted.
+const MAX_FRAME_SIZE: u16 = 256;
+
+/// What a given stack slot holds.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum SlotKind {
+    /// A local variable (or a register saved by the callee).
+    Local,
+    /// The caller's frame pointer (saved R5).
+    SavedFP,
+    /// The return address (saved R7).
+    ReturnAddr,
+    /// The slot reserved for the return value.
+    ReturnValue,
+    /// An argument pushed by the caller.
+    Argument,
+}
+impl SlotKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            SlotKind::Local       => "local",
+            SlotKind::SavedFP     => "savedFP",
+            SlotKind::ReturnAddr  => "returnAddr",
+            SlotKind::ReturnValue => "returnValue",
+            SlotKind::Argument    => "argument",
+        }
+    }
+}
+
+/// A single word on the stack.
+#[derive(Debug)]
+pub(crate) struct StackSlot {
+    addr: u16,
+    value: u16,
+    kind: SlotKind,
+    /// The argument name (if the subroutine was declared with one).
+    name: Option<String>,
+}
+
+/// A subroutine's frame on the stack.
+#[derive(Debug)]
+pub(crate) struct StackFrame {
+    /// The label of the subroutine (or trap handler) this frame belongs to.
+    label: Option<String>,
+    caller_addr: u16,
+    callee_addr: u16,
+    /// The frame pointer of this frame (if it follows the calling convention).
+    frame_ptr: Option<u16>,
+    /// Whether the saved return address matches the address after the caller.
+    ///
+    /// This is false when the prologue has not been executed yet
+    /// or when the stack has been corrupted.
+    ra_matches: bool,
+    /// The slots of the frame, ordered from top of stack (lowest address) to bottom.
+    slots: Vec<StackSlot>,
+}
+
+/// Walks the stack of the simulator, producing the active frames from innermost to outermost.
+///
+/// The `label_of` function is used to look up the label of a subroutine's address.
+///
+/// This requires the simulator's `debug_frames` flag to be enabled.
+/// If it is not, no frames are returned.
+pub(crate) fn walk_stack<'l>(sim: &Simulator, label_of: impl Fn(u16) -> Option<&'l str>) -> Vec<StackFrame> {
+    let Some(frames) = sim.frame_stack.frames() else { return vec![] };
+
+    // The top of the region of the stack which has not been attributed to a frame yet
+    // (unknown outside of a trap or interrupt, which has its own stack).
+    let mut m_sp = Some(sim.reg_file[R6].get());
+    // The frame pointer of the current frame, if the chain is still valid.
+    let mut m_fp = Some(sim.reg_file[R5].get());
+
+    let mut result = Vec::with_capacity(frames.len());
+    for frame in frames.iter().rev() {
+        let &Frame { caller_addr, callee_addr, frame_type, .. } = frame;
+
+        let label = match frame_type {
+            FrameType::Subroutine => label_of(callee_addr),
+            // Traps and interrupts are vectors, so look up their handler instead:
+            FrameType::Trap | FrameType::Interrupt => label_of(sim.mem[callee_addr].get()),
+        }.map(str::to_string);
+
+        // Only subroutines follow the calling convention.
+        // Traps and interrupts run on their own stack, so their frames have no slots.
+        if frame_type != FrameType::Subroutine {
+            m_sp = None;
+            result.push(StackFrame { label, caller_addr, callee_addr, frame_ptr: None, ra_matches: false, slots: vec![] });
+            continue;
+        }
+
+        // Prefer the frame pointer computed at the call (R6 - 4, where the prologue will put it),
+        // which is known even before the prologue has run (when R6 is still up to 4 words below it).
+        // Otherwise, fall back to the saved R5 chain.
+        let m_fp_here = match (frame.frame_ptr, m_sp) {
+            (Some(fp), Some(sp)) => Some(fp.get()).filter(|&fp| {
+                let top = u32::from(fp) + 4;
+                u32::from(sp) <= top && top - u32::from(sp) <= u32::from(MAX_FRAME_SIZE)
+            }),
+            (None, Some(sp)) => m_fp.filter(|&fp| sp <= fp && fp - sp <= MAX_FRAME_SIZE),
+            (Some(fp), None) => Some(fp.get()),
+            (None, None) => m_fp,
+        };
+        let Some(fp) = m_fp_here else {
+            // The chain is broken, so nothing else on the stack can be attributed.
+            m_fp = None;
+            result.push(StackFrame { label, caller_addr, callee_addr, frame_ptr: None, ra_matches: false, slots: vec![] });
+            continue;
+        };
+        // Before the prologue has set R5, the R5 chain still points at the caller's frame
+        // (which can be told apart by its return address not pointing back to this frame's caller),
+        // so the frame can't be attributed, but the caller's can:
+        let ra_matches = sim.mem[fp.wrapping_add(2)].get() == caller_addr.wrapping_add(1);
+        if frame.frame_ptr.is_none() && !ra_matches {
+            result.push(StackFrame { label, caller_addr, callee_addr, frame_ptr: None, ra_matches: false, slots: vec![] });
+            continue;
+        }
+
+        let arg_names = match sim.frame_stack.get_subroutine_def(callee_addr) {
+            Some(ParameterList::CallingConvention { params }) => params.as_slice(),
+            _ => &[],
+        };
+
+        let slot = |addr: u16, kind, name: Option<&String>| StackSlot {
+            addr,
+            value: sim.mem[addr].get(),
+            kind,
+            name: name.cloned(),
+        };
+
+        let mut slots: Vec<_> = (m_sp.unwrap_or(fp)..=fp)
+            .map(|addr| slot(addr, SlotKind::Local, None))
+            .collect();
+        slots.push(slot(fp.wrapping_add(1), SlotKind::SavedFP, None));
+        slots.push(slot(fp.wrapping_add(2), SlotKind::ReturnAddr, None));
+        slots.push(slot(fp.wrapping_add(3), SlotKind::ReturnValue, None));
+        slots.extend({
+            arg_names.iter()
+                .enumerate()
+                .map(|(i, name)| slot(fp.wrapping_add(4).wrapping_add(i as u16), SlotKind::Argument, Some(name)))
+        });
+
+        result.push(StackFrame { label, caller_addr, callee_addr, frame_ptr: Some(fp), ra_matches, slots });
+
+        // Anything past this frame's arguments belongs to the caller.
+        m_sp = Some(fp.wrapping_add(4).wrapping_add(arg_names.len() as u16));
+        m_fp = Some(sim.mem[fp.wrapping_add(1)].get());
+    }
+
+    result
+}
+
+impl TryIntoJsValue for StackSlot {
+    type Value = JsObject;
+
+    fn try_into_js<'a>(self, cx: &mut impl Context<'a>) -> JsResult<'a, Self::Value> {
+        let obj = cx.empty_object();
+
+        let addr = cx.number(self.addr);
+        obj.set(cx, "addr", addr)?;
+        let value = cx.number(self.value);
+        obj.set(cx, "value", value)?;
+        let kind = cx.string(self.kind.as_str());
+        obj.set(cx, "kind", kind)?;
+        let name = self.name.try_into_js(cx)?;
+        obj.set(cx, "name", name)?;
+
+        Ok(obj)
+    }
+}
+impl TryIntoJsValue for StackFrame {
+    type Value = JsObject;
+
+    fn try_into_js<'a>(self, cx: &mut impl Context<'a>) -> JsResult<'a, Self::Value> {
+        let obj = cx.empty_object();
+
+        let label = self.label.try_into_js(cx)?;
+        obj.set(cx, "label", label)?;
+        let caller_addr = cx.number(self.caller_addr);
+        obj.set(cx, "callerAddr", caller_addr)?;
+        let callee_addr = cx.number(self.callee_addr);
+        obj.set(cx, "calleeAddr", callee_addr)?;
+        let frame_ptr = self.frame_ptr.try_into_js(cx)?;
+        obj.set(cx, "framePtr", frame_ptr)?;
+        let ra_matches = cx.boolean(self.ra_matches);
+        obj.set(cx, "raMatches", ra_matches)?;
+        let slots = self.slots.try_into_js(cx)?;
+        obj.set(cx, "slots", slots)?;
+
+        Ok(obj)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use lc3_ensemble::asm::ObjectFile;
+    use lc3_ensemble::sim::device::TimerDevice;
+    use lc3_ensemble::sim::mem::MachineInitStrategy;
+    use lc3_ensemble::sim::SimFlags;
+
+    use super::*;
+    use crate::asm::tests::assemble;
+
+    /// `F(n)` (which has one local) calls `G()`, which prints a character.
+    const PROGRAM: &str = "
+        .ORIG x3000
+        LD R6, STACK
+        LD R5, STACK
+        AND R0, R0, #0
+        ADD R0, R0, #5
+        ADD R6, R6, #-1
+        STR R0, R6, #0
+CALL_F  JSR F
+        HALT
+STACK   .FILL xF000
+
+F       ADD R6, R6, #-4
+        STR R7, R6, #2
+        STR R5, R6, #1
+        ADD R5, R6, #0
+        ADD R6, R6, #-1
+CALL_G  JSR G
+        ADD R6, R5, #3
+        LDR R7, R5, #2
+        LDR R5, R5, #1
+        RET
+
+G       ADD R6, R6, #-4
+        STR R7, R6, #2
+G_SAVE  STR R5, R6, #1
+        ADD R5, R6, #0
+G_BODY  OUT
+        ADD R6, R5, #3
+        LDR R7, R5, #2
+        LDR R5, R5, #1
+        RET
+        .END
+    ";
+
+    /// A simulator with the program loaded (with memory zeroed, and `F` declared if `defs`).
+    fn simulator(defs: bool) -> (Simulator, ObjectFile) {
+        let obj = assemble(PROGRAM);
+        let flags = SimFlags { debug_frames: true, machine_init: MachineInitStrategy::Known { value: 0 }, ..Default::default() };
+        let mut sim = Simulator::new(flags);
+        sim.load_obj_file(&obj).expect("program should load");
+        if defs {
+            sim.frame_stack.set_subroutine_def(addr(&obj, "F"), ParameterList::with_calling_convention(&["n"]));
+            sim.frame_stack.set_subroutine_def(addr(&obj, "G"), ParameterList::with_calling_convention(&[]));
+        }
+        (sim, obj)
+    }
+    fn addr(obj: &ObjectFile, label: &str) -> u16 {
+        obj.symbol_table()
+            .and_then(|sym| sym.lookup_label(label))
+            .unwrap_or_else(|| panic!("{label} should be defined"))
+    }
+    /// Runs until the instruction at the given address is next.
+    fn run_to(sim: &mut Simulator, addr: u16) {
+        sim.run_while(|sim| sim.pc != addr).expect("program should run");
+        assert_eq!(sim.pc, addr);
+    }
+    fn walk(sim: &Simulator, obj: &ObjectFile) -> Vec<StackFrame> {
+        walk_stack(sim, |addr| obj.symbol_table()?.rev_lookup_label(addr))
+    }
+    fn labels(frames: &[StackFrame]) -> Vec<Option<&str>> {
+        frames.iter().map(|f| f.label.as_deref()).collect()
+    }
+    fn slots(frame: &StackFrame) -> Vec<(u16, SlotKind)> {
+        frame.slots.iter().map(|s| (s.addr, s.kind)).collect()
+    }
+
+    #[test]
+    fn frame_after_prologue() {
+        for defs in [false, true] {
+            let (mut sim, obj) = simulator(defs);
+            run_to(&mut sim, addr(&obj, "CALL_G"));
+
+            let frames = walk(&sim, &obj);
+            assert_eq!(labels(&frames), [Some("F")]);
+            let f = &frames[0];
+            assert_eq!((f.caller_addr, f.callee_addr), (addr(&obj, "CALL_F"), addr(&obj, "F")));
+            assert_eq!(f.frame_ptr, Some(0xEFFB));
+            assert!(f.ra_matches);
+
+            let mut expected = vec![
+                (0xEFFA, SlotKind::Local),
+                (0xEFFB, SlotKind::Local),
+                (0xEFFC, SlotKind::SavedFP),
+                (0xEFFD, SlotKind::ReturnAddr),
+                (0xEFFE, SlotKind::ReturnValue),
+            ];
+            if defs {
+                expected.push((0xEFFF, SlotKind::Argument));
+                let arg = &f.slots[5];
+                assert_eq!((arg.value, arg.name.as_deref()), (5, Some("n")));
+            }
+            assert_eq!(slots(f), expected);
+            assert_eq!(f.slots[2].value, 0xF000);
+            assert_eq!(f.slots[3].value, addr(&obj, "CALL_F") + 1);
+        }
+    }
+
+    #[test]
+    fn frame_before_prologue() {
+        // With a definition, the frame pointer is known as soon as the subroutine is called:
+        let (mut sim, obj) = simulator(true);
+        run_to(&mut sim, addr(&obj, "F"));
+        let frames = walk(&sim, &obj);
+        assert_eq!(labels(&frames), [Some("F")]);
+        assert_eq!(frames[0].frame_ptr, Some(0xEFFB));
+        assert!(!frames[0].ra_matches);
+
+        // Without one, the frame can't be attributed (but R5 still points to the caller's frame):
+        let (mut sim, obj) = simulator(false);
+        run_to(&mut sim, addr(&obj, "F"));
+        let frames = walk(&sim, &obj);
+        assert_eq!(labels(&frames), [Some("F")]);
+        assert_eq!(frames[0].frame_ptr, None);
+        assert!(frames[0].slots.is_empty());
+    }
+
+    #[test]
+    fn frame_mid_prologue() {
+        // The return address is saved, but R5 isn't set yet:
+        let (mut sim, obj) = simulator(true);
+        run_to(&mut sim, addr(&obj, "G_SAVE"));
+        let frames = walk(&sim, &obj);
+        assert_eq!(labels(&frames), [Some("G"), Some("F")]);
+        assert_eq!(frames[0].frame_ptr, Some(0xEFF6));
+        assert!(frames[0].ra_matches);
+        assert_eq!(frames[1].frame_ptr, Some(0xEFFB));
+
+        let (mut sim, obj) = simulator(false);
+        run_to(&mut sim, addr(&obj, "G_SAVE"));
+        let frames = walk(&sim, &obj);
+        assert_eq!(labels(&frames), [Some("G"), Some("F")]);
+        assert_eq!(frames[0].frame_ptr, None);
+        assert_eq!(frames[1].frame_ptr, Some(0xEFFB));
+        assert!(frames[1].ra_matches);
+    }
+
+    #[test]
+    fn nested_frames() {
+        for defs in [false, true] {
+            let (mut sim, obj) = simulator(defs);
+            run_to(&mut sim, addr(&obj, "G_BODY"));
+
+            let frames = walk(&sim, &obj);
+            assert_eq!(labels(&frames), [Some("G"), Some("F")]);
+            assert_eq!(frames.iter().map(|f| f.frame_ptr).collect::<Vec<_>>(), [Some(0xEFF6), Some(0xEFFB)]);
+            assert!(frames.iter().all(|f| f.ra_matches));
+            assert_eq!(frames[1].caller_addr, addr(&obj, "CALL_F"));
+            assert_eq!(frames[0].caller_addr, addr(&obj, "CALL_G"));
+            // F's locals end where G's arguments would be:
+            assert_eq!(slots(&frames[1])[0], (0xEFFA, SlotKind::Local));
+        }
+    }
+
+    #[test]
+    fn trap_frame() {
+        let (mut sim, obj) = simulator(false);
+        run_to(&mut sim, addr(&obj, "G_BODY"));
+        let handler = sim.mem[0x21].get();
+        run_to(&mut sim, handler);
+
+        let frames = walk_stack(&sim, |addr| (addr == handler).then_some("TRAP_OUT").or_else(|| obj.symbol_table()?.rev_lookup_label(addr)));
+        assert_eq!(labels(&frames), [Some("TRAP_OUT"), Some("G"), Some("F")]);
+        assert_eq!((frames[0].callee_addr, frames[0].frame_ptr), (0x21, None));
+        assert!(frames[0].slots.is_empty());
+        // R6 is the supervisor stack in the trap, so only the frame pointers of the subroutines are known:
+        assert_eq!(frames[1].frame_ptr, Some(0xEFF6));
+        assert_eq!(slots(&frames[1])[0], (0xEFF6, SlotKind::Local));
+        assert_eq!(frames[2].frame_ptr, Some(0xEFFB));
+        assert_eq!(slots(&frames[2])[0], (0xEFFA, SlotKind::Local));
+    }
+
+    #[test]
+    fn interrupt_frame() {
+        let obj = assemble("
+            .ORIG x3000
+            LD R6, STACK
+            LD R5, STACK
+            JSR F
+            HALT
+    STACK   .FILL xF000
+    F       ADD R6, R6, #-4
+            STR R7, R6, #2
+            STR R5, R6, #1
+            ADD R5, R6, #0
+    LOOP    BR LOOP
+            .END
+            .ORIG x4000
+    HANDLER RTI
+            .END
+            .ORIG x0181
+            .FILL HANDLER
+            .END
+        ");
+        let flags = SimFlags { debug_frames: true, machine_init: MachineInitStrategy::Known { value: 0 }, ..Default::default() };
+        let mut sim = Simulator::new(flags);
+        let mut timer = TimerDevice::new(None, 20..=20, 0x81, 4);
+        timer.enabled = true;
+        sim.device_handler.add_device(Arc::new(Mutex::new(timer)), &[]).expect("timer should be added");
+        sim.load_obj_file(&obj).expect("program should load");
+        run_to(&mut sim, addr(&obj, "HANDLER"));
+
+        let frames = walk(&sim, &obj);
+        assert_eq!(labels(&frames), [Some("HANDLER"), Some("F")]);
+        assert_eq!((frames[0].callee_addr, frames[0].frame_ptr), (0x181, None));
+        assert!(frames[0].slots.is_empty());
+        assert_eq!(frames[1].frame_ptr, Some(0xEFFC));
+        assert!(frames[1].ra_matches);
+    }
+
+    #[test]
+    fn corrupted_frame_pointers() {
+        let (mut sim, obj) = simulator(false);
+        run_to(&mut sim, addr(&obj, "G_BODY"));
+
+        // G's saved frame pointer points back to G's frame:
+        sim.mem[0xEFF7].set(0xEFF6);
+        let frames = walk(&sim, &obj);
+        assert_eq!(labels(&frames), [Some("G"), Some("F")]);
+        assert_eq!(frames[0].frame_ptr, Some(0xEFF6));
+        assert_eq!(frames[1].frame_ptr, None);
+        assert!(frames[1].slots.is_empty());
+
+        // R5 points nowhere near the stack (or wraps around memory):
+        for (r5, r6) in [(0x0000, 0xEFF6), (0xFFFF, 0xEFF6), (0xFFFE, 0xFFFF), (0x0001, 0xFFFF)] {
+            sim.reg_file[R5].set(r5);
+            sim.reg_file[R6].set(r6);
+            let frames = walk(&sim, &obj);
+            assert_eq!(labels(&frames), [Some("G"), Some("F")]);
+            assert!(frames.iter().all(|f| f.slots.len() <= usize::from(MAX_FRAME_SIZE) + 4));
+        }
+    }
+}
@@ -0,0 +1,171 @@
+//! The standard library, a library of routines which programs commonly need (see [`crate::archive`]).
+//!
+//! `link` resolves external labels against it after the libraries it's given,
+//! so a program can use a routine by declaring it `.EXTERNAL` (e.g., `.EXTERNAL PRINTDEC`).
+//! External labels can't be PC offsets (`JSR PRINTDEC` fails to assemble), so routines are called through a pointer:
+//! ```text
+//!         LD R6, P_PRINTDEC
+//!         JSRR R6
+//!         ...
+//! P_PRINTDEC .FILL PRINTDEC
+//! ```
+//!
+//! Each routine is a relocatable section in its own source file (in `src/stdlib`),
+//! whose header documents the registers it reads and writes.
+//! The sources are assembled the first time the library is linked, so their source is linked along with them
+//! (and the debugger can step into them).
+
+use std::path::PathBuf;
+use std::sync::LazyLock;
+
+use crate::archive::Member;
+use crate::asm;
+use crate::dialect::Dialect;
+use crate::preproc;
+
+/// The path the standard library's members are named by (e.g., `stdlib.lib(mult.obj)`).
+const LIBRARY_PATH: &str = "stdlib.lib";
+
+/// The source file of each routine.
+const SOURCES: &[(&str, &str)] = &[
+    ("print_dec.asm", include_str!("stdlib/print_dec.asm")),
+    ("read_dec.asm",  include_str!("stdlib/read_dec.asm")),
+    ("print_hex.asm", include_str!("stdlib/print_hex.asm")),
+    ("mult.asm",      include_str!("stdlib/mult.asm")),
+    ("divmod.asm",    include_str!("stdlib/divmod.asm")),
+    ("strlen.asm",    include_str!("stdlib/strlen.asm")),
+    ("strcmp.asm",    include_str!("stdlib/strcmp.asm")),
+];
+
+/// The members of the standard library, which are assembled the first time it's linked.
+///
+/// A routine which fails to assemble is left out (which the tests check never happens).
+static MEMBERS: LazyLock<Vec<Member>> = LazyLock::new(|| {
+    SOURCES.iter()
+        .filter_map(|&(name, src)| {
+            let (expanded, errors) = preproc::preprocess(src, None);
+            if !errors.is_empty() { return None; }
+            let (obj, relocs) = asm::assemble_all(&expanded, Dialect::default()).ok()?;
+            Some(Member::new(name.replace(".asm", ".obj"), obj, relocs))
+        })
+        .collect()
+});
+
+/// The standard library, as its path and its members.
+pub(crate) fn library() -> (PathBuf, Vec<Member>) {
+    (PathBuf::from(LIBRARY_PATH), MEMBERS.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use lc3_ensemble::asm::ObjectFile;
+    use lc3_ensemble::sim::device::{BufferedDisplay, BufferedKeyboard};
+    use lc3_ensemble::sim::{SimFlags, Simulator};
+
+    use super::*;
+    use crate::{archive, link};
+
+    /// Calls every routine (through pointers, see the module docs), storing the results from `RES`.
+    const PROGRAM: &str = "
+        .ORIG x3000
+        .EXTERNAL PRINTDEC
+        .EXTERNAL READDEC
+        .EXTERNAL PRINTHEX
+        .EXTERNAL MULT
+        .EXTERNAL DIVMOD
+        .EXTERNAL STRLEN
+        .EXTERNAL STRCMP
+        LD R0, N1
+        LD R1, N2
+        LD R6, P_MULT
+        JSRR R6
+        ST R0, RES
+        LD R0, N3
+        LD R1, N4
+        LD R6, P_DIVMOD
+        JSRR R6
+        ST R0, RES+1
+        ST R1, RES+2
+        LEA R0, S1
+        LD R6, P_STRLEN
+        JSRR R6
+        ST R0, RES+3
+        LEA R0, S1
+        LEA R1, S2
+        LD R6, P_STRCMP
+        JSRR R6
+        ST R0, RES+4
+        LD R6, P_READDEC
+        JSRR R6
+        ST R0, RES+5
+        LD R6, P_PRINTDEC
+        JSRR R6
+        LD R0, N5
+        LD R6, P_PRINTHEX
+        JSRR R6
+        HALT
+        N1 .FILL #6
+        N2 .FILL #-7
+        N3 .FILL #-45
+        N4 .FILL #7
+        N5 .FILL x3A0F
+        S1 .STRINGZ \"hello\"
+        S2 .STRINGZ \"help\"
+        RES .BLKW 6
+        P_PRINTDEC .FILL PRINTDEC
+        P_READDEC .FILL READDEC
+        P_PRINTHEX .FILL PRINTHEX
+        P_MULT .FILL MULT
+        P_DIVMOD .FILL DIVMOD
+        P_STRLEN .FILL STRLEN
+        P_STRCMP .FILL STRCMP
+        .END
+    ";
+
+    #[test]
+    fn every_routine_assembles() {
+        for &(name, src) in SOURCES {
+            let (expanded, errors) = preproc::preprocess(src, None);
+            assert!(errors.is_empty(), "{name} should preprocess: {errors:?}");
+            if let Err(e) = asm::assemble_all(&expanded, Dialect::default()) {
+                panic!("{name} should assemble: {e:?}");
+            }
+        }
+        assert_eq!(library().1.len(), SOURCES.len());
+    }
+
+    #[test]
+    fn every_routine_runs() {
+        let (path, members) = library();
+
+        let (expanded, _) = preproc::preprocess(PROGRAM, None);
+        let (obj, relocs) = asm::assemble_all(&expanded, Dialect::default()).unwrap_or_else(|e| panic!("program should assemble: {e:?}"));
+        let mut inputs = vec![(PathBuf::from("program.obj"), obj, relocs)];
+        let selected = archive::select(&inputs, vec![(path, members)]);
+        assert_eq!(selected.len(), SOURCES.len());
+        inputs.extend(selected);
+
+        let placed = link::place(&inputs, None).unwrap_or_else(|_| panic!("routines should be placed"));
+        assert!(link::check_unresolved(&placed).is_empty());
+        let linked = placed.into_iter()
+            .try_fold(ObjectFile::empty(), |linked, (_, obj)| ObjectFile::link(linked, obj))
+            .expect("routines should link");
+
+        let mut sim = Simulator::new(SimFlags::default());
+        let input = BufferedKeyboard::default();
+        let output = BufferedDisplay::default();
+        input.get_buffer().write().unwrap().extend(b"-123\n");
+        sim.device_handler.set_keyboard(input);
+        sim.device_handler.set_display(output.clone());
+        sim.load_obj_file(&linked).expect("program should load");
+        sim.run_with_limit(100_000).expect("program should run");
+        assert!(sim.hit_halt());
+
+        let res = linked.symbol_table()
+            .and_then(|sym| sym.lookup_label("RES"))
+            .expect("RES should be defined");
+        let results: Vec<_> = (0..6).map(|i| sim.mem[res + i].get() as i16).collect();
+        assert_eq!(results, [-42, -6, -3, 5, i16::from(b'l') - i16::from(b'p'), -123]);
+        assert_eq!(String::from_utf8_lossy(&output.get_buffer().read().unwrap()), "-123\n-123x3A0F");
+    }
+}
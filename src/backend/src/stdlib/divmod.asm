@@ -0,0 +1,84 @@
+; DIVMOD: divides two signed numbers.
+;
+;   In:  R0 (the dividend), R1 (the divisor)
+;   Out: R0 = R0 / R1 (rounded toward zero), R1 = R0 % R1 (with the sign of the dividend)
+;
+; Dividing by zero gives a quotient of 0 and a remainder of the dividend,
+; and x8000 / -1 overflows to x8000.
+; Every other register is preserved.
+; Registers are saved in the routine's own memory, so R6 doesn't have to point to a stack.
+.RELOC
+DIVMOD  ST R2, .r2
+        ST R3, .r3
+        ST R4, .r4
+        ST R5, .r5
+        ADD R1, R1, #0
+        BRnp .nonzero
+        ADD R1, R0, #0      ; dividing by zero
+        AND R0, R0, #0
+        BRnzp .return
+.nonzero
+        ST R0, .dividend
+        AND R4, R4, #0      ; R4 = whether the quotient is negative (0 or -1)
+        ADD R2, R0, #0      ; R2 = |dividend| (unsigned, since |x8000| is x8000)
+        BRzp .divisor
+        NOT R2, R2
+        ADD R2, R2, #1
+        NOT R4, R4
+.divisor
+        ADD R3, R1, #0      ; R3 = -|divisor|
+        BRn .negative
+        NOT R3, R3
+        ADD R3, R3, #1
+        BRnzp .sign
+.negative
+        NOT R4, R4
+.sign   ST R4, .quotneg
+
+        ; Long division, one bit of the dividend at a time:
+        AND R4, R4, #0      ; R4 = the quotient
+        AND R5, R5, #0      ; R5 = the remainder
+        AND R1, R1, #0
+        ADD R1, R1, #15
+        ADD R1, R1, #1      ; R1 = the bits left
+.loop   ADD R5, R5, R5      ; shift the next bit of the dividend into the remainder
+        ADD R2, R2, #0
+        BRzp .shifted
+        ADD R5, R5, #1
+.shifted
+        ADD R2, R2, R2
+        ADD R4, R4, R4
+        ADD R5, R5, #0
+        BRn .subtract       ; (the remainder is at least x8000, so it's at least the divisor)
+        ADD R0, R5, R3
+        BRn .next
+.subtract
+        ADD R5, R5, R3
+        ADD R4, R4, #1
+.next   ADD R1, R1, #-1
+        BRp .loop
+
+        LD R0, .quotneg
+        BRz .quotient
+        NOT R4, R4
+        ADD R4, R4, #1
+.quotient
+        LD R0, .dividend
+        BRzp .remainder
+        NOT R5, R5
+        ADD R5, R5, #1
+.remainder
+        ADD R0, R4, #0
+        ADD R1, R5, #0
+.return LD R2, .r2
+        LD R3, .r3
+        LD R4, .r4
+        LD R5, .r5
+        RET
+.dividend .BLKW 1
+.quotneg  .BLKW 1
+.r2     .BLKW 1
+.r3     .BLKW 1
+.r4     .BLKW 1
+.r5     .BLKW 1
+.END
@@ -0,0 +1,33 @@
+; MULT: multiplies two numbers.
+;
+;   In:  R0, R1 (signed or unsigned)
+;   Out: R0 = R0 * R1 (the low 16 bits of the product)
+;
+; Every other register is preserved.
+; Registers are saved in the routine's own memory, so R6 doesn't have to point to a stack.
+.RELOC
+MULT    ST R1, .r1
+        ST R2, .r2
+        ST R3, .r3
+        ST R4, .r4
+        AND R2, R2, #0      ; R2 = the product
+        ADD R3, R0, #0      ; R3 = R0, shifted left once per bit of R1
+        AND R4, R4, #0
+        ADD R4, R4, #1      ; R4 = the current bit of R1
+.loop   AND R0, R1, R4
+        BRz .skip
+        ADD R2, R2, R3
+.skip   ADD R3, R3, R3
+        ADD R4, R4, R4
+        BRnp .loop
+        ADD R0, R2, #0
+        LD R1, .r1
+        LD R2, .r2
+        LD R3, .r3
+        LD R4, .r4
+        RET
+.r1     .BLKW 1
+.r2     .BLKW 1
+.r3     .BLKW 1
+.r4     .BLKW 1
+.END
@@ -0,0 +1,74 @@
+; PRINTDEC: prints a signed number in decimal (e.g., -42).
+;
+;   In:  R0 (the number)
+;
+; Every register is preserved.
+; Registers are saved in the routine's own memory, so R6 doesn't have to point to a stack.
+.RELOC
+PRINTDEC
+        ST R0, .r0
+        ST R1, .r1
+        ST R2, .r2
+        ST R3, .r3
+        ST R4, .r4
+        ST R7, .r7
+        ADD R1, R0, #0      ; R1 = the number
+        BRzp .digits
+        LD R0, .minus
+        OUT
+        NOT R1, R1
+        ADD R1, R1, #1
+        BRzp .digits
+        LEA R0, .min        ; (x8000 has no positive counterpart)
+        PUTS
+        BRnzp .return
+.digits LEA R2, .powers     ; R2 = the address of the current power of ten
+        AND R4, R4, #0      ; R4 = whether a digit has been printed
+.place  LDR R3, R2, #0
+        NOT R3, R3
+        ADD R3, R3, #1      ; R3 = -(the power of ten)
+        LD R0, .zero        ; R0 = the digit
+.count  ADD R1, R1, R3
+        BRn .counted
+        ADD R0, R0, #1
+        BRnzp .count
+.counted
+        LDR R3, R2, #0
+        ADD R1, R1, R3      ; undo the last subtraction
+        LD R3, .negzero
+        ADD R3, R0, R3
+        BRp .print
+        ADD R4, R4, #0      ; leading zeros aren't printed (except for the ones place)
+        BRp .print
+        LDR R3, R2, #1
+        BRnp .next
+.print  OUT
+        AND R4, R4, #0
+        ADD R4, R4, #1
+.next   ADD R2, R2, #1
+        LDR R3, R2, #0
+        BRnp .place
+.return LD R0, .r0
+        LD R1, .r1
+        LD R2, .r2
+        LD R3, .r3
+        LD R4, .r4
+        LD R7, .r7
+        RET
+.powers .FILL #10000
+        .FILL #1000
+        .FILL #100
+        .FILL #10
+        .FILL #1
+        .FILL #0
+.minus  .FILL x2D
+.zero   .FILL x30
+.negzero .FILL #-48
+.min    .STRINGZ "32768"
+.r0     .BLKW 1
+.r1     .BLKW 1
+.r2     .BLKW 1
+.r3     .BLKW 1
+.r4     .BLKW 1
+.r7     .BLKW 1
+.END
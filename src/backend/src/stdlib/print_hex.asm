@@ -0,0 +1,50 @@
+; PRINTHEX: prints a number in hexadecimal (e.g., x3000).
+;
+;   In:  R0 (the number)
+;
+; Every register is preserved.
+; Registers are saved in the routine's own memory, so R6 doesn't have to point to a stack.
+.RELOC
+PRINTHEX
+        ST R0, .r0
+        ST R1, .r1
+        ST R2, .r2
+        ST R3, .r3
+        ST R7, .r7
+        ADD R1, R0, #0      ; R1 = the number, shifted left a digit at a time
+        LD R0, .prefix
+        OUT
+        AND R2, R2, #0
+        ADD R2, R2, #4      ; R2 = the digits left
+.digit  AND R0, R0, #0      ; R0 = the top 4 bits of R1
+        AND R3, R3, #0
+        ADD R3, R3, #4      ; R3 = the bits left
+.bit    ADD R0, R0, R0
+        ADD R1, R1, #0
+        BRzp .shift
+        ADD R0, R0, #1
+.shift  ADD R1, R1, R1
+        ADD R3, R3, #-1
+        BRp .bit
+        ADD R3, R0, #-10
+        BRn .number
+        ADD R0, R0, #7      ; (the distance from '9' + 1 to 'A')
+.number LD R3, .zero
+        ADD R0, R0, R3
+        OUT
+        ADD R2, R2, #-1
+        BRp .digit
+        LD R0, .r0
+        LD R1, .r1
+        LD R2, .r2
+        LD R3, .r3
+        LD R7, .r7
+        RET
+.prefix .FILL x78
+.zero   .FILL x30
+.r0     .BLKW 1
+.r1     .BLKW 1
+.r2     .BLKW 1
+.r3     .BLKW 1
+.r7     .BLKW 1
+.END
@@ -0,0 +1,51 @@
+; READDEC: reads a signed decimal number from the keyboard (e.g., -42), echoing it.
+;
+;   Out: R0 (the number)
+;
+; The number can start with a `-`, and ends at the first character which isn't a digit (such as Enter).
+; Numbers which don't fit in 16 bits wrap around.
+; Every other register is preserved.
+; Registers are saved in the routine's own memory, so R6 doesn't have to point to a stack.
+.RELOC
+READDEC ST R1, .r1
+        ST R2, .r2
+        ST R3, .r3
+        ST R7, .r7
+        AND R1, R1, #0      ; R1 = the number
+        AND R2, R2, #0      ; R2 = whether the number is negative
+        GETC
+        OUT
+        LD R3, .negminus
+        ADD R3, R0, R3
+        BRnp .digit
+        ADD R2, R2, #1
+.read   GETC
+        OUT
+.digit  LD R3, .negzero
+        ADD R0, R0, R3      ; R0 = the digit
+        BRn .done
+        ADD R3, R0, #-9
+        BRp .done
+        ADD R3, R1, R1      ; R1 = R1 * 10 + the digit
+        ADD R1, R3, R3
+        ADD R1, R1, R1
+        ADD R1, R1, R3
+        ADD R1, R1, R0
+        BRnzp .read
+.done   ADD R0, R1, #0
+        ADD R2, R2, #0
+        BRz .return
+        NOT R0, R0
+        ADD R0, R0, #1
+.return LD R1, .r1
+        LD R2, .r2
+        LD R3, .r3
+        LD R7, .r7
+        RET
+.negminus .FILL #-45
+.negzero  .FILL #-48
+.r1     .BLKW 1
+.r2     .BLKW 1
+.r3     .BLKW 1
+.r7     .BLKW 1
+.END
@@ -0,0 +1,34 @@
+; STRCMP: compares two strings.
+;
+;   In:  R0, R1 (the addresses of null-terminated strings)
+;   Out: R0 = the difference of the first characters which differ
+;        (negative if the first string comes first, zero if they're equal, and positive otherwise)
+;
+; The condition codes are set by R0, so the result can be branched on directly.
+; Every other register is preserved.
+; Registers are saved in the routine's own memory, so R6 doesn't have to point to a stack.
+.RELOC
+STRCMP  ST R1, .r1
+        ST R2, .r2
+        ST R3, .r3
+.loop   LDR R2, R0, #0
+        LDR R3, R1, #0
+        NOT R3, R3
+        ADD R3, R3, #1
+        ADD R3, R2, R3      ; R3 = the difference of the current characters
+        BRnp .done
+        ADD R2, R2, #0      ; (both strings ended)
+        BRz .done
+        ADD R0, R0, #1
+        ADD R1, R1, #1
+        BRnzp .loop
+.done   ADD R0, R3, #0
+        LD R1, .r1
+        LD R2, .r2
+        LD R3, .r3
+        ADD R0, R0, #0
+        RET
+.r1     .BLKW 1
+.r2     .BLKW 1
+.r3     .BLKW 1
+.END
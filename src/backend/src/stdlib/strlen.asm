@@ -0,0 +1,23 @@
+; STRLEN: finds the length of a string.
+;
+;   In:  R0 (the address of a null-terminated string)
+;   Out: R0 = the number of characters before the null terminator
+;
+; Every other register is preserved.
+; Registers are saved in the routine's own memory, so R6 doesn't have to point to a stack.
+.RELOC
+STRLEN  ST R1, .r1
+        ST R2, .r2
+        ADD R1, R0, #0      ; R1 = the address of the current character
+        AND R0, R0, #0
+.loop   LDR R2, R1, #0
+        BRz .return
+        ADD R0, R0, #1
+        ADD R1, R1, #1
+        BRnzp .loop
+.return LD R1, .r1
+        LD R2, .r2
+        RET
+.r1     .BLKW 1
+.r2     .BLKW 1
+.END